@@ -0,0 +1,286 @@
+//! Approval of tool calls that permission rules send to "ask"
+//!
+//! Before such a call runs, the agent registers a request with its
+//! `ApprovalBroker` and suspends the turn until the interface showing the
+//! request (CLI prompt, SSE/WebSocket event, Telegram button) calls
+//! `resolve`. Requests time out after `tools.approval_timeout_secs`. With
+//! nobody to ask (heartbeat runs, plain HTTP requests) the call is handled by
+//! `tools.unattended_approval`: denied, or denied and queued for review.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use tokio::sync::oneshot;
+use tokio_util::sync::CancellationToken;
+
+use super::permissions::suggest_pattern;
+use super::providers::ToolCall;
+use super::tools::extract_tool_detail;
+
+/// Result recorded for a call the user refused
+pub const DENIED_TOOL_OUTPUT: &str = "[Denied by user: the tool was not run]";
+
+/// Result recorded when nobody answered in time
+pub const APPROVAL_TIMEOUT_OUTPUT: &str = "[Approval timed out: the tool was not run]";
+
+/// Result recorded for a call denied because nobody could be asked
+pub const UNATTENDED_DENIED_OUTPUT: &str =
+    "[Denied: this tool needs approval and no one is available to approve it]";
+
+/// Result recorded for a call queued for later review
+pub const QUEUED_TOOL_OUTPUT: &str =
+    "[Queued for approval: the tool was not run. The user will review it later]";
+
+const QUEUE_FILENAME: &str = "approval_queue.jsonl";
+
+/// A tool call waiting for a decision
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ApprovalRequest {
+    /// ID to answer the request with
+    pub id: String,
+    /// ID of the tool call it holds back
+    pub call_id: String,
+    pub name: String,
+    pub arguments: String,
+    /// Short description for display (command, path, URL...)
+    pub detail: Option<String>,
+    /// Pattern saved if the user chooses "always allow" (None: the whole tool)
+    pub pattern: Option<String>,
+}
+
+/// Answer to an approval request
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ApprovalDecision {
+    Approve,
+    /// Approve, and allow the request's pattern from now on
+    AlwaysAllow,
+    Deny,
+}
+
+impl ApprovalDecision {
+    /// From the approved/always flags the interfaces send
+    pub fn from_flags(approved: bool, always: bool) -> Self {
+        match (approved, always) {
+            (true, true) => Self::AlwaysAllow,
+            (true, false) => Self::Approve,
+            (false, _) => Self::Deny,
+        }
+    }
+}
+
+/// How a call that needed approval was settled
+#[derive(Debug, Clone, PartialEq)]
+pub enum Approval {
+    Approved,
+    /// Approved, with the request's pattern to allow from now on
+    ApprovedAlways,
+    /// Not run; the output to record instead
+    Refused(String),
+    /// The turn was cancelled while waiting
+    Cancelled,
+}
+
+/// Pending approval requests, shared by everything that can answer them
+#[derive(Clone, Default)]
+pub struct ApprovalBroker {
+    pending: Arc<Mutex<HashMap<String, oneshot::Sender<ApprovalDecision>>>>,
+}
+
+impl ApprovalBroker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Open a request for a tool call; pass the receiver to `wait`
+    pub fn register(
+        &self,
+        call: &ToolCall,
+    ) -> (ApprovalRequest, oneshot::Receiver<ApprovalDecision>) {
+        let request = ApprovalRequest {
+            id: uuid::Uuid::new_v4().to_string(),
+            call_id: call.id.clone(),
+            name: call.name.clone(),
+            arguments: call.arguments.clone(),
+            detail: extract_tool_detail(&call.name, &call.arguments),
+            pattern: suggest_pattern(&call.name, &call.arguments),
+        };
+        let (tx, rx) = oneshot::channel();
+        if let Ok(mut pending) = self.pending.lock() {
+            // Drop requests whose turn went away without an answer
+            pending.retain(|_, tx| !tx.is_closed());
+            pending.insert(request.id.clone(), tx);
+        }
+        (request, rx)
+    }
+
+    /// Answer a request. Returns false if it is unknown or already settled.
+    pub fn resolve(&self, id: &str, decision: ApprovalDecision) -> bool {
+        let sender = self.pending.lock().ok().and_then(|mut p| p.remove(id));
+        sender.is_some_and(|tx| tx.send(decision).is_ok())
+    }
+
+    /// IDs of requests still waiting
+    pub fn pending(&self) -> Vec<String> {
+        self.pending
+            .lock()
+            .map(|p| p.keys().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// Wait for the answer to a request. A timeout of zero waits forever.
+    pub async fn wait(
+        &self,
+        request_id: &str,
+        decision: oneshot::Receiver<ApprovalDecision>,
+        timeout: Duration,
+        cancel: &CancellationToken,
+    ) -> Approval {
+        let answer = async {
+            if timeout.is_zero() {
+                Ok(decision.await)
+            } else {
+                tokio::time::timeout(timeout, decision).await
+            }
+        };
+        let approval = tokio::select! {
+            answer = answer => match answer {
+                Ok(Ok(ApprovalDecision::Approve)) => Approval::Approved,
+                Ok(Ok(ApprovalDecision::AlwaysAllow)) => Approval::ApprovedAlways,
+                Ok(Ok(ApprovalDecision::Deny)) | Ok(Err(_)) => {
+                    Approval::Refused(DENIED_TOOL_OUTPUT.to_string())
+                }
+                Err(_) => Approval::Refused(APPROVAL_TIMEOUT_OUTPUT.to_string()),
+            },
+            _ = cancel.cancelled() => Approval::Cancelled,
+        };
+        self.forget(request_id);
+        approval
+    }
+
+    fn forget(&self, id: &str) {
+        if let Ok(mut pending) = self.pending.lock() {
+            pending.remove(id);
+        }
+    }
+}
+
+/// Entry in the queue of calls held for review
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QueuedApproval {
+    pub ts: String,
+    pub agent_id: String,
+    #[serde(flatten)]
+    pub request: ApprovalRequest,
+}
+
+pub fn approval_queue_path(state_dir: &Path) -> PathBuf {
+    state_dir.join(QUEUE_FILENAME)
+}
+
+/// Append a call that couldn't be approved unattended to the review queue
+pub fn queue_for_approval(
+    state_dir: &Path,
+    agent_id: &str,
+    request: &ApprovalRequest,
+) -> Result<()> {
+    fs::create_dir_all(state_dir)?;
+    let entry = QueuedApproval {
+        ts: chrono::Utc::now().to_rfc3339(),
+        agent_id: agent_id.to_string(),
+        request: request.clone(),
+    };
+    let mut file = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(approval_queue_path(state_dir))
+        .context("Failed to open approval queue")?;
+    writeln!(file, "{}", serde_json::to_string(&entry)?)
+        .context("Failed to write approval queue")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn call() -> ToolCall {
+        ToolCall {
+            id: "call_1".to_string(),
+            name: "bash".to_string(),
+            arguments: r#"{"command": "rm -rf build"}"#.to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_resolve_wakes_waiting_turn() {
+        let broker = ApprovalBroker::new();
+        let cancel = CancellationToken::new();
+
+        let (request, rx) = broker.register(&call());
+        assert_eq!(request.detail.as_deref(), Some("rm -rf build"));
+        assert_eq!(broker.pending(), vec![request.id.clone()]);
+
+        let answer = broker.clone();
+        let id = request.id.clone();
+        tokio::spawn(async move { answer.resolve(&id, ApprovalDecision::Approve) });
+        let approval = broker
+            .wait(&request.id, rx, Duration::from_secs(5), &cancel)
+            .await;
+        assert_eq!(approval, Approval::Approved);
+        assert!(broker.pending().is_empty());
+        // Settled requests can't be answered again
+        assert!(!broker.resolve(&request.id, ApprovalDecision::Deny));
+
+        let (request, rx) = broker.register(&call());
+        assert_eq!(request.pattern.as_deref(), Some("rm -rf build"));
+        assert!(broker.resolve(&request.id, ApprovalDecision::Deny));
+        let approval = broker
+            .wait(&request.id, rx, Duration::from_secs(5), &cancel)
+            .await;
+        assert_eq!(approval, Approval::Refused(DENIED_TOOL_OUTPUT.to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_timeout_and_cancel() {
+        let broker = ApprovalBroker::new();
+        let cancel = CancellationToken::new();
+
+        let (request, rx) = broker.register(&call());
+        let approval = broker
+            .wait(&request.id, rx, Duration::from_millis(20), &cancel)
+            .await;
+        assert_eq!(
+            approval,
+            Approval::Refused(APPROVAL_TIMEOUT_OUTPUT.to_string())
+        );
+        assert!(!broker.resolve(&request.id, ApprovalDecision::Approve));
+
+        let (request, rx) = broker.register(&call());
+        cancel.cancel();
+        let approval = broker.wait(&request.id, rx, Duration::ZERO, &cancel).await;
+        assert_eq!(approval, Approval::Cancelled);
+    }
+
+    #[test]
+    fn test_queue_for_approval() {
+        let tmp = tempfile::tempdir().unwrap();
+        let (request, _rx) = ApprovalBroker::new().register(&call());
+        queue_for_approval(tmp.path(), "main", &request).unwrap();
+        queue_for_approval(tmp.path(), "main", &request).unwrap();
+
+        let content = fs::read_to_string(approval_queue_path(tmp.path())).unwrap();
+        let entries: Vec<QueuedApproval> = content
+            .lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].agent_id, "main");
+        assert_eq!(entries[0].request.name, "bash");
+    }
+}
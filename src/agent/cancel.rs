@@ -0,0 +1,96 @@
+//! Cancellation of an in-flight agent turn
+//!
+//! Every turn runs against a fresh token. Interfaces hold a `CancelHandle`
+//! (cheap to clone, usable while the agent is borrowed by the turn) and call
+//! `cancel()` to stop the provider call or tool currently running.
+
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use tokio_util::sync::CancellationToken;
+
+/// Result recorded for tool calls that were cut short or never started
+pub const CANCELLED_TOOL_OUTPUT: &str = "[Cancelled by user]";
+
+/// Handle for cancelling whatever turn the agent is running
+#[derive(Clone, Default)]
+pub struct CancelHandle(Arc<Mutex<CancellationToken>>);
+
+impl CancelHandle {
+    /// Cancel the turn in flight (no-op between turns)
+    pub fn cancel(&self) {
+        if let Ok(token) = self.0.lock() {
+            token.cancel();
+        }
+    }
+
+    /// Token of the current turn
+    pub fn token(&self) -> CancellationToken {
+        self.0
+            .lock()
+            .map(|t| t.clone())
+            .unwrap_or_else(|_| CancellationToken::new())
+    }
+
+    /// Start a new turn; a cancel from a previous turn doesn't carry over
+    pub(crate) fn reset(&self) {
+        if let Ok(mut token) = self.0.lock() {
+            *token = CancellationToken::new();
+        }
+    }
+}
+
+/// Error returned when the user cancels a turn
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cancelled;
+
+impl fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Cancelled by user")
+    }
+}
+
+impl std::error::Error for Cancelled {}
+
+/// Whether an error is a user cancel rather than a failure
+pub fn is_cancelled(err: &anyhow::Error) -> bool {
+    err.is::<Cancelled>()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::agent::tools::{BashTool, Tool};
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn test_cancel_does_not_carry_over() {
+        let handle = CancelHandle::default();
+        let first = handle.token();
+        handle.cancel();
+        assert!(first.is_cancelled());
+
+        handle.reset();
+        assert!(!handle.token().is_cancelled());
+        assert!(is_cancelled(&anyhow::Error::new(Cancelled)));
+        assert!(!is_cancelled(&anyhow::anyhow!("Cancelled")));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_cancel_kills_bash_command() {
+        let tool = BashTool::new(30_000, std::env::temp_dir(), None);
+        let token = CancellationToken::new();
+        let trigger = token.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            trigger.cancel();
+        });
+
+        let start = Instant::now();
+        let result = tool
+            .execute_cancellable(r#"{"command": "sleep 30"}"#, &token)
+            .await;
+        assert!(result.is_err());
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+}
@@ -0,0 +1,922 @@
+//! Workspace checkpoints
+//!
+//! Before `write_file`/`edit_file` touch a file, or `bash` runs, the agent
+//! snapshots what the call may change, and records the new contents once it
+//! has run. Each turn that changed files leaves one checkpoint: contents are
+//! stored once by SHA-256 under `checkpoints/objects/` in the state dir, and
+//! the checkpoint is a JSON file listing every file's blob before and after
+//! the turn. Restoring a checkpoint puts the files back as they were before
+//! that turn, rolling back the turns after it too. While a turn is running,
+//! a `.pending` file next to the checkpoints lists the blobs it snapshotted,
+//! so garbage collection elsewhere doesn't remove them.
+
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tracing::{debug, warn};
+
+use super::providers::ToolCall;
+use crate::config::Config;
+
+/// Lines of context around each change in a diff
+const DIFF_CONTEXT: usize = 3;
+
+/// Above this many lines compared, a diff just replaces the whole file
+const MAX_DIFF_CELLS: usize = 4_000_000;
+
+/// Characters of the user's message kept to describe the turn
+const PROMPT_PREVIEW_CHARS: usize = 120;
+
+/// Pending markers older than this are from turns that never finished
+const STALE_PENDING_SECS: u64 = 24 * 60 * 60;
+
+/// Files a turn changed, with their contents before and after it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Checkpoint {
+    pub id: String,
+    pub created_at: String,
+    pub agent_id: String,
+    pub session_id: String,
+    /// Index of the turn's user message in the session
+    pub message_index: usize,
+    /// Start of the user message that began the turn
+    pub prompt: String,
+    /// Tool calls that were checkpointed, e.g. "edit_file /ws/MEMORY.md"
+    pub calls: Vec<String>,
+    /// Blobs by absolute path
+    pub files: BTreeMap<PathBuf, FileChange>,
+}
+
+/// Blob hashes of a file before and after a turn (None: no such file)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FileChange {
+    pub before: Option<String>,
+    pub after: Option<String>,
+}
+
+impl Checkpoint {
+    /// Files whose contents the turn changed
+    pub fn changed_files(&self) -> Vec<&Path> {
+        self.files
+            .iter()
+            .filter(|(_, change)| change.before != change.after)
+            .map(|(path, _)| path.as_path())
+            .collect()
+    }
+}
+
+/// What a tool call may change
+enum Target {
+    File(PathBuf),
+    /// Anything in the workspace (bash)
+    Workspace,
+}
+
+fn call_target(call: &ToolCall) -> Option<Target> {
+    match call.name.as_str() {
+        "write_file" | "edit_file" => {
+            let args: serde_json::Value = serde_json::from_str(&call.arguments).ok()?;
+            let path = shellexpand::tilde(args["path"].as_str()?).to_string();
+            std::path::absolute(path).ok().map(Target::File)
+        }
+        "bash" => Some(Target::Workspace),
+        _ => None,
+    }
+}
+
+/// Checkpoints of one workspace
+#[derive(Debug, Clone)]
+pub struct CheckpointStore {
+    /// Shared by all workspaces (`checkpoints` in the state dir)
+    root: PathBuf,
+    workspace: PathBuf,
+    keep: usize,
+    max_file_bytes: u64,
+}
+
+impl CheckpointStore {
+    pub fn new(root: PathBuf, workspace: PathBuf, keep: usize, max_file_bytes: u64) -> Self {
+        Self {
+            root,
+            workspace,
+            keep,
+            max_file_bytes,
+        }
+    }
+
+    pub fn for_config(config: &Config) -> Self {
+        Self::new(
+            config.paths.checkpoints_dir(),
+            config.workspace_path(),
+            config.checkpoints.keep,
+            config.checkpoints.max_file_kb * 1024,
+        )
+    }
+
+    fn log_dir(&self) -> PathBuf {
+        let digest = crate::security::content_sha256(&self.workspace.to_string_lossy());
+        self.root.join(format!("workspace-{}", &digest[..12]))
+    }
+
+    fn objects_dir(&self) -> PathBuf {
+        self.root.join("objects")
+    }
+
+    fn checkpoint_path(&self, id: &str) -> PathBuf {
+        self.log_dir().join(format!("{}.json", id))
+    }
+
+    fn pending_path(&self, id: &str) -> PathBuf {
+        self.log_dir().join(format!("{}.pending", id))
+    }
+
+    /// Record the blobs a turn in progress refers to
+    fn save_pending(&self, checkpoint: &Checkpoint) -> Result<()> {
+        fs::create_dir_all(self.log_dir())?;
+        fs::write(
+            self.pending_path(&checkpoint.id),
+            serde_json::to_string(checkpoint)?,
+        )?;
+        Ok(())
+    }
+
+    /// Checkpoints of the workspace, oldest first
+    pub fn list(&self) -> Result<Vec<Checkpoint>> {
+        let dir = self.log_dir();
+        if !dir.exists() {
+            return Ok(Vec::new());
+        }
+        let mut checkpoints = Vec::new();
+        for entry in fs::read_dir(&dir)? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|e| e == "json") {
+                match fs::read_to_string(&path)
+                    .map_err(anyhow::Error::from)
+                    .and_then(|c| Ok(serde_json::from_str::<Checkpoint>(&c)?))
+                {
+                    Ok(checkpoint) => checkpoints.push(checkpoint),
+                    Err(e) => warn!("Skipping checkpoint {}: {}", path.display(), e),
+                }
+            }
+        }
+        checkpoints.sort_by(|a, b| a.id.cmp(&b.id));
+        Ok(checkpoints)
+    }
+
+    /// Checkpoint by ID or unique ID prefix
+    pub fn find(&self, id: &str) -> Result<Checkpoint> {
+        let mut matching: Vec<Checkpoint> = self
+            .list()?
+            .into_iter()
+            .filter(|c| c.id.starts_with(id))
+            .collect();
+        match matching.len() {
+            0 => anyhow::bail!("No checkpoint matching '{}'", id),
+            1 => Ok(matching.remove(0)),
+            _ => anyhow::bail!("Multiple checkpoints match '{}'", id),
+        }
+    }
+
+    /// Write a checkpoint, or drop it if the turn changed nothing after all
+    fn save(&self, checkpoint: &Checkpoint) -> Result<()> {
+        let path = self.checkpoint_path(&checkpoint.id);
+        if checkpoint.changed_files().is_empty() {
+            if path.exists() {
+                fs::remove_file(&path)?;
+            }
+        } else {
+            fs::create_dir_all(self.log_dir())?;
+            fs::write(&path, serde_json::to_string_pretty(checkpoint)?)
+                .with_context(|| format!("Failed to write checkpoint {}", path.display()))?;
+        }
+        // The checkpoint (if any) now holds the blobs the marker protected
+        let pending = self.pending_path(&checkpoint.id);
+        if pending.exists() {
+            fs::remove_file(&pending)?;
+        }
+
+        let checkpoints = self.list()?;
+        if self.keep > 0 && checkpoints.len() > self.keep {
+            for old in &checkpoints[..checkpoints.len() - self.keep] {
+                fs::remove_file(self.checkpoint_path(&old.id))?;
+            }
+            self.collect_garbage();
+        }
+        Ok(())
+    }
+
+    /// Put files back as they were before checkpoint `id`, rolling back it
+    /// and every later checkpoint. Returns the IDs rolled back (newest
+    /// first) and the files restored.
+    pub fn restore(&self, id: &str) -> Result<(Vec<String>, Vec<PathBuf>)> {
+        let target = self.find(id)?;
+        let later: Vec<Checkpoint> = self
+            .list()?
+            .into_iter()
+            .filter(|c| c.id >= target.id)
+            .rev()
+            .collect();
+        self.roll_back(later)
+    }
+
+    /// Like `restore`, but only rolls back the checkpoints of `session_id`.
+    /// Refuses if another session later changed any of the same files, as
+    /// restoring would overwrite its work.
+    pub fn restore_in_session(
+        &self,
+        id: &str,
+        session_id: &str,
+    ) -> Result<(Vec<String>, Vec<PathBuf>)> {
+        let target = self.find(id)?;
+        let (later, others): (Vec<Checkpoint>, Vec<Checkpoint>) = self
+            .list()?
+            .into_iter()
+            .filter(|c| c.id >= target.id)
+            .rev()
+            .partition(|c| c.session_id == session_id);
+
+        let ours: HashSet<&Path> = later.iter().flat_map(|c| c.changed_files()).collect();
+        for other in &others {
+            if let Some(path) = other.changed_files().into_iter().find(|p| ours.contains(p)) {
+                anyhow::bail!(
+                    "{} was changed since by session {} (checkpoint {}); \
+                     restore that checkpoint instead to roll back both",
+                    path.display(),
+                    &other.session_id[..8.min(other.session_id.len())],
+                    other.id
+                );
+            }
+        }
+        self.roll_back(later)
+    }
+
+    /// Restore the files of `checkpoints` (newest first) and delete them
+    fn roll_back(&self, checkpoints: Vec<Checkpoint>) -> Result<(Vec<String>, Vec<PathBuf>)> {
+        // Newest first, so each file ends up as the oldest checkpoint had it
+        let mut restored: BTreeMap<PathBuf, Option<String>> = BTreeMap::new();
+        for checkpoint in &checkpoints {
+            for (path, change) in &checkpoint.files {
+                if change.before != change.after {
+                    restored.insert(path.clone(), change.before.clone());
+                }
+            }
+        }
+        for (path, blob) in &restored {
+            self.write_back(path, blob.as_deref())?;
+        }
+
+        for checkpoint in &checkpoints {
+            fs::remove_file(self.checkpoint_path(&checkpoint.id))?;
+        }
+        self.collect_garbage();
+
+        Ok((
+            checkpoints.into_iter().map(|c| c.id).collect(),
+            restored.into_keys().collect(),
+        ))
+    }
+
+    /// Unified diff of what a checkpoint's turn changed
+    pub fn diff(&self, checkpoint: &Checkpoint) -> Result<String> {
+        let mut out = String::new();
+        for path in checkpoint.changed_files() {
+            let change = &checkpoint.files[path];
+            let name = path
+                .strip_prefix(&self.workspace)
+                .unwrap_or(path)
+                .display()
+                .to_string();
+            let old_name = match change.before {
+                Some(_) => format!("a/{}", name),
+                None => "/dev/null".to_string(),
+            };
+            let new_name = match change.after {
+                Some(_) => format!("b/{}", name),
+                None => "/dev/null".to_string(),
+            };
+
+            let old = self.read_blob_opt(change.before.as_deref())?;
+            let new = self.read_blob_opt(change.after.as_deref())?;
+            match (String::from_utf8(old), String::from_utf8(new)) {
+                (Ok(old), Ok(new)) => {
+                    out.push_str(&format!("--- {}\n+++ {}\n", old_name, new_name));
+                    out.push_str(&unified_diff(&old, &new));
+                }
+                _ => out.push_str(&format!(
+                    "Binary files {} and {} differ\n",
+                    old_name, new_name
+                )),
+            }
+        }
+        Ok(out)
+    }
+
+    fn write_back(&self, path: &Path, blob: Option<&str>) -> Result<()> {
+        match blob {
+            Some(hash) => {
+                if let Some(parent) = path.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                fs::write(path, self.read_blob(hash)?)
+                    .with_context(|| format!("Failed to restore {}", path.display()))
+            }
+            None if path.exists() => fs::remove_file(path)
+                .with_context(|| format!("Failed to remove {}", path.display())),
+            None => Ok(()),
+        }
+    }
+
+    /// Store a file's contents, returning its blob hash (None: no such file)
+    fn store_file(&self, path: &Path) -> Result<Option<String>> {
+        let content = match fs::read(path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        let hash = format!("{:x}", Sha256::digest(&content));
+        let object = self.objects_dir().join(&hash);
+        if object.exists() {
+            // Mark it as in use, so garbage collection before this turn's
+            // checkpoint is saved keeps it
+            fs::File::options()
+                .append(true)
+                .open(&object)?
+                .set_modified(std::time::SystemTime::now())?;
+        } else {
+            fs::create_dir_all(self.objects_dir())?;
+            fs::write(&object, &content)?;
+        }
+        Ok(Some(hash))
+    }
+
+    fn read_blob(&self, hash: &str) -> Result<Vec<u8>> {
+        fs::read(self.objects_dir().join(hash))
+            .with_context(|| format!("Checkpoint blob {} is missing", hash))
+    }
+
+    fn read_blob_opt(&self, hash: Option<&str>) -> Result<Vec<u8>> {
+        hash.map_or(Ok(Vec::new()), |h| self.read_blob(h))
+    }
+
+    fn too_large(&self, path: &Path) -> bool {
+        fs::metadata(path).is_ok_and(|m| m.len() > self.max_file_bytes)
+    }
+
+    /// Workspace files a bash call may change (hidden entries and files
+    /// over the size limit are left out)
+    fn workspace_files(&self) -> Vec<PathBuf> {
+        let mut files = Vec::new();
+        let mut dirs = vec![self.workspace.clone()];
+        while let Some(dir) = dirs.pop() {
+            let Ok(entries) = fs::read_dir(&dir) else {
+                continue;
+            };
+            for entry in entries.flatten() {
+                if entry.file_name().to_string_lossy().starts_with('.') {
+                    continue;
+                }
+                let path = entry.path();
+                match entry.file_type() {
+                    Ok(t) if t.is_dir() => dirs.push(path),
+                    Ok(t) if t.is_file() && !self.too_large(&path) => files.push(path),
+                    _ => {}
+                }
+            }
+        }
+        files
+    }
+
+    /// Remove blobs no checkpoint of any workspace refers to. Blobs written
+    /// since the oldest turn in progress started are kept: that turn may not
+    /// have recorded them yet.
+    fn collect_garbage(&self) {
+        let now = std::time::SystemTime::now();
+        let stale = std::time::Duration::from_secs(STALE_PENDING_SECS);
+        let mut referenced = HashSet::new();
+        let mut oldest_pending: Option<std::time::SystemTime> = None;
+        let mut complete = true;
+
+        let Ok(dirs) = fs::read_dir(&self.root) else {
+            return;
+        };
+        for entry in dirs.flatten() {
+            let dir = entry.path();
+            if !dir.is_dir() || dir == self.objects_dir() {
+                continue;
+            }
+            let Ok(files) = fs::read_dir(&dir) else {
+                warn!("Skipping checkpoint GC: cannot read {}", dir.display());
+                return;
+            };
+            for file in files.flatten() {
+                let path = file.path();
+                let checkpoint = fs::read_to_string(&path)
+                    .map_err(anyhow::Error::from)
+                    .and_then(|c| Ok(serde_json::from_str::<Checkpoint>(&c)?));
+                let checkpoint = match checkpoint {
+                    Ok(c) => c,
+                    Err(e) => {
+                        warn!("Unreadable checkpoint {}: {}", path.display(), e);
+                        complete = false;
+                        continue;
+                    }
+                };
+                if path.extension().is_some_and(|e| e == "pending")
+                    && let Ok(modified) = file.metadata().and_then(|m| m.modified())
+                    && now.duration_since(modified).unwrap_or_default() < stale
+                {
+                    let started = chrono::DateTime::parse_from_rfc3339(&checkpoint.created_at)
+                        .map(std::time::SystemTime::from)
+                        .unwrap_or(modified);
+                    oldest_pending = Some(oldest_pending.map_or(started, |o| o.min(started)));
+                }
+                for change in checkpoint.files.into_values() {
+                    referenced.extend(change.before);
+                    referenced.extend(change.after);
+                }
+            }
+        }
+        // Blobs of an unreadable checkpoint are unknown: keep everything
+        // rather than lose one
+        if !complete {
+            warn!("Skipping checkpoint GC: some checkpoints could not be read");
+            return;
+        }
+
+        let Ok(objects) = fs::read_dir(self.objects_dir()) else {
+            return;
+        };
+        for object in objects.flatten() {
+            if referenced.contains(object.file_name().to_string_lossy().as_ref()) {
+                continue;
+            }
+            let in_flight = oldest_pending.is_some_and(|started| {
+                object
+                    .metadata()
+                    .and_then(|m| m.modified())
+                    .is_ok_and(|modified| modified >= started)
+            });
+            if in_flight {
+                continue;
+            }
+            if let Err(e) = fs::remove_file(object.path()) {
+                warn!("Failed to remove blob {}: {}", object.path().display(), e);
+            }
+        }
+    }
+}
+
+/// Builds the checkpoint of the turn in flight
+pub struct Checkpointer {
+    store: CheckpointStore,
+    current: Option<Checkpoint>,
+    /// When the last turn began; IDs must sort in turn order
+    last_start: Option<chrono::DateTime<chrono::Local>>,
+}
+
+impl Checkpointer {
+    pub fn new(store: CheckpointStore) -> Self {
+        Self {
+            store,
+            current: None,
+            last_start: None,
+        }
+    }
+
+    pub fn store(&self) -> &CheckpointStore {
+        &self.store
+    }
+
+    /// Start a new turn's checkpoint (saved only if the turn changes files)
+    pub fn begin_turn(
+        &mut self,
+        agent_id: &str,
+        session_id: &str,
+        message_index: usize,
+        prompt: &str,
+    ) {
+        let mut now = chrono::Local::now();
+        if let Some(last) = self.last_start
+            && now.timestamp_millis() <= last.timestamp_millis()
+        {
+            now = last + chrono::Duration::milliseconds(1);
+        }
+        self.last_start = Some(now);
+        let suffix = uuid::Uuid::new_v4().simple().to_string();
+        self.current = Some(Checkpoint {
+            id: format!("{}-{}", now.format("%Y%m%d-%H%M%S%3f"), &suffix[..6]),
+            created_at: now.to_rfc3339(),
+            agent_id: agent_id.to_string(),
+            session_id: session_id.to_string(),
+            message_index,
+            prompt: prompt.chars().take(PROMPT_PREVIEW_CHARS).collect(),
+            calls: Vec::new(),
+            files: BTreeMap::new(),
+        });
+    }
+
+    /// Snapshot what the calls may change, before they run. The first
+    /// snapshot of a file in a turn is the one kept.
+    pub fn before_calls(&mut self, calls: &[&ToolCall]) {
+        let Some(checkpoint) = self.current.as_mut() else {
+            return;
+        };
+        if !calls.iter().any(|c| call_target(c).is_some()) {
+            return;
+        }
+        // Protect the blobs about to be written until the checkpoint is saved
+        if let Err(e) = self.store.save_pending(checkpoint) {
+            warn!("Failed to mark checkpoint {} pending: {}", checkpoint.id, e);
+        }
+        for call in calls {
+            let paths = match call_target(call) {
+                Some(Target::File(path)) => {
+                    checkpoint
+                        .calls
+                        .push(format!("{} {}", call.name, path.display()));
+                    vec![path]
+                }
+                Some(Target::Workspace) => {
+                    checkpoint.calls.push(call.name.clone());
+                    self.store.workspace_files()
+                }
+                None => continue,
+            };
+            for path in paths {
+                if checkpoint.files.contains_key(&path) || self.store.too_large(&path) {
+                    continue;
+                }
+                match self.store.store_file(&path) {
+                    Ok(blob) => {
+                        checkpoint.files.insert(
+                            path,
+                            FileChange {
+                                before: blob.clone(),
+                                after: blob,
+                            },
+                        );
+                    }
+                    Err(e) => warn!("Failed to checkpoint {}: {}", path.display(), e),
+                }
+            }
+        }
+        if let Err(e) = self.store.save_pending(checkpoint) {
+            warn!("Failed to mark checkpoint {} pending: {}", checkpoint.id, e);
+        }
+    }
+
+    /// Record what the calls changed and save the turn's checkpoint
+    pub fn after_calls(&mut self, calls: &[&ToolCall]) {
+        let Some(checkpoint) = self.current.as_mut() else {
+            return;
+        };
+        let targets: Vec<Target> = calls.iter().filter_map(|c| call_target(c)).collect();
+        if targets.is_empty() {
+            return;
+        }
+
+        // Files bash created are new to the checkpoint
+        if targets.iter().any(|t| matches!(t, Target::Workspace)) {
+            for path in self.store.workspace_files() {
+                checkpoint.files.entry(path).or_insert(FileChange {
+                    before: None,
+                    after: None,
+                });
+            }
+        }
+        for (path, change) in checkpoint.files.iter_mut() {
+            match self.store.store_file(path) {
+                Ok(blob) => change.after = blob,
+                Err(e) => warn!("Failed to checkpoint {}: {}", path.display(), e),
+            }
+        }
+
+        match self.store.save(checkpoint) {
+            Ok(()) => debug!("Saved checkpoint {}", checkpoint.id),
+            Err(e) => warn!("Failed to save checkpoint {}: {}", checkpoint.id, e),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum DiffOp {
+    Equal,
+    Delete,
+    Insert,
+}
+
+/// Line operations turning `a` into `b` (longest common subsequence)
+fn diff_lines<'a>(a: &[&'a str], b: &[&'a str]) -> Vec<(DiffOp, &'a str)> {
+    let prefix = a.iter().zip(b).take_while(|(x, y)| x == y).count();
+    let suffix = a[prefix..]
+        .iter()
+        .rev()
+        .zip(b[prefix..].iter().rev())
+        .take_while(|(x, y)| x == y)
+        .count();
+    let (a_mid, b_mid) = (&a[prefix..a.len() - suffix], &b[prefix..b.len() - suffix]);
+
+    let mut ops: Vec<(DiffOp, &str)> = a[..prefix].iter().map(|l| (DiffOp::Equal, *l)).collect();
+    let (n, m) = (a_mid.len(), b_mid.len());
+    if (n + 1) * (m + 1) > MAX_DIFF_CELLS {
+        ops.extend(a_mid.iter().map(|l| (DiffOp::Delete, *l)));
+        ops.extend(b_mid.iter().map(|l| (DiffOp::Insert, *l)));
+    } else {
+        // lcs[i][j]: common lines of a_mid[i..] and b_mid[j..]
+        let mut lcs = vec![0u32; (n + 1) * (m + 1)];
+        for i in (0..n).rev() {
+            for j in (0..m).rev() {
+                lcs[i * (m + 1) + j] = if a_mid[i] == b_mid[j] {
+                    lcs[(i + 1) * (m + 1) + j + 1] + 1
+                } else {
+                    lcs[(i + 1) * (m + 1) + j].max(lcs[i * (m + 1) + j + 1])
+                };
+            }
+        }
+        let (mut i, mut j) = (0, 0);
+        while i < n || j < m {
+            if i < n && j < m && a_mid[i] == b_mid[j] {
+                ops.push((DiffOp::Equal, a_mid[i]));
+                i += 1;
+                j += 1;
+            } else if j == m || (i < n && lcs[(i + 1) * (m + 1) + j] >= lcs[i * (m + 1) + j + 1]) {
+                ops.push((DiffOp::Delete, a_mid[i]));
+                i += 1;
+            } else {
+                ops.push((DiffOp::Insert, b_mid[j]));
+                j += 1;
+            }
+        }
+    }
+    ops.extend(a[a.len() - suffix..].iter().map(|l| (DiffOp::Equal, *l)));
+    ops
+}
+
+/// Hunks of a unified diff between two texts
+fn unified_diff(old: &str, new: &str) -> String {
+    let a: Vec<&str> = old.lines().collect();
+    let b: Vec<&str> = new.lines().collect();
+    let ops = diff_lines(&a, &b);
+
+    // Line numbers in a and b before each op
+    let mut positions = Vec::with_capacity(ops.len() + 1);
+    let (mut ai, mut bi) = (0, 0);
+    for (op, _) in &ops {
+        positions.push((ai, bi));
+        match op {
+            DiffOp::Equal => {
+                ai += 1;
+                bi += 1;
+            }
+            DiffOp::Delete => ai += 1,
+            DiffOp::Insert => bi += 1,
+        }
+    }
+    positions.push((ai, bi));
+
+    let changes: Vec<usize> = ops
+        .iter()
+        .enumerate()
+        .filter(|(_, (op, _))| *op != DiffOp::Equal)
+        .map(|(i, _)| i)
+        .collect();
+
+    let mut out = String::new();
+    let mut k = 0;
+    while k < changes.len() {
+        let start = changes[k].saturating_sub(DIFF_CONTEXT);
+        let mut end = changes[k] + 1;
+        // Changes close enough to share context go in one hunk
+        while k + 1 < changes.len() && changes[k + 1] <= end + 2 * DIFF_CONTEXT {
+            k += 1;
+            end = changes[k] + 1;
+        }
+        k += 1;
+        let end = (end + DIFF_CONTEXT).min(ops.len());
+
+        let ((a0, b0), (a1, b1)) = (positions[start], positions[end]);
+        let range = |from: usize, len: usize| {
+            if len == 0 {
+                format!("{},0", from)
+            } else {
+                format!("{},{}", from + 1, len)
+            }
+        };
+        out.push_str(&format!(
+            "@@ -{} +{} @@\n",
+            range(a0, a1 - a0),
+            range(b0, b1 - b0)
+        ));
+        for (op, line) in &ops[start..end] {
+            let sign = match op {
+                DiffOp::Equal => ' ',
+                DiffOp::Delete => '-',
+                DiffOp::Insert => '+',
+            };
+            out.push(sign);
+            out.push_str(line);
+            out.push('\n');
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn call(name: &str, arguments: serde_json::Value) -> ToolCall {
+        ToolCall {
+            id: format!("call_{}", name),
+            name: name.to_string(),
+            arguments: arguments.to_string(),
+        }
+    }
+
+    fn setup(keep: usize) -> (tempfile::TempDir, PathBuf, Checkpointer) {
+        let tmp = tempfile::tempdir().unwrap();
+        let workspace = tmp.path().join("workspace");
+        fs::create_dir_all(&workspace).unwrap();
+        let store = CheckpointStore::new(
+            tmp.path().join("checkpoints"),
+            workspace.clone(),
+            keep,
+            1024,
+        );
+        (tmp, workspace, Checkpointer::new(store))
+    }
+
+    #[test]
+    fn test_file_edit_checkpoint_and_restore() {
+        let (_tmp, workspace, mut checkpointer) = setup(10);
+        let memory = workspace.join("MEMORY.md");
+        fs::write(&memory, "# Memory\n- likes tea\n").unwrap();
+
+        checkpointer.begin_turn("main", "s1", 4, "remember coffee");
+        let edit = call("edit_file", serde_json::json!({"path": memory}));
+        checkpointer.before_calls(&[&edit]);
+        fs::write(&memory, "# Memory\n- likes coffee\n").unwrap();
+        checkpointer.after_calls(&[&edit]);
+
+        let store = checkpointer.store().clone();
+        let checkpoints = store.list().unwrap();
+        assert_eq!(checkpoints.len(), 1);
+        assert_eq!(checkpoints[0].message_index, 4);
+        assert_eq!(checkpoints[0].changed_files(), vec![memory.as_path()]);
+
+        let diff = store.diff(&checkpoints[0]).unwrap();
+        assert!(diff.contains("--- a/MEMORY.md\n+++ b/MEMORY.md\n"));
+        assert!(diff.contains("-- likes tea\n+- likes coffee\n"));
+
+        let (undone, files) = store.restore(&checkpoints[0].id[..10]).unwrap();
+        assert_eq!(undone, vec![checkpoints[0].id.clone()]);
+        assert_eq!(files, vec![memory.clone()]);
+        assert_eq!(
+            fs::read_to_string(&memory).unwrap(),
+            "# Memory\n- likes tea\n"
+        );
+        assert!(store.list().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_bash_checkpoint_covers_new_and_deleted_files() {
+        let (_tmp, workspace, mut checkpointer) = setup(10);
+        fs::write(workspace.join("notes.md"), "keep me").unwrap();
+        fs::write(workspace.join("big.bin"), vec![0u8; 4096]).unwrap();
+
+        checkpointer.begin_turn("main", "s1", 0, "clean up");
+        let bash = call("bash", serde_json::json!({"command": "rm notes.md"}));
+        checkpointer.before_calls(&[&bash]);
+        fs::remove_file(workspace.join("notes.md")).unwrap();
+        fs::write(workspace.join("new.md"), "created").unwrap();
+        checkpointer.after_calls(&[&bash]);
+
+        let store = checkpointer.store().clone();
+        let checkpoint = &store.list().unwrap()[0];
+        // Files over max_file_bytes aren't snapshotted
+        assert!(!checkpoint.files.contains_key(&workspace.join("big.bin")));
+
+        store.restore(&checkpoint.id).unwrap();
+        assert_eq!(
+            fs::read_to_string(workspace.join("notes.md")).unwrap(),
+            "keep me"
+        );
+        assert!(!workspace.join("new.md").exists());
+    }
+
+    #[test]
+    fn test_unchanged_turns_are_dropped_and_old_ones_pruned() {
+        let (_tmp, workspace, mut checkpointer) = setup(2);
+        let file = workspace.join("log.md");
+
+        checkpointer.begin_turn("main", "s1", 0, "no-op");
+        let write = call("write_file", serde_json::json!({"path": file}));
+        checkpointer.before_calls(&[&write]);
+        checkpointer.after_calls(&[&write]);
+        assert!(checkpointer.store().list().unwrap().is_empty());
+
+        for i in 0..3 {
+            checkpointer.begin_turn("main", "s1", i, "write");
+            checkpointer.before_calls(&[&write]);
+            fs::write(&file, format!("version {}", i)).unwrap();
+            checkpointer.after_calls(&[&write]);
+        }
+        let checkpoints = checkpointer.store().list().unwrap();
+        assert_eq!(checkpoints.len(), 2);
+        assert_eq!(checkpoints[0].message_index, 1);
+
+        // Restoring the older one rolls back the newer one too
+        let store = checkpointer.store().clone();
+        let (undone, _) = store.restore(&checkpoints[0].id).unwrap();
+        assert_eq!(undone.len(), 2);
+        assert_eq!(fs::read_to_string(&file).unwrap(), "version 0");
+    }
+
+    #[test]
+    fn test_session_restore_leaves_other_sessions_alone() {
+        let (_tmp, workspace, mut checkpointer) = setup(10);
+        let mine = workspace.join("mine.md");
+        let theirs = workspace.join("theirs.md");
+        let write = |path: &Path| call("write_file", serde_json::json!({"path": path}));
+
+        checkpointer.begin_turn("main", "s1", 0, "mine");
+        checkpointer.before_calls(&[&write(&mine)]);
+        fs::write(&mine, "mine v1").unwrap();
+        checkpointer.after_calls(&[&write(&mine)]);
+
+        checkpointer.begin_turn("main", "s2", 0, "theirs");
+        checkpointer.before_calls(&[&write(&theirs)]);
+        fs::write(&theirs, "theirs v1").unwrap();
+        checkpointer.after_calls(&[&write(&theirs)]);
+
+        let store = checkpointer.store().clone();
+        let checkpoints = store.list().unwrap();
+        let (undone, _) = store.restore_in_session(&checkpoints[0].id, "s1").unwrap();
+        assert_eq!(undone, vec![checkpoints[0].id.clone()]);
+        assert!(!mine.exists());
+        assert_eq!(fs::read_to_string(&theirs).unwrap(), "theirs v1");
+        assert_eq!(store.list().unwrap().len(), 1);
+
+        // A later change by another session to the same file blocks the undo
+        checkpointer.begin_turn("main", "s1", 2, "mine again");
+        checkpointer.before_calls(&[&write(&theirs)]);
+        fs::write(&theirs, "theirs v2").unwrap();
+        checkpointer.after_calls(&[&write(&theirs)]);
+        checkpointer.begin_turn("main", "s2", 2, "theirs again");
+        checkpointer.before_calls(&[&write(&theirs)]);
+        fs::write(&theirs, "theirs v3").unwrap();
+        checkpointer.after_calls(&[&write(&theirs)]);
+
+        let last_mine = store.list().unwrap()[1].id.clone();
+        assert!(store.restore_in_session(&last_mine, "s1").is_err());
+        assert_eq!(fs::read_to_string(&theirs).unwrap(), "theirs v3");
+    }
+
+    #[test]
+    fn test_garbage_collection_keeps_blobs_of_turns_in_progress() {
+        let (_tmp, workspace, mut checkpointer) = setup(1);
+        let file = workspace.join("draft.md");
+        fs::write(&file, "draft").unwrap();
+        let write = call("write_file", serde_json::json!({"path": file}));
+
+        // A turn in another process snapshotted the file but hasn't saved
+        let mut other = Checkpointer::new(checkpointer.store().clone());
+        other.begin_turn("main", "s2", 0, "in progress");
+        other.before_calls(&[&write]);
+        let blob = format!("{:x}", Sha256::digest(b"draft"));
+        let objects = checkpointer.store().objects_dir();
+
+        // Pruning in this process runs garbage collection
+        for i in 0..2 {
+            checkpointer.begin_turn("main", "s1", i, "write");
+            checkpointer.before_calls(&[&write]);
+            fs::write(&file, format!("version {}", i)).unwrap();
+            checkpointer.after_calls(&[&write]);
+        }
+        assert!(objects.join(&blob).exists());
+
+        // An unreadable checkpoint doesn't abort the save
+        fs::write(checkpointer.store().log_dir().join("broken.json"), "{").unwrap();
+        checkpointer.begin_turn("main", "s1", 2, "write");
+        checkpointer.before_calls(&[&write]);
+        fs::write(&file, "version 2").unwrap();
+        checkpointer.after_calls(&[&write]);
+        assert_eq!(checkpointer.store().list().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_unified_diff() {
+        let old = "a\nb\nc\nd\ne\nf\ng\nh\ni\nj\n";
+        let new = "a\nB\nc\nd\ne\nf\ng\nh\ni\nj\nk\n";
+        assert_eq!(
+            unified_diff(old, new),
+            "@@ -1,5 +1,5 @@\n a\n-b\n+B\n c\n d\n e\n@@ -8,3 +8,4 @@\n h\n i\n j\n+k\n"
+        );
+        assert_eq!(unified_diff("", "x\n"), "@@ -0,0 +1,1 @@\n+x\n");
+        assert_eq!(unified_diff("same\n", "same\n"), "");
+    }
+}
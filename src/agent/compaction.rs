@@ -0,0 +1,261 @@
+//! Structure-preserving session compaction
+//!
+//! Old messages are summarized in rolling windows, oldest first, each summary
+//! folding in the one before it, until the transcript fits a token target.
+//! An assistant message with tool calls and the results of those calls are
+//! summarized or kept together, so no tool result loses its call. Pinned
+//! messages and the latest exchange are kept verbatim. Summaries are
+//! structured (decisions, open tasks, files touched) so they survive being
+//! folded again. The summarized messages stay in the session file, where
+//! `/expand` can restore them from.
+
+use std::collections::BTreeSet;
+use std::ops::Range;
+
+use super::providers::Role;
+use super::sanitize::truncate_with_notice;
+use super::session::SessionMessage;
+
+/// Opens the transcript message that holds a compaction summary
+pub const SUMMARY_HEADER: &str = "Previous conversation summary:";
+
+/// Characters of a tool result or tool arguments shown to the summarizer
+const MAX_TOOL_CHARS: usize = 2000;
+
+/// Summary of compacted messages
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CompactionSummary {
+    /// What happened, in prose
+    pub summary: String,
+    pub decisions: Vec<String>,
+    pub open_tasks: Vec<String>,
+    pub files_touched: Vec<String>,
+}
+
+impl CompactionSummary {
+    /// Parse a summarizer reply in the format `request` asks for. Text outside
+    /// the known sections is kept as the prose summary.
+    pub fn parse(text: &str) -> Self {
+        let text = text.trim();
+        let text = text.strip_prefix(SUMMARY_HEADER).unwrap_or(text);
+        let mut parsed = Self::default();
+        let mut prose = Vec::new();
+        let mut section = "summary".to_string();
+
+        for line in text.lines() {
+            if let Some(heading) = line.trim().strip_prefix("## ") {
+                section = heading.trim().to_lowercase();
+                continue;
+            }
+            let item = line.trim().trim_start_matches(['-', '*']).trim();
+            let list = match section.as_str() {
+                "decisions" => &mut parsed.decisions,
+                "open tasks" => &mut parsed.open_tasks,
+                "files touched" => &mut parsed.files_touched,
+                _ => {
+                    prose.push(line);
+                    continue;
+                }
+            };
+            if !item.is_empty() && !item.eq_ignore_ascii_case("none") {
+                list.push(item.to_string());
+            }
+        }
+
+        parsed.summary = prose.join("\n").trim().to_string();
+        parsed
+    }
+
+    /// Add files the summarized tool calls named, keeping the list unique
+    pub fn add_files(&mut self, files: impl IntoIterator<Item = String>) {
+        for file in files {
+            if !self.files_touched.contains(&file) {
+                self.files_touched.push(file);
+            }
+        }
+    }
+
+    /// Content of the transcript message holding this summary
+    pub fn render(&self) -> String {
+        let mut out = format!("{}\n\n## Summary\n{}\n", SUMMARY_HEADER, self.summary);
+        for (title, items) in [
+            ("Decisions", &self.decisions),
+            ("Open tasks", &self.open_tasks),
+            ("Files touched", &self.files_touched),
+        ] {
+            if items.is_empty() {
+                continue;
+            }
+            out.push_str(&format!("\n## {}\n", title));
+            for item in items {
+                out.push_str(&format!("- {}\n", item));
+            }
+        }
+        out.trim_end().to_string()
+    }
+}
+
+/// Group messages into units compaction never splits: an assistant message
+/// with tool calls and the tool results that follow it, or a single message
+pub fn units(messages: &[SessionMessage]) -> Vec<Range<usize>> {
+    let mut units = Vec::new();
+    let mut start = 0;
+    while start < messages.len() {
+        let mut end = start + 1;
+        if messages[start]
+            .message
+            .tool_calls
+            .as_ref()
+            .is_some_and(|calls| !calls.is_empty())
+        {
+            while end < messages.len() && messages[end].message.role == Role::Tool {
+                end += 1;
+            }
+        }
+        units.push(start..end);
+        start = end;
+    }
+    units
+}
+
+/// Text asking the summarizer to fold `messages` into `previous`
+pub fn request(previous: Option<&str>, messages: &[&SessionMessage]) -> String {
+    let mut text = String::from(
+        "This summary replaces the messages below in a conversation that continues \
+         afterwards. Keep what is needed to carry on: facts, preferences, decisions \
+         and their reasons, unfinished work, and names of files, commands and errors.",
+    );
+    if previous.is_some() {
+        text.push_str(
+            " Fold the earlier summary into the new one, dropping only what no \
+             longer matters.",
+        );
+    }
+    text.push_str(
+        "\n\nReply in exactly this format, writing \"- none\" for an empty section:\n\n\
+         ## Summary\n<a few sentences>\n\n\
+         ## Decisions\n- <decision and why>\n\n\
+         ## Open tasks\n- <work still to do>\n\n\
+         ## Files touched\n- <path>\n",
+    );
+    if let Some(previous) = previous {
+        text.push_str(&format!("\n# Earlier summary\n\n{}\n", previous));
+    }
+    text.push_str("\n# Messages\n");
+    for sm in messages {
+        text.push('\n');
+        text.push_str(&transcript_entry(sm));
+    }
+    text
+}
+
+/// One message as the summarizer sees it
+fn transcript_entry(sm: &SessionMessage) -> String {
+    let message = &sm.message;
+    let mut entry = match message.role {
+        Role::User => format!("[user] {}", message.content),
+        Role::Assistant => format!("[assistant] {}", message.content),
+        Role::System => format!("[system] {}", message.content),
+        Role::Tool => {
+            let (output, _) = truncate_with_notice(&message.content, MAX_TOOL_CHARS);
+            format!("[tool result] {}", output)
+        }
+    };
+    for image in &message.images {
+        entry.push_str(&format!(" [image attached: {}]", image.media_type));
+    }
+    for call in message.tool_calls.iter().flatten() {
+        let (arguments, _) = truncate_with_notice(&call.arguments, MAX_TOOL_CHARS);
+        entry.push_str(&format!("\n[tool call: {}] {}", call.name, arguments));
+    }
+    entry.trim_end().to_string()
+}
+
+/// Paths named by the tool calls in `messages`
+pub fn files_touched(messages: &[&SessionMessage]) -> BTreeSet<String> {
+    messages
+        .iter()
+        .flat_map(|sm| sm.message.tool_calls.iter().flatten())
+        .filter_map(|call| {
+            let args: serde_json::Value = serde_json::from_str(&call.arguments).ok()?;
+            args["path"].as_str().map(|p| p.to_string())
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::agent::providers::{Message, ToolCall};
+
+    fn message(role: Role, content: &str, calls: &[(&str, &str)]) -> SessionMessage {
+        SessionMessage::new(Message {
+            role,
+            content: content.to_string(),
+            tool_calls: (!calls.is_empty()).then(|| {
+                calls
+                    .iter()
+                    .map(|(name, arguments)| ToolCall {
+                        id: format!("call_{}", name),
+                        name: name.to_string(),
+                        arguments: arguments.to_string(),
+                    })
+                    .collect()
+            }),
+            tool_call_id: None,
+            images: Vec::new(),
+            thinking: Vec::new(),
+        })
+    }
+
+    #[test]
+    fn test_units_keep_tool_results_with_their_call() {
+        let messages = [
+            message(Role::User, "fix it", &[]),
+            message(
+                Role::Assistant,
+                "",
+                &[
+                    ("read_file", r#"{"path":"src/main.rs"}"#),
+                    ("bash", r#"{"command":"ls"}"#),
+                ],
+            ),
+            message(Role::Tool, "fn main() {}", &[]),
+            message(Role::Tool, "Cargo.toml", &[]),
+            message(Role::Assistant, "done", &[]),
+        ];
+        assert_eq!(units(&messages), [0..1, 1..4, 4..5]);
+
+        let window: Vec<&SessionMessage> = messages.iter().collect();
+        assert_eq!(
+            files_touched(&window).into_iter().collect::<Vec<_>>(),
+            ["src/main.rs"]
+        );
+        let text = request(Some("## Summary\nEarlier"), &window);
+        assert!(text.contains("# Earlier summary"));
+        assert!(text.contains("[tool call: bash] {\"command\":\"ls\"}"));
+        assert!(text.contains("[tool result] Cargo.toml"));
+    }
+
+    #[test]
+    fn test_summary_parse_and_render_round_trip() {
+        let reply = "## Summary\nSet up the project.\n\n## Decisions\n- Use SQLite for storage\n\n\
+                     ## Open tasks\n- none\n\n## Files touched\n* src/db.rs\n";
+        let mut summary = CompactionSummary::parse(reply);
+        assert_eq!(summary.summary, "Set up the project.");
+        assert_eq!(summary.decisions, ["Use SQLite for storage"]);
+        assert!(summary.open_tasks.is_empty());
+        summary.add_files(["src/db.rs".to_string(), "src/main.rs".to_string()]);
+        assert_eq!(summary.files_touched, ["src/db.rs", "src/main.rs"]);
+
+        let rendered = summary.render();
+        assert!(rendered.starts_with(SUMMARY_HEADER));
+        assert_eq!(CompactionSummary::parse(&rendered), summary);
+
+        // Replies that ignore the format are kept as prose
+        assert_eq!(
+            CompactionSummary::parse("We talked about tea.").summary,
+            "We talked about tea."
+        );
+    }
+}
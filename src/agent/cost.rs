@@ -0,0 +1,459 @@
+//! Cost tracking - model pricing, per-call cost and the spend ledger
+//!
+//! Every API call is priced from the built-in table below (overridable via
+//! `[cost.pricing]`) and accumulated in a ledger under the state directory:
+//! `costs/YYYY-MM.json`, one entry per (day, agent, session, source).
+//! Daily and monthly budgets are checked against the ledger before
+//! heartbeat runs.
+
+use anyhow::Result;
+use chrono::{Local, NaiveDate};
+use fs2::FileExt;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use tracing::debug;
+
+use super::providers::Usage;
+use super::session::MessageCost;
+use crate::config::{Config, CostConfig, ModelPricing};
+
+const fn price(input: f64, output: f64, cache_read: f64, cache_write: f64) -> ModelPricing {
+    ModelPricing {
+        input,
+        output,
+        cache_read: Some(cache_read),
+        cache_write: Some(cache_write),
+    }
+}
+
+/// Built-in prices in USD per million tokens, keyed by "provider/model"
+/// as produced by alias resolution. "provider/*" matches any model.
+const BUILTIN_PRICING: &[(&str, ModelPricing)] = &[
+    ("anthropic/claude-opus-4-5", price(5.0, 25.0, 0.5, 6.25)),
+    ("anthropic/claude-sonnet-4-5", price(3.0, 15.0, 0.3, 3.75)),
+    ("anthropic/claude-haiku-4-5", price(1.0, 5.0, 0.1, 1.25)),
+    ("openai/gpt-4o", price(2.5, 10.0, 1.25, 0.0)),
+    ("openai/gpt-4o-mini", price(0.15, 0.6, 0.075, 0.0)),
+    ("openai/gpt-4-turbo", price(10.0, 30.0, 10.0, 0.0)),
+    ("glm/glm-4.7", price(0.6, 2.2, 0.11, 0.0)),
+    // Local models, recorded responses and subscription-billed CLI usage
+    ("ollama/*", price(0.0, 0.0, 0.0, 0.0)),
+    ("gguf/*", price(0.0, 0.0, 0.0, 0.0)),
+    ("replay/*", price(0.0, 0.0, 0.0, 0.0)),
+    ("claude-cli/*", price(0.0, 0.0, 0.0, 0.0)),
+];
+
+/// Where an API call originated, for ledger breakdowns
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum UsageSource {
+    Chat,
+    Heartbeat,
+    Telegram,
+    /// Sub-agents started by the `delegate` tool
+    Delegate,
+}
+
+/// Look up the price for a provider/model: `[cost.pricing]` overrides
+/// first, then a `price` declared in the `[models]` registry, then the
+/// built-in table. Falls back to the model ID without a trailing date stamp
+/// (e.g. "claude-opus-4-5-20251101"), then to "provider/*".
+pub fn pricing_for(config: &Config, provider: &str, model: &str) -> Option<ModelPricing> {
+    let lookup = |key: &str| {
+        config
+            .cost
+            .pricing
+            .get(key)
+            .copied()
+            .or_else(|| {
+                config
+                    .models
+                    .values()
+                    .filter(|e| format!("{}/{}", e.provider, e.id) == key)
+                    .find_map(|e| e.price)
+            })
+            .or_else(|| {
+                BUILTIN_PRICING
+                    .iter()
+                    .find(|(k, _)| *k == key)
+                    .map(|(_, p)| *p)
+            })
+    };
+
+    let undated = model
+        .rsplit_once('-')
+        .filter(|(_, date)| date.len() == 8 && date.chars().all(|c| c.is_ascii_digit()))
+        .map(|(base, _)| base);
+
+    lookup(&format!("{}/{}", provider, model))
+        .or_else(|| undated.and_then(|base| lookup(&format!("{}/{}", provider, base))))
+        .or_else(|| lookup(&format!("{}/*", provider)))
+}
+
+/// Cost of one API call's usage at the given price
+pub fn usage_cost(usage: &Usage, pricing: &ModelPricing) -> MessageCost {
+    const PER_TOKEN: f64 = 1.0 / 1_000_000.0;
+    let input = usage.input_tokens as f64 * pricing.input * PER_TOKEN;
+    let output = usage.output_tokens as f64 * pricing.output * PER_TOKEN;
+    let cache_read =
+        usage.cache_read_tokens as f64 * pricing.cache_read.unwrap_or(pricing.input) * PER_TOKEN;
+    let cache_write =
+        usage.cache_write_tokens as f64 * pricing.cache_write.unwrap_or(pricing.input) * PER_TOKEN;
+
+    MessageCost {
+        input,
+        output,
+        cache_read,
+        cache_write,
+        total: input + output + cache_read + cache_write,
+    }
+}
+
+/// Accumulated usage and spend for one ledger entry
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CostTotals {
+    pub requests: u64,
+    pub input_tokens: u64,
+    pub output_tokens: u64,
+    pub cache_read_tokens: u64,
+    pub cache_write_tokens: u64,
+    pub cost_usd: f64,
+}
+
+/// Ledger entry: totals for one (day, agent, session, source)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LedgerEntry {
+    /// Local date, YYYY-MM-DD
+    pub day: String,
+    pub agent_id: String,
+    pub session_id: String,
+    pub source: UsageSource,
+    #[serde(flatten)]
+    pub totals: CostTotals,
+}
+
+/// Persistent spend ledger, one JSON file per month
+pub struct CostLedger {
+    dir: PathBuf,
+}
+
+impl CostLedger {
+    pub fn new(dir: PathBuf) -> Self {
+        Self { dir }
+    }
+
+    /// Ledger in the configured state directory
+    pub fn for_config(config: &Config) -> Self {
+        Self::new(config.paths.cost_ledger_dir())
+    }
+
+    fn month_path(&self, month: &str) -> PathBuf {
+        self.dir.join(format!("{}.json", month))
+    }
+
+    /// Entries for a month ("YYYY-MM"); empty if nothing was recorded
+    pub fn month_entries(&self, month: &str) -> Result<Vec<LedgerEntry>> {
+        read_entries(&self.month_path(month))
+    }
+
+    /// Add one API call to today's entry for this agent/session/source
+    pub fn record(
+        &self,
+        agent_id: &str,
+        session_id: &str,
+        source: UsageSource,
+        usage: &Usage,
+        cost_usd: f64,
+    ) -> Result<()> {
+        self.record_on(
+            Local::now().date_naive(),
+            agent_id,
+            session_id,
+            source,
+            usage,
+            cost_usd,
+        )
+    }
+
+    fn record_on(
+        &self,
+        date: NaiveDate,
+        agent_id: &str,
+        session_id: &str,
+        source: UsageSource,
+        usage: &Usage,
+        cost_usd: f64,
+    ) -> Result<()> {
+        let path = self.month_path(&date.format("%Y-%m").to_string());
+        let day = date.format("%Y-%m-%d").to_string();
+
+        // Held across the read-modify-write: the daemon, the CLI and agents
+        // with other workspaces (and so other workspace locks) all record here
+        let _lock = self.lock()?;
+        let mut entries = read_entries(&path)?;
+        let index = entries
+            .iter()
+            .position(|e| {
+                e.day == day
+                    && e.agent_id == agent_id
+                    && e.session_id == session_id
+                    && e.source == source
+            })
+            .unwrap_or_else(|| {
+                entries.push(LedgerEntry {
+                    day,
+                    agent_id: agent_id.to_string(),
+                    session_id: session_id.to_string(),
+                    source,
+                    totals: CostTotals::default(),
+                });
+                entries.len() - 1
+            });
+
+        let totals = &mut entries[index].totals;
+        totals.requests += 1;
+        totals.input_tokens += usage.input_tokens;
+        totals.output_tokens += usage.output_tokens;
+        totals.cache_read_tokens += usage.cache_read_tokens;
+        totals.cache_write_tokens += usage.cache_write_tokens;
+        totals.cost_usd += cost_usd;
+
+        write_entries(&path, &entries)
+    }
+
+    /// Exclusive lock on the ledger, released when the file is dropped
+    fn lock(&self) -> Result<fs::File> {
+        fs::create_dir_all(&self.dir)?;
+        let file = fs::File::create(self.dir.join(".lock"))?;
+        file.lock_exclusive()?;
+        Ok(file)
+    }
+
+    /// Spend for a given day, across all agents and sources
+    pub fn spend_on(&self, date: NaiveDate) -> Result<f64> {
+        let day = date.format("%Y-%m-%d").to_string();
+        Ok(self
+            .month_entries(&date.format("%Y-%m").to_string())?
+            .iter()
+            .filter(|e| e.day == day)
+            .map(|e| e.totals.cost_usd)
+            .sum())
+    }
+
+    /// Spend for the month containing `date`
+    pub fn spend_in_month(&self, date: NaiveDate) -> Result<f64> {
+        Ok(self
+            .month_entries(&date.format("%Y-%m").to_string())?
+            .iter()
+            .map(|e| e.totals.cost_usd)
+            .sum())
+    }
+
+    /// Describe the exceeded budget, if any, as of `date`
+    pub fn exceeded_budget(&self, config: &CostConfig, date: NaiveDate) -> Result<Option<String>> {
+        if let Some(limit) = config.daily_budget_usd {
+            let spent = self.spend_on(date)?;
+            if spent >= limit {
+                return Ok(Some(format!(
+                    "daily budget exceeded (${:.2} of ${:.2})",
+                    spent, limit
+                )));
+            }
+        }
+        if let Some(limit) = config.monthly_budget_usd {
+            let spent = self.spend_in_month(date)?;
+            if spent >= limit {
+                return Ok(Some(format!(
+                    "monthly budget exceeded (${:.2} of ${:.2})",
+                    spent, limit
+                )));
+            }
+        }
+        Ok(None)
+    }
+}
+
+fn read_entries(path: &Path) -> Result<Vec<LedgerEntry>> {
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    let content = fs::read_to_string(path)?;
+    Ok(serde_json::from_str(&content)?)
+}
+
+/// Atomic write (temp file + rename), same as the session store
+fn write_entries(path: &Path, entries: &[LedgerEntry]) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let tmp_path = path.with_extension(format!(
+        "{}.{}.tmp",
+        std::process::id(),
+        uuid::Uuid::new_v4().as_simple()
+    ));
+    fs::write(&tmp_path, serde_json::to_string_pretty(entries)?)?;
+    fs::rename(&tmp_path, path)?;
+    debug!("Updated cost ledger {:?}", path);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ModelEntry;
+
+    fn usage(input: u64, output: u64, cache_read: u64, cache_write: u64) -> Usage {
+        Usage {
+            input_tokens: input,
+            output_tokens: output,
+            cache_read_tokens: cache_read,
+            cache_write_tokens: cache_write,
+        }
+    }
+
+    #[test]
+    fn test_pricing_lookup() {
+        let mut config = Config::default();
+        let opus = pricing_for(&config, "anthropic", "claude-opus-4-5").unwrap();
+        assert_eq!(opus.input, 5.0);
+
+        // Dated API IDs resolve to the undated entry
+        let dated = pricing_for(&config, "anthropic", "claude-opus-4-5-20251101").unwrap();
+        assert_eq!(dated, opus);
+
+        // Wildcards
+        assert_eq!(
+            pricing_for(&config, "ollama", "llama3").unwrap().output,
+            0.0
+        );
+        assert!(pricing_for(&config, "openai", "unknown-model").is_none());
+
+        // Registry prices cover models missing from the table
+        config.models.insert(
+            "mini".to_string(),
+            ModelEntry {
+                provider: "openai".to_string(),
+                id: "unknown-model".to_string(),
+                context_window: None,
+                max_output_tokens: None,
+                vision: None,
+                tools: None,
+                price: Some(price(0.5, 1.0, 0.0, 0.0)),
+                thinking_budget: None,
+                reasoning_effort: None,
+            },
+        );
+        assert_eq!(
+            pricing_for(&config, "openai", "unknown-model")
+                .unwrap()
+                .input,
+            0.5
+        );
+
+        // Config overrides win
+        config.cost.pricing.insert(
+            "anthropic/claude-opus-4-5".to_string(),
+            ModelPricing {
+                input: 1.0,
+                output: 2.0,
+                cache_read: None,
+                cache_write: None,
+            },
+        );
+        assert_eq!(
+            pricing_for(&config, "anthropic", "claude-opus-4-5")
+                .unwrap()
+                .input,
+            1.0
+        );
+    }
+
+    #[test]
+    fn test_usage_cost() {
+        let pricing = price(3.0, 15.0, 0.3, 3.75);
+        let cost = usage_cost(&usage(1_000_000, 100_000, 2_000_000, 0), &pricing);
+        assert!((cost.input - 3.0).abs() < 1e-9);
+        assert!((cost.output - 1.5).abs() < 1e-9);
+        assert!((cost.cache_read - 0.6).abs() < 1e-9);
+        assert!((cost.total - 5.1).abs() < 1e-9);
+
+        // Cache prices default to the input price
+        let pricing = ModelPricing {
+            input: 2.0,
+            output: 0.0,
+            cache_read: None,
+            cache_write: None,
+        };
+        let cost = usage_cost(&usage(0, 0, 500_000, 500_000), &pricing);
+        assert!((cost.total - 2.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_ledger_accumulates_and_checks_budget() {
+        let tmp = tempfile::tempdir().unwrap();
+        let ledger = CostLedger::new(tmp.path().to_path_buf());
+        let day1 = NaiveDate::from_ymd_opt(2026, 3, 1).unwrap();
+        let day2 = NaiveDate::from_ymd_opt(2026, 3, 2).unwrap();
+        let u = usage(100, 10, 0, 0);
+
+        ledger
+            .record_on(day1, "main", "s1", UsageSource::Chat, &u, 1.0)
+            .unwrap();
+        ledger
+            .record_on(day1, "main", "s1", UsageSource::Chat, &u, 0.5)
+            .unwrap();
+        ledger
+            .record_on(day1, "main", "s2", UsageSource::Heartbeat, &u, 2.0)
+            .unwrap();
+        ledger
+            .record_on(day2, "main", "s3", UsageSource::Telegram, &u, 4.0)
+            .unwrap();
+
+        let entries = ledger.month_entries("2026-03").unwrap();
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[0].totals.requests, 2);
+        assert_eq!(entries[0].totals.input_tokens, 200);
+
+        assert!((ledger.spend_on(day1).unwrap() - 3.5).abs() < 1e-9);
+        assert!((ledger.spend_in_month(day2).unwrap() - 7.5).abs() < 1e-9);
+
+        let mut config = CostConfig {
+            daily_budget_usd: Some(5.0),
+            ..Default::default()
+        };
+        assert!(ledger.exceeded_budget(&config, day2).unwrap().is_none());
+        config.monthly_budget_usd = Some(7.0);
+        let reason = ledger.exceeded_budget(&config, day2).unwrap().unwrap();
+        assert!(reason.contains("monthly"));
+    }
+
+    #[test]
+    fn test_concurrent_records_are_all_kept() {
+        let tmp = tempfile::tempdir().unwrap();
+        let day = NaiveDate::from_ymd_opt(2026, 3, 1).unwrap();
+        let u = usage(1, 1, 0, 0);
+
+        // Separate ledgers stand in for separate processes
+        std::thread::scope(|scope| {
+            for agent in ["main", "work", "notes", "ops"] {
+                let ledger = CostLedger::new(tmp.path().to_path_buf());
+                let u = &u;
+                scope.spawn(move || {
+                    for _ in 0..25 {
+                        ledger
+                            .record_on(day, agent, "s1", UsageSource::Chat, u, 0.01)
+                            .unwrap();
+                    }
+                });
+            }
+        });
+
+        let ledger = CostLedger::new(tmp.path().to_path_buf());
+        let entries = ledger.month_entries("2026-03").unwrap();
+        let requests: u64 = entries.iter().map(|e| e.totals.requests).sum();
+        assert_eq!(requests, 100);
+    }
+}
@@ -0,0 +1,319 @@
+//! Sub-agent delegation
+//!
+//! The `delegate` tool hands a self-contained task to a child `Agent` with a
+//! fresh session: optionally another model, a subset of the tools, a turn and
+//! token budget, and another agent's workspace and index. The child runs to its
+//! final answer, its transcript is saved next to the parent session (linked
+//! by `parentSession` in its header), and the parent gets the answer back,
+//! truncated, with the child session ID and transcript path.
+//!
+//! Children never get the `delegate` tool themselves, and have no one to ask
+//! for approvals: tools that need one follow `tools.unattended_approval`.
+
+use std::sync::{Arc, Mutex};
+
+use anyhow::Result;
+use async_trait::async_trait;
+use serde_json::{Value, json};
+use tracing::info;
+
+use super::providers::ToolSchema;
+use super::tools::{Tool, create_default_tools};
+use super::{Agent, UsageSource};
+use crate::config::{Config, is_valid_agent_id};
+use crate::mcp::create_mcp_tools;
+use crate::memory::MemoryManager;
+
+pub const DELEGATE_TOOL_NAME: &str = "delegate";
+
+/// The session delegations hang off, refreshed by the parent each turn
+#[derive(Debug, Clone, Default)]
+pub struct DelegateParent {
+    pub agent_id: String,
+    pub session_id: String,
+    pub model: String,
+}
+
+pub struct DelegateTool {
+    config: Config,
+    memory: Arc<MemoryManager>,
+    parent: Arc<Mutex<DelegateParent>>,
+}
+
+impl DelegateTool {
+    pub fn new(
+        config: Config,
+        memory: Arc<MemoryManager>,
+        parent: Arc<Mutex<DelegateParent>>,
+    ) -> Self {
+        Self {
+            config,
+            memory,
+            parent,
+        }
+    }
+
+    /// Config of the child's agent with its model and turn limit
+    fn child_config(&self, agent_id: &str, model: &str, max_turns: usize) -> Config {
+        let mut config = self.config.for_agent(agent_id);
+        config.agent.default_model = model.to_string();
+        config.agent.tool_limits.max_rounds = max_turns;
+        config
+    }
+}
+
+#[async_trait]
+impl Tool for DelegateTool {
+    fn name(&self) -> &str {
+        DELEGATE_TOOL_NAME
+    }
+
+    fn schema(&self) -> ToolSchema {
+        ToolSchema {
+            name: DELEGATE_TOOL_NAME.to_string(),
+            description: "Hand a self-contained task to a sub-agent with a fresh context and \
+                          get its final answer back. Use it for research or memory grooming \
+                          that would clutter this conversation. The sub-agent sees none of \
+                          this conversation, so the task must say everything it needs."
+                .to_string(),
+            parameters: json!({
+                "type": "object",
+                "properties": {
+                    "task": {
+                        "type": "string",
+                        "description": "Complete instructions, including what to return"
+                    },
+                    "model": {
+                        "type": "string",
+                        "description": "Model for the sub-agent (default: configured delegate model)"
+                    },
+                    "tools": {
+                        "type": "array",
+                        "items": { "type": "string" },
+                        "description": "Tools the sub-agent may use (default: all except delegate)"
+                    },
+                    "max_turns": {
+                        "type": "integer",
+                        "description": "Rounds of tool calls the sub-agent may make"
+                    },
+                    "max_tokens": {
+                        "type": "integer",
+                        "description": "Tokens the sub-agent may spend"
+                    },
+                    "agent_id": {
+                        "type": "string",
+                        "description": "Agent ID whose workspace and memory the sub-agent uses (default: this agent's)"
+                    }
+                },
+                "required": ["task"]
+            }),
+        }
+    }
+
+    async fn execute(&self, arguments: &str) -> Result<String> {
+        let args: Value = serde_json::from_str(arguments)?;
+        let task = args["task"]
+            .as_str()
+            .filter(|t| !t.trim().is_empty())
+            .ok_or_else(|| anyhow::anyhow!("Missing task"))?;
+
+        let parent = self
+            .parent
+            .lock()
+            .map(|p| p.clone())
+            .map_err(|_| anyhow::anyhow!("Delegate parent state poisoned"))?;
+        let limits = &self.config.agent.delegate;
+
+        let agent_id = match args["agent_id"].as_str() {
+            Some(id) => validate_agent_id(id)?,
+            None => parent.agent_id.clone(),
+        };
+        // Another agent runs its own default model unless told otherwise
+        let agent_model = if agent_id == parent.agent_id {
+            parent.model.clone()
+        } else {
+            self.config.for_agent(&agent_id).agent.default_model
+        };
+        let model = args["model"]
+            .as_str()
+            .filter(|m| !m.is_empty())
+            .or_else(|| Some(limits.model.as_str()).filter(|m| !m.is_empty()))
+            .unwrap_or(&agent_model)
+            .to_string();
+        let max_turns = clamp_budget(
+            args["max_turns"].as_u64().map(|n| n as usize),
+            limits.max_turns,
+        );
+        let max_tokens = clamp_budget(args["max_tokens"].as_u64(), limits.max_tokens);
+        let requested_tools: Option<Vec<String>> = args["tools"].as_array().map(|names| {
+            names
+                .iter()
+                .filter_map(|n| n.as_str().map(|s| s.to_string()))
+                .collect()
+        });
+
+        let child_config = self.child_config(&agent_id, &model, max_turns);
+        let memory = if agent_id == parent.agent_id {
+            Arc::clone(&self.memory)
+        } else {
+            Arc::new(MemoryManager::new_with_full_config(
+                &child_config.memory,
+                Some(&child_config),
+                &agent_id,
+            )?)
+        };
+        let mut available = create_default_tools(&child_config, Some(Arc::clone(&memory)))?;
+        available.extend(create_mcp_tools(&child_config).await);
+        let tools = select_tools(available, requested_tools.as_deref())?;
+
+        let mut child = Agent::new_with_tools(child_config, &agent_id, memory, tools)?;
+        child.set_usage_attribution(&agent_id, UsageSource::Delegate);
+        child.set_token_budget((max_tokens > 0).then_some(max_tokens));
+        child.new_session().await?;
+        child.set_parent_session(&parent.session_id);
+        info!(
+            "Delegating to sub-agent {} ({}, session {})",
+            agent_id,
+            model,
+            child.session_status().id
+        );
+
+        let result = child.chat(task).await;
+
+        // Keep the transcript even when the child failed part way
+        let sessions_dir = self.config.paths.sessions_dir(&parent.agent_id);
+        let transcript = child.save_session_to_dir(&sessions_dir)?;
+        let answer = result?;
+
+        let status = child.session_status();
+        let (provider, served_model) = child.served_model();
+        let mut footer = format!(
+            "[Sub-agent {}/{} in session {}: {} tokens",
+            provider,
+            served_model,
+            status.id,
+            child.usage().total()
+        );
+        if let Some(reason) = child.take_tool_limit() {
+            footer.push_str(&format!(", stopped early: {}", reason));
+        }
+        footer.push_str(&format!(". Transcript: {}]", transcript.display()));
+
+        Ok(format!(
+            "{}\n\n{}",
+            condense(&answer, limits.max_result_chars),
+            footer
+        ))
+    }
+}
+
+/// Budget for the child: the requested value clamped to the cap, or the cap
+/// itself. A cap of 0 means no cap.
+fn clamp_budget<T: Ord + Copy + Default>(requested: Option<T>, cap: T) -> T {
+    match requested.filter(|r| *r > T::default()) {
+        Some(r) if cap > T::default() => r.min(cap),
+        Some(r) => r,
+        None => cap,
+    }
+}
+
+fn validate_agent_id(id: &str) -> Result<String> {
+    if !is_valid_agent_id(id) {
+        anyhow::bail!(
+            "Invalid agent_id {:?}: use letters, digits, '-' and '_'",
+            id
+        );
+    }
+    Ok(id.to_string())
+}
+
+/// Keep only the requested tools (all of them when none are named)
+fn select_tools(
+    available: Vec<Box<dyn Tool>>,
+    requested: Option<&[String]>,
+) -> Result<Vec<Box<dyn Tool>>> {
+    let Some(requested) = requested else {
+        return Ok(available);
+    };
+    let unknown: Vec<&str> = requested
+        .iter()
+        .map(|n| n.as_str())
+        .filter(|n| !available.iter().any(|t| t.name() == *n))
+        .collect();
+    if !unknown.is_empty() {
+        let names: Vec<&str> = available.iter().map(|t| t.name()).collect();
+        anyhow::bail!(
+            "Tools not available to sub-agents: {}. Available: {}",
+            unknown.join(", "),
+            names.join(", ")
+        );
+    }
+    Ok(available
+        .into_iter()
+        .filter(|t| requested.iter().any(|n| n == t.name()))
+        .collect())
+}
+
+/// The child's answer as returned to the parent: cut at `max_chars`
+fn condense(answer: &str, max_chars: usize) -> String {
+    let total = answer.chars().count();
+    if max_chars == 0 || total <= max_chars {
+        return answer.trim().to_string();
+    }
+    let kept: String = answer.chars().take(max_chars).collect();
+    format!(
+        "{}\n[...{} more characters in the transcript]",
+        kept.trim_end(),
+        total - max_chars
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::agent::tools::{MemoryGetTool, WebFetchTool};
+
+    #[test]
+    fn test_clamp_budget() {
+        assert_eq!(clamp_budget(Some(5), 10), 5);
+        assert_eq!(clamp_budget(Some(50), 10), 10);
+        assert_eq!(clamp_budget(None, 10), 10);
+        // Zero requests fall back to the cap; a zero cap doesn't clamp
+        assert_eq!(clamp_budget(Some(0), 10), 10);
+        assert_eq!(clamp_budget(Some(50u64), 0), 50);
+        assert_eq!(clamp_budget(None, 0u64), 0);
+    }
+
+    #[test]
+    fn test_select_tools() {
+        let available = || -> Vec<Box<dyn Tool>> {
+            vec![
+                Box::new(MemoryGetTool::new(std::env::temp_dir())),
+                Box::new(WebFetchTool::new(1000)),
+            ]
+        };
+        assert_eq!(select_tools(available(), None).unwrap().len(), 2);
+
+        let only = vec!["web_fetch".to_string()];
+        let tools = select_tools(available(), Some(&only)).unwrap();
+        let names: Vec<&str> = tools.iter().map(|t| t.name()).collect();
+        assert_eq!(names, vec!["web_fetch"]);
+
+        let err = select_tools(available(), Some(&["delegate".to_string()]))
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("delegate"));
+    }
+
+    #[test]
+    fn test_condense_and_agent_id() {
+        assert_eq!(condense("  short answer \n", 100), "short answer");
+        let condensed = condense(&"é".repeat(30), 10);
+        assert!(condensed.starts_with(&"é".repeat(10)));
+        assert!(condensed.contains("20 more characters"));
+
+        assert_eq!(validate_agent_id("research_2").unwrap(), "research_2");
+        assert!(validate_agent_id("../main").is_err());
+        assert!(validate_agent_id("").is_err());
+    }
+}
@@ -0,0 +1,670 @@
+//! In-process llama.cpp chat provider (`gguf/<file>`, requires `gguf` feature)
+//!
+//! Loads a local GGUF chat model and runs it on CPU inside the process, so no
+//! Ollama daemon or network access is needed. Prompts are rendered with the
+//! model's own chat template. Tools are described in the system prompt and the
+//! reply is constrained by a GBNF grammar: either free text, or a single JSON
+//! object `{"name": "<tool>", "arguments": {...}}`.
+
+use anyhow::Result;
+use serde_json::Value;
+use std::path::PathBuf;
+
+use super::providers::{Message, Role, ToolCall, ToolSchema};
+
+#[cfg(feature = "gguf")]
+pub use provider::{GgufProvider, GgufTokenizer};
+
+/// Locate a model file: `spec` as given, then inside `models_dir`, each with
+/// and without a `.gguf` extension
+pub fn resolve_model_path(spec: &str, models_dir: &str) -> Result<PathBuf> {
+    let expanded = PathBuf::from(shellexpand::tilde(spec).to_string());
+    let dir = PathBuf::from(shellexpand::tilde(models_dir).to_string());
+
+    let mut candidates = vec![expanded.clone(), dir.join(spec)];
+    if !spec.ends_with(".gguf") {
+        candidates.push(expanded.with_extension("gguf"));
+        candidates.push(dir.join(format!("{}.gguf", spec)));
+    }
+
+    candidates.into_iter().find(|p| p.is_file()).ok_or_else(|| {
+        anyhow::anyhow!(
+            "GGUF model not found: '{}'. Place the file in {} or use an absolute path \
+                 (e.g. gguf/qwen2.5-7b-instruct-q4_k_m.gguf)",
+            spec,
+            dir.display()
+        )
+    })
+}
+
+/// Instructions appended to the system prompt when tools are offered
+fn tool_prompt(tools: &[ToolSchema]) -> String {
+    let mut prompt = String::from(
+        "# Tools\n\n\
+         You can call one tool per reply. To call a tool, reply with only a JSON object \
+         of the form {\"name\": \"<tool name>\", \"arguments\": {...}} and nothing else. \
+         The tool result will be sent back to you. Otherwise, reply with plain text.\n\n\
+         Available tools:\n",
+    );
+    for tool in tools {
+        prompt.push_str(&format!(
+            "\n- {}: {}\n  Arguments (JSON schema): {}\n",
+            tool.name, tool.description, tool.parameters
+        ));
+    }
+    prompt
+}
+
+/// GBNF grammar for a reply: plain text (not starting with `{`) or a single
+/// tool call whose name is one of the offered tools
+fn tool_call_grammar(tools: &[ToolSchema]) -> String {
+    let names = tools
+        .iter()
+        .map(|t| format!("\"\\\"{}\\\"\"", t.name))
+        .collect::<Vec<_>>()
+        .join(" | ");
+
+    format!(
+        r#"root ::= tool-call | text
+text ::= [^{{] .*
+tool-call ::= "{{" ws "\"name\"" ws ":" ws name ws "," ws "\"arguments\"" ws ":" ws object ws "}}"
+name ::= {names}
+object ::= "{{" ws ( member ( ws "," ws member )* )? ws "}}"
+member ::= string ws ":" ws value
+array ::= "[" ws ( value ( ws "," ws value )* )? ws "]"
+value ::= object | array | string | number | "true" | "false" | "null"
+string ::= "\"" ( [^"\\\x7F\x00-\x1F] | "\\" ( ["\\/bfnrt] | "u" hex hex hex hex ) )* "\""
+hex ::= [0-9a-fA-F]
+number ::= "-"? ( "0" | [1-9] [0-9]* ) ( "." [0-9]+ )? ( [eE] [-+]? [0-9]+ )?
+ws ::= [ \t\n]*
+"#
+    )
+}
+
+/// Flatten the conversation into (role, content) turns for the chat template.
+/// Tool traffic is rendered in the same JSON format the model is asked to use,
+/// since many templates have no tool role.
+fn chat_turns(messages: &[Message], tools: Option<&[ToolSchema]>) -> Vec<(String, String)> {
+    let tools = tools.filter(|t| !t.is_empty());
+    let mut system = String::new();
+    let mut turns = Vec::new();
+
+    for message in messages {
+        match message.role {
+            Role::System => {
+                if !system.is_empty() {
+                    system.push_str("\n\n");
+                }
+                system.push_str(&message.content);
+            }
+            Role::User => turns.push(("user".to_string(), message.content.clone())),
+            Role::Assistant => {
+                let mut content = message.content.clone();
+                for call in message.tool_calls.iter().flatten() {
+                    let arguments: Value =
+                        serde_json::from_str(&call.arguments).unwrap_or(Value::Null);
+                    if !content.is_empty() {
+                        content.push('\n');
+                    }
+                    content.push_str(
+                        &serde_json::json!({"name": call.name, "arguments": arguments}).to_string(),
+                    );
+                }
+                turns.push(("assistant".to_string(), content));
+            }
+            Role::Tool => turns.push((
+                "user".to_string(),
+                format!("Tool result:\n{}", message.content),
+            )),
+        }
+    }
+
+    if let Some(tools) = tools {
+        if !system.is_empty() {
+            system.push_str("\n\n");
+        }
+        system.push_str(&tool_prompt(tools));
+    }
+    if !system.is_empty() {
+        turns.insert(0, ("system".to_string(), system));
+    }
+    turns
+}
+
+/// Parse a grammar-constrained reply into a tool call, if it is one
+fn parse_tool_call(reply: &str) -> Option<ToolCall> {
+    let reply = reply.trim();
+    if !reply.starts_with('{') {
+        return None;
+    }
+    let value: Value = serde_json::from_str(reply).ok()?;
+    let name = value.get("name")?.as_str()?.to_string();
+    let arguments = value
+        .get("arguments")
+        .cloned()
+        .unwrap_or_else(|| serde_json::json!({}));
+
+    Some(ToolCall {
+        id: format!("call_{}", uuid::Uuid::new_v4().simple()),
+        name,
+        arguments: arguments.to_string(),
+    })
+}
+
+/// Take the longest complete UTF-8 prefix from `pending`, leaving a split
+/// multi-byte sequence for the next token
+fn take_utf8(pending: &mut Vec<u8>) -> String {
+    match std::str::from_utf8(pending) {
+        Ok(text) => {
+            let text = text.to_string();
+            pending.clear();
+            text
+        }
+        Err(e) if e.error_len().is_none() => {
+            let valid = e.valid_up_to();
+            let text = String::from_utf8_lossy(&pending[..valid]).into_owned();
+            pending.drain(..valid);
+            text
+        }
+        Err(_) => {
+            let text = String::from_utf8_lossy(pending).into_owned();
+            pending.clear();
+            text
+        }
+    }
+}
+
+#[cfg(feature = "gguf")]
+mod provider {
+    use anyhow::Result;
+    use async_trait::async_trait;
+    use llama_cpp_2::context::params::LlamaContextParams;
+    use llama_cpp_2::llama_backend::LlamaBackend;
+    use llama_cpp_2::llama_batch::LlamaBatch;
+    use llama_cpp_2::model::params::LlamaModelParams;
+    use llama_cpp_2::model::{AddBos, LlamaChatMessage, LlamaChatTemplate, LlamaModel, Special};
+    use llama_cpp_2::sampling::LlamaSampler;
+    use rand::RngExt;
+    use std::collections::HashMap;
+    use std::num::NonZeroU32;
+    use std::path::{Path, PathBuf};
+    use std::sync::{Arc, Mutex as StdMutex, OnceLock};
+    use tokio::sync::mpsc;
+    use tokio_stream::wrappers::UnboundedReceiverStream;
+    use tracing::{debug, info, warn};
+
+    use super::{chat_turns, parse_tool_call, take_utf8, tool_call_grammar};
+    use crate::agent::providers::{
+        LLMProvider, LLMResponse, LLMResponseContent, Message, Role, StreamChunk, StreamResult,
+        ThinkingBlock, ToolSchema, Usage, split_think_tags,
+    };
+    use crate::agent::tokenizer::{Tiktoken, Tokenizer};
+    use crate::config::GgufConfig;
+    use crate::memory::shared_llama_backend;
+
+    /// Loaded models, shared by every provider instance in the process
+    static MODELS: OnceLock<StdMutex<HashMap<PathBuf, Arc<LlamaModel>>>> = OnceLock::new();
+
+    fn load_model(backend: &LlamaBackend, path: &Path) -> Result<Arc<LlamaModel>> {
+        let mut models = MODELS
+            .get_or_init(Default::default)
+            .lock()
+            .map_err(|e| anyhow::anyhow!("Lock poisoned: {}", e))?;
+        if let Some(model) = models.get(path) {
+            return Ok(Arc::clone(model));
+        }
+
+        info!("Loading GGUF chat model: {}", path.display());
+        let model = Arc::new(LlamaModel::load_from_file(
+            backend,
+            path,
+            &LlamaModelParams::default(),
+        )?);
+        models.insert(path.to_path_buf(), Arc::clone(&model));
+        Ok(model)
+    }
+
+    /// Counts with a local model's own vocabulary. The model is loaded on
+    /// first use and shared with the chat provider; cl100k stands in if it
+    /// can't be loaded.
+    pub struct GgufTokenizer {
+        name: String,
+        path: PathBuf,
+        model: OnceLock<Option<Arc<LlamaModel>>>,
+        fallback: Tiktoken,
+    }
+
+    impl GgufTokenizer {
+        pub fn new(path: PathBuf) -> Self {
+            let name = path
+                .file_stem()
+                .and_then(|s| s.to_str())
+                .map(|stem| format!("gguf vocab ({})", stem))
+                .unwrap_or_else(|| "gguf vocab".to_string());
+            Self {
+                name,
+                path,
+                model: OnceLock::new(),
+                fallback: Tiktoken::cl100k(),
+            }
+        }
+    }
+
+    impl Tokenizer for GgufTokenizer {
+        fn name(&self) -> &str {
+            &self.name
+        }
+
+        fn count(&self, text: &str) -> usize {
+            let model = self.model.get_or_init(|| {
+                shared_llama_backend()
+                    .and_then(|backend| load_model(&backend, &self.path))
+                    .inspect_err(|e| warn!("Falling back to cl100k token counts: {}", e))
+                    .ok()
+            });
+            model
+                .as_ref()
+                .and_then(|model| model.str_to_token(text, AddBos::Never).ok())
+                .map_or_else(|| self.fallback.count(text), |tokens| tokens.len())
+        }
+    }
+
+    #[derive(Debug, Clone)]
+    struct Settings {
+        context_size: u32,
+        threads: Option<i32>,
+        temperature: f32,
+        max_tokens: usize,
+    }
+
+    pub struct GgufProvider {
+        model: Arc<LlamaModel>,
+        backend: Arc<LlamaBackend>,
+        model_name: String,
+        settings: Settings,
+        stream_usage: Arc<StdMutex<Option<Usage>>>,
+    }
+
+    impl GgufProvider {
+        pub fn new(path: &Path, config: &GgufConfig, max_tokens: usize) -> Result<Self> {
+            let backend = shared_llama_backend()?;
+            let model = load_model(&backend, path)?;
+            let model_name = path
+                .file_stem()
+                .and_then(|s| s.to_str())
+                .unwrap_or("gguf")
+                .to_string();
+
+            Ok(Self {
+                model,
+                backend,
+                model_name,
+                settings: Settings {
+                    context_size: config.context_size,
+                    threads: config.threads,
+                    temperature: config.temperature,
+                    max_tokens,
+                },
+                stream_usage: Arc::new(StdMutex::new(None)),
+            })
+        }
+
+        /// Render the conversation with the model's chat template
+        /// (ChatML if the model doesn't ship one)
+        fn render_prompt(
+            &self,
+            messages: &[Message],
+            tools: Option<&[ToolSchema]>,
+        ) -> Result<String> {
+            let template = match self.model.chat_template(None) {
+                Ok(template) => template,
+                Err(e) => {
+                    debug!(
+                        "{} has no chat template ({}), using chatml",
+                        self.model_name, e
+                    );
+                    LlamaChatTemplate::new("chatml")?
+                }
+            };
+            let chat = chat_turns(messages, tools)
+                .into_iter()
+                .map(|(role, content)| LlamaChatMessage::new(role, content))
+                .collect::<Result<Vec<_>, _>>()?;
+            Ok(self.model.apply_chat_template(&template, &chat, true)?)
+        }
+
+        /// Run generation on a blocking thread. `on_piece` receives decoded
+        /// text as it is produced and returns false to stop early.
+        async fn generate<F>(
+            &self,
+            prompt: String,
+            grammar: Option<String>,
+            on_piece: F,
+        ) -> Result<(String, Usage)>
+        where
+            F: FnMut(&str) -> bool + Send + 'static,
+        {
+            let model = Arc::clone(&self.model);
+            let backend = Arc::clone(&self.backend);
+            let settings = self.settings.clone();
+            tokio::task::spawn_blocking(move || {
+                generate_blocking(
+                    &model,
+                    &backend,
+                    &settings,
+                    &prompt,
+                    grammar.as_deref(),
+                    on_piece,
+                )
+            })
+            .await?
+        }
+    }
+
+    fn generate_blocking(
+        model: &LlamaModel,
+        backend: &LlamaBackend,
+        settings: &Settings,
+        prompt: &str,
+        grammar: Option<&str>,
+        mut on_piece: impl FnMut(&str) -> bool,
+    ) -> Result<(String, Usage)> {
+        let mut ctx_params = LlamaContextParams::default()
+            .with_n_ctx(NonZeroU32::new(settings.context_size))
+            .with_n_batch(settings.context_size);
+        if let Some(threads) = settings.threads {
+            ctx_params = ctx_params
+                .with_n_threads(threads)
+                .with_n_threads_batch(threads);
+        }
+        let mut ctx = model.new_context(backend, ctx_params)?;
+
+        // The chat template already emits BOS where the model expects it
+        let tokens = model.str_to_token(prompt, AddBos::Never)?;
+        let n_ctx = settings.context_size as usize;
+        if tokens.len() >= n_ctx {
+            anyhow::bail!(
+                "Prompt is {} tokens, exceeding the GGUF context size of {} \
+                 (providers.gguf.context_size)",
+                tokens.len(),
+                n_ctx
+            );
+        }
+
+        let n_batch = ctx.n_batch() as usize;
+        let mut batch = LlamaBatch::new(n_batch, 1);
+        let last = tokens.len() - 1;
+        for (chunk_index, chunk) in tokens.chunks(n_batch).enumerate() {
+            batch.clear();
+            for (i, token) in chunk.iter().enumerate() {
+                let pos = chunk_index * n_batch + i;
+                batch.add(*token, pos as i32, &[0], pos == last)?;
+            }
+            ctx.decode(&mut batch)?;
+        }
+
+        let mut samplers = Vec::new();
+        if let Some(grammar) = grammar {
+            samplers.push(LlamaSampler::grammar(model, grammar, "root")?);
+        }
+        if settings.temperature > 0.0 {
+            samplers.push(LlamaSampler::temp(settings.temperature));
+            samplers.push(LlamaSampler::dist(rand::rng().random()));
+        } else {
+            samplers.push(LlamaSampler::greedy());
+        }
+        let mut sampler = LlamaSampler::chain_simple(samplers);
+
+        let max_tokens = settings.max_tokens.min(n_ctx - tokens.len());
+        let mut pos = tokens.len();
+        let mut generated = 0;
+        let mut pending = Vec::new();
+        let mut text = String::new();
+
+        while generated < max_tokens {
+            let token = sampler.sample(&ctx, batch.n_tokens() - 1);
+            if model.is_eog_token(token) {
+                break;
+            }
+            generated += 1;
+
+            pending.extend(model.token_to_bytes(token, Special::Plaintext)?);
+            let piece = take_utf8(&mut pending);
+            if !piece.is_empty() {
+                text.push_str(&piece);
+                if !on_piece(&piece) {
+                    break;
+                }
+            }
+
+            batch.clear();
+            batch.add(token, pos as i32, &[0], true)?;
+            pos += 1;
+            ctx.decode(&mut batch)?;
+        }
+
+        let usage = Usage {
+            input_tokens: tokens.len() as u64,
+            output_tokens: generated as u64,
+            ..Default::default()
+        };
+        Ok((text, usage))
+    }
+
+    #[async_trait]
+    impl LLMProvider for GgufProvider {
+        async fn chat(
+            &self,
+            messages: &[Message],
+            tools: Option<&[ToolSchema]>,
+        ) -> Result<LLMResponse> {
+            let tools = tools.filter(|t| !t.is_empty());
+            let prompt = self.render_prompt(messages, tools)?;
+            let grammar = tools.map(tool_call_grammar);
+
+            let (text, usage) = self.generate(prompt, grammar, |_| true).await?;
+
+            match tools.and_then(|_| parse_tool_call(&text)) {
+                Some(call) => Ok(LLMResponse::tool_calls_with_usage(vec![call], usage)),
+                None => {
+                    // Reasoning models (DeepSeek-R1 distills, Qwen3) inline <think>
+                    let (thinking, text) = split_think_tags(&text);
+                    Ok(LLMResponse::text_with_usage(text.trim().to_string(), usage)
+                        .with_thinking(vec![ThinkingBlock::text(thinking)]))
+                }
+            }
+        }
+
+        async fn summarize(&self, text: &str) -> Result<String> {
+            let messages = vec![Message {
+                role: Role::User,
+                content: format!(
+                    "Summarize the following conversation concisely, preserving key information and context:\n\n{}",
+                    text
+                ),
+                tool_calls: None,
+                tool_call_id: None,
+                images: Vec::new(),
+                thinking: Vec::new(),
+            }];
+
+            match self.chat(&messages, None).await?.content {
+                LLMResponseContent::Text(summary) => Ok(summary),
+                _ => anyhow::bail!("Unexpected response type"),
+            }
+        }
+
+        fn take_stream_usage(&self) -> Option<Usage> {
+            self.stream_usage.lock().ok()?.take()
+        }
+
+        async fn chat_stream(
+            &self,
+            messages: &[Message],
+            tools: Option<&[ToolSchema]>,
+        ) -> Result<StreamResult> {
+            let tools = tools.filter(|t| !t.is_empty());
+            let prompt = self.render_prompt(messages, tools)?;
+            let grammar = tools.map(tool_call_grammar);
+            let (tx, rx) = mpsc::unbounded_channel();
+
+            // Text replies are streamed as they are generated; a reply that
+            // opens with '{' is a tool call and is held back until complete.
+            let piece_tx = tx.clone();
+            let mut is_tool_call: Option<bool> = None;
+            let on_piece = move |piece: &str| {
+                let tool_call = *is_tool_call.get_or_insert_with(|| piece.starts_with('{'));
+                tool_call
+                    || piece_tx
+                        .send(Ok(StreamChunk {
+                            delta: piece.to_string(),
+                            done: false,
+                            tool_calls: None,
+                            thinking: String::new(),
+                        }))
+                        .is_ok()
+            };
+
+            let model = Arc::clone(&self.model);
+            let backend = Arc::clone(&self.backend);
+            let settings = self.settings.clone();
+            let stream_usage = Arc::clone(&self.stream_usage);
+            let has_tools = tools.is_some();
+            tokio::task::spawn_blocking(move || {
+                let result = generate_blocking(
+                    &model,
+                    &backend,
+                    &settings,
+                    &prompt,
+                    grammar.as_deref(),
+                    on_piece,
+                );
+                let chunk = result.map(|(text, usage)| {
+                    if let Ok(mut slot) = stream_usage.lock() {
+                        *slot = Some(usage);
+                    }
+                    match has_tools.then(|| parse_tool_call(&text)).flatten() {
+                        Some(call) => StreamChunk {
+                            delta: String::new(),
+                            done: true,
+                            tool_calls: Some(vec![call]),
+                            thinking: String::new(),
+                        },
+                        None => StreamChunk {
+                            delta: String::new(),
+                            done: true,
+                            tool_calls: None,
+                            thinking: String::new(),
+                        },
+                    }
+                });
+                let _ = tx.send(chunk);
+            });
+
+            Ok(Box::pin(UnboundedReceiverStream::new(rx)))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn schema(name: &str) -> ToolSchema {
+        ToolSchema {
+            name: name.to_string(),
+            description: format!("The {} tool", name),
+            parameters: json!({"type": "object", "properties": {"path": {"type": "string"}}}),
+        }
+    }
+
+    fn message(role: Role, content: &str) -> Message {
+        Message {
+            role,
+            content: content.to_string(),
+            tool_calls: None,
+            tool_call_id: None,
+            images: Vec::new(),
+            thinking: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_resolve_model_path() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("qwen.gguf");
+        std::fs::write(&file, b"GGUF").unwrap();
+        let models_dir = dir.path().to_str().unwrap();
+
+        assert_eq!(resolve_model_path("qwen.gguf", models_dir).unwrap(), file);
+        assert_eq!(resolve_model_path("qwen", models_dir).unwrap(), file);
+        assert_eq!(
+            resolve_model_path(file.to_str().unwrap(), "/nonexistent").unwrap(),
+            file
+        );
+        assert!(resolve_model_path("missing", models_dir).is_err());
+    }
+
+    #[test]
+    fn test_chat_turns_render_tools() {
+        let tools = [schema("read_file")];
+        let mut assistant = message(Role::Assistant, "");
+        assistant.tool_calls = Some(vec![ToolCall {
+            id: "call_1".to_string(),
+            name: "read_file".to_string(),
+            arguments: r#"{"path":"a.txt"}"#.to_string(),
+        }]);
+        let messages = [
+            message(Role::System, "Be brief."),
+            message(Role::User, "Read a.txt"),
+            assistant,
+            message(Role::Tool, "hello"),
+        ];
+
+        let turns = chat_turns(&messages, Some(&tools));
+        let roles: Vec<&str> = turns.iter().map(|(r, _)| r.as_str()).collect();
+        assert_eq!(roles, ["system", "user", "assistant", "user"]);
+        assert!(turns[0].1.starts_with("Be brief."));
+        assert!(turns[0].1.contains("- read_file: The read_file tool"));
+        let call: Value = serde_json::from_str(&turns[2].1).unwrap();
+        assert_eq!(
+            call,
+            json!({"name": "read_file", "arguments": {"path": "a.txt"}})
+        );
+        assert_eq!(turns[3].1, "Tool result:\nhello");
+
+        // No tools: no tool instructions
+        let turns = chat_turns(&messages[..2], None);
+        assert_eq!(turns[0].1, "Be brief.");
+    }
+
+    #[test]
+    fn test_tool_call_grammar_lists_tools() {
+        let grammar = tool_call_grammar(&[schema("read_file"), schema("bash")]);
+        assert!(grammar.contains(r#"name ::= "\"read_file\"" | "\"bash\"""#));
+        assert!(grammar.starts_with("root ::= tool-call | text\ntext ::= [^{] .*\n"));
+    }
+
+    #[test]
+    fn test_parse_tool_call() {
+        let call =
+            parse_tool_call(r#" {"name": "bash", "arguments": {"command": "ls"}} "#).unwrap();
+        assert_eq!(call.name, "bash");
+        assert_eq!(call.arguments, r#"{"command":"ls"}"#);
+        assert!(call.id.starts_with("call_"));
+
+        assert!(parse_tool_call("Just text").is_none());
+        assert!(parse_tool_call(r#"{"arguments": {}}"#).is_none());
+    }
+
+    #[test]
+    fn test_take_utf8_holds_split_sequences() {
+        let mut pending = "h€".as_bytes()[..2].to_vec();
+        assert_eq!(take_utf8(&mut pending), "h");
+        assert_eq!(pending.len(), 1);
+        pending.extend(&"€".as_bytes()[1..]);
+        assert_eq!(take_utf8(&mut pending), "€");
+        assert!(pending.is_empty());
+    }
+}
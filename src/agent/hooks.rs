@@ -0,0 +1,480 @@
+//! User-configured lifecycle hooks
+//!
+//! `[[hooks]]` entries in config.toml run a shell command at an event, under
+//! the same sandbox policy as the `bash` tool, with the event as JSON on
+//! stdin: `event`, `agent_id`, `session_id` and `workspace`, plus `tool`
+//! (`name`, `arguments`) for tool events, `output` after a tool, `prompt` when
+//! the user submits one, `reason` when a session ends and token counts after
+//! compaction.
+//!
+//! Before a tool runs, a hook can veto the call by exiting with code 2 (stderr
+//! is the reason) or printing `{"decision": "deny", "reason": "..."}`, or
+//! change what runs by printing `{"arguments": {...}}`; the transcript keeps
+//! the model's call. After a tool, printing `{"output": "..."}` replaces the
+//! output the model sees. Hooks for an event run in config order. A tool hook
+//! that fails or times out follows its `on_error`: by default a failing
+//! `pre_tool_use` hook blocks the call, and other hooks are logged and
+//! skipped.
+
+use std::path::PathBuf;
+use std::process::Output;
+use std::time::Duration;
+
+use anyhow::Result;
+use serde_json::{Value, json};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, warn};
+
+use super::providers::ToolCall;
+use super::tools::sandbox_policy;
+use crate::config::{Config, HookConfig};
+use crate::sandbox::{self, SandboxPolicy};
+
+/// Exit code with which a `pre_tool_use` hook vetoes the call
+pub const HOOK_DENY_EXIT_CODE: i32 = 2;
+
+/// Output for a call a failing `pre_tool_use` hook blocked
+const HOOK_FAILED_OUTPUT: &str = "[Blocked: a pre_tool_use hook failed, so the tool was not run]";
+
+/// Output the model sees when a blocking `post_tool_use` hook failed
+const OUTPUT_WITHHELD: &str = "[Output withheld: a post_tool_use hook failed]";
+
+/// What to do with a tool call after its `pre_tool_use` hooks
+#[derive(Debug, Clone)]
+pub enum PreToolUse {
+    /// Run the call, with the arguments the hooks left it
+    Run(ToolCall),
+    /// Don't run it; the output to record instead
+    Deny(String),
+}
+
+/// Agent and session an event belongs to
+#[derive(Debug, Clone, Copy)]
+pub struct HookSession<'a> {
+    pub agent_id: &'a str,
+    pub session_id: &'a str,
+}
+
+pub struct Hooks {
+    hooks: Vec<HookConfig>,
+    workspace: PathBuf,
+    policy: Option<SandboxPolicy>,
+}
+
+impl Hooks {
+    pub fn for_config(config: &Config) -> Self {
+        Self {
+            hooks: config.hooks.clone(),
+            workspace: config.workspace_path(),
+            // Only worth detecting sandbox support for if there are hooks
+            policy: if config.hooks.is_empty() {
+                None
+            } else {
+                sandbox_policy(config)
+            },
+        }
+    }
+
+    /// Give a tool call to its `pre_tool_use` hooks
+    pub async fn pre_tool_use(
+        &self,
+        session: HookSession<'_>,
+        call: &ToolCall,
+        cancel: &CancellationToken,
+    ) -> PreToolUse {
+        let mut call = call.clone();
+        for hook in self.matching("pre_tool_use", Some(&call.name)) {
+            let input = self.input("pre_tool_use", session, json!({ "tool": tool_json(&call) }));
+            let Some(output) = self.run(hook, &input, cancel).await else {
+                if hook.blocks_on_error() {
+                    return PreToolUse::Deny(HOOK_FAILED_OUTPUT.to_string());
+                }
+                continue;
+            };
+            if output.status.code() == Some(HOOK_DENY_EXIT_CODE) {
+                let reason = String::from_utf8_lossy(&output.stderr);
+                return PreToolUse::Deny(denied_output(reason.trim()));
+            }
+            if !output.status.success() {
+                warn_failed(hook, &output);
+                if hook.blocks_on_error() {
+                    return PreToolUse::Deny(HOOK_FAILED_OUTPUT.to_string());
+                }
+                continue;
+            }
+
+            let response = parse_response(&output);
+            if response["decision"] == "deny" {
+                return PreToolUse::Deny(denied_output(
+                    response["reason"].as_str().unwrap_or_default(),
+                ));
+            }
+            if let Some(arguments) = response.get("arguments").filter(|a| a.is_object()) {
+                debug!("Hook {:?} rewrote {} arguments", hook.command, call.name);
+                call.arguments = arguments.to_string();
+            }
+        }
+        PreToolUse::Run(call)
+    }
+
+    /// Give a tool's output to its `post_tool_use` hooks, returning the
+    /// output the model should see
+    pub async fn post_tool_use(
+        &self,
+        session: HookSession<'_>,
+        call: &ToolCall,
+        mut output: String,
+        cancel: &CancellationToken,
+    ) -> String {
+        for hook in self.matching("post_tool_use", Some(&call.name)) {
+            let input = self.input(
+                "post_tool_use",
+                session,
+                json!({ "tool": tool_json(call), "output": output }),
+            );
+            let Some(result) = self.run(hook, &input, cancel).await else {
+                if hook.blocks_on_error() {
+                    return OUTPUT_WITHHELD.to_string();
+                }
+                continue;
+            };
+            if !result.status.success() {
+                warn_failed(hook, &result);
+                if hook.blocks_on_error() {
+                    return OUTPUT_WITHHELD.to_string();
+                }
+                continue;
+            }
+            if let Some(replaced) = parse_response(&result)["output"].as_str() {
+                output = replaced.to_string();
+            }
+        }
+        output
+    }
+
+    /// Run the hooks of an event that can't change anything (prompt submit,
+    /// session start and end, compaction)
+    pub async fn notify(&self, event: &str, session: HookSession<'_>, fields: Value) {
+        let cancel = CancellationToken::new();
+        let mut hooks = self.matching(event, None).peekable();
+        if hooks.peek().is_none() {
+            return;
+        }
+        let input = self.input(event, session, fields);
+        for hook in hooks {
+            if let Some(output) = self.run(hook, &input, &cancel).await
+                && !output.status.success()
+            {
+                warn_failed(hook, &output);
+            }
+        }
+    }
+
+    fn matching<'a>(
+        &'a self,
+        event: &'a str,
+        tool: Option<&'a str>,
+    ) -> impl Iterator<Item = &'a HookConfig> {
+        self.hooks.iter().filter(move |hook| {
+            hook.event == event
+                && match (&hook.tool, tool) {
+                    (Some(pattern), Some(tool)) => {
+                        glob::Pattern::new(pattern).is_ok_and(|p| p.matches(tool))
+                    }
+                    _ => true,
+                }
+        })
+    }
+
+    /// The JSON a hook gets on stdin
+    fn input(&self, event: &str, session: HookSession<'_>, fields: Value) -> String {
+        let mut input = json!({
+            "event": event,
+            "agent_id": session.agent_id,
+            "session_id": session.session_id,
+            "workspace": self.workspace,
+        });
+        if let (Some(input), Value::Object(fields)) = (input.as_object_mut(), fields) {
+            input.extend(fields);
+        }
+        input.to_string()
+    }
+
+    /// Run a hook's command; None if it couldn't be run or timed out
+    async fn run(
+        &self,
+        hook: &HookConfig,
+        input: &str,
+        cancel: &CancellationToken,
+    ) -> Option<Output> {
+        debug!("Running {} hook: {}", hook.event, hook.command);
+        let result = match &self.policy {
+            Some(policy) => {
+                sandbox::run_sandboxed_with_input(
+                    &hook.command,
+                    Some(input),
+                    policy,
+                    hook.timeout_ms,
+                    cancel,
+                )
+                .await
+            }
+            None => self.run_unsandboxed(hook, input, cancel).await,
+        };
+        result
+            .inspect_err(|e| warn!("{} hook {:?} failed: {}", hook.event, hook.command, e))
+            .ok()
+    }
+
+    /// Without sandbox support hooks run like unsandboxed `bash` calls
+    async fn run_unsandboxed(
+        &self,
+        hook: &HookConfig,
+        input: &str,
+        cancel: &CancellationToken,
+    ) -> Result<Output> {
+        let mut child = sandbox::executor::new_process_group(
+            tokio::process::Command::new("bash")
+                .arg("-c")
+                .arg(&hook.command)
+                .current_dir(&self.workspace)
+                .stdin(std::process::Stdio::piped())
+                .stdout(std::process::Stdio::piped())
+                .stderr(std::process::Stdio::piped())
+                .kill_on_drop(true),
+        )
+        .spawn()?;
+        sandbox::executor::write_stdin(&mut child, Some(input));
+
+        let timeout = Duration::from_millis(hook.timeout_ms);
+        sandbox::executor::wait_for_group(child, timeout, cancel, "Hook").await
+    }
+}
+
+fn tool_json(call: &ToolCall) -> Value {
+    let arguments = serde_json::from_str(&call.arguments)
+        .unwrap_or_else(|_| Value::String(call.arguments.clone()));
+    json!({ "name": call.name, "arguments": arguments })
+}
+
+/// A hook's stdout as JSON (Null if it printed nothing or something else)
+fn parse_response(output: &Output) -> Value {
+    serde_json::from_slice(&output.stdout).unwrap_or(Value::Null)
+}
+
+fn denied_output(reason: &str) -> String {
+    if reason.is_empty() {
+        "[Denied by hook: the tool was not run]".to_string()
+    } else {
+        format!("[Denied by hook: {}. The tool was not run]", reason)
+    }
+}
+
+fn warn_failed(hook: &HookConfig, output: &Output) {
+    warn!(
+        "{} hook {:?} exited with {}: {}",
+        hook.event,
+        hook.command,
+        output.status,
+        String::from_utf8_lossy(&output.stderr).trim()
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::HookOnError;
+
+    fn hooks(workspace: &std::path::Path, hooks: Vec<HookConfig>) -> Hooks {
+        Hooks {
+            hooks,
+            workspace: workspace.to_path_buf(),
+            policy: None,
+        }
+    }
+
+    fn hook(event: &str, tool: Option<&str>, command: &str) -> HookConfig {
+        HookConfig {
+            event: event.to_string(),
+            tool: tool.map(|t| t.to_string()),
+            command: command.to_string(),
+            timeout_ms: 5_000,
+            on_error: None,
+        }
+    }
+
+    fn call(name: &str, arguments: &str) -> ToolCall {
+        ToolCall {
+            id: "call_1".to_string(),
+            name: name.to_string(),
+            arguments: arguments.to_string(),
+        }
+    }
+
+    const SESSION: HookSession<'static> = HookSession {
+        agent_id: "main",
+        session_id: "s1",
+    };
+
+    #[tokio::test]
+    async fn test_pre_tool_use_can_deny_and_rewrite() {
+        let tmp = tempfile::tempdir().unwrap();
+        let cancel = CancellationToken::new();
+        let hooks = hooks(
+            tmp.path(),
+            vec![
+                hook(
+                    "pre_tool_use",
+                    Some("*_file"),
+                    r#"grep -q '"path":"secrets' && { echo "secrets are off limits" >&2; exit 2; }; exit 0"#,
+                ),
+                hook(
+                    "pre_tool_use",
+                    Some("write_file"),
+                    r#"echo '{"arguments": {"path": "notes.md", "content": "rewritten"}}'"#,
+                ),
+                // Failing hooks are skipped when told to allow
+                HookConfig {
+                    on_error: Some(HookOnError::Allow),
+                    ..hook("pre_tool_use", None, "exit 1")
+                },
+            ],
+        );
+
+        let PreToolUse::Deny(denied) = hooks
+            .pre_tool_use(
+                SESSION,
+                &call("edit_file", r#"{"path":"secrets/key"}"#),
+                &cancel,
+            )
+            .await
+        else {
+            panic!("edit_file of secrets wasn't denied");
+        };
+        assert_eq!(
+            denied,
+            "[Denied by hook: secrets are off limits. The tool was not run]"
+        );
+
+        let PreToolUse::Run(rewritten) = hooks
+            .pre_tool_use(SESSION, &call("write_file", r#"{"path":"a.md"}"#), &cancel)
+            .await
+        else {
+            panic!("write_file was denied");
+        };
+        let args: Value = serde_json::from_str(&rewritten.arguments).unwrap();
+        assert_eq!(args["content"], "rewritten");
+
+        let bash = call("bash", r#"{"command":"ls"}"#);
+        let PreToolUse::Run(unchanged) = hooks.pre_tool_use(SESSION, &bash, &cancel).await else {
+            panic!("bash was denied");
+        };
+        assert_eq!(unchanged.arguments, bash.arguments);
+    }
+
+    #[tokio::test]
+    async fn test_failing_hooks_block_by_default() {
+        let tmp = tempfile::tempdir().unwrap();
+        let cancel = CancellationToken::new();
+        let bash = call("bash", r#"{"command":"ls"}"#);
+
+        let failing = hooks(tmp.path(), vec![hook("pre_tool_use", None, "exit 1")]);
+        let PreToolUse::Deny(output) = failing.pre_tool_use(SESSION, &bash, &cancel).await else {
+            panic!("a failing pre_tool_use hook let the call run");
+        };
+        assert_eq!(output, HOOK_FAILED_OUTPUT);
+
+        let timing_out = hooks(
+            tmp.path(),
+            vec![HookConfig {
+                timeout_ms: 50,
+                ..hook("pre_tool_use", None, "sleep 5")
+            }],
+        );
+        assert!(matches!(
+            timing_out.pre_tool_use(SESSION, &bash, &cancel).await,
+            PreToolUse::Deny(_)
+        ));
+
+        // post_tool_use hooks are skipped unless told to block
+        let post = hooks(tmp.path(), vec![hook("post_tool_use", None, "exit 1")]);
+        let output = post
+            .post_tool_use(SESSION, &bash, "raw".to_string(), &cancel)
+            .await;
+        assert_eq!(output, "raw");
+        let post = hooks(
+            tmp.path(),
+            vec![HookConfig {
+                on_error: Some(HookOnError::Block),
+                ..hook("post_tool_use", None, "exit 1")
+            }],
+        );
+        let output = post
+            .post_tool_use(SESSION, &bash, "raw".to_string(), &cancel)
+            .await;
+        assert_eq!(output, OUTPUT_WITHHELD);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_timed_out_hooks_are_killed_with_their_children() {
+        let tmp = tempfile::tempdir().unwrap();
+        let cancel = CancellationToken::new();
+        let bash = call("bash", r#"{"command":"ls"}"#);
+        let timing_out = hooks(
+            tmp.path(),
+            vec![HookConfig {
+                timeout_ms: 50,
+                ..hook("pre_tool_use", None, "(sleep 0.3; touch late) & sleep 5")
+            }],
+        );
+        assert!(matches!(
+            timing_out.pre_tool_use(SESSION, &bash, &cancel).await,
+            PreToolUse::Deny(_)
+        ));
+
+        tokio::time::sleep(Duration::from_millis(600)).await;
+        assert!(!tmp.path().join("late").exists());
+    }
+
+    #[tokio::test]
+    async fn test_post_tool_use_and_notify() {
+        let tmp = tempfile::tempdir().unwrap();
+        let cancel = CancellationToken::new();
+        let hooks = hooks(
+            tmp.path(),
+            vec![
+                hook(
+                    "post_tool_use",
+                    Some("bash"),
+                    r#"echo '{"output": "post-processed"}'"#,
+                ),
+                hook("session_start", None, "cat > event.json"),
+            ],
+        );
+
+        let output = hooks
+            .post_tool_use(SESSION, &call("bash", "{}"), "raw".to_string(), &cancel)
+            .await;
+        assert_eq!(output, "post-processed");
+        let output = hooks
+            .post_tool_use(
+                SESSION,
+                &call("read_file", "{}"),
+                "raw".to_string(),
+                &cancel,
+            )
+            .await;
+        assert_eq!(output, "raw");
+
+        hooks
+            .notify("session_start", SESSION, json!({ "source": "new" }))
+            .await;
+        let event: Value =
+            serde_json::from_str(&std::fs::read_to_string(tmp.path().join("event.json")).unwrap())
+                .unwrap();
+        assert_eq!(event["event"], "session_start");
+        assert_eq!(event["session_id"], "s1");
+        assert_eq!(event["source"], "new");
+    }
+}
@@ -0,0 +1,258 @@
+//! Model registry - aliases and capability metadata
+//!
+//! Built-in entries describe the models behind the default aliases.
+//! `[models.<alias>]` in config declares additional aliases or overrides
+//! metadata; anything left unset falls back to the built-in entry for the
+//! same provider/model.
+
+use crate::config::{Config, ModelPricing};
+
+use super::providers::resolve_provider_model;
+
+/// Capability metadata for a built-in model
+struct BuiltinModel {
+    provider: &'static str,
+    /// Model ID as used in "provider/model" specs
+    model: &'static str,
+    /// ID sent to the provider API
+    api_id: &'static str,
+    context_window: usize,
+    max_output_tokens: usize,
+    vision: bool,
+    tools: bool,
+}
+
+const BUILTIN_MODELS: &[BuiltinModel] = &[
+    BuiltinModel {
+        provider: "anthropic",
+        model: "claude-opus-4-5",
+        api_id: "claude-opus-4-5-20251101",
+        context_window: 200_000,
+        max_output_tokens: 64_000,
+        vision: true,
+        tools: true,
+    },
+    BuiltinModel {
+        provider: "anthropic",
+        model: "claude-sonnet-4-5",
+        api_id: "claude-sonnet-4-5-20250929",
+        context_window: 200_000,
+        max_output_tokens: 64_000,
+        vision: true,
+        tools: true,
+    },
+    BuiltinModel {
+        provider: "anthropic",
+        model: "claude-haiku-4-5",
+        api_id: "claude-haiku-4-5-20251001",
+        context_window: 200_000,
+        max_output_tokens: 64_000,
+        vision: true,
+        tools: true,
+    },
+    BuiltinModel {
+        provider: "openai",
+        model: "gpt-4o",
+        api_id: "gpt-4o",
+        context_window: 128_000,
+        max_output_tokens: 16_384,
+        vision: true,
+        tools: true,
+    },
+    BuiltinModel {
+        provider: "openai",
+        model: "gpt-4o-mini",
+        api_id: "gpt-4o-mini",
+        context_window: 128_000,
+        max_output_tokens: 16_384,
+        vision: true,
+        tools: true,
+    },
+    BuiltinModel {
+        provider: "openai",
+        model: "gpt-4-turbo",
+        api_id: "gpt-4-turbo",
+        context_window: 128_000,
+        max_output_tokens: 4_096,
+        vision: true,
+        tools: true,
+    },
+    BuiltinModel {
+        provider: "glm",
+        model: "glm-4.7",
+        api_id: "glm-4.7",
+        context_window: 200_000,
+        max_output_tokens: 128_000,
+        vision: false,
+        tools: true,
+    },
+];
+
+/// Resolved model with its capabilities
+#[derive(Debug, Clone, PartialEq)]
+pub struct ModelInfo {
+    pub provider: String,
+    /// Model ID as used in "provider/model" specs and pricing keys
+    pub model: String,
+    /// ID sent to the provider API
+    pub api_id: String,
+    /// None: unknown, use `agent.context_window`
+    pub context_window: Option<usize>,
+    /// None: unknown, use `agent.max_tokens`
+    pub max_output_tokens: Option<usize>,
+    pub vision: bool,
+    pub tools: bool,
+    /// Price declared in the registry (the cost module has its own table)
+    pub price: Option<ModelPricing>,
+    /// Extended thinking budget in tokens (None: thinking off)
+    pub thinking_budget: Option<u32>,
+    /// Reasoning effort for OpenAI-compatible reasoning models
+    pub reasoning_effort: Option<String>,
+}
+
+impl ModelInfo {
+    /// Context window, falling back to the configured default
+    pub fn context_window_or(&self, default: usize) -> usize {
+        self.context_window.unwrap_or(default)
+    }
+
+    /// Output token limit for requests: the configured max, capped by the model
+    pub fn max_tokens(&self, configured: usize) -> usize {
+        self.max_output_tokens
+            .map_or(configured, |limit| configured.min(limit))
+    }
+}
+
+fn builtin(provider: &str, model: &str) -> Option<&'static BuiltinModel> {
+    BUILTIN_MODELS.iter().find(|b| {
+        b.provider == provider
+            && (b.model.eq_ignore_ascii_case(model) || b.api_id.eq_ignore_ascii_case(model))
+    })
+}
+
+/// Map a model ID to the ID sent to the provider API (e.g. an undated
+/// Anthropic ID to its dated snapshot). Unknown IDs pass through unchanged.
+pub fn api_model_id(provider: &str, model: &str) -> String {
+    builtin(provider, model)
+        .map(|b| b.api_id.to_string())
+        .unwrap_or_else(|| model.to_string())
+}
+
+/// Resolve a model spec (alias, "provider/model" or bare ID) to its
+/// provider, IDs and capabilities
+pub fn lookup_model(spec: &str, config: &Config) -> ModelInfo {
+    let (provider, model) = resolve_provider_model(spec, config);
+
+    // Registry entry: addressed by alias, or matching provider/id
+    let entry = config.models.get(spec).or_else(|| {
+        config
+            .models
+            .values()
+            .find(|e| e.provider == provider && e.id == model)
+    });
+    let base = builtin(&provider, &model);
+
+    ModelInfo {
+        api_id: api_model_id(&provider, &model),
+        context_window: entry
+            .and_then(|e| e.context_window)
+            .or(base.map(|b| b.context_window)),
+        max_output_tokens: entry
+            .and_then(|e| e.max_output_tokens)
+            .or(base.map(|b| b.max_output_tokens)),
+        // Unknown models keep the permissive behavior: send images and tools
+        // (the in-process llama.cpp provider is text-only)
+        vision: entry
+            .and_then(|e| e.vision)
+            .or(base.map(|b| b.vision))
+            .unwrap_or(provider != "gguf"),
+        tools: entry
+            .and_then(|e| e.tools)
+            .or(base.map(|b| b.tools))
+            .unwrap_or(true),
+        price: entry.and_then(|e| e.price),
+        thinking_budget: entry.and_then(|e| e.thinking_budget),
+        reasoning_effort: entry.and_then(|e| e.reasoning_effort.clone()),
+        provider,
+        model,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ModelEntry;
+
+    #[test]
+    fn test_builtin_alias_metadata() {
+        let config = Config::default();
+        let info = lookup_model("opus", &config);
+        assert_eq!(info.provider, "anthropic");
+        assert_eq!(info.model, "claude-opus-4-5");
+        assert_eq!(info.api_id, "claude-opus-4-5-20251101");
+        assert_eq!(info.context_window, Some(200_000));
+        assert!(info.vision && info.tools);
+
+        // Output is capped by the model, never raised above the config
+        assert_eq!(info.max_tokens(4096), 4096);
+        assert_eq!(lookup_model("gpt-4-turbo", &config).max_tokens(8192), 4096);
+    }
+
+    #[test]
+    fn test_unknown_model_passes_through() {
+        let config = Config::default();
+        let info = lookup_model("anthropic/claude-3-7-sonnet-latest", &config);
+        assert_eq!(info.api_id, "claude-3-7-sonnet-latest");
+        assert_eq!(info.context_window, None);
+        assert_eq!(info.context_window_or(128_000), 128_000);
+        assert!(info.vision && info.tools);
+    }
+
+    #[test]
+    fn test_registry_entry() {
+        let mut config = Config::default();
+        config.models.insert(
+            "local".to_string(),
+            ModelEntry {
+                provider: "ollama".to_string(),
+                id: "qwen2.5:14b".to_string(),
+                context_window: Some(32_768),
+                max_output_tokens: None,
+                vision: Some(false),
+                tools: Some(true),
+                price: None,
+                thinking_budget: None,
+                reasoning_effort: None,
+            },
+        );
+        // Override one field of a built-in model, inherit the rest
+        config.models.insert(
+            "glm-vision".to_string(),
+            ModelEntry {
+                provider: "glm".to_string(),
+                id: "glm-4.7".to_string(),
+                context_window: None,
+                max_output_tokens: None,
+                vision: Some(true),
+                tools: None,
+                price: None,
+                thinking_budget: None,
+                reasoning_effort: None,
+            },
+        );
+
+        let info = lookup_model("local", &config);
+        assert_eq!(info.provider, "ollama");
+        assert_eq!(info.api_id, "qwen2.5:14b");
+        assert_eq!(info.context_window, Some(32_768));
+        assert!(!info.vision);
+
+        // Addressing by provider/id finds the same entry
+        assert_eq!(lookup_model("ollama/qwen2.5:14b", &config), info);
+
+        let info = lookup_model("glm-vision", &config);
+        assert!(info.vision);
+        assert_eq!(info.context_window, Some(200_000));
+        assert_eq!(info.thinking_budget, None);
+    }
+}
@@ -0,0 +1,588 @@
+//! Allow/ask/deny rules for tool calls, matched on their arguments
+//!
+//! `tools.rules` are matched against the call's subject: the path for file
+//! tools, the command for bash, the URL host for web_fetch (other tools only
+//! match rules without a pattern). Deny rules always win; otherwise the first
+//! matching rule decides, with patterns the user chose to always allow
+//! checked first. Calls no rule matches fall back to `tools.require_approval`.
+//! Each part of a chained bash command is checked on its own and the
+//! strictest outcome applies.
+
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+use anyhow::Result;
+use serde_json::Value;
+use tracing::warn;
+
+use super::providers::ToolCall;
+use crate::config::{ToolAction, ToolRule, ToolsConfig};
+
+const ALLOWED_RULES_FILENAME: &str = "tool_permissions.json";
+
+/// Ordered from least to most strict
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Permission {
+    Allow,
+    Ask,
+    Deny,
+}
+
+/// Outcome of checking a call against the rules
+#[derive(Debug, Clone, PartialEq)]
+pub struct PermissionCheck {
+    pub permission: Permission,
+    /// Rule that decided it, for the audit log (None: no rule matched)
+    pub rule: Option<String>,
+}
+
+/// What rules are matched on: one entry, or one per part of a chained
+/// bash command
+pub fn call_subjects(name: &str, arguments: &str) -> Vec<String> {
+    let args: Value = serde_json::from_str(arguments).unwrap_or(Value::Null);
+    let field = |keys: &[&str]| {
+        keys.iter()
+            .find_map(|k| args.get(*k).and_then(|v| v.as_str()))
+            .map(|s| s.to_string())
+    };
+
+    match name {
+        "read_file" | "write_file" | "edit_file" => field(&["path", "file_path"])
+            .map(|p| vec![resolve_path(&p)])
+            .unwrap_or_default(),
+        "bash" => field(&["command"])
+            .map(|c| split_command(&c))
+            .unwrap_or_default(),
+        "web_fetch" => field(&["url"])
+            .and_then(|u| reqwest::Url::parse(&u).ok())
+            .and_then(|u| u.host_str().map(|h| h.to_string()))
+            .into_iter()
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// Split a shell command on `&&`, `||`, `;`, `|`, `&` and newlines
+/// (redirections like `2>&1` stay in one part)
+fn split_command(command: &str) -> Vec<String> {
+    let chars: Vec<char> = command.chars().collect();
+    let mut parts = Vec::new();
+    let mut current = String::new();
+    for (i, &c) in chars.iter().enumerate() {
+        let redirect = c == '&'
+            && (i > 0 && matches!(chars[i - 1], '>' | '<') || chars.get(i + 1) == Some(&'>'));
+        if matches!(c, '\n' | ';' | '|' | '&') && !redirect {
+            parts.push(std::mem::take(&mut current));
+        } else {
+            current.push(c);
+        }
+    }
+    parts.push(current);
+    parts
+        .iter()
+        .map(|s| s.trim())
+        .filter(|s| !s.is_empty())
+        .map(|s| s.to_string())
+        .collect()
+}
+
+/// Absolute, normalized form of a file tool's path, as the tool would
+/// open it: `~` expanded, relative to the current directory, `.` and `..`
+/// resolved, and symlinks followed as far as the path exists
+fn resolve_path(path: &str) -> String {
+    let expanded = PathBuf::from(shellexpand::tilde(path).to_string());
+    let absolute = std::path::absolute(&expanded).unwrap_or(expanded);
+
+    let mut normalized = PathBuf::new();
+    for component in absolute.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            other => normalized.push(other),
+        }
+    }
+
+    // Canonicalize the longest part that exists and keep the rest as is
+    let mut existing = normalized.as_path();
+    let mut rest = Vec::new();
+    while !existing.exists() {
+        match (existing.parent(), existing.file_name()) {
+            (Some(parent), Some(name)) => {
+                rest.push(name);
+                existing = parent;
+            }
+            _ => break,
+        }
+    }
+    let mut resolved = fs::canonicalize(existing).unwrap_or_else(|_| existing.to_path_buf());
+    resolved.extend(rest.iter().rev());
+    resolved.to_string_lossy().to_string()
+}
+
+/// Path patterns are resolved like paths up to their first wildcard, so
+/// both sides agree on symlinks such as /tmp -> /private/tmp
+fn resolve_pattern(pattern: &str) -> String {
+    if !(pattern.starts_with('/') || pattern.starts_with('~')) {
+        return pattern.to_string();
+    }
+    let expanded = shellexpand::tilde(pattern).to_string();
+    let literal = match expanded.find(['*', '?', '[']) {
+        Some(i) => expanded[..i].rfind('/').map_or(0, |slash| slash + 1),
+        None => expanded.len(),
+    };
+    let (prefix, glob) = expanded.split_at(literal);
+    if prefix.is_empty() {
+        return expanded;
+    }
+    let resolved = glob::Pattern::escape(&resolve_path(prefix.trim_end_matches('/')));
+    if glob.is_empty() {
+        resolved
+    } else {
+        format!("{}/{}", resolved.trim_end_matches('/'), glob)
+    }
+}
+
+/// Whether a pattern matches one subject
+fn pattern_matches(pattern: &str, subject: &str, is_path: bool) -> bool {
+    if let Some(re) = pattern.strip_prefix("re:") {
+        return match regex::Regex::new(re) {
+            Ok(re) => re.is_match(subject),
+            Err(e) => {
+                warn!("Invalid tool rule regex {:?}: {}", re, e);
+                false
+            }
+        };
+    }
+    let pattern = if is_path {
+        resolve_pattern(pattern)
+    } else {
+        shellexpand::tilde(pattern).to_string()
+    };
+    // In absolute path patterns `*` stays within one directory; `**` recurses
+    let options = glob::MatchOptions {
+        require_literal_separator: is_path && pattern.starts_with('/'),
+        ..Default::default()
+    };
+    match glob::Pattern::new(&pattern) {
+        Ok(glob) => glob.matches_with(subject, options),
+        Err(e) => {
+            warn!("Invalid tool rule pattern {:?}: {}", pattern, e);
+            false
+        }
+    }
+}
+
+/// Whether a bash command does more than a pattern can vouch for: writes or
+/// reads files through redirection, chains commands or substitutes output.
+/// Duplicating a descriptor (`2>&1`) is fine.
+fn has_shell_side_effects(command: &str) -> bool {
+    let fd_dup = regex::Regex::new(r"\d*[<>]&\d+").expect("valid regex");
+    let command = fd_dup.replace_all(command, "");
+    ["$(", "`", ">", "<", ";", "&&", "||", "|", "&", "\n"]
+        .iter()
+        .any(|s| command.contains(s))
+}
+
+/// Allow patterns never match bash commands with redirection, chaining or
+/// substitution (`git status*` must not allow `git status > ~/.bashrc`)
+fn rule_matches(rule: &ToolRule, name: &str, subject: Option<&str>) -> bool {
+    if rule.tool != "*" && rule.tool != name {
+        return false;
+    }
+    let Some(pattern) = &rule.pattern else {
+        return true;
+    };
+    let Some(subject) = subject else {
+        return false;
+    };
+    if rule.action == ToolAction::Allow && name == "bash" && has_shell_side_effects(subject) {
+        return false;
+    }
+    let is_path = matches!(name, "read_file" | "write_file" | "edit_file");
+    pattern_matches(pattern, subject, is_path)
+}
+
+fn describe(rule: &ToolRule) -> String {
+    match &rule.pattern {
+        Some(pattern) => format!("{} {} `{}`", rule.action.as_str(), rule.tool, pattern),
+        None => format!("{} {}", rule.action.as_str(), rule.tool),
+    }
+}
+
+/// Check a call against configured rules and patterns the user always allows
+pub fn evaluate(config: &ToolsConfig, allowed: &[ToolRule], call: &ToolCall) -> PermissionCheck {
+    let subjects = call_subjects(&call.name, &call.arguments);
+    if subjects.is_empty() {
+        return evaluate_subject(config, allowed, &call.name, None);
+    }
+    subjects
+        .iter()
+        .map(|s| evaluate_subject(config, allowed, &call.name, Some(s)))
+        .max_by_key(|check| check.permission)
+        .expect("subjects is not empty")
+}
+
+fn evaluate_subject(
+    config: &ToolsConfig,
+    allowed: &[ToolRule],
+    name: &str,
+    subject: Option<&str>,
+) -> PermissionCheck {
+    let matches = |rule: &&ToolRule| rule_matches(rule, name, subject);
+
+    let deny = config
+        .rules
+        .iter()
+        .filter(|r| r.action == ToolAction::Deny)
+        .find(matches);
+    let decided = deny.or_else(|| {
+        allowed
+            .iter()
+            .filter(|r| r.action == ToolAction::Allow)
+            .chain(config.rules.iter().filter(|r| r.action != ToolAction::Deny))
+            .find(matches)
+    });
+
+    match decided {
+        Some(rule) => PermissionCheck {
+            permission: match rule.action {
+                ToolAction::Allow => Permission::Allow,
+                ToolAction::Ask => Permission::Ask,
+                ToolAction::Deny => Permission::Deny,
+            },
+            rule: Some(describe(rule)),
+        },
+        None => PermissionCheck {
+            permission: if config.require_approval.iter().any(|t| t == name) {
+                Permission::Ask
+            } else {
+                Permission::Allow
+            },
+            rule: None,
+        },
+    }
+}
+
+/// Pattern offered for "always allow": the exact command for bash (a
+/// prefix would let `rm -rf build` allow `rm -rf ~`), the directory for file
+/// tools, the host for web_fetch. None allows every call to the tool.
+pub fn suggest_pattern(name: &str, arguments: &str) -> Option<String> {
+    let subjects = call_subjects(name, arguments);
+    match name {
+        // Only offered for a single command; a chain would need every part
+        "bash" if subjects.len() == 1 => Some(glob::Pattern::escape(&subjects[0])),
+        "read_file" | "write_file" | "edit_file" => subjects.first().and_then(|p| {
+            Path::new(p)
+                .parent()
+                .filter(|d| !d.as_os_str().is_empty())
+                .map(|d| format!("{}/*", glob::Pattern::escape(&d.to_string_lossy())))
+        }),
+        "web_fetch" => subjects.first().map(|h| glob::Pattern::escape(h)),
+        _ => None,
+    }
+}
+
+pub fn allowed_rules_path(state_dir: &Path) -> PathBuf {
+    state_dir.join(ALLOWED_RULES_FILENAME)
+}
+
+/// Patterns the user chose to always allow
+pub fn load_allowed_rules(state_dir: &Path) -> Vec<ToolRule> {
+    fs::read_to_string(allowed_rules_path(state_dir))
+        .ok()
+        .and_then(|content| serde_json::from_str(&content).ok())
+        .unwrap_or_default()
+}
+
+/// Persist an "always allow" choice
+pub fn save_allowed_rule(state_dir: &Path, rule: ToolRule) -> Result<()> {
+    let mut rules = load_allowed_rules(state_dir);
+    if rules.contains(&rule) {
+        return Ok(());
+    }
+    rules.push(rule);
+    fs::create_dir_all(state_dir)?;
+    let path = allowed_rules_path(state_dir);
+    let tmp_path = path.with_extension(format!("{}.tmp", std::process::id()));
+    fs::write(&tmp_path, serde_json::to_string_pretty(&rules)?)?;
+    fs::rename(&tmp_path, &path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(tool: &str, pattern: &str, action: &str) -> ToolRule {
+        ToolRule {
+            tool: tool.to_string(),
+            pattern: Some(pattern.to_string()),
+            action: match action {
+                "allow" => ToolAction::Allow,
+                "ask" => ToolAction::Ask,
+                _ => ToolAction::Deny,
+            },
+        }
+    }
+
+    fn call(name: &str, arguments: Value) -> ToolCall {
+        ToolCall {
+            id: "call_1".to_string(),
+            name: name.to_string(),
+            arguments: arguments.to_string(),
+        }
+    }
+
+    fn bash(command: &str) -> ToolCall {
+        call("bash", serde_json::json!({ "command": command }))
+    }
+
+    fn permission(config: &ToolsConfig, allowed: &[ToolRule], call: &ToolCall) -> Permission {
+        evaluate(config, allowed, call).permission
+    }
+
+    #[test]
+    fn test_bash_rules() {
+        let config = ToolsConfig {
+            require_approval: vec!["bash".to_string()],
+            rules: vec![
+                rule("bash", "git status*", "allow"),
+                rule("bash", "curl *", "ask"),
+                rule("bash", "sudo *", "deny"),
+                rule("bash", "re:^ls( |$)", "allow"),
+            ],
+            ..Default::default()
+        };
+
+        assert_eq!(
+            permission(&config, &[], &bash("git status -s")),
+            Permission::Allow
+        );
+        assert_eq!(permission(&config, &[], &bash("ls -la")), Permission::Allow);
+        assert_eq!(
+            permission(&config, &[], &bash("curl https://x.io")),
+            Permission::Ask
+        );
+        assert_eq!(
+            permission(&config, &[], &bash("sudo rm -rf /")),
+            Permission::Deny
+        );
+        // No rule: falls back to require_approval
+        assert_eq!(permission(&config, &[], &bash("make")), Permission::Ask);
+
+        // Chains are allowed only if every part is, and denied if any part is
+        assert_eq!(
+            permission(&config, &[], &bash("git status && ls")),
+            Permission::Allow
+        );
+        assert_eq!(
+            permission(&config, &[], &bash("git status 2>&1")),
+            Permission::Allow
+        );
+        assert_eq!(
+            permission(&config, &[], &bash("ls & sudo reboot")),
+            Permission::Deny
+        );
+        assert_eq!(
+            permission(&config, &[], &bash("git status; rm -rf ~")),
+            Permission::Ask
+        );
+        assert_eq!(
+            permission(&config, &[], &bash("ls | sudo tee /etc/x")),
+            Permission::Deny
+        );
+        assert_eq!(
+            permission(&config, &[], &bash("git status $(rm -rf ~)")),
+            Permission::Ask
+        );
+        // Redirection can write anywhere, whatever the command
+        assert_eq!(
+            permission(&config, &[], &bash("git status > ~/.bashrc")),
+            Permission::Ask
+        );
+        assert_eq!(
+            permission(&config, &[], &bash("ls &> ~/.profile")),
+            Permission::Ask
+        );
+    }
+
+    #[test]
+    fn test_path_and_host_rules() {
+        let config = ToolsConfig {
+            rules: vec![
+                rule("write_file", "/tmp/scratch/*", "allow"),
+                rule("write_file", "*", "ask"),
+                rule("web_fetch", "*.github.com", "allow"),
+                rule("web_fetch", "*", "deny"),
+            ],
+            ..Default::default()
+        };
+        let write = |path: &str| call("write_file", serde_json::json!({ "path": path }));
+        let fetch = |url: &str| call("web_fetch", serde_json::json!({ "url": url }));
+
+        assert_eq!(
+            permission(&config, &[], &write("/tmp/scratch/a.txt")),
+            Permission::Allow
+        );
+        assert_eq!(
+            permission(&config, &[], &write("/etc/passwd")),
+            Permission::Ask
+        );
+        assert_eq!(
+            permission(&config, &[], &write("/tmp/scratch/sub/a.txt")),
+            Permission::Ask
+        );
+        // Deny wins over an earlier allow
+        assert_eq!(
+            permission(&config, &[], &fetch("https://api.github.com/repos")),
+            Permission::Deny
+        );
+        let config = ToolsConfig {
+            rules: vec![rule("web_fetch", "*.github.com", "allow")],
+            require_approval: vec!["web_fetch".to_string()],
+            ..Default::default()
+        };
+        assert_eq!(
+            permission(&config, &[], &fetch("https://api.github.com/repos")),
+            Permission::Allow
+        );
+        assert_eq!(
+            permission(&config, &[], &fetch("https://example.com")),
+            Permission::Ask
+        );
+    }
+
+    #[test]
+    fn test_paths_are_resolved_before_matching() {
+        let config = ToolsConfig {
+            rules: vec![
+                rule("write_file", "~/.ssh/*", "deny"),
+                rule("write_file", "*", "allow"),
+            ],
+            ..Default::default()
+        };
+        let write = |path: &str| call("write_file", serde_json::json!({ "path": path }));
+
+        assert_eq!(
+            permission(&config, &[], &write("~/.ssh/authorized_keys")),
+            Permission::Deny
+        );
+        assert_eq!(
+            permission(&config, &[], &write("~/notes/../.ssh/authorized_keys")),
+            Permission::Deny
+        );
+        assert_eq!(
+            permission(&config, &[], &write("~/./.ssh/./config")),
+            Permission::Deny
+        );
+
+        // Relative paths are relative to the current directory, as in the tools
+        let cwd = std::env::current_dir().unwrap();
+        let config = ToolsConfig {
+            rules: vec![
+                rule(
+                    "write_file",
+                    &format!("{}/secrets/*", cwd.display()),
+                    "deny",
+                ),
+                rule("write_file", "*", "allow"),
+            ],
+            ..Default::default()
+        };
+        assert_eq!(
+            permission(&config, &[], &write("secrets/key")),
+            Permission::Deny
+        );
+        assert_eq!(
+            permission(&config, &[], &write("src/../secrets/key")),
+            Permission::Deny
+        );
+        assert_eq!(
+            permission(&config, &[], &write("src/key")),
+            Permission::Allow
+        );
+    }
+
+    #[test]
+    fn test_always_allowed_patterns() {
+        let tmp = tempfile::tempdir().unwrap();
+        let config = ToolsConfig {
+            require_approval: vec!["bash".to_string()],
+            rules: vec![rule("bash", "git push --force*", "deny")],
+            ..Default::default()
+        };
+
+        let push = bash("git push origin main");
+        let pattern = suggest_pattern("bash", &push.arguments).unwrap();
+        assert_eq!(pattern, "git push origin main");
+        assert_eq!(permission(&config, &[], &push), Permission::Ask);
+
+        save_allowed_rule(tmp.path(), rule("bash", &pattern, "allow")).unwrap();
+        save_allowed_rule(tmp.path(), rule("bash", &pattern, "allow")).unwrap();
+        let allowed = load_allowed_rules(tmp.path());
+        assert_eq!(allowed.len(), 1);
+        assert_eq!(permission(&config, &allowed, &push), Permission::Allow);
+        // Configured deny rules still win
+        assert_eq!(
+            permission(&config, &allowed, &bash("git push --force")),
+            Permission::Deny
+        );
+
+        // Allowing one rm doesn't allow others
+        let rm = suggest_pattern("bash", &bash("rm -rf build/*").arguments).unwrap();
+        let allowed = vec![rule("bash", &rm, "allow")];
+        assert_eq!(
+            permission(&config, &allowed, &bash("rm -rf build/*")),
+            Permission::Allow
+        );
+        assert_eq!(
+            permission(&config, &allowed, &bash("rm -rf build/ ~")),
+            Permission::Ask
+        );
+        assert_eq!(
+            permission(&config, &allowed, &bash("rmdir build")),
+            Permission::Ask
+        );
+
+        assert_eq!(
+            suggest_pattern("write_file", r#"{"path": "/srv/app/main.rs"}"#).as_deref(),
+            Some("/srv/app/*")
+        );
+        assert_eq!(
+            suggest_pattern("web_fetch", r#"{"url": "https://docs.rs/tokio"}"#).as_deref(),
+            Some("docs.rs")
+        );
+
+        // Wildcards in the directory name are taken literally
+        let bracketed = |file: &str| {
+            let path = tmp.path().join("[ab]").join(file);
+            call("write_file", serde_json::json!({ "path": path }))
+        };
+        let dir = suggest_pattern("write_file", &bracketed("x.txt").arguments).unwrap();
+        assert!(dir.ends_with("/[[]ab[]]/*"));
+        let allowed = vec![rule("write_file", &dir, "allow")];
+        let config = ToolsConfig {
+            require_approval: vec!["write_file".to_string()],
+            ..Default::default()
+        };
+        assert_eq!(
+            permission(&config, &allowed, &bracketed("x.txt")),
+            Permission::Allow
+        );
+        let sibling = tmp.path().join("a").join("x.txt");
+        assert_eq!(
+            permission(
+                &config,
+                &allowed,
+                &call("write_file", serde_json::json!({ "path": sibling }))
+            ),
+            Permission::Ask
+        );
+        assert_eq!(
+            permission(&config, &allowed, &bracketed("sub/x.txt")),
+            Permission::Ask
+        );
+    }
+}
@@ -5,13 +5,19 @@ use futures::StreamExt;
 use reqwest::Client;
 use serde::{Deserialize, Serialize};
 use serde_json::{Value, json};
+use std::collections::HashMap;
+use std::path::PathBuf;
 use std::pin::Pin;
 use std::process::Stdio;
+use std::sync::Arc;
 use std::sync::Mutex as StdMutex;
 use tokio::io::{AsyncBufReadExt, BufReader};
-use tracing::{debug, info};
+use tracing::{debug, info, warn};
 
-use crate::config::Config;
+use super::models::lookup_model;
+use super::replay::{ReplayProvider, cassette_path};
+use super::retry::{RetryPolicy, send_with_retry};
+use crate::config::{Config, CustomProviderConfig};
 
 /// Image attachment for multimodal messages
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -33,6 +39,38 @@ pub struct Message {
     /// Optional image attachments (for multimodal messages)
     #[serde(default, skip_serializing_if = "Vec::is_empty")]
     pub images: Vec<ImageAttachment>,
+    /// Reasoning the model produced before this (assistant) message
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub thinking: Vec<ThinkingBlock>,
+}
+
+/// A block of model reasoning (extended thinking)
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ThinkingBlock {
+    /// Reasoning text (empty for redacted blocks)
+    pub text: String,
+    /// Anthropic signature; signed blocks must be sent back unchanged
+    /// with the tool results that follow them
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signature: Option<String>,
+    /// Encrypted reasoning the provider chose not to show
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub redacted: Option<String>,
+}
+
+impl ThinkingBlock {
+    /// Unsigned reasoning text (Ollama, OpenAI-compatible servers)
+    pub fn text(text: impl Into<String>) -> Self {
+        Self {
+            text: text.into(),
+            ..Default::default()
+        }
+    }
+
+    /// Whether the provider needs this block back in later requests
+    pub fn is_signed(&self) -> bool {
+        self.signature.is_some() || self.redacted.is_some()
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
@@ -61,19 +99,46 @@ pub struct ToolSchema {
 /// Token usage statistics from API response
 #[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct Usage {
+    /// Uncached prompt tokens
     pub input_tokens: u64,
     pub output_tokens: u64,
+    /// Prompt tokens read from the provider's prompt cache
+    #[serde(default)]
+    pub cache_read_tokens: u64,
+    /// Prompt tokens written to the provider's prompt cache
+    #[serde(default)]
+    pub cache_write_tokens: u64,
 }
 
 impl Usage {
     pub fn total(&self) -> u64 {
-        self.input_tokens + self.output_tokens
+        self.prompt_tokens() + self.output_tokens
+    }
+
+    /// All prompt tokens, cached or not
+    pub fn prompt_tokens(&self) -> u64 {
+        self.input_tokens + self.cache_read_tokens + self.cache_write_tokens
+    }
+
+    /// Fraction of prompt tokens served from cache (None if nothing was sent)
+    pub fn cache_hit_rate(&self) -> Option<f64> {
+        let prompt = self.prompt_tokens();
+        (prompt > 0).then(|| self.cache_read_tokens as f64 / prompt as f64)
+    }
+
+    pub fn add(&mut self, other: &Usage) {
+        self.input_tokens += other.input_tokens;
+        self.output_tokens += other.output_tokens;
+        self.cache_read_tokens += other.cache_read_tokens;
+        self.cache_write_tokens += other.cache_write_tokens;
     }
 }
 
 pub struct LLMResponse {
     pub content: LLMResponseContent,
     pub usage: Option<Usage>,
+    /// Reasoning that preceded the content
+    pub thinking: Vec<ThinkingBlock>,
 }
 
 pub enum LLMResponseContent {
@@ -86,6 +151,7 @@ impl LLMResponse {
         Self {
             content: LLMResponseContent::Text(content),
             usage: None,
+            thinking: Vec::new(),
         }
     }
 
@@ -93,6 +159,7 @@ impl LLMResponse {
         Self {
             content: LLMResponseContent::Text(content),
             usage: Some(usage),
+            thinking: Vec::new(),
         }
     }
 
@@ -100,6 +167,7 @@ impl LLMResponse {
         Self {
             content: LLMResponseContent::ToolCalls(calls),
             usage: None,
+            thinking: Vec::new(),
         }
     }
 
@@ -107,8 +175,18 @@ impl LLMResponse {
         Self {
             content: LLMResponseContent::ToolCalls(calls),
             usage: Some(usage),
+            thinking: Vec::new(),
         }
     }
+
+    /// Attach reasoning blocks, dropping empty ones
+    pub fn with_thinking(mut self, thinking: Vec<ThinkingBlock>) -> Self {
+        self.thinking = thinking
+            .into_iter()
+            .filter(|b| !b.text.is_empty() || b.is_signed())
+            .collect();
+        self
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -117,6 +195,8 @@ pub struct StreamChunk {
     pub done: bool,
     /// Tool calls accumulated during streaming (only set when done=true)
     pub tool_calls: Option<Vec<ToolCall>>,
+    /// Reasoning text delta, shown separately from the answer
+    pub thinking: String,
 }
 
 /// Events emitted during streaming with tools
@@ -124,12 +204,23 @@ pub struct StreamChunk {
 pub enum StreamEvent {
     /// Text content chunk
     Content(String),
+    /// Model reasoning, shown separately from the answer
+    Thinking(String),
     /// Tool call started
     ToolCallStart {
         name: String,
         id: String,
         arguments: String,
     },
+    /// Tool call waiting for approval; answer with `ApprovalBroker::resolve`
+    ToolApprovalRequired {
+        request_id: String,
+        name: String,
+        id: String,
+        arguments: String,
+        /// Pattern offered for "always allow" (None: the whole tool)
+        pattern: Option<String>,
+    },
     /// Tool call completed
     ToolCallEnd {
         name: String,
@@ -137,10 +228,29 @@ pub enum StreamEvent {
         output: String,
         warnings: Vec<String>,
     },
+    /// Memory auto-recalled for the turn (`file:start-end` of each chunk)
+    MemoryRecalled { citations: Vec<String> },
+    /// Primary model failed and the fallback chain moved on to the next one
+    Fallback {
+        from: String,
+        to: String,
+        reason: String,
+    },
     /// Stream completed
     Done,
 }
 
+/// Record of a fallback chain skipping a failing model
+#[derive(Debug, Clone)]
+pub struct FallbackNotice {
+    /// Model that failed ("provider/model")
+    pub from: String,
+    /// Model tried next ("provider/model")
+    pub to: String,
+    /// Error returned by the failing model
+    pub reason: String,
+}
+
 pub type StreamResult = Pin<Box<dyn Stream<Item = Result<StreamChunk>> + Send>>;
 
 #[async_trait]
@@ -155,6 +265,29 @@ pub trait LLMProvider: Send + Sync {
     /// Default: no-op (most providers are stateless).
     fn reset_session(&self) {}
 
+    /// Provider and model that served the most recent request.
+    /// Only wrapping providers (e.g., fallback chains) know this; others return None.
+    fn served_by(&self) -> Option<(String, String)> {
+        None
+    }
+
+    /// Drain fallback notices raised since the last call.
+    fn take_fallback_notices(&self) -> Vec<FallbackNotice> {
+        Vec::new()
+    }
+
+    /// Take usage reported by the most recently finished stream.
+    /// Stream chunks carry no usage, so providers that get it stash it here.
+    fn take_stream_usage(&self) -> Option<Usage> {
+        None
+    }
+
+    /// Take the thinking blocks of the most recently finished stream, with
+    /// signatures intact (chunks only carry the reasoning text).
+    fn take_stream_thinking(&self) -> Vec<ThinkingBlock> {
+        Vec::new()
+    }
+
     /// Stream chat response (default: falls back to non-streaming)
     async fn chat_stream(
         &self,
@@ -163,12 +296,14 @@ pub trait LLMProvider: Send + Sync {
     ) -> Result<StreamResult> {
         // Default implementation: single chunk with full response
         let resp = self.chat(messages, tools).await?;
+        let thinking = thinking_text(&resp.thinking);
         match resp.content {
             LLMResponseContent::Text(text) => Ok(Box::pin(futures::stream::once(async move {
                 Ok(StreamChunk {
                     delta: text,
                     done: true,
                     tool_calls: None,
+                    thinking,
                 })
             }))),
             LLMResponseContent::ToolCalls(calls) => {
@@ -177,6 +312,7 @@ pub trait LLMProvider: Send + Sync {
                         delta: String::new(),
                         done: true,
                         tool_calls: Some(calls),
+                        thinking,
                     })
                 })))
             }
@@ -184,6 +320,95 @@ pub trait LLMProvider: Send + Sync {
     }
 }
 
+/// Readable text of a response's thinking blocks
+pub fn thinking_text(blocks: &[ThinkingBlock]) -> String {
+    blocks
+        .iter()
+        .map(|b| b.text.as_str())
+        .filter(|t| !t.is_empty())
+        .collect::<Vec<_>>()
+        .join("\n\n")
+}
+
+/// Split `<think>...</think>` reasoning out of model output (DeepSeek-R1,
+/// Qwen3 and other models that inline their reasoning).
+/// Returns (thinking, content).
+pub fn split_think_tags(text: &str) -> (String, String) {
+    let mut splitter = ThinkTagSplitter::default();
+    let (mut thinking, mut content) = splitter.push(text);
+    let (t, c) = splitter.finish();
+    thinking.push_str(&t);
+    content.push_str(&c);
+    if thinking.trim().is_empty() {
+        return (String::new(), content);
+    }
+    (
+        thinking.trim().to_string(),
+        content.trim_start().to_string(),
+    )
+}
+
+/// Incremental `<think>` splitter for streamed output, where a tag can be
+/// cut across chunks
+#[derive(Debug, Default)]
+pub struct ThinkTagSplitter {
+    in_think: bool,
+    /// Text held back because it may be the start of a tag
+    pending: String,
+}
+
+impl ThinkTagSplitter {
+    const OPEN: &'static str = "<think>";
+    const CLOSE: &'static str = "</think>";
+
+    /// Feed a chunk; returns the (thinking, content) text it completes
+    pub fn push(&mut self, chunk: &str) -> (String, String) {
+        let mut thinking = String::new();
+        let mut content = String::new();
+        self.pending.push_str(chunk);
+
+        loop {
+            let tag = if self.in_think {
+                Self::CLOSE
+            } else {
+                Self::OPEN
+            };
+            let out = if self.in_think {
+                &mut thinking
+            } else {
+                &mut content
+            };
+
+            if let Some(pos) = self.pending.find(tag) {
+                out.push_str(&self.pending[..pos]);
+                self.pending.drain(..pos + tag.len());
+                self.in_think = !self.in_think;
+                continue;
+            }
+
+            // Hold back a suffix that could still grow into the tag
+            let keep = (1..tag.len())
+                .rev()
+                .find(|&n| self.pending.ends_with(&tag[..n]))
+                .unwrap_or(0);
+            let emit = self.pending.len() - keep;
+            out.push_str(&self.pending[..emit]);
+            self.pending.drain(..emit);
+            return (thinking, content);
+        }
+    }
+
+    /// Flush held-back text at the end of the stream
+    pub fn finish(&mut self) -> (String, String) {
+        let rest = std::mem::take(&mut self.pending);
+        if self.in_think {
+            (rest, String::new())
+        } else {
+            (String::new(), rest)
+        }
+    }
+}
+
 /// Resolve model alias to provider/model format (OpenClaw-compatible)
 fn resolve_model_alias(model: &str) -> String {
     // OpenClaw-compatible aliases
@@ -191,6 +416,7 @@ fn resolve_model_alias(model: &str) -> String {
         // Short aliases → latest 4.5 models
         "opus" => "anthropic/claude-opus-4-5".to_string(),
         "sonnet" => "anthropic/claude-sonnet-4-5".to_string(),
+        "haiku" => "anthropic/claude-haiku-4-5".to_string(),
         "gpt" => "openai/gpt-4o".to_string(),
         "gpt-mini" => "openai/gpt-4o-mini".to_string(),
         "glm" => "glm/glm-4.7".to_string(),
@@ -198,33 +424,39 @@ fn resolve_model_alias(model: &str) -> String {
     }
 }
 
-/// Map OpenClaw model ID to actual API model ID
-/// See: https://docs.anthropic.com/en/docs/about-claude/models
-fn normalize_model_id(provider: &str, model_id: &str) -> String {
-    match provider {
-        "anthropic" => {
-            match model_id.to_lowercase().as_str() {
-                // Claude 4.5 models only
-                "claude-opus-4-5" | "opus" => "claude-opus-4-5-20251101".to_string(),
-                "claude-sonnet-4-5" | "sonnet" => "claude-sonnet-4-5-20250929".to_string(),
-                // Default to Opus 4.5 for any other input
-                _ => "claude-opus-4-5-20251101".to_string(),
-            }
-        }
-        _ => model_id.to_string(),
+/// Split a model spec into (provider, model_id), resolving aliases and
+/// inferring the provider from well-known prefixes.
+pub(crate) fn resolve_provider_model(model: &str, config: &Config) -> (String, String) {
+    // User-defined aliases from [models.<alias>] take precedence
+    if let Some(entry) = config.models.get(model) {
+        return (entry.provider.to_lowercase(), entry.id.clone());
     }
-}
-
-pub fn create_provider(model: &str, config: &Config) -> Result<Box<dyn LLMProvider>> {
-    let workspace = config.workspace_path();
 
     // Resolve aliases first (e.g., "opus" → "anthropic/claude-opus-4-5")
     let model = resolve_model_alias(model);
 
     // Parse provider/model format (OpenClaw-compatible)
-    let (provider, model_id) = if let Some(pos) = model.find('/') {
+    if let Some(pos) = model.find('/') {
         let (p, m) = model.split_at(pos);
-        (p.to_lowercase(), m[1..].to_string()) // Skip the '/'
+        let provider = p.to_lowercase();
+        let mut model_id = m[1..].to_string(); // Skip the '/'
+        if model_id.is_empty()
+            && let Some(default) = config
+                .providers
+                .custom_provider(&provider)
+                .and_then(|c| c.default_model)
+        {
+            model_id = default;
+        }
+        (provider, model_id)
+    } else if let Some(default) = config
+        .providers
+        .custom
+        .get(&model)
+        .and_then(|c| c.default_model.clone())
+    {
+        // Bare custom provider name → its default model
+        (model.clone(), default)
     } else if model.starts_with("gpt-") || model.starts_with("o1") {
         ("openai".to_string(), model.clone())
     } else if model.starts_with("claude-") {
@@ -240,7 +472,54 @@ pub fn create_provider(model: &str, config: &Config) -> Result<Box<dyn LLMProvid
         } else {
             ("unknown".to_string(), model.clone())
         }
-    };
+    }
+}
+
+/// Create the provider for `model`, wrapped in a [`FallbackProvider`] when
+/// `agent.fallback_models` is configured.
+pub fn create_provider(model: &str, config: &Config) -> Result<Box<dyn LLMProvider>> {
+    let primary = create_single_provider(model, config)?;
+    if config.agent.fallback_models.is_empty() {
+        return Ok(primary);
+    }
+
+    let (provider, model_id) = resolve_provider_model(model, config);
+    let mut chain = vec![FallbackEntry {
+        provider,
+        model: model_id,
+        inner: primary,
+    }];
+
+    for fallback in &config.agent.fallback_models {
+        let (provider, model_id) = resolve_provider_model(fallback, config);
+        if chain
+            .iter()
+            .any(|e| e.provider == provider && e.model == model_id)
+        {
+            continue;
+        }
+        match create_single_provider(fallback, config) {
+            Ok(inner) => chain.push(FallbackEntry {
+                provider,
+                model: model_id,
+                inner,
+            }),
+            Err(e) => warn!("Skipping fallback model {}: {}", fallback, e),
+        }
+    }
+
+    if chain.len() == 1 {
+        return Ok(chain.remove(0).inner);
+    }
+
+    Ok(Box::new(FallbackProvider::new(chain)))
+}
+
+fn create_single_provider(model: &str, config: &Config) -> Result<Box<dyn LLMProvider>> {
+    let workspace = config.workspace_path();
+    let info = lookup_model(model, config);
+    let (provider, model_id) = (info.provider.clone(), info.api_id.clone());
+    let model = resolve_model_alias(model);
 
     match provider.as_str() {
         "anthropic" => {
@@ -253,13 +532,17 @@ pub fn create_provider(model: &str, config: &Config) -> Result<Box<dyn LLMProvid
                 )
             })?;
 
-            let full_model = normalize_model_id("anthropic", &model_id);
-            Ok(Box::new(AnthropicProvider::new(
-                &anthropic_config.api_key,
-                &anthropic_config.base_url,
-                &full_model,
-                config.agent.max_tokens,
-            )?))
+            Ok(Box::new(
+                AnthropicProvider::new(
+                    &anthropic_config.api_key,
+                    &anthropic_config.base_url,
+                    &model_id,
+                    info.max_tokens(config.agent.max_tokens),
+                )?
+                .with_retry(RetryPolicy::from(&anthropic_config.retry))
+                .with_prompt_caching(anthropic_config.prompt_caching)
+                .with_thinking_budget(info.thinking_budget),
+            ))
         }
 
         "openai" => {
@@ -272,11 +555,11 @@ pub fn create_provider(model: &str, config: &Config) -> Result<Box<dyn LLMProvid
                 )
             })?;
 
-            Ok(Box::new(OpenAIProvider::new(
-                &openai_config.api_key,
-                &openai_config.base_url,
-                &model_id,
-            )?))
+            Ok(Box::new(
+                OpenAIProvider::new(&openai_config.api_key, &openai_config.base_url, &model_id)?
+                    .with_retry(RetryPolicy::from(&openai_config.retry))
+                    .with_reasoning_effort(info.reasoning_effort.clone()),
+            ))
         }
 
         "claude-cli" => {
@@ -297,30 +580,81 @@ pub fn create_provider(model: &str, config: &Config) -> Result<Box<dyn LLMProvid
                 )
             })?;
 
-            Ok(Box::new(OllamaProvider::new(
-                &ollama_config.endpoint,
-                &model_id,
+            Ok(Box::new(
+                OllamaProvider::new(&ollama_config.endpoint, &model_id)?
+                    .with_retry(RetryPolicy::from(&ollama_config.retry))
+                    .with_thinking(info.thinking_budget.is_some()),
+            ))
+        }
+
+        #[cfg(feature = "gguf")]
+        "gguf" => {
+            let gguf_config = config.providers.gguf.clone().unwrap_or_default();
+            let path = super::gguf::resolve_model_path(&model_id, &gguf_config.models_dir)?;
+            Ok(Box::new(super::gguf::GgufProvider::new(
+                &path,
+                &gguf_config,
+                info.max_tokens(config.agent.max_tokens),
             )?))
         }
 
-        "glm" => {
-            let glm_config = config.providers.glm.as_ref().ok_or_else(|| {
-                anyhow::anyhow!(
+        #[cfg(not(feature = "gguf"))]
+        "gguf" => anyhow::bail!(
+            "GGUF chat models require the 'gguf' feature. Build with --features gguf."
+        ),
+
+        "replay" => {
+            let replay_config = config.providers.replay.clone().unwrap_or_default();
+            let dir = replay_config
+                .cassette_dir
+                .map(|d| PathBuf::from(shellexpand::tilde(&d).to_string()))
+                .unwrap_or_else(|| config.paths.cassettes_dir());
+            let path = cassette_path(&model_id, &dir);
+
+            match replay_config.record.as_deref() {
+                Some(record_model) => {
+                    if record_model.starts_with("replay/") {
+                        anyhow::bail!("providers.replay.record must name a real model");
+                    }
+                    info!("Recording {} to cassette {}", record_model, path.display());
+                    let inner = create_provider(record_model, config)?;
+                    Ok(Box::new(ReplayProvider::record(
+                        &path,
+                        inner,
+                        resolve_provider_model(record_model, config),
+                    )))
+                }
+                None => Ok(Box::new(ReplayProvider::replay(&path)?)),
+            }
+        }
+
+        name => {
+            // Named OpenAI-compatible endpoints, including the GLM preset
+            if let Some(custom) = config.providers.custom_provider(name) {
+                if model_id.is_empty() {
+                    anyhow::bail!(
+                        "No model given for provider '{}'. Use {}/<model> or set \
+                        default_model in [providers.custom.{}]",
+                        name,
+                        name,
+                        name
+                    );
+                }
+                return Ok(Box::new(
+                    OpenAIProvider::from_custom(&custom, &model_id)?
+                        .with_reasoning_effort(info.reasoning_effort.clone()),
+                ));
+            }
+
+            if name == "glm" {
+                anyhow::bail!(
                     "GLM provider not configured.\n\
                     Set GLM_API_KEY env var or add to ~/.localgpt/config.toml:\n\n\
                     [providers.glm]\n\
                     api_key = \"your-glm-api-key\""
-                )
-            })?;
-
-            Ok(Box::new(OpenAIProvider::new(
-                &glm_config.api_key,
-                &glm_config.base_url,
-                &model_id,
-            )?))
-        }
+                );
+            }
 
-        _ => {
             // Fallback: try Claude CLI if configured
             if let Some(cli_config) = &config.providers.claude_cli {
                 return Ok(Box::new(ClaudeCliProvider::new(
@@ -337,7 +671,10 @@ pub fn create_provider(model: &str, config: &Config) -> Result<Box<dyn LLMProvid
                 - openai/gpt-4o, openai/gpt-4o-mini\n  \
                 - glm/glm-4.7\n  \
                 - claude-cli/opus, claude-cli/sonnet\n  \
-                - ollama/llama3, ollama/mistral\n\n\
+                - ollama/llama3, ollama/mistral\n  \
+                - gguf/<file> (in-process llama.cpp, requires gguf feature)\n  \
+                - replay/<cassette> (recorded responses, for tests)\n  \
+                - <name>/<model> for [providers.custom.<name>] endpoints\n\n\
                 Or use aliases: opus, sonnet, haiku, gpt, gpt-mini, glm",
                 provider,
                 model
@@ -346,12 +683,204 @@ pub fn create_provider(model: &str, config: &Config) -> Result<Box<dyn LLMProvid
     }
 }
 
+/// Maximum fallback notices kept between drains
+const MAX_PENDING_FALLBACK_NOTICES: usize = 32;
+
+/// Error markers that indicate a transient upstream failure
+const RETRYABLE_ERROR_MARKERS: &[&str] = &[
+    "overloaded",
+    "rate_limit",
+    "rate limit",
+    "too many requests",
+    "server_error",
+    "api_error",
+    "internal server error",
+    "bad gateway",
+    "service unavailable",
+    "gateway timeout",
+    "timed out",
+    "connection",
+    "stream error",
+];
+
+/// Whether an error is transient (5xx, overload, rate limit, network) and
+/// worth retrying against another model.
+pub(crate) fn is_retryable_error(err: &anyhow::Error) -> bool {
+    if let Some(e) = err.downcast_ref::<reqwest::Error>() {
+        if let Some(status) = e.status() {
+            return status.is_server_error() || status == reqwest::StatusCode::TOO_MANY_REQUESTS;
+        }
+        return e.is_timeout() || e.is_connect() || e.is_request() || e.is_body() || e.is_decode();
+    }
+
+    let msg = err.to_string().to_lowercase();
+    RETRYABLE_ERROR_MARKERS.iter().any(|m| msg.contains(m))
+}
+
+struct FallbackEntry {
+    provider: String,
+    model: String,
+    inner: Box<dyn LLMProvider>,
+}
+
+impl FallbackEntry {
+    fn label(&self) -> String {
+        format!("{}/{}", self.provider, self.model)
+    }
+}
+
+/// Fallback Provider - tries an ordered chain of models, moving to the next
+/// one when the current model fails with a retryable error
+pub struct FallbackProvider {
+    chain: Vec<FallbackEntry>,
+    /// Index of the entry that served the most recent request
+    served: StdMutex<usize>,
+    notices: StdMutex<Vec<FallbackNotice>>,
+}
+
+impl FallbackProvider {
+    fn new(chain: Vec<FallbackEntry>) -> Self {
+        Self {
+            chain,
+            served: StdMutex::new(0),
+            notices: StdMutex::new(Vec::new()),
+        }
+    }
+
+    /// Decide whether to move past entry `index` after `err`.
+    /// Records a notice and returns true if another entry should be tried.
+    fn should_fall_back(&self, index: usize, err: &anyhow::Error) -> bool {
+        let Some(next) = self.chain.get(index + 1) else {
+            return false;
+        };
+        if !is_retryable_error(err) {
+            return false;
+        }
+
+        let notice = FallbackNotice {
+            from: self.chain[index].label(),
+            to: next.label(),
+            reason: err.to_string(),
+        };
+        warn!(
+            "Model {} failed ({}), falling back to {}",
+            notice.from, notice.reason, notice.to
+        );
+
+        if let Ok(mut notices) = self.notices.lock() {
+            if notices.len() >= MAX_PENDING_FALLBACK_NOTICES {
+                notices.remove(0);
+            }
+            notices.push(notice);
+        }
+        true
+    }
+
+    fn mark_served(&self, index: usize) {
+        if let Ok(mut served) = self.served.lock() {
+            *served = index;
+        }
+    }
+}
+
+#[async_trait]
+impl LLMProvider for FallbackProvider {
+    async fn chat(
+        &self,
+        messages: &[Message],
+        tools: Option<&[ToolSchema]>,
+    ) -> Result<LLMResponse> {
+        let mut index = 0;
+        loop {
+            match self.chain[index].inner.chat(messages, tools).await {
+                Ok(resp) => {
+                    self.mark_served(index);
+                    return Ok(resp);
+                }
+                Err(e) if self.should_fall_back(index, &e) => index += 1,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    async fn summarize(&self, text: &str) -> Result<String> {
+        let mut index = 0;
+        loop {
+            match self.chain[index].inner.summarize(text).await {
+                Ok(summary) => {
+                    self.mark_served(index);
+                    return Ok(summary);
+                }
+                Err(e) if self.should_fall_back(index, &e) => index += 1,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    fn reset_session(&self) {
+        for entry in &self.chain {
+            entry.inner.reset_session();
+        }
+    }
+
+    fn served_by(&self) -> Option<(String, String)> {
+        let index = self.served.lock().map(|s| *s).unwrap_or(0);
+        self.chain
+            .get(index)
+            .map(|e| (e.provider.clone(), e.model.clone()))
+    }
+
+    fn take_fallback_notices(&self) -> Vec<FallbackNotice> {
+        self.notices
+            .lock()
+            .map(|mut n| std::mem::take(&mut *n))
+            .unwrap_or_default()
+    }
+
+    fn take_stream_usage(&self) -> Option<Usage> {
+        let index = self.served.lock().map(|s| *s).unwrap_or(0);
+        self.chain.get(index)?.inner.take_stream_usage()
+    }
+
+    fn take_stream_thinking(&self) -> Vec<ThinkingBlock> {
+        let index = self.served.lock().map(|s| *s).unwrap_or(0);
+        self.chain
+            .get(index)
+            .map(|entry| entry.inner.take_stream_thinking())
+            .unwrap_or_default()
+    }
+
+    /// Falls back only while opening the stream; once chunks flow, errors
+    /// are surfaced as-is since output has already been emitted.
+    async fn chat_stream(
+        &self,
+        messages: &[Message],
+        tools: Option<&[ToolSchema]>,
+    ) -> Result<StreamResult> {
+        let mut index = 0;
+        loop {
+            match self.chain[index].inner.chat_stream(messages, tools).await {
+                Ok(stream) => {
+                    self.mark_served(index);
+                    return Ok(stream);
+                }
+                Err(e) if self.should_fall_back(index, &e) => index += 1,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
 // OpenAI Provider
 pub struct OpenAIProvider {
     client: Client,
     api_key: String,
     base_url: String,
     model: String,
+    retry: RetryPolicy,
+    headers: Vec<(String, String)>,
+    /// "low" / "medium" / "high" for reasoning models
+    reasoning_effort: Option<String>,
 }
 
 impl OpenAIProvider {
@@ -359,11 +888,42 @@ impl OpenAIProvider {
         Ok(Self {
             client: Client::new(),
             api_key: api_key.to_string(),
-            base_url: base_url.to_string(),
+            base_url: base_url.trim_end_matches('/').to_string(),
             model: model.to_string(),
+            retry: RetryPolicy::default(),
+            headers: Vec::new(),
+            reasoning_effort: None,
         })
     }
 
+    /// Override the retry policy for transient HTTP failures
+    pub fn with_retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// Extra HTTP headers sent with every request
+    pub fn with_headers(mut self, headers: &HashMap<String, String>) -> Self {
+        self.headers = headers
+            .iter()
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect();
+        self
+    }
+
+    /// Reasoning effort sent to reasoning models (o-series and compatible)
+    pub fn with_reasoning_effort(mut self, effort: Option<String>) -> Self {
+        self.reasoning_effort = effort;
+        self
+    }
+
+    /// OpenAI-compatible provider for a named endpoint
+    fn from_custom(config: &CustomProviderConfig, model: &str) -> Result<Self> {
+        Ok(Self::new(&config.api_key, &config.base_url, model)?
+            .with_retry(RetryPolicy::from(&config.retry))
+            .with_headers(&config.headers))
+    }
+
     fn format_tools(&self, tools: &[ToolSchema]) -> Vec<Value> {
         tools
             .iter()
@@ -469,16 +1029,27 @@ impl LLMProvider for OpenAIProvider {
             body["tools"] = json!(self.format_tools(tools));
         }
 
+        if let Some(ref effort) = self.reasoning_effort {
+            body["reasoning_effort"] = json!(effort);
+        }
+
         debug!("OpenAI request: {}", serde_json::to_string_pretty(&body)?);
 
-        let response = self
-            .client
-            .post(format!("{}/chat/completions", self.base_url))
-            .header("Authorization", format!("Bearer {}", self.api_key))
-            .header("Content-Type", "application/json")
-            .json(&body)
-            .send()
-            .await?;
+        let response = send_with_retry(&self.retry, "OpenAI", || {
+            let mut request = self
+                .client
+                .post(format!("{}/chat/completions", self.base_url))
+                .header("Content-Type", "application/json");
+            // Local servers often run without auth
+            if !self.api_key.is_empty() {
+                request = request.header("Authorization", format!("Bearer {}", self.api_key));
+            }
+            for (name, value) in &self.headers {
+                request = request.header(name, value);
+            }
+            request.json(&body)
+        })
+        .await?;
 
         let response_body: Value = response.json().await?;
         debug!(
@@ -497,10 +1068,28 @@ impl LLMProvider for OpenAIProvider {
 
         let message = &choice["message"];
 
+        // Compatible servers (DeepSeek, vLLM) return reasoning separately;
+        // others inline it in <think> tags, split out below
+        let mut thinking: Vec<ThinkingBlock> = message["reasoning_content"]
+            .as_str()
+            .or_else(|| message["reasoning"].as_str())
+            .map(|t| vec![ThinkingBlock::text(t)])
+            .unwrap_or_default();
+
         // Parse usage
-        let usage = response_body.get("usage").map(|u| Usage {
-            input_tokens: u["prompt_tokens"].as_u64().unwrap_or(0),
-            output_tokens: u["completion_tokens"].as_u64().unwrap_or(0),
+        // prompt_tokens includes cached tokens; split them out
+        let usage = response_body.get("usage").map(|u| {
+            let prompt = u["prompt_tokens"].as_u64().unwrap_or(0);
+            let cached = u["prompt_tokens_details"]["cached_tokens"]
+                .as_u64()
+                .unwrap_or(0)
+                .min(prompt);
+            Usage {
+                input_tokens: prompt - cached,
+                output_tokens: u["completion_tokens"].as_u64().unwrap_or(0),
+                cache_read_tokens: cached,
+                cache_write_tokens: 0,
+            }
         });
 
         // Check for tool calls
@@ -523,16 +1112,21 @@ impl LLMProvider for OpenAIProvider {
                 return Ok(LLMResponse {
                     content: LLMResponseContent::ToolCalls(parsed_calls),
                     usage,
-                });
+                    thinking: Vec::new(),
+                }
+                .with_thinking(thinking));
             }
         }
 
-        let content = message["content"].as_str().unwrap_or("").to_string();
+        let (inline, content) = split_think_tags(message["content"].as_str().unwrap_or(""));
+        thinking.push(ThinkingBlock::text(inline));
 
         Ok(LLMResponse {
             content: LLMResponseContent::Text(content),
             usage,
-        })
+            thinking: Vec::new(),
+        }
+        .with_thinking(thinking))
     }
 
     async fn summarize(&self, text: &str) -> Result<String> {
@@ -545,6 +1139,7 @@ impl LLMProvider for OpenAIProvider {
             tool_calls: None,
             tool_call_id: None,
             images: Vec::new(),
+            thinking: Vec::new(),
         }];
 
         match self.chat(&messages, None).await?.content {
@@ -555,12 +1150,22 @@ impl LLMProvider for OpenAIProvider {
 }
 
 // Anthropic Provider
+#[derive(Clone)]
 pub struct AnthropicProvider {
     client: Client,
     api_key: String,
     base_url: String,
     model: String,
     max_tokens: usize,
+    retry: RetryPolicy,
+    /// Add cache_control breakpoints to the system prompt and tools
+    prompt_caching: bool,
+    /// Usage from the last streamed response (shared with the stream task)
+    stream_usage: Arc<StdMutex<Option<Usage>>>,
+    /// Extended thinking budget in tokens (None: thinking off)
+    thinking_budget: Option<u32>,
+    /// Thinking blocks from the last streamed response
+    stream_thinking: Arc<StdMutex<Vec<ThinkingBlock>>>,
 }
 
 impl AnthropicProvider {
@@ -571,11 +1176,47 @@ impl AnthropicProvider {
             base_url: base_url.to_string(),
             model: model.to_string(),
             max_tokens,
+            retry: RetryPolicy::default(),
+            prompt_caching: true,
+            stream_usage: Arc::new(StdMutex::new(None)),
+            thinking_budget: None,
+            stream_thinking: Arc::new(StdMutex::new(Vec::new())),
         })
     }
 
+    /// Enable extended thinking with a token budget
+    pub fn with_thinking_budget(mut self, budget: Option<u32>) -> Self {
+        self.thinking_budget = budget;
+        self
+    }
+
+    /// Enable or disable prompt caching breakpoints
+    pub fn with_prompt_caching(mut self, enabled: bool) -> Self {
+        self.prompt_caching = enabled;
+        self
+    }
+
+    /// Override the retry policy for transient HTTP failures
+    pub fn with_retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// POST a request body to the Messages API, retrying transient failures
+    async fn send_messages(&self, body: &Value) -> Result<reqwest::Response> {
+        send_with_retry(&self.retry, "Anthropic", || {
+            self.client
+                .post(format!("{}/v1/messages", self.base_url))
+                .header("x-api-key", &self.api_key)
+                .header("anthropic-version", "2023-06-01")
+                .header("Content-Type", "application/json")
+                .json(body)
+        })
+        .await
+    }
+
     fn format_tools(&self, tools: &[ToolSchema]) -> Vec<Value> {
-        tools
+        let mut formatted: Vec<Value> = tools
             .iter()
             .map(|t| {
                 json!({
@@ -584,6 +1225,76 @@ impl AnthropicProvider {
                     "input_schema": t.parameters
                 })
             })
+            .collect();
+
+        // A breakpoint on the last tool caches the whole tool list
+        if self.prompt_caching
+            && let Some(last) = formatted.last_mut()
+        {
+            last["cache_control"] = json!({"type": "ephemeral"});
+        }
+        formatted
+    }
+
+    /// System prompt, as a cacheable text block when prompt caching is on.
+    /// Tools come before the system prompt in the cache prefix, so this
+    /// breakpoint covers both.
+    fn format_system(&self, system: String) -> Value {
+        if self.prompt_caching {
+            json!([{
+                "type": "text",
+                "text": system,
+                "cache_control": {"type": "ephemeral"}
+            }])
+        } else {
+            json!(system)
+        }
+    }
+
+    /// Enable extended thinking on a request body. The budget counts
+    /// toward max_tokens, so the answer keeps its full allowance on top.
+    fn apply_thinking(&self, body: &mut Value) {
+        if let Some(budget) = self.thinking_budget {
+            // The API rejects budgets below 1024
+            let budget = budget.max(1024);
+            body["thinking"] = json!({"type": "enabled", "budget_tokens": budget});
+            body["max_tokens"] = json!(self.max_tokens + budget as usize);
+        }
+    }
+
+    /// Thinking blocks, in the form the API expects them back
+    fn format_thinking(thinking: &[ThinkingBlock]) -> Vec<Value> {
+        thinking
+            .iter()
+            .filter_map(|b| {
+                if let Some(ref data) = b.redacted {
+                    Some(json!({"type": "redacted_thinking", "data": data}))
+                } else {
+                    // Unsigned reasoning from other providers can't be replayed
+                    b.signature.as_ref().map(|signature| {
+                        json!({"type": "thinking", "thinking": b.text, "signature": signature})
+                    })
+                }
+            })
+            .collect()
+    }
+
+    /// Thinking and redacted_thinking blocks of a response
+    fn parse_thinking(content: &[Value]) -> Vec<ThinkingBlock> {
+        content
+            .iter()
+            .filter_map(|c| match c["type"].as_str() {
+                Some("thinking") => Some(ThinkingBlock {
+                    text: c["thinking"].as_str().unwrap_or("").to_string(),
+                    signature: c["signature"].as_str().map(|s| s.to_string()),
+                    redacted: None,
+                }),
+                Some("redacted_thinking") => Some(ThinkingBlock {
+                    redacted: c["data"].as_str().map(|s| s.to_string()),
+                    ..Default::default()
+                }),
+                _ => None,
+            })
             .collect()
     }
 
@@ -634,18 +1345,34 @@ impl AnthropicProvider {
                     }
                 }
                 Role::Assistant => {
+                    // Signed thinking must precede the tool_use it led to
+                    let thinking = Self::format_thinking(&m.thinking);
                     if let Some(ref tool_calls) = m.tool_calls {
-                        let tool_use: Vec<Value> = tool_calls.iter().map(|tc| {
+                        let tool_use = tool_calls.iter().map(|tc| {
                             json!({
                                 "type": "tool_use",
                                 "id": tc.id,
                                 "name": tc.name,
                                 "input": serde_json::from_str::<Value>(&tc.arguments).unwrap_or(json!({}))
                             })
-                        }).collect();
+                        });
+                        let mut content = thinking;
+                        if !m.content.is_empty() {
+                            content.push(json!({"type": "text", "text": m.content}));
+                        }
+                        content.extend(tool_use);
                         formatted.push(json!({
                             "role": "assistant",
-                            "content": tool_use
+                            "content": content
+                        }));
+                    } else if !thinking.is_empty() {
+                        let mut content = thinking;
+                        if !m.content.is_empty() {
+                            content.push(json!({"type": "text", "text": m.content}));
+                        }
+                        formatted.push(json!({
+                            "role": "assistant",
+                            "content": content
                         }));
                     } else {
                         formatted.push(json!({
@@ -687,9 +1414,10 @@ impl LLMProvider for AnthropicProvider {
             "max_tokens": self.max_tokens,
             "messages": formatted_messages
         });
+        self.apply_thinking(&mut body);
 
         if let Some(system) = system_prompt {
-            body["system"] = json!(system);
+            body["system"] = self.format_system(system);
         }
 
         if let Some(tools) = tools
@@ -703,15 +1431,7 @@ impl LLMProvider for AnthropicProvider {
             serde_json::to_string_pretty(&body)?
         );
 
-        let response = self
-            .client
-            .post(format!("{}/v1/messages", self.base_url))
-            .header("x-api-key", &self.api_key)
-            .header("anthropic-version", "2023-06-01")
-            .header("Content-Type", "application/json")
-            .json(&body)
-            .send()
-            .await?;
+        let response = self.send_messages(&body).await?;
 
         let response_body: Value = response.json().await?;
         debug!(
@@ -728,11 +1448,14 @@ impl LLMProvider for AnthropicProvider {
             .as_array()
             .ok_or_else(|| anyhow::anyhow!("No content in response"))?;
 
-        // Parse usage (Anthropic uses input_tokens/output_tokens directly)
+        // Parse usage (input_tokens excludes cache reads/writes)
         let usage = response_body.get("usage").map(|u| Usage {
             input_tokens: u["input_tokens"].as_u64().unwrap_or(0),
             output_tokens: u["output_tokens"].as_u64().unwrap_or(0),
+            cache_read_tokens: u["cache_read_input_tokens"].as_u64().unwrap_or(0),
+            cache_write_tokens: u["cache_creation_input_tokens"].as_u64().unwrap_or(0),
         });
+        let thinking = Self::parse_thinking(content);
 
         // Check for tool use
         let tool_calls: Vec<ToolCall> = content
@@ -749,6 +1472,7 @@ impl LLMProvider for AnthropicProvider {
             return Ok(LLMResponse {
                 content: LLMResponseContent::ToolCalls(tool_calls),
                 usage,
+                thinking,
             });
         }
 
@@ -763,6 +1487,7 @@ impl LLMProvider for AnthropicProvider {
         Ok(LLMResponse {
             content: LLMResponseContent::Text(text),
             usage,
+            thinking,
         })
     }
 
@@ -776,6 +1501,7 @@ impl LLMProvider for AnthropicProvider {
             tool_calls: None,
             tool_call_id: None,
             images: Vec::new(),
+            thinking: Vec::new(),
         }];
 
         match self.chat(&messages, None).await?.content {
@@ -784,6 +1510,17 @@ impl LLMProvider for AnthropicProvider {
         }
     }
 
+    fn take_stream_usage(&self) -> Option<Usage> {
+        self.stream_usage.lock().ok()?.take()
+    }
+
+    fn take_stream_thinking(&self) -> Vec<ThinkingBlock> {
+        self.stream_thinking
+            .lock()
+            .map(|mut slot| std::mem::take(&mut *slot))
+            .unwrap_or_default()
+    }
+
     async fn chat_stream(
         &self,
         messages: &[Message],
@@ -797,9 +1534,10 @@ impl LLMProvider for AnthropicProvider {
             "messages": formatted_messages,
             "stream": true
         });
+        self.apply_thinking(&mut body);
 
         if let Some(system) = system_prompt {
-            body["system"] = json!(system);
+            body["system"] = self.format_system(system);
         }
 
         // Include tools so the model uses native tool_use instead of XML
@@ -814,15 +1552,7 @@ impl LLMProvider for AnthropicProvider {
             serde_json::to_string_pretty(&body)?
         );
 
-        let response = self
-            .client
-            .post(format!("{}/v1/messages", self.base_url))
-            .header("x-api-key", &self.api_key)
-            .header("anthropic-version", "2023-06-01")
-            .header("Content-Type", "application/json")
-            .json(&body)
-            .send()
-            .await?;
+        let response = self.send_messages(&body).await?;
 
         // Check for error status
         if !response.status().is_success() {
@@ -832,116 +1562,223 @@ impl LLMProvider for AnthropicProvider {
 
         // Anthropic streams Server-Sent Events (SSE)
         // We need to track tool_use blocks and accumulate their JSON input
+        let provider = self.clone();
         let stream = async_stream::stream! {
-            let mut byte_stream = response.bytes_stream();
-            let mut buffer = String::new();
-
-            // Track tool calls being accumulated
-            let mut pending_tool_calls: Vec<ToolCall> = Vec::new();
-            let mut current_tool_id: Option<String> = None;
-            let mut current_tool_name: Option<String> = None;
-            let mut current_tool_input: String = String::new();
-
-            while let Some(chunk) = byte_stream.next().await {
-                match chunk {
-                    Ok(bytes) => {
-                        buffer.push_str(&String::from_utf8_lossy(&bytes));
-
-                        // Process complete SSE events (lines starting with "data: ")
-                        while let Some(pos) = buffer.find("\n\n") {
-                            let event = buffer[..pos].to_string();
-                            buffer = buffer[pos + 2..].to_string();
-
-                            // Parse SSE event
-                            for line in event.lines() {
-                                if let Some(data) = line.strip_prefix("data: ") {
-                                    if data == "[DONE]" {
-                                        // Return any accumulated tool calls
-                                        let tool_calls = if pending_tool_calls.is_empty() {
-                                            None
-                                        } else {
-                                            Some(pending_tool_calls.clone())
-                                        };
-                                        yield Ok(StreamChunk {
-                                            delta: String::new(),
-                                            done: true,
-                                            tool_calls,
-                                        });
-                                        continue;
-                                    }
+            let mut response = response;
+            let mut attempt = 1;
+            // Once text has been yielded a retry would duplicate output, so
+            // failures are only retried while nothing has been emitted yet
+            let mut emitted = false;
+            let mut usage = Usage::default();
+
+            loop {
+                let mut byte_stream = response.bytes_stream();
+                let mut buffer = String::new();
+                let mut retry_reason: Option<String> = None;
+                let can_retry = attempt < provider.retry.max_attempts;
+
+                // Track tool calls being accumulated
+                let mut pending_tool_calls: Vec<ToolCall> = Vec::new();
+                let mut current_tool_id: Option<String> = None;
+                let mut current_tool_name: Option<String> = None;
+                let mut current_tool_input: String = String::new();
+
+                // Track thinking blocks (text streams out, signature arrives last)
+                let mut thinking_blocks: Vec<ThinkingBlock> = Vec::new();
+                let mut current_thinking: Option<ThinkingBlock> = None;
+
+                'read: while let Some(chunk) = byte_stream.next().await {
+                    match chunk {
+                        Ok(bytes) => {
+                            buffer.push_str(&String::from_utf8_lossy(&bytes));
+
+                            // Process complete SSE events (lines starting with "data: ")
+                            while let Some(pos) = buffer.find("\n\n") {
+                                let event = buffer[..pos].to_string();
+                                buffer = buffer[pos + 2..].to_string();
+
+                                // Parse SSE event
+                                for line in event.lines() {
+                                    if let Some(data) = line.strip_prefix("data: ") {
+                                        if data == "[DONE]" {
+                                            // Return any accumulated tool calls
+                                            let tool_calls = if pending_tool_calls.is_empty() {
+                                                None
+                                            } else {
+                                                Some(pending_tool_calls.clone())
+                                            };
+                                            yield Ok(StreamChunk {
+                                                delta: String::new(),
+                                                done: true,
+                                                tool_calls,
+                                                thinking: String::new(),
+                                            });
+                                            continue;
+                                        }
 
-                                    if let Ok(json) = serde_json::from_str::<Value>(data) {
-                                        let event_type = json["type"].as_str().unwrap_or("");
+                                        if let Ok(json) = serde_json::from_str::<Value>(data) {
+                                            let event_type = json["type"].as_str().unwrap_or("");
+
+                                            match event_type {
+                                                // Text content delta
+                                                "content_block_delta" => {
+                                                    // Check if it's text or tool input
+                                                    if let Some(delta) = json["delta"]["text"].as_str() {
+                                                        emitted = true;
+                                                        yield Ok(StreamChunk {
+                                                            delta: delta.to_string(),
+                                                            done: false,
+                                                            tool_calls: None,
+                                                            thinking: String::new(),
+                                                        });
+                                                    } else if let Some(input_delta) = json["delta"]["partial_json"].as_str() {
+                                                        // Accumulate tool input JSON
+                                                        current_tool_input.push_str(input_delta);
+                                                    } else if let Some(thinking) = json["delta"]["thinking"].as_str() {
+                                                        emitted = true;
+                                                        if let Some(block) = current_thinking.as_mut() {
+                                                            block.text.push_str(thinking);
+                                                        }
+                                                        yield Ok(StreamChunk {
+                                                            delta: String::new(),
+                                                            done: false,
+                                                            tool_calls: None,
+                                                            thinking: thinking.to_string(),
+                                                        });
+                                                    } else if let Some(signature) = json["delta"]["signature"].as_str()
+                                                        && let Some(block) = current_thinking.as_mut() {
+                                                            block.signature.get_or_insert_with(String::new).push_str(signature);
+                                                        }
+                                                }
 
-                                        match event_type {
-                                            // Text content delta
-                                            "content_block_delta" => {
-                                                // Check if it's text or tool input
-                                                if let Some(delta) = json["delta"]["text"].as_str() {
-                                                    yield Ok(StreamChunk {
-                                                        delta: delta.to_string(),
-                                                        done: false,
-                                                        tool_calls: None,
-                                                    });
-                                                } else if let Some(input_delta) = json["delta"]["partial_json"].as_str() {
-                                                    // Accumulate tool input JSON
-                                                    current_tool_input.push_str(input_delta);
+                                                // Tool use block started
+                                                "content_block_start" => {
+                                                    let content_block = &json["content_block"];
+                                                    match content_block["type"].as_str() {
+                                                        Some("tool_use") => {
+                                                            current_tool_id = content_block["id"].as_str().map(|s| s.to_string());
+                                                            current_tool_name = content_block["name"].as_str().map(|s| s.to_string());
+                                                            current_tool_input.clear();
+                                                        }
+                                                        Some("thinking") => {
+                                                            current_thinking = Some(ThinkingBlock::default());
+                                                        }
+                                                        Some("redacted_thinking") => {
+                                                            thinking_blocks.push(ThinkingBlock {
+                                                                redacted: content_block["data"].as_str().map(|s| s.to_string()),
+                                                                ..Default::default()
+                                                            });
+                                                        }
+                                                        _ => {}
+                                                    }
                                                 }
-                                            }
 
-                                            // Tool use block started
-                                            "content_block_start" => {
-                                                if let Some(content_block) = json.get("content_block")
-                                                    && content_block["type"] == "tool_use" {
-                                                        current_tool_id = content_block["id"].as_str().map(|s| s.to_string());
-                                                        current_tool_name = content_block["name"].as_str().map(|s| s.to_string());
-                                                        current_tool_input.clear();
+                                                // Content block finished
+                                                "content_block_stop" => {
+                                                    // If we were accumulating a tool call, finalize it
+                                                    if let (Some(id), Some(name)) = (current_tool_id.take(), current_tool_name.take()) {
+                                                        pending_tool_calls.push(ToolCall {
+                                                            id,
+                                                            name,
+                                                            arguments: std::mem::take(&mut current_tool_input),
+                                                        });
                                                     }
-                                            }
+                                                    if let Some(block) = current_thinking.take() {
+                                                        thinking_blocks.push(block);
+                                                    }
+                                                }
+
+                                                // Prompt usage (including cache reads/writes)
+                                                "message_start" => {
+                                                    let u = &json["message"]["usage"];
+                                                    usage.input_tokens = u["input_tokens"].as_u64().unwrap_or(0);
+                                                    usage.cache_read_tokens = u["cache_read_input_tokens"].as_u64().unwrap_or(0);
+                                                    usage.cache_write_tokens = u["cache_creation_input_tokens"].as_u64().unwrap_or(0);
+                                                }
+
+                                                // Cumulative output token count
+                                                "message_delta" => {
+                                                    if let Some(output) = json["usage"]["output_tokens"].as_u64() {
+                                                        usage.output_tokens = output;
+                                                    }
+                                                }
 
-                                            // Content block finished
-                                            "content_block_stop" => {
-                                                // If we were accumulating a tool call, finalize it
-                                                if let (Some(id), Some(name)) = (current_tool_id.take(), current_tool_name.take()) {
-                                                    pending_tool_calls.push(ToolCall {
-                                                        id,
-                                                        name,
-                                                        arguments: std::mem::take(&mut current_tool_input),
+                                                // Message complete
+                                                "message_stop" => {
+                                                    if let Ok(mut slot) = provider.stream_usage.lock() {
+                                                        *slot = Some(usage.clone());
+                                                    }
+                                                    if let Ok(mut slot) = provider.stream_thinking.lock() {
+                                                        *slot = std::mem::take(&mut thinking_blocks);
+                                                    }
+                                                    let tool_calls = if pending_tool_calls.is_empty() {
+                                                        None
+                                                    } else {
+                                                        Some(pending_tool_calls.clone())
+                                                    };
+                                                    yield Ok(StreamChunk {
+                                                        delta: String::new(),
+                                                        done: true,
+                                                        tool_calls,
+                                                        thinking: String::new(),
                                                     });
                                                 }
-                                            }
 
-                                            // Message complete
-                                            "message_stop" => {
-                                                let tool_calls = if pending_tool_calls.is_empty() {
-                                                    None
-                                                } else {
-                                                    Some(pending_tool_calls.clone())
-                                                };
-                                                yield Ok(StreamChunk {
-                                                    delta: String::new(),
-                                                    done: true,
-                                                    tool_calls,
-                                                });
-                                            }
+                                                // Error
+                                                "error" => {
+                                                    let error_type = json["error"]["type"].as_str().unwrap_or("");
+                                                    let error_msg = json["error"]["message"]
+                                                        .as_str()
+                                                        .unwrap_or("Unknown error");
+                                                    if !emitted
+                                                        && can_retry
+                                                        && matches!(error_type, "overloaded_error" | "api_error" | "rate_limit_error")
+                                                    {
+                                                        retry_reason = Some(format!("{}: {}", error_type, error_msg));
+                                                        break 'read;
+                                                    }
+                                                    yield Err(anyhow::anyhow!("Anthropic error: {}", error_msg));
+                                                }
 
-                                            // Error
-                                            "error" => {
-                                                let error_msg = json["error"]["message"]
-                                                    .as_str()
-                                                    .unwrap_or("Unknown error");
-                                                yield Err(anyhow::anyhow!("Anthropic error: {}", error_msg));
+                                                _ => {} // Ignore other events
                                             }
-
-                                            _ => {} // Ignore other events
                                         }
                                     }
                                 }
                             }
                         }
+                        Err(e) => {
+                            if !emitted && can_retry {
+                                retry_reason = Some(e.to_string());
+                                break;
+                            }
+                            yield Err(anyhow::anyhow!("Stream error: {}", e));
+                            break;
+                        }
+                    }
+                }
+
+                let Some(reason) = retry_reason else {
+                    break;
+                };
+
+                let delay = provider.retry.backoff(attempt);
+                warn!(
+                    "Anthropic stream: attempt {}/{} failed before any output ({}), retrying in {:?}",
+                    attempt, provider.retry.max_attempts, reason, delay
+                );
+                tokio::time::sleep(delay).await;
+                attempt += 1;
+
+                match provider.send_messages(&body).await {
+                    Ok(next) if next.status().is_success() => response = next,
+                    Ok(next) => {
+                        let error_body = next.text().await.unwrap_or_default();
+                        yield Err(anyhow::anyhow!("Anthropic API error: {}", error_body));
+                        break;
                     }
                     Err(e) => {
-                        yield Err(anyhow::anyhow!("Stream error: {}", e));
+                        yield Err(e);
                         break;
                     }
                 }
@@ -953,10 +1790,16 @@ impl LLMProvider for AnthropicProvider {
 }
 
 // Ollama Provider (for local models)
+#[derive(Clone)]
 pub struct OllamaProvider {
     client: Client,
     endpoint: String,
     model: String,
+    retry: RetryPolicy,
+    /// Ask thinking models to reason before answering
+    think: bool,
+    /// Reasoning from the last streamed response
+    stream_thinking: Arc<StdMutex<Vec<ThinkingBlock>>>,
 }
 
 impl OllamaProvider {
@@ -965,7 +1808,45 @@ impl OllamaProvider {
             client: Client::new(),
             endpoint: endpoint.to_string(),
             model: model.to_string(),
+            retry: RetryPolicy::default(),
+            think: false,
+            stream_thinking: Arc::new(StdMutex::new(Vec::new())),
+        })
+    }
+
+    /// Enable thinking for models that support it (qwen3, deepseek-r1, ...)
+    pub fn with_thinking(mut self, enabled: bool) -> Self {
+        self.think = enabled;
+        self
+    }
+
+    /// Reasoning from a response message: the `thinking` field, or
+    /// `<think>` tags inlined in the content. Returns (thinking, content).
+    fn split_thinking(message: &Value) -> (Vec<ThinkingBlock>, String) {
+        let (inline, content) = split_think_tags(message["content"].as_str().unwrap_or(""));
+        let thinking = message["thinking"]
+            .as_str()
+            .filter(|t| !t.is_empty())
+            .map(|t| t.to_string())
+            .unwrap_or(inline);
+        (vec![ThinkingBlock::text(thinking)], content)
+    }
+
+    /// Override the retry policy for transient HTTP failures
+    pub fn with_retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// POST a request body to /api/chat, retrying transient failures
+    async fn send_chat(&self, body: &Value) -> Result<reqwest::Response> {
+        send_with_retry(&self.retry, "Ollama", || {
+            self.client
+                .post(format!("{}/api/chat", self.endpoint))
+                .header("Content-Type", "application/json")
+                .json(body)
         })
+        .await
     }
 }
 
@@ -1013,6 +1894,9 @@ impl LLMProvider for OllamaProvider {
             "messages": formatted_messages,
             "stream": false
         });
+        if self.think {
+            body["think"] = json!(true);
+        }
 
         // Send tool schemas if provided
         if let Some(tool_schemas) = tools
@@ -1036,35 +1920,21 @@ impl LLMProvider for OllamaProvider {
 
         debug!("Ollama request: {}", serde_json::to_string_pretty(&body)?);
 
-        let response = self
-            .client
-            .post(format!("{}/api/chat", self.endpoint))
-            .header("Content-Type", "application/json")
-            .json(&body)
-            .send()
-            .await?;
+        let response = self.send_chat(&body).await?;
 
         // If Ollama returns 400 (model doesn't support tools), retry without tools
         if response.status() == reqwest::StatusCode::BAD_REQUEST && body.get("tools").is_some() {
             debug!("Ollama returned 400 with tools, retrying without tools");
             let mut body_no_tools = body.clone();
             body_no_tools.as_object_mut().map(|o| o.remove("tools"));
-            let retry_response = self
-                .client
-                .post(format!("{}/api/chat", self.endpoint))
-                .header("Content-Type", "application/json")
-                .json(&body_no_tools)
-                .send()
-                .await?;
+            let retry_response = self.send_chat(&body_no_tools).await?;
             let response_body: Value = retry_response.json().await?;
-            let content = response_body["message"]["content"]
-                .as_str()
-                .unwrap_or("")
-                .to_string();
+            let (thinking, content) = Self::split_thinking(&response_body["message"]);
             let usage = if response_body.get("prompt_eval_count").is_some() {
                 Some(Usage {
                     input_tokens: response_body["prompt_eval_count"].as_u64().unwrap_or(0),
                     output_tokens: response_body["eval_count"].as_u64().unwrap_or(0),
+                    ..Default::default()
                 })
             } else {
                 None
@@ -1072,7 +1942,9 @@ impl LLMProvider for OllamaProvider {
             return Ok(LLMResponse {
                 content: LLMResponseContent::Text(content),
                 usage,
-            });
+                thinking: Vec::new(),
+            }
+            .with_thinking(thinking));
         }
 
         let response_body: Value = response.json().await?;
@@ -1086,10 +1958,12 @@ impl LLMProvider for OllamaProvider {
             Some(Usage {
                 input_tokens: response_body["prompt_eval_count"].as_u64().unwrap_or(0),
                 output_tokens: response_body["eval_count"].as_u64().unwrap_or(0),
+                ..Default::default()
             })
         } else {
             None
         };
+        let (thinking, content) = Self::split_thinking(&response_body["message"]);
 
         // Check for tool calls in response
         if let Some(tool_calls) = response_body["message"]["tool_calls"].as_array()
@@ -1117,19 +1991,18 @@ impl LLMProvider for OllamaProvider {
                 return Ok(LLMResponse {
                     content: LLMResponseContent::ToolCalls(calls),
                     usage,
-                });
+                    thinking: Vec::new(),
+                }
+                .with_thinking(thinking));
             }
         }
 
-        let content = response_body["message"]["content"]
-            .as_str()
-            .unwrap_or("")
-            .to_string();
-
         Ok(LLMResponse {
             content: LLMResponseContent::Text(content),
             usage,
-        })
+            thinking: Vec::new(),
+        }
+        .with_thinking(thinking))
     }
 
     async fn summarize(&self, text: &str) -> Result<String> {
@@ -1142,6 +2015,7 @@ impl LLMProvider for OllamaProvider {
             tool_calls: None,
             tool_call_id: None,
             images: Vec::new(),
+            thinking: Vec::new(),
         }];
 
         match self.chat(&messages, None).await?.content {
@@ -1150,6 +2024,13 @@ impl LLMProvider for OllamaProvider {
         }
     }
 
+    fn take_stream_thinking(&self) -> Vec<ThinkingBlock> {
+        self.stream_thinking
+            .lock()
+            .map(|mut slot| std::mem::take(&mut *slot))
+            .unwrap_or_default()
+    }
+
     async fn chat_stream(
         &self,
         messages: &[Message],
@@ -1158,12 +2039,17 @@ impl LLMProvider for OllamaProvider {
         // For tool-enabled requests, use non-streaming to properly handle tool calls
         if tools.is_some() && tools.map(|t| !t.is_empty()).unwrap_or(false) {
             let resp = self.chat(messages, tools).await?;
+            let thinking = thinking_text(&resp.thinking);
+            if let Ok(mut slot) = self.stream_thinking.lock() {
+                *slot = resp.thinking;
+            }
             return match resp.content {
                 LLMResponseContent::Text(text) => Ok(Box::pin(futures::stream::once(async move {
                     Ok(StreamChunk {
                         delta: text,
                         done: true,
                         tool_calls: None,
+                        thinking,
                     })
                 }))),
                 LLMResponseContent::ToolCalls(calls) => {
@@ -1172,6 +2058,7 @@ impl LLMProvider for OllamaProvider {
                             delta: String::new(),
                             done: true,
                             tool_calls: Some(calls),
+                            thinking,
                         })
                     })))
                 }
@@ -1193,61 +2080,106 @@ impl LLMProvider for OllamaProvider {
             })
             .collect();
 
-        let body = json!({
+        let mut body = json!({
             "model": self.model,
             "messages": formatted_messages,
             "stream": true
         });
+        if self.think {
+            body["think"] = json!(true);
+        }
 
         debug!(
             "Ollama streaming request: {}",
             serde_json::to_string_pretty(&body)?
         );
 
-        let response = self
-            .client
-            .post(format!("{}/api/chat", self.endpoint))
-            .header("Content-Type", "application/json")
-            .json(&body)
-            .send()
-            .await?;
+        let response = self.send_chat(&body).await?;
 
         // Ollama streams newline-delimited JSON
+        let provider = self.clone();
         let stream = async_stream::stream! {
-            let mut byte_stream = response.bytes_stream();
-            let mut buffer = String::new();
-
-            while let Some(chunk) = byte_stream.next().await {
-                match chunk {
-                    Ok(bytes) => {
-                        buffer.push_str(&String::from_utf8_lossy(&bytes));
+            let mut response = response;
+            let mut attempt = 1;
+            // Only retry a dropped stream if no content reached the caller yet
+            let mut emitted = false;
+
+            loop {
+                let mut byte_stream = response.bytes_stream();
+                let mut buffer = String::new();
+                let mut retry_reason: Option<String> = None;
+                let mut splitter = ThinkTagSplitter::default();
+                let mut reasoning = String::new();
+
+                while let Some(chunk) = byte_stream.next().await {
+                    match chunk {
+                        Ok(bytes) => {
+                            buffer.push_str(&String::from_utf8_lossy(&bytes));
+
+                            // Process complete lines
+                            while let Some(pos) = buffer.find('\n') {
+                                let line = buffer[..pos].to_string();
+                                buffer = buffer[pos + 1..].to_string();
+
+                                if line.is_empty() {
+                                    continue;
+                                }
 
-                        // Process complete lines
-                        while let Some(pos) = buffer.find('\n') {
-                            let line = buffer[..pos].to_string();
-                            buffer = buffer[pos + 1..].to_string();
+                                if let Ok(json) = serde_json::from_str::<Value>(&line) {
+                                    let done = json["done"].as_bool().unwrap_or(false);
+                                    let (mut thinking, mut content) =
+                                        splitter.push(json["message"]["content"].as_str().unwrap_or(""));
+                                    if let Some(t) = json["message"]["thinking"].as_str() {
+                                        thinking.push_str(t);
+                                    }
+                                    if done {
+                                        let (t, c) = splitter.finish();
+                                        thinking.push_str(&t);
+                                        content.push_str(&c);
+                                    }
+                                    reasoning.push_str(&thinking);
+                                    if done && let Ok(mut slot) = provider.stream_thinking.lock() {
+                                        *slot = vec![ThinkingBlock::text(reasoning.trim())];
+                                        slot.retain(|b| !b.text.is_empty());
+                                    }
 
-                            if line.is_empty() {
-                                continue;
+                                    emitted |= !content.is_empty() || !thinking.is_empty();
+                                    yield Ok(StreamChunk {
+                                        delta: content,
+                                        done,
+                                        tool_calls: None,
+                                        thinking,
+                                    });
+                                }
                             }
-
-                            if let Ok(json) = serde_json::from_str::<Value>(&line) {
-                                let content = json["message"]["content"]
-                                    .as_str()
-                                    .unwrap_or("")
-                                    .to_string();
-                                let done = json["done"].as_bool().unwrap_or(false);
-
-                                yield Ok(StreamChunk {
-                                    delta: content,
-                                    done,
-                                    tool_calls: None,
-                                });
+                        }
+                        Err(e) => {
+                            if !emitted && attempt < provider.retry.max_attempts {
+                                retry_reason = Some(e.to_string());
+                                break;
                             }
+                            yield Err(anyhow::anyhow!("Stream error: {}", e));
+                            break;
                         }
                     }
+                }
+
+                let Some(reason) = retry_reason else {
+                    break;
+                };
+
+                let delay = provider.retry.backoff(attempt);
+                warn!(
+                    "Ollama stream: attempt {}/{} failed before any output ({}), retrying in {:?}",
+                    attempt, provider.retry.max_attempts, reason, delay
+                );
+                tokio::time::sleep(delay).await;
+                attempt += 1;
+
+                match provider.send_chat(&body).await {
+                    Ok(next) => response = next,
                     Err(e) => {
-                        yield Err(anyhow::anyhow!("Stream error: {}", e));
+                        yield Err(e);
                         break;
                     }
                 }
@@ -1634,6 +2566,7 @@ impl LLMProvider for ClaudeCliProvider {
             tool_calls: None,
             tool_call_id: None,
             images: Vec::new(),
+            thinking: Vec::new(),
         }];
 
         match self.chat(&messages, None).await?.content {
@@ -1731,6 +2664,7 @@ impl LLMProvider for ClaudeCliProvider {
                                         delta: format!("[Model: {} | Tools: {}]\n", model, tools_count),
                                         done: false,
                                         tool_calls: None,
+                                        thinking: String::new(),
                                     });
                                 }
                         }
@@ -1789,6 +2723,7 @@ impl LLMProvider for ClaudeCliProvider {
                                                 delta: tool_msg,
                                                 done: false,
                                                 tool_calls: None,
+                                                thinking: String::new(),
                                             });
                                         }
                                     }
@@ -1803,6 +2738,7 @@ impl LLMProvider for ClaudeCliProvider {
                                     delta,
                                     done: false,
                                     tool_calls: None,
+                                    thinking: String::new(),
                                 });
                             }
                         }
@@ -1825,6 +2761,7 @@ impl LLMProvider for ClaudeCliProvider {
                                             delta: format!(" [{}]\n", status),
                                             done: false,
                                             tool_calls: None,
+                                            thinking: String::new(),
                                         });
                                     }
                                 }
@@ -1865,6 +2802,7 @@ impl LLMProvider for ClaudeCliProvider {
                                             delta,
                                             done: false,
                                             tool_calls: None,
+                                            thinking: String::new(),
                                         });
                                     }
                                 }
@@ -1875,6 +2813,7 @@ impl LLMProvider for ClaudeCliProvider {
                                 delta: String::new(),
                                 done: true,
                                 tool_calls: None,
+                                thinking: String::new(),
                             });
                         }
 
@@ -1937,6 +2876,7 @@ mod tests {
         let usage = Usage {
             input_tokens: 100,
             output_tokens: 50,
+            ..Default::default()
         };
         assert_eq!(usage.total(), 150);
     }
@@ -1949,6 +2889,129 @@ mod tests {
         assert_eq!(usage.total(), 0);
     }
 
+    #[test]
+    fn test_usage_cache_hit_rate() {
+        let usage = Usage {
+            input_tokens: 100,
+            output_tokens: 50,
+            cache_read_tokens: 800,
+            cache_write_tokens: 100,
+        };
+        assert_eq!(usage.prompt_tokens(), 1000);
+        assert_eq!(usage.total(), 1050);
+        assert_eq!(usage.cache_hit_rate(), Some(0.8));
+        assert_eq!(Usage::default().cache_hit_rate(), None);
+
+        let mut sum = Usage::default();
+        sum.add(&usage);
+        sum.add(&usage);
+        assert_eq!(sum.cache_read_tokens, 1600);
+        assert_eq!(sum.input_tokens, 200);
+    }
+
+    #[test]
+    fn test_anthropic_cache_breakpoints() {
+        let provider = AnthropicProvider::new("key", "http://localhost", "model", 1024).unwrap();
+        let tools = vec![
+            ToolSchema {
+                name: "a".to_string(),
+                description: "first".to_string(),
+                parameters: json!({}),
+            },
+            ToolSchema {
+                name: "b".to_string(),
+                description: "second".to_string(),
+                parameters: json!({}),
+            },
+        ];
+
+        let formatted = provider.format_tools(&tools);
+        assert!(formatted[0].get("cache_control").is_none());
+        assert_eq!(formatted[1]["cache_control"]["type"], "ephemeral");
+
+        let system = provider.format_system("soul".to_string());
+        assert_eq!(system[0]["text"], "soul");
+        assert_eq!(system[0]["cache_control"]["type"], "ephemeral");
+
+        let provider = provider.with_prompt_caching(false);
+        assert!(
+            provider.format_tools(&tools)[1]
+                .get("cache_control")
+                .is_none()
+        );
+        assert_eq!(provider.format_system("soul".to_string()), json!("soul"));
+    }
+
+    #[test]
+    fn test_anthropic_thinking_round_trip() {
+        let provider = AnthropicProvider::new("key", "http://localhost", "model", 4096)
+            .unwrap()
+            .with_thinking_budget(Some(8000));
+
+        let mut body = json!({"max_tokens": 4096});
+        provider.apply_thinking(&mut body);
+        assert_eq!(body["thinking"]["budget_tokens"], 8000);
+        assert_eq!(body["max_tokens"], 12096);
+
+        let content = vec![
+            json!({"type": "thinking", "thinking": "Need the file.", "signature": "sig"}),
+            json!({"type": "redacted_thinking", "data": "opaque"}),
+            json!({"type": "tool_use", "id": "t1", "name": "read_file", "input": {}}),
+        ];
+        let thinking = AnthropicProvider::parse_thinking(&content);
+        assert_eq!(thinking.len(), 2);
+        assert_eq!(thinking[0].signature.as_deref(), Some("sig"));
+
+        // Signed blocks go back ahead of the tool_use; unsigned ones are dropped
+        let mut blocks = thinking.clone();
+        blocks.push(ThinkingBlock::text("from another provider"));
+        let messages = vec![Message {
+            role: Role::Assistant,
+            content: String::new(),
+            tool_calls: Some(vec![ToolCall {
+                id: "t1".to_string(),
+                name: "read_file".to_string(),
+                arguments: "{}".to_string(),
+            }]),
+            tool_call_id: None,
+            images: Vec::new(),
+            thinking: blocks,
+        }];
+        let (_, formatted) = provider.format_messages(&messages);
+        let sent = formatted[0]["content"].as_array().unwrap();
+        assert_eq!(sent.len(), 3);
+        assert_eq!(sent[0]["type"], "thinking");
+        assert_eq!(sent[0]["signature"], "sig");
+        assert_eq!(sent[1]["type"], "redacted_thinking");
+        assert_eq!(sent[2]["type"], "tool_use");
+    }
+
+    #[test]
+    fn test_split_think_tags() {
+        let (thinking, content) = split_think_tags("<think>\nLet me add.\n</think>\n\n4");
+        assert_eq!(thinking, "Let me add.");
+        assert_eq!(content, "4");
+
+        let (thinking, content) = split_think_tags("  plain answer");
+        assert!(thinking.is_empty());
+        assert_eq!(content, "  plain answer");
+
+        // Tags cut across stream chunks
+        let mut splitter = ThinkTagSplitter::default();
+        let mut thinking = String::new();
+        let mut content = String::new();
+        for chunk in ["<thi", "nk>why", " so</th", "ink>ans", "wer <"] {
+            let (t, c) = splitter.push(chunk);
+            thinking.push_str(&t);
+            content.push_str(&c);
+        }
+        let (t, c) = splitter.finish();
+        thinking.push_str(&t);
+        content.push_str(&c);
+        assert_eq!(thinking, "why so");
+        assert_eq!(content, "answer <");
+    }
+
     #[test]
     fn test_llm_response_constructors() {
         // Text response
@@ -1960,6 +3023,7 @@ mod tests {
         let usage = Usage {
             input_tokens: 10,
             output_tokens: 5,
+            ..Default::default()
         };
         let resp = LLMResponse::text_with_usage("hello".to_string(), usage);
         assert!(matches!(resp.content, LLMResponseContent::Text(_)));
@@ -1977,6 +3041,115 @@ mod tests {
         assert!(resp.usage.is_none());
     }
 
+    /// Provider that always fails with the given error message
+    struct FailingProvider(&'static str);
+
+    #[async_trait]
+    impl LLMProvider for FailingProvider {
+        async fn chat(&self, _: &[Message], _: Option<&[ToolSchema]>) -> Result<LLMResponse> {
+            anyhow::bail!("{}", self.0)
+        }
+
+        async fn summarize(&self, _: &str) -> Result<String> {
+            anyhow::bail!("{}", self.0)
+        }
+    }
+
+    /// Provider that always answers with a fixed text
+    struct EchoProvider(&'static str);
+
+    #[async_trait]
+    impl LLMProvider for EchoProvider {
+        async fn chat(&self, _: &[Message], _: Option<&[ToolSchema]>) -> Result<LLMResponse> {
+            Ok(LLMResponse::text(self.0.to_string()))
+        }
+
+        async fn summarize(&self, _: &str) -> Result<String> {
+            Ok(self.0.to_string())
+        }
+    }
+
+    fn entry(provider: &str, model: &str, inner: Box<dyn LLMProvider>) -> FallbackEntry {
+        FallbackEntry {
+            provider: provider.to_string(),
+            model: model.to_string(),
+            inner,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_fallback_on_retryable_error() {
+        let provider = FallbackProvider::new(vec![
+            entry(
+                "anthropic",
+                "claude-opus-4-5",
+                Box::new(FailingProvider(
+                    r#"Anthropic API error: {"type":"overloaded_error"}"#,
+                )),
+            ),
+            entry("ollama", "llama3", Box::new(EchoProvider("from ollama"))),
+        ]);
+
+        let resp = provider.chat(&[], None).await.unwrap();
+        assert!(matches!(resp.content, LLMResponseContent::Text(ref t) if t == "from ollama"));
+        assert_eq!(
+            provider.served_by(),
+            Some(("ollama".to_string(), "llama3".to_string()))
+        );
+
+        let notices = provider.take_fallback_notices();
+        assert_eq!(notices.len(), 1);
+        assert_eq!(notices[0].from, "anthropic/claude-opus-4-5");
+        assert_eq!(notices[0].to, "ollama/llama3");
+        assert!(provider.take_fallback_notices().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_fallback_stops_on_non_retryable_error() {
+        let provider = FallbackProvider::new(vec![
+            entry(
+                "openai",
+                "gpt-4o",
+                Box::new(FailingProvider("OpenAI API error: invalid_api_key")),
+            ),
+            entry("ollama", "llama3", Box::new(EchoProvider("unused"))),
+        ]);
+
+        assert!(provider.chat(&[], None).await.is_err());
+        assert!(provider.take_fallback_notices().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_fallback_returns_last_error_when_chain_exhausted() {
+        let provider = FallbackProvider::new(vec![
+            entry(
+                "openai",
+                "gpt-4o",
+                Box::new(FailingProvider("503 Service Unavailable")),
+            ),
+            entry(
+                "glm",
+                "glm-4.7",
+                Box::new(FailingProvider("502 Bad Gateway")),
+            ),
+        ]);
+
+        let err = provider.summarize("text").await.unwrap_err();
+        assert!(err.to_string().contains("Bad Gateway"));
+        assert_eq!(provider.take_fallback_notices().len(), 1);
+    }
+
+    #[test]
+    fn test_is_retryable_error() {
+        assert!(is_retryable_error(&anyhow::anyhow!(
+            "Anthropic API error: {{\"type\":\"overloaded_error\"}}"
+        )));
+        assert!(is_retryable_error(&anyhow::anyhow!("Rate limit reached")));
+        assert!(!is_retryable_error(&anyhow::anyhow!(
+            "Anthropic API error: {{\"type\":\"authentication_error\"}}"
+        )));
+    }
+
     #[test]
     fn test_resolve_model_alias() {
         assert_eq!(resolve_model_alias("opus"), "anthropic/claude-opus-4-5");
@@ -1988,4 +3161,47 @@ mod tests {
             "custom-model".to_string()
         );
     }
+
+    #[test]
+    fn test_custom_provider_resolution() {
+        use crate::config::GlmConfig;
+
+        let mut config = Config::default();
+        config.providers.custom.insert(
+            "vllm".to_string(),
+            CustomProviderConfig {
+                base_url: "http://127.0.0.1:8000/v1".to_string(),
+                api_key: String::new(),
+                headers: HashMap::new(),
+                default_model: Some("qwen2.5-7b".to_string()),
+                retry: Default::default(),
+            },
+        );
+
+        let resolve = |spec: &str| resolve_provider_model(spec, &config);
+        assert_eq!(
+            resolve("vllm/meta-llama/Llama-3-8B"),
+            ("vllm".to_string(), "meta-llama/Llama-3-8B".to_string())
+        );
+        assert_eq!(
+            resolve("vllm"),
+            ("vllm".to_string(), "qwen2.5-7b".to_string())
+        );
+        assert_eq!(
+            resolve("vllm/"),
+            ("vllm".to_string(), "qwen2.5-7b".to_string())
+        );
+        assert!(create_single_provider("vllm/any-model", &config).is_ok());
+        assert!(create_single_provider("lmstudio/any-model", &config).is_err());
+
+        // GLM is a preset of the same mechanism
+        assert!(create_single_provider("glm", &config).is_err());
+        config.providers.glm = Some(GlmConfig {
+            api_key: "key".to_string(),
+            base_url: "https://api.z.ai/api/coding/paas/v4".to_string(),
+            retry: Default::default(),
+        });
+        assert!(config.providers.custom_provider("glm").is_some());
+        assert!(create_single_provider("glm", &config).is_ok());
+    }
 }
@@ -0,0 +1,168 @@
+//! Automatic memory recall
+//!
+//! With `memory.auto_recall` enabled, each turn starts with a memory search
+//! on the user's message, so models that rarely call `memory_search` still
+//! see relevant notes. The best chunks above the score threshold are added
+//! to the turn's provider calls (never to the session), within a token
+//! budget and skipping anything already in context.
+
+use crate::config::AutoRecallConfig;
+use crate::memory::{MemoryChunk, MemoryManager};
+
+use super::sanitize::{self, MemorySource};
+use super::tokenizer::Tokenizer;
+
+/// Memory recalled for a turn
+#[derive(Debug, Clone, Default)]
+pub struct Recall {
+    /// Text added to the turn's provider calls
+    pub context: String,
+    /// Where each chunk came from (`file:start-end`), for the user
+    pub citations: Vec<String>,
+}
+
+/// Search memory for `query`, keeping chunks that aren't in `in_context`
+/// already. None if nothing qualifies.
+pub fn recall(
+    memory: &MemoryManager,
+    config: &AutoRecallConfig,
+    query: &str,
+    in_context: &[&str],
+    use_delimiters: bool,
+    tokenizer: &dyn Tokenizer,
+) -> Option<Recall> {
+    if query.trim().is_empty() || config.max_results == 0 {
+        return None;
+    }
+    // Search a little deeper so chunks dropped below still leave top-k
+    let chunks = memory
+        .search(query, config.max_results * 2)
+        .inspect_err(|e| tracing::warn!("Memory recall failed: {}", e))
+        .ok()?;
+    select(chunks, config, in_context, use_delimiters, tokenizer)
+}
+
+fn select(
+    chunks: Vec<MemoryChunk>,
+    config: &AutoRecallConfig,
+    in_context: &[&str],
+    use_delimiters: bool,
+    tokenizer: &dyn Tokenizer,
+) -> Option<Recall> {
+    let mut recall = Recall::default();
+    let mut kept: Vec<&MemoryChunk> = Vec::new();
+    let mut tokens = 0;
+
+    for chunk in &chunks {
+        if kept.len() >= config.max_results {
+            break;
+        }
+        let content = chunk.content.trim();
+        let overlaps_kept = kept.iter().any(|k| {
+            k.file == chunk.file && k.line_start <= chunk.line_end && chunk.line_start <= k.line_end
+        });
+        if chunk.score < config.min_score
+            || content.is_empty()
+            || overlaps_kept
+            || in_context.iter().any(|c| c.contains(content))
+        {
+            continue;
+        }
+
+        let citation = citation(chunk);
+        let block = if use_delimiters {
+            sanitize::wrap_memory_content(&citation, content, MemorySource::Recalled)
+        } else {
+            format!("## {}\n\n{}", citation, content)
+        };
+        let block_tokens = tokenizer.count(&block);
+        if tokens + block_tokens > config.max_tokens {
+            continue;
+        }
+        tokens += block_tokens;
+
+        recall.context.push_str(&block);
+        recall.context.push_str("\n\n");
+        recall.citations.push(citation);
+        kept.push(chunk);
+    }
+
+    if kept.is_empty() {
+        return None;
+    }
+    recall.context = format!(
+        "# Recalled Memory\n\nNotes from memory that may be relevant to the latest message. \
+         Use them only if they are, and cite the file when you do.\n\n{}",
+        recall.context.trim_end()
+    );
+    Some(recall)
+}
+
+fn citation(chunk: &MemoryChunk) -> String {
+    if chunk.line_start == chunk.line_end {
+        format!("{}:{}", chunk.file, chunk.line_start)
+    } else {
+        format!("{}:{}-{}", chunk.file, chunk.line_start, chunk.line_end)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::agent::tokenizer::Heuristic;
+
+    fn chunk(file: &str, lines: (i32, i32), content: &str, score: f64) -> MemoryChunk {
+        MemoryChunk {
+            file: file.to_string(),
+            line_start: lines.0,
+            line_end: lines.1,
+            content: content.to_string(),
+            score,
+        }
+    }
+
+    #[test]
+    fn test_select_filters_dedups_and_budgets() {
+        let config = AutoRecallConfig {
+            enabled: true,
+            max_results: 3,
+            min_score: 0.2,
+            max_tokens: 60,
+        };
+        let chunks = vec![
+            chunk(
+                "memory/2024-01-02.md",
+                (3, 5),
+                "Dentist on Friday at 9",
+                0.9,
+            ),
+            // Overlaps the chunk above
+            chunk("memory/2024-01-02.md", (5, 7), "at 9, then lunch", 0.8),
+            // Already loaded with MEMORY.md
+            chunk("MEMORY.md", (1, 1), "Prefers tea", 0.7),
+            chunk("memory/notes.md", (10, 10), "Dentist is Dr. Lee", 0.6),
+            // Below the score threshold
+            chunk("memory/old.md", (1, 2), "Dentist moved", 0.1),
+            // Over the token budget
+            chunk("memory/long.md", (1, 40), &"dentist ".repeat(100), 0.5),
+        ];
+
+        let recall = select(
+            chunks,
+            &config,
+            &["# Memory\nPrefers tea\n"],
+            true,
+            &Heuristic,
+        )
+        .unwrap();
+        assert_eq!(
+            recall.citations,
+            ["memory/2024-01-02.md:3-5", "memory/notes.md:10"]
+        );
+        assert!(recall.context.contains("Dentist is Dr. Lee"));
+        assert!(recall.context.contains(sanitize::MEMORY_CONTENT_START));
+        assert!(!recall.context.contains("lunch"));
+
+        assert!(select(Vec::new(), &config, &[], true, &Heuristic).is_none());
+    }
+}
@@ -0,0 +1,725 @@
+//! Record/replay provider for deterministic, offline agent tests
+//!
+//! Addressed as `replay/<cassette>`. In record mode (`providers.replay.record`
+//! names a real model) every request/response pair, including stream chunks,
+//! tool calls, usage and errors, is appended to the cassette file. In replay
+//! mode the cassette is served back, matching each request against the
+//! recorded ones after normalization: system messages are skipped (they carry
+//! the clock and workspace state), whitespace is collapsed and dates, times
+//! and UUIDs are masked.
+
+use anyhow::Result;
+use async_trait::async_trait;
+use futures::StreamExt;
+use once_cell::sync::Lazy;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex as StdMutex};
+use tracing::debug;
+
+use super::providers::{
+    FallbackNotice, LLMProvider, LLMResponse, LLMResponseContent, Message, Role, StreamChunk,
+    StreamResult, ThinkingBlock, ToolCall, ToolSchema, Usage,
+};
+
+/// Volatile substrings masked before requests are compared
+static VOLATILE_PATTERNS: Lazy<Vec<(Regex, &'static str)>> = Lazy::new(|| {
+    vec![
+        (
+            Regex::new(
+                r"[0-9a-fA-F]{8}-[0-9a-fA-F]{4}-[0-9a-fA-F]{4}-[0-9a-fA-F]{4}-[0-9a-fA-F]{12}",
+            )
+            .unwrap(),
+            "<uuid>",
+        ),
+        (Regex::new(r"\d{4}-\d{2}-\d{2}").unwrap(), "<date>"),
+        (Regex::new(r"\d{2}:\d{2}(:\d{2})?").unwrap(), "<time>"),
+    ]
+});
+
+fn normalize_text(text: &str) -> String {
+    let mut text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    for (pattern, mask) in VOLATILE_PATTERNS.iter() {
+        text = pattern.replace_all(&text, *mask).into_owned();
+    }
+    text
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum RequestKind {
+    Chat,
+    Stream,
+    Summarize,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct RecordedMessage {
+    role: Role,
+    content: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    tool_calls: Vec<RecordedToolCall>,
+    #[serde(default, skip_serializing_if = "is_zero")]
+    images: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct RecordedToolCall {
+    name: String,
+    arguments: String,
+}
+
+fn is_zero(n: &usize) -> bool {
+    *n == 0
+}
+
+/// A request reduced to the parts that identify it
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct RecordedRequest {
+    kind: RequestKind,
+    messages: Vec<RecordedMessage>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    tools: Vec<String>,
+}
+
+impl RecordedRequest {
+    fn new(kind: RequestKind, messages: &[Message], tools: Option<&[ToolSchema]>) -> Self {
+        let messages = messages
+            .iter()
+            .filter(|m| m.role != Role::System)
+            .map(|m| RecordedMessage {
+                role: m.role,
+                content: normalize_text(&m.content),
+                tool_calls: m
+                    .tool_calls
+                    .iter()
+                    .flatten()
+                    .map(|c| RecordedToolCall {
+                        name: c.name.clone(),
+                        arguments: normalize_text(&c.arguments),
+                    })
+                    .collect(),
+                images: m.images.len(),
+            })
+            .collect();
+        let tools = tools
+            .unwrap_or_default()
+            .iter()
+            .map(|t| t.name.clone())
+            .collect();
+        Self {
+            kind,
+            messages,
+            tools,
+        }
+    }
+
+    fn summarize(text: &str) -> Self {
+        Self {
+            kind: RequestKind::Summarize,
+            messages: vec![RecordedMessage {
+                role: Role::User,
+                content: normalize_text(text),
+                tool_calls: Vec::new(),
+                images: 0,
+            }],
+            tools: Vec::new(),
+        }
+    }
+}
+
+/// Stream chunk as recorded (tool calls are kept verbatim, IDs included,
+/// so replayed tool results line up)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct RecordedChunk {
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    delta: String,
+    #[serde(default)]
+    done: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    tool_calls: Option<Vec<ToolCall>>,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    thinking: String,
+}
+
+impl From<&StreamChunk> for RecordedChunk {
+    fn from(chunk: &StreamChunk) -> Self {
+        Self {
+            delta: chunk.delta.clone(),
+            done: chunk.done,
+            tool_calls: chunk.tool_calls.clone(),
+            thinking: chunk.thinking.clone(),
+        }
+    }
+}
+
+impl From<RecordedChunk> for StreamChunk {
+    fn from(chunk: RecordedChunk) -> Self {
+        Self {
+            delta: chunk.delta,
+            done: chunk.done,
+            tool_calls: chunk.tool_calls,
+            thinking: chunk.thinking,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum RecordedResponse {
+    Text {
+        content: String,
+    },
+    ToolCalls {
+        calls: Vec<ToolCall>,
+    },
+    Stream {
+        chunks: Vec<RecordedChunk>,
+        /// Error that ended the stream, if any
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        error: Option<String>,
+    },
+    Error {
+        message: String,
+    },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Interaction {
+    request: RecordedRequest,
+    response: RecordedResponse,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    usage: Option<Usage>,
+    /// Thinking blocks with signatures, so replayed tool loops send them back
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    thinking: Vec<ThinkingBlock>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Cassette {
+    interactions: Vec<Interaction>,
+}
+
+fn load_cassette(path: &Path) -> Result<Cassette> {
+    let content = fs::read_to_string(path)
+        .map_err(|e| anyhow::anyhow!("Failed to read cassette {}: {}", path.display(), e))?;
+    Ok(serde_json::from_str(&content)?)
+}
+
+/// Atomic write (temp file + rename), same as the session store
+fn save_cassette(path: &Path, cassette: &Cassette) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let tmp_path = path.with_extension(format!(
+        "{}.{}.tmp",
+        std::process::id(),
+        uuid::Uuid::new_v4().as_simple()
+    ));
+    fs::write(&tmp_path, serde_json::to_string_pretty(cassette)?)?;
+    fs::rename(&tmp_path, path)?;
+    debug!("Updated cassette {:?}", path);
+    Ok(())
+}
+
+/// Cassette path for `replay/<cassette>`: absolute paths are used as is,
+/// anything else is relative to `cassette_dir`. ".json" is appended when
+/// there is no extension.
+pub fn cassette_path(cassette: &str, cassette_dir: &Path) -> PathBuf {
+    let mut path = PathBuf::from(shellexpand::tilde(cassette).to_string());
+    if path.extension().is_none() {
+        path.set_extension("json");
+    }
+    if path.is_absolute() {
+        path
+    } else {
+        cassette_dir.join(path)
+    }
+}
+
+enum Mode {
+    Record {
+        inner: Arc<dyn LLMProvider>,
+        /// Provider/model behind `inner`, used to price recorded spend
+        served_by: (String, String),
+        cassette: Arc<StdMutex<Cassette>>,
+    },
+    Replay {
+        interactions: Vec<Interaction>,
+        used: StdMutex<Vec<bool>>,
+    },
+}
+
+pub struct ReplayProvider {
+    path: PathBuf,
+    mode: Mode,
+    stream_usage: Arc<StdMutex<Option<Usage>>>,
+    stream_thinking: Arc<StdMutex<Vec<ThinkingBlock>>>,
+}
+
+impl ReplayProvider {
+    /// Wrap `inner` (serving `served_by` provider/model), recording every
+    /// call to a new cassette at `path`
+    pub fn record(path: &Path, inner: Box<dyn LLMProvider>, served_by: (String, String)) -> Self {
+        Self {
+            path: path.to_path_buf(),
+            mode: Mode::Record {
+                inner: Arc::from(inner),
+                served_by,
+                cassette: Arc::new(StdMutex::new(Cassette::default())),
+            },
+            stream_usage: Arc::new(StdMutex::new(None)),
+            stream_thinking: Arc::new(StdMutex::new(Vec::new())),
+        }
+    }
+
+    /// Serve responses from the cassette at `path`
+    pub fn replay(path: &Path) -> Result<Self> {
+        let cassette = load_cassette(path)?;
+        let used = vec![false; cassette.interactions.len()];
+        Ok(Self {
+            path: path.to_path_buf(),
+            mode: Mode::Replay {
+                interactions: cassette.interactions,
+                used: StdMutex::new(used),
+            },
+            stream_usage: Arc::new(StdMutex::new(None)),
+            stream_thinking: Arc::new(StdMutex::new(Vec::new())),
+        })
+    }
+
+    /// Find the first unused recorded interaction matching `request`
+    fn next_interaction(&self, request: &RecordedRequest) -> Result<Interaction> {
+        let Mode::Replay { interactions, used } = &self.mode else {
+            anyhow::bail!("Not in replay mode");
+        };
+        let mut used = used
+            .lock()
+            .map_err(|e| anyhow::anyhow!("Lock poisoned: {}", e))?;
+
+        let index = interactions
+            .iter()
+            .enumerate()
+            .position(|(i, interaction)| !used[i] && interaction.request == *request)
+            .ok_or_else(|| {
+                anyhow::anyhow!(
+                    "No recorded response in cassette {} for this {:?} request ({} messages, last: {:?}). \
+                     Re-record with providers.replay.record set.",
+                    self.path.display(),
+                    request.kind,
+                    request.messages.len(),
+                    request
+                        .messages
+                        .last()
+                        .map(|m| m.content.chars().take(80).collect::<String>())
+                        .unwrap_or_default()
+                )
+            })?;
+        used[index] = true;
+        Ok(interactions[index].clone())
+    }
+
+    fn inner(&self) -> Option<&Arc<dyn LLMProvider>> {
+        match &self.mode {
+            Mode::Record { inner, .. } => Some(inner),
+            Mode::Replay { .. } => None,
+        }
+    }
+}
+
+fn append_interaction(
+    cassette: &StdMutex<Cassette>,
+    path: &Path,
+    interaction: Interaction,
+) -> Result<()> {
+    let mut cassette = cassette
+        .lock()
+        .map_err(|e| anyhow::anyhow!("Lock poisoned: {}", e))?;
+    cassette.interactions.push(interaction);
+    save_cassette(path, &cassette)
+}
+
+fn response_to_recorded(result: &Result<LLMResponse>) -> (RecordedResponse, Option<Usage>) {
+    match result {
+        Ok(response) => {
+            let recorded = match &response.content {
+                LLMResponseContent::Text(content) => RecordedResponse::Text {
+                    content: content.clone(),
+                },
+                LLMResponseContent::ToolCalls(calls) => RecordedResponse::ToolCalls {
+                    calls: calls.clone(),
+                },
+            };
+            (recorded, response.usage.clone())
+        }
+        Err(e) => (
+            RecordedResponse::Error {
+                message: e.to_string(),
+            },
+            None,
+        ),
+    }
+}
+
+#[async_trait]
+impl LLMProvider for ReplayProvider {
+    async fn chat(
+        &self,
+        messages: &[Message],
+        tools: Option<&[ToolSchema]>,
+    ) -> Result<LLMResponse> {
+        let request = RecordedRequest::new(RequestKind::Chat, messages, tools);
+
+        if let Mode::Record {
+            inner, cassette, ..
+        } = &self.mode
+        {
+            let result = inner.chat(messages, tools).await;
+            let (response, usage) = response_to_recorded(&result);
+            let thinking = result
+                .as_ref()
+                .map(|r| r.thinking.clone())
+                .unwrap_or_default();
+            append_interaction(
+                cassette,
+                &self.path,
+                Interaction {
+                    request,
+                    response,
+                    usage,
+                    thinking,
+                },
+            )?;
+            return result;
+        }
+
+        let interaction = self.next_interaction(&request)?;
+        let content = match interaction.response {
+            RecordedResponse::Text { content } => LLMResponseContent::Text(content),
+            RecordedResponse::ToolCalls { calls } => LLMResponseContent::ToolCalls(calls),
+            RecordedResponse::Error { message } => anyhow::bail!(message),
+            RecordedResponse::Stream { .. } => {
+                anyhow::bail!("Cassette has a stream response for a chat request")
+            }
+        };
+        Ok(LLMResponse {
+            content,
+            usage: interaction.usage,
+            thinking: interaction.thinking,
+        })
+    }
+
+    async fn summarize(&self, text: &str) -> Result<String> {
+        let request = RecordedRequest::summarize(text);
+
+        if let Mode::Record {
+            inner, cassette, ..
+        } = &self.mode
+        {
+            let result = inner.summarize(text).await;
+            let response = match &result {
+                Ok(content) => RecordedResponse::Text {
+                    content: content.clone(),
+                },
+                Err(e) => RecordedResponse::Error {
+                    message: e.to_string(),
+                },
+            };
+            append_interaction(
+                cassette,
+                &self.path,
+                Interaction {
+                    request,
+                    response,
+                    usage: None,
+                    thinking: Vec::new(),
+                },
+            )?;
+            return result;
+        }
+
+        match self.next_interaction(&request)?.response {
+            RecordedResponse::Text { content } => Ok(content),
+            RecordedResponse::Error { message } => anyhow::bail!(message),
+            _ => anyhow::bail!("Unexpected response type"),
+        }
+    }
+
+    fn reset_session(&self) {
+        if let Some(inner) = self.inner() {
+            inner.reset_session();
+        }
+    }
+
+    fn served_by(&self) -> Option<(String, String)> {
+        match &self.mode {
+            Mode::Record {
+                inner, served_by, ..
+            } => inner.served_by().or_else(|| Some(served_by.clone())),
+            Mode::Replay { .. } => None,
+        }
+    }
+
+    fn take_fallback_notices(&self) -> Vec<FallbackNotice> {
+        self.inner()
+            .map(|inner| inner.take_fallback_notices())
+            .unwrap_or_default()
+    }
+
+    fn take_stream_usage(&self) -> Option<Usage> {
+        self.stream_usage.lock().ok()?.take()
+    }
+
+    fn take_stream_thinking(&self) -> Vec<ThinkingBlock> {
+        self.stream_thinking
+            .lock()
+            .map(|mut slot| std::mem::take(&mut *slot))
+            .unwrap_or_default()
+    }
+
+    async fn chat_stream(
+        &self,
+        messages: &[Message],
+        tools: Option<&[ToolSchema]>,
+    ) -> Result<StreamResult> {
+        let request = RecordedRequest::new(RequestKind::Stream, messages, tools);
+        let stream_usage = Arc::clone(&self.stream_usage);
+        let stream_thinking = Arc::clone(&self.stream_thinking);
+
+        if let Mode::Record {
+            inner, cassette, ..
+        } = &self.mode
+        {
+            let mut stream = match inner.chat_stream(messages, tools).await {
+                Ok(stream) => stream,
+                Err(e) => {
+                    append_interaction(
+                        cassette,
+                        &self.path,
+                        Interaction {
+                            request,
+                            response: RecordedResponse::Error {
+                                message: e.to_string(),
+                            },
+                            usage: None,
+                            thinking: Vec::new(),
+                        },
+                    )?;
+                    return Err(e);
+                }
+            };
+
+            // Pass chunks through, recording them; the interaction is
+            // written once the stream ends
+            let inner = Arc::clone(inner);
+            let cassette = Arc::clone(cassette);
+            let path = self.path.clone();
+            let recorded = async_stream::stream! {
+                let mut chunks = Vec::new();
+                let mut error = None;
+                while let Some(item) = stream.next().await {
+                    match &item {
+                        Ok(chunk) => chunks.push(RecordedChunk::from(chunk)),
+                        Err(e) => error = Some(e.to_string()),
+                    }
+                    yield item;
+                }
+
+                let usage = inner.take_stream_usage();
+                if let Ok(mut slot) = stream_usage.lock() {
+                    *slot = usage.clone();
+                }
+                let thinking = inner.take_stream_thinking();
+                if let Ok(mut slot) = stream_thinking.lock() {
+                    *slot = thinking.clone();
+                }
+                let interaction = Interaction {
+                    request,
+                    response: RecordedResponse::Stream { chunks, error },
+                    usage,
+                    thinking,
+                };
+                if let Err(e) = append_interaction(&cassette, &path, interaction) {
+                    yield Err(e);
+                }
+            };
+            return Ok(Box::pin(recorded));
+        }
+
+        let interaction = self.next_interaction(&request)?;
+        let (chunks, error) = match interaction.response {
+            RecordedResponse::Stream { chunks, error } => (chunks, error),
+            RecordedResponse::Error { message } => anyhow::bail!(message),
+            _ => anyhow::bail!("Cassette has a chat response for a stream request"),
+        };
+        if let Ok(mut slot) = stream_usage.lock() {
+            *slot = interaction.usage;
+        }
+        if let Ok(mut slot) = stream_thinking.lock() {
+            *slot = interaction.thinking;
+        }
+
+        let items = chunks
+            .into_iter()
+            .map(|chunk| Ok(StreamChunk::from(chunk)))
+            .chain(error.map(|message| Err(anyhow::anyhow!(message))));
+        Ok(Box::pin(futures::stream::iter(items)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::agent::test_support::{ScriptedProvider, new_agent, test_config, tool_call};
+
+    fn message(role: Role, content: &str) -> Message {
+        Message {
+            role,
+            content: content.to_string(),
+            tool_calls: None,
+            tool_call_id: None,
+            images: Vec::new(),
+            thinking: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_normalize_masks_volatile_text() {
+        assert_eq!(
+            normalize_text(
+                "Use  memory/2026-01-31.md\n at 09:15:02 for 6f1c2d3e-0000-4000-8000-00000000abcd"
+            ),
+            "Use memory/<date>.md at <time> for <uuid>"
+        );
+    }
+
+    #[test]
+    fn test_cassette_path() {
+        let dir = Path::new("/data/cassettes");
+        assert_eq!(
+            cassette_path("tool-loop", dir),
+            PathBuf::from("/data/cassettes/tool-loop.json")
+        );
+        assert_eq!(
+            cassette_path("/tmp/x.json", dir),
+            PathBuf::from("/tmp/x.json")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_record_then_replay() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cassette.json");
+        let conversation = [
+            message(Role::System, "Today is 2026-01-31"),
+            message(Role::User, "List files"),
+        ];
+
+        let recorder = ReplayProvider::record(
+            &path,
+            Box::new(ScriptedProvider::new(vec![
+                LLMResponse::tool_calls_with_usage(
+                    vec![tool_call("bash", r#"{"command":"ls"}"#)],
+                    Usage {
+                        input_tokens: 10,
+                        output_tokens: 2,
+                        ..Default::default()
+                    },
+                ),
+                LLMResponse::text("Two files.".to_string()),
+            ])),
+            ("ollama".to_string(), "llama3".to_string()),
+        );
+        recorder.chat(&conversation, None).await.unwrap();
+        let mut stream = recorder
+            .chat_stream(&conversation[1..], None)
+            .await
+            .unwrap();
+        while stream.next().await.is_some() {}
+        assert_eq!(
+            recorder.summarize("some text").await.unwrap(),
+            "summary of 9 chars"
+        );
+
+        // Replay ignores the system prompt, so a different date still matches
+        let replayer = ReplayProvider::replay(&path).unwrap();
+        let conversation = [
+            message(Role::System, "Today is 2026-02-01"),
+            message(Role::User, "List   files"),
+        ];
+        let response = replayer.chat(&conversation, None).await.unwrap();
+        assert_eq!(response.usage.unwrap().input_tokens, 10);
+        match response.content {
+            LLMResponseContent::ToolCalls(calls) => {
+                assert_eq!(calls[0].name, "bash");
+                assert_eq!(calls[0].id, "call_1");
+            }
+            LLMResponseContent::Text(_) => panic!("expected tool calls"),
+        }
+
+        let chunks: Vec<StreamChunk> = replayer
+            .chat_stream(&conversation[1..], None)
+            .await
+            .unwrap()
+            .map(|c| c.unwrap())
+            .collect()
+            .await;
+        assert_eq!(chunks.len(), 1);
+        assert_eq!(chunks[0].delta, "Two files.");
+        assert!(chunks[0].done);
+
+        assert_eq!(
+            replayer.summarize("some text").await.unwrap(),
+            "summary of 9 chars"
+        );
+
+        // Each interaction is served once; unknown requests fail loudly
+        assert!(replayer.chat(&conversation, None).await.is_err());
+        assert!(
+            replayer
+                .chat(&[message(Role::User, "Something else")], None)
+                .await
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_agent_tool_loop_replays_offline() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = test_config(dir.path());
+        let note = dir.path().join("workspace").join("note.txt");
+        let read_note = format!(r#"{{"path":"{}"}}"#, note.display());
+
+        // Record: the model asks for a file, then answers from its content
+        let cassette = dir.path().join("tool-loop.json");
+        let mut agent = new_agent(&config, "ollama/llama3").await;
+        std::fs::write(&note, "the answer is 42").unwrap();
+        agent.provider = Box::new(ReplayProvider::record(
+            &cassette,
+            Box::new(ScriptedProvider::new(vec![
+                LLMResponse::tool_calls(vec![tool_call("read_file", &read_note)]),
+                LLMResponse::text("It says 42.".to_string()),
+            ])),
+            ("ollama".to_string(), "llama3".to_string()),
+        ));
+        assert_eq!(
+            agent.chat("What's in note.txt?").await.unwrap(),
+            "It says 42."
+        );
+
+        // Replay through the regular provider factory, no network involved
+        config.agent.default_model = format!("replay/{}", cassette.display());
+        let mut agent = new_agent(&config, &config.agent.default_model).await;
+        assert_eq!(
+            agent.chat("What's in note.txt?").await.unwrap(),
+            "It says 42."
+        );
+
+        // A different tool result is a different request
+        std::fs::write(&note, "the answer is 43").unwrap();
+        let mut agent = new_agent(&config, &config.agent.default_model).await;
+        assert!(agent.chat("What's in note.txt?").await.is_err());
+    }
+}
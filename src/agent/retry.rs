@@ -0,0 +1,308 @@
+//! Retry with exponential backoff for HTTP LLM providers
+//!
+//! Transient failures (429, 5xx, Anthropic's 529 "overloaded", connection
+//! resets) are retried with jittered exponential backoff. Server hints from
+//! `Retry-After` and `anthropic-ratelimit-*-reset` headers take precedence
+//! over the computed delay.
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use reqwest::header::HeaderMap;
+use reqwest::{RequestBuilder, Response, StatusCode};
+use std::time::Duration;
+use tracing::{debug, warn};
+
+use crate::config::RetryConfig;
+
+/// Anthropic's "overloaded" status code (not in `reqwest::StatusCode` constants)
+const STATUS_OVERLOADED: u16 = 529;
+
+/// Rate limit kinds reported by Anthropic's `anthropic-ratelimit-<kind>-*` headers
+const ANTHROPIC_RATELIMIT_KINDS: &[&str] = &["requests", "tokens", "input-tokens", "output-tokens"];
+
+/// Retry policy shared by the HTTP providers
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    /// Total attempts including the first (1 = no retries)
+    pub max_attempts: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+    /// Random spread applied to each delay, as a fraction (0.2 = ±20%)
+    pub jitter: f64,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self::from(&RetryConfig::default())
+    }
+}
+
+impl From<&RetryConfig> for RetryPolicy {
+    fn from(config: &RetryConfig) -> Self {
+        Self {
+            max_attempts: config.max_attempts.max(1),
+            base_delay: Duration::from_millis(config.base_delay_ms),
+            max_delay: Duration::from_millis(config.max_delay_ms),
+            jitter: config.jitter.clamp(0.0, 1.0),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Backoff before retry number `attempt` (1-based), without jitter
+    fn base_backoff(&self, attempt: u32) -> Duration {
+        let factor = 2u32.saturating_pow(attempt.saturating_sub(1));
+        self.base_delay.saturating_mul(factor).min(self.max_delay)
+    }
+
+    /// Backoff before retry number `attempt` (1-based), with jitter applied
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let delay = self.base_backoff(attempt);
+        if self.jitter <= 0.0 {
+            return delay;
+        }
+        use rand::RngExt;
+        let spread = rand::rng().random_range(-self.jitter..=self.jitter);
+        delay.mul_f64(1.0 + spread).min(self.max_delay)
+    }
+
+    /// Delay before the next attempt after a failed `response`.
+    /// Server hints win over computed backoff; None means "do not retry"
+    /// (hint exceeds `max_delay`, so fail fast and let a fallback take over).
+    fn delay_for(&self, attempt: u32, headers: &HeaderMap) -> Option<Duration> {
+        match server_retry_hint(headers, Utc::now()) {
+            Some(hint) if hint > self.max_delay => None,
+            Some(hint) => Some(hint),
+            None => Some(self.backoff(attempt)),
+        }
+    }
+}
+
+/// Whether an HTTP status is worth retrying
+pub fn is_retryable_status(status: StatusCode) -> bool {
+    status == StatusCode::TOO_MANY_REQUESTS
+        || status == StatusCode::REQUEST_TIMEOUT
+        || status.is_server_error()
+        || status.as_u16() == STATUS_OVERLOADED
+}
+
+/// Whether a transport-level error is worth retrying
+pub fn is_retryable_transport(err: &reqwest::Error) -> bool {
+    err.is_timeout() || err.is_connect() || err.is_request()
+}
+
+/// Server-advised wait from `Retry-After` (seconds or HTTP date) or, failing
+/// that, from exhausted `anthropic-ratelimit-<kind>-reset` timestamps.
+/// Non-finite seconds are ignored; waits too long for a `Duration` come back
+/// as `Duration::MAX`, which `delay_for` treats as over `max_delay`.
+pub fn server_retry_hint(headers: &HeaderMap, now: DateTime<Utc>) -> Option<Duration> {
+    if let Some(value) = headers.get("retry-after").and_then(|v| v.to_str().ok()) {
+        let value = value.trim();
+        if let Ok(secs) = value.parse::<f64>()
+            && secs.is_finite()
+        {
+            return Some(Duration::try_from_secs_f64(secs.max(0.0)).unwrap_or(Duration::MAX));
+        }
+        if let Ok(date) = DateTime::parse_from_rfc2822(value) {
+            return Some(until(date.with_timezone(&Utc), now));
+        }
+    }
+
+    ANTHROPIC_RATELIMIT_KINDS
+        .iter()
+        .filter(|kind| {
+            headers
+                .get(format!("anthropic-ratelimit-{}-remaining", kind))
+                .and_then(|v| v.to_str().ok())
+                .is_some_and(|v| v.trim() == "0")
+        })
+        .filter_map(|kind| {
+            let reset = headers
+                .get(format!("anthropic-ratelimit-{}-reset", kind))?
+                .to_str()
+                .ok()?;
+            let reset = DateTime::parse_from_rfc3339(reset.trim()).ok()?;
+            Some(until(reset.with_timezone(&Utc), now))
+        })
+        .max()
+}
+
+fn until(when: DateTime<Utc>, now: DateTime<Utc>) -> Duration {
+    (when - now).to_std().unwrap_or(Duration::ZERO)
+}
+
+/// Send a request built by `build`, retrying transient failures per `policy`.
+///
+/// Returns the last response once retries are exhausted (even if it is an
+/// error status) so callers keep their existing error-body handling.
+pub async fn send_with_retry<F>(policy: &RetryPolicy, label: &str, build: F) -> Result<Response>
+where
+    F: Fn() -> RequestBuilder,
+{
+    let mut attempt = 1;
+    loop {
+        let last_attempt = attempt >= policy.max_attempts;
+
+        match build().send().await {
+            Ok(response) => {
+                let status = response.status();
+                if !is_retryable_status(status) || last_attempt {
+                    if attempt > 1 {
+                        debug!("{}: attempt {} returned {}", label, attempt, status);
+                    }
+                    return Ok(response);
+                }
+                let Some(delay) = policy.delay_for(attempt, response.headers()) else {
+                    warn!(
+                        "{}: attempt {}/{} returned {}, server asked to wait longer than {:?}; giving up",
+                        label, attempt, policy.max_attempts, status, policy.max_delay
+                    );
+                    return Ok(response);
+                };
+                warn!(
+                    "{}: attempt {}/{} returned {}, retrying in {:?}",
+                    label, attempt, policy.max_attempts, status, delay
+                );
+                tokio::time::sleep(delay).await;
+            }
+            Err(e) if is_retryable_transport(&e) && !last_attempt => {
+                let delay = policy.backoff(attempt);
+                warn!(
+                    "{}: attempt {}/{} failed ({}), retrying in {:?}",
+                    label, attempt, policy.max_attempts, e, delay
+                );
+                tokio::time::sleep(delay).await;
+            }
+            Err(e) => return Err(e.into()),
+        }
+
+        attempt += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::header::HeaderValue;
+
+    fn policy() -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: 4,
+            base_delay: Duration::from_millis(100),
+            max_delay: Duration::from_millis(1000),
+            jitter: 0.0,
+        }
+    }
+
+    #[test]
+    fn test_backoff_doubles_and_caps() {
+        let p = policy();
+        assert_eq!(p.backoff(1), Duration::from_millis(100));
+        assert_eq!(p.backoff(2), Duration::from_millis(200));
+        assert_eq!(p.backoff(3), Duration::from_millis(400));
+        assert_eq!(p.backoff(5), Duration::from_millis(1000));
+        assert_eq!(p.backoff(40), Duration::from_millis(1000));
+    }
+
+    #[test]
+    fn test_backoff_jitter_stays_in_bounds() {
+        let p = RetryPolicy {
+            jitter: 0.5,
+            ..policy()
+        };
+        for _ in 0..50 {
+            let d = p.backoff(2);
+            assert!(d >= Duration::from_millis(100) && d <= Duration::from_millis(300));
+        }
+    }
+
+    #[test]
+    fn test_retryable_status() {
+        assert!(is_retryable_status(StatusCode::TOO_MANY_REQUESTS));
+        assert!(is_retryable_status(StatusCode::SERVICE_UNAVAILABLE));
+        assert!(is_retryable_status(StatusCode::from_u16(529).unwrap()));
+        assert!(!is_retryable_status(StatusCode::BAD_REQUEST));
+        assert!(!is_retryable_status(StatusCode::UNAUTHORIZED));
+    }
+
+    #[test]
+    fn test_retry_after_seconds() {
+        let mut headers = HeaderMap::new();
+        headers.insert("retry-after", HeaderValue::from_static("7"));
+        assert_eq!(
+            server_retry_hint(&headers, Utc::now()),
+            Some(Duration::from_secs(7))
+        );
+    }
+
+    #[test]
+    fn test_retry_after_out_of_range() {
+        let hint = |value: &'static str| {
+            let mut headers = HeaderMap::new();
+            headers.insert("retry-after", HeaderValue::from_static(value));
+            (
+                server_retry_hint(&headers, Utc::now()),
+                policy().delay_for(1, &headers),
+            )
+        };
+        // Ignored: plain backoff
+        for value in ["inf", "-inf", "nan", "NaN"] {
+            assert_eq!(hint(value), (None, Some(Duration::from_millis(100))));
+        }
+        // Longer than max_delay: give up on this model
+        assert_eq!(hint("1e30"), (Some(Duration::MAX), None));
+        assert_eq!(hint("-5"), (Some(Duration::ZERO), Some(Duration::ZERO)));
+    }
+
+    #[test]
+    fn test_retry_after_http_date() {
+        let now = DateTime::parse_from_rfc3339("2026-01-01T00:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "retry-after",
+            HeaderValue::from_static("Thu, 01 Jan 2026 00:00:30 GMT"),
+        );
+        assert_eq!(
+            server_retry_hint(&headers, now),
+            Some(Duration::from_secs(30))
+        );
+    }
+
+    #[test]
+    fn test_anthropic_ratelimit_reset() {
+        let now = DateTime::parse_from_rfc3339("2026-01-01T00:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let mut headers = HeaderMap::new();
+        // Exhausted input tokens reset in 12s; requests still available
+        headers.insert(
+            "anthropic-ratelimit-input-tokens-remaining",
+            HeaderValue::from_static("0"),
+        );
+        headers.insert(
+            "anthropic-ratelimit-input-tokens-reset",
+            HeaderValue::from_static("2026-01-01T00:00:12Z"),
+        );
+        headers.insert(
+            "anthropic-ratelimit-requests-remaining",
+            HeaderValue::from_static("40"),
+        );
+        headers.insert(
+            "anthropic-ratelimit-requests-reset",
+            HeaderValue::from_static("2026-01-01T00:01:00Z"),
+        );
+        assert_eq!(
+            server_retry_hint(&headers, now),
+            Some(Duration::from_secs(12))
+        );
+    }
+
+    #[test]
+    fn test_hint_longer_than_max_delay_gives_up() {
+        let mut headers = HeaderMap::new();
+        headers.insert("retry-after", HeaderValue::from_static("120"));
+        assert_eq!(policy().delay_for(1, &headers), None);
+    }
+}
@@ -90,6 +90,7 @@ pub enum MemorySource {
     Memory,
     DailyLog,
     Heartbeat,
+    Recalled,
     Other,
 }
 
@@ -104,6 +105,7 @@ impl MemorySource {
             MemorySource::Memory => "Long-term Memory",
             MemorySource::DailyLog => "Daily Log",
             MemorySource::Heartbeat => "Pending Tasks",
+            MemorySource::Recalled => "Recalled Memory",
             MemorySource::Other => "Context",
         }
     }
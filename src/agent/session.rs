@@ -2,21 +2,31 @@
 //!
 //! JSONL format matches Pi's SessionManager for OpenClaw compatibility:
 //! - Header: {type: "session", version, id, timestamp, cwd}
-//! - Messages: {type: "message", message: {role, content, ...}}
+//! - Messages: {type: "message", id, parentId, message: {role, content, ...}}
+//!
+//! Messages form a tree: retrying a reply or editing an earlier message starts
+//! a new branch from that point. The active branch is the path from the root
+//! to the last message entry in the file; other branches are kept alongside.
 
 use anyhow::Result;
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use serde_json::json;
+use std::collections::{HashMap, HashSet};
 use std::fs::{self, File};
 use std::io::{BufRead, BufReader, Write};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use uuid::Uuid;
 
-use super::providers::{LLMProvider, Message, Role, ToolCall, Usage};
+use super::compaction::{self, CompactionSummary};
+use super::providers::{
+    ImageAttachment, LLMProvider, Message, Role, ThinkingBlock, ToolCall, Usage,
+};
+use super::tokenizer::{Tiktoken, Tokenizer};
 
-/// Current session format version (matches Pi)
-pub const CURRENT_SESSION_VERSION: u32 = 1;
+/// Current session format version (2: entries carry id and parentId, as in Pi)
+pub const CURRENT_SESSION_VERSION: u32 = 2;
 
 /// Session state (internal representation)
 #[derive(Debug, Clone)]
@@ -24,16 +34,40 @@ pub struct Session {
     id: String,
     created_at: DateTime<Utc>,
     cwd: String,
+    /// Active branch, root first
     messages: Vec<SessionMessage>,
+    /// Messages on other branches
+    branches: Vec<SessionMessage>,
     system_context: Option<String>,
     token_count: usize,
     compaction_count: u32,
     memory_flush_compaction_count: u32,
+    /// Write thinking blocks to the transcript (agent.persist_thinking)
+    persist_thinking: bool,
+    /// Session that delegated this one (sub-agent transcripts)
+    parent_id: Option<String>,
+    /// Counts `token_count` for the model in use
+    tokenizer: Arc<dyn Tokenizer>,
+    /// Messages replaced by compaction summaries, kept for `expand`
+    compacted: Vec<CompactedMessage>,
+}
+
+/// A message a compaction summarized, with its place in the transcript
+/// before that compaction
+#[derive(Debug, Clone)]
+pub struct CompactedMessage {
+    pub compaction: u32,
+    pub position: usize,
+    pub message: SessionMessage,
 }
 
 /// Message with metadata for persistence
 #[derive(Debug, Clone)]
 pub struct SessionMessage {
+    /// Short ID, unique within the session
+    pub id: String,
+    /// Message this one follows (None: the root)
+    pub parent_id: Option<String>,
     pub message: Message,
     pub provider: Option<String>,
     pub model: Option<String>,
@@ -41,6 +75,10 @@ pub struct SessionMessage {
     pub usage: Option<MessageUsage>,
     pub stop_reason: Option<String>,
     pub timestamp: u64,
+    /// Kept verbatim by compaction
+    pub pinned: bool,
+    /// Compaction whose summary this message holds
+    pub summary_of: Option<u32>,
 }
 
 /// Per-message usage tracking (Pi-compatible)
@@ -64,6 +102,10 @@ pub struct MessageUsage {
 pub struct MessageCost {
     pub input: f64,
     pub output: f64,
+    #[serde(default)]
+    pub cache_read: f64,
+    #[serde(default)]
+    pub cache_write: f64,
     pub total: f64,
 }
 
@@ -72,10 +114,10 @@ impl From<&Usage> for MessageUsage {
         Self {
             input: usage.input_tokens,
             output: usage.output_tokens,
-            cache_read: None,
-            cache_write: None,
+            cache_read: (usage.cache_read_tokens > 0).then_some(usage.cache_read_tokens),
+            cache_write: (usage.cache_write_tokens > 0).then_some(usage.cache_write_tokens),
             total_tokens: usage.total(),
-            cost: None, // Cost calculation not implemented
+            cost: None, // Filled in by the agent from the pricing table
         }
     }
 }
@@ -83,6 +125,8 @@ impl From<&Usage> for MessageUsage {
 impl SessionMessage {
     pub fn new(message: Message) -> Self {
         Self {
+            id: new_message_id(),
+            parent_id: None,
             message,
             provider: None,
             model: None,
@@ -90,6 +134,8 @@ impl SessionMessage {
             usage: None,
             stop_reason: None,
             timestamp: Utc::now().timestamp_millis() as u64,
+            pinned: false,
+            summary_of: None,
         }
     }
 
@@ -97,29 +143,60 @@ impl SessionMessage {
         message: Message,
         provider: Option<&str>,
         model: Option<&str>,
-        usage: Option<&Usage>,
+        usage: Option<MessageUsage>,
         stop_reason: Option<&str>,
     ) -> Self {
         Self {
+            id: new_message_id(),
+            parent_id: None,
             message,
             provider: provider.map(|s| s.to_string()),
             model: model.map(|s| s.to_string()),
             api: provider.map(|p| format!("{}-messages", p)), // e.g., "anthropic-messages"
-            usage: usage.map(MessageUsage::from),
+            usage,
             stop_reason: stop_reason.map(|s| s.to_string()),
             timestamp: Utc::now().timestamp_millis() as u64,
+            pinned: false,
+            summary_of: None,
         }
     }
 }
 
+/// Tip of a branch of the session tree
+#[derive(Debug, Clone)]
+pub struct Branch {
+    pub leaf_id: String,
+    /// Messages from the root to the tip
+    pub message_count: usize,
+    /// Latest user message on the branch
+    pub preview: String,
+    /// The active branch
+    pub current: bool,
+}
+
 #[derive(Debug, Clone)]
 pub struct SessionStatus {
     pub id: String,
     pub message_count: usize,
     pub token_count: usize,
+    /// Tokenizer `token_count` was measured with
+    pub tokenizer: String,
     pub compaction_count: u32,
     pub api_input_tokens: u64,
     pub api_output_tokens: u64,
+    pub api_cache_read_tokens: u64,
+    pub api_cache_write_tokens: u64,
+    /// Priced spend in USD (calls to unpriced models count as zero)
+    pub api_cost_usd: f64,
+}
+
+impl SessionStatus {
+    /// Fraction of prompt tokens served from the prompt cache
+    pub fn cache_hit_rate(&self) -> Option<f64> {
+        let prompt =
+            self.api_input_tokens + self.api_cache_read_tokens + self.api_cache_write_tokens;
+        (prompt > 0).then(|| self.api_cache_read_tokens as f64 / prompt as f64)
+    }
 }
 
 impl Session {
@@ -137,17 +214,52 @@ impl Session {
             created_at: Utc::now(),
             cwd,
             messages: Vec::new(),
+            branches: Vec::new(),
             system_context: None,
             token_count: 0,
             compaction_count: 0,
             memory_flush_compaction_count: 0,
+            persist_thinking: false,
+            parent_id: None,
+            tokenizer: Arc::new(Tiktoken::cl100k()),
+            compacted: Vec::new(),
         }
     }
 
+    /// Count tokens with the model's tokenizer (cl100k by default)
+    pub fn with_tokenizer(mut self, tokenizer: Arc<dyn Tokenizer>) -> Self {
+        self.set_tokenizer(tokenizer);
+        self
+    }
+
+    pub fn set_tokenizer(&mut self, tokenizer: Arc<dyn Tokenizer>) {
+        self.tokenizer = tokenizer;
+        self.recalculate_tokens();
+    }
+
+    pub fn tokenizer(&self) -> &Arc<dyn Tokenizer> {
+        &self.tokenizer
+    }
+
+    /// Keep model reasoning in the saved transcript (off by default)
+    pub fn with_persist_thinking(mut self, enabled: bool) -> Self {
+        self.persist_thinking = enabled;
+        self
+    }
+
     pub fn id(&self) -> &str {
         &self.id
     }
 
+    /// Mark this session as delegated from another one
+    pub fn set_parent(&mut self, parent_id: &str) {
+        self.parent_id = Some(parent_id.to_string());
+    }
+
+    pub fn parent_id(&self) -> Option<&str> {
+        self.parent_id.as_deref()
+    }
+
     pub fn token_count(&self) -> usize {
         self.token_count
     }
@@ -171,9 +283,7 @@ impl Session {
 
     /// Add a message without metadata
     pub fn add_message(&mut self, message: Message) {
-        let tokens = estimate_tokens(&message.content);
-        self.token_count += tokens;
-        self.messages.push(SessionMessage::new(message));
+        self.push(SessionMessage::new(message));
     }
 
     /// Add a message with provider/model metadata
@@ -182,12 +292,10 @@ impl Session {
         message: Message,
         provider: Option<&str>,
         model: Option<&str>,
-        usage: Option<&Usage>,
+        usage: Option<MessageUsage>,
         stop_reason: Option<&str>,
     ) {
-        let tokens = estimate_tokens(&message.content);
-        self.token_count += tokens;
-        self.messages.push(SessionMessage::with_metadata(
+        self.push(SessionMessage::with_metadata(
             message,
             provider,
             model,
@@ -196,6 +304,128 @@ impl Session {
         ));
     }
 
+    /// Append to the active branch
+    fn push(&mut self, mut sm: SessionMessage) {
+        sm.parent_id = self.messages.last().map(|last| last.id.clone());
+        self.token_count += self.tokenizer.count_message(&sm.message);
+        self.messages.push(sm);
+    }
+
+    /// ID of the last message on the active branch
+    pub fn leaf_id(&self) -> Option<&str> {
+        self.messages.last().map(|sm| sm.id.as_str())
+    }
+
+    /// Keep the first `len` messages of the active branch; the rest stay in
+    /// the session as another branch, so the next message starts a new one
+    pub fn rewind(&mut self, len: usize) {
+        if len < self.messages.len() {
+            let rest = self.messages.split_off(len);
+            self.branches.extend(rest);
+            self.recalculate_tokens();
+        }
+    }
+
+    /// Tips of all branches, including the active one
+    pub fn branches(&self) -> Vec<Branch> {
+        let lookup = self.lookup();
+        let parents: HashSet<&str> = self
+            .tree()
+            .filter_map(|sm| sm.parent_id.as_deref())
+            .collect();
+        let current = self.leaf_id();
+
+        self.tree()
+            .filter(|sm| !parents.contains(sm.id.as_str()) || Some(sm.id.as_str()) == current)
+            .map(|leaf| {
+                let path = path_to(&lookup, &leaf.id);
+                let preview = path
+                    .iter()
+                    .rev()
+                    .find(|sm| sm.message.role == Role::User)
+                    .unwrap_or(leaf)
+                    .message
+                    .content
+                    .chars()
+                    .take(80)
+                    .collect::<String>();
+                Branch {
+                    leaf_id: leaf.id.clone(),
+                    message_count: path.len(),
+                    preview: preview.trim().to_string(),
+                    current: Some(leaf.id.as_str()) == current,
+                }
+            })
+            .collect()
+    }
+
+    /// Make the branch ending at `leaf_id` (or an unambiguous prefix of it)
+    /// the active one
+    pub fn switch_branch(&mut self, leaf_id: &str) -> Result<()> {
+        let mut matching: Vec<&str> = self
+            .tree()
+            .map(|sm| sm.id.as_str())
+            .filter(|id| id.starts_with(leaf_id))
+            .collect();
+        if matching.contains(&leaf_id) {
+            matching = vec![leaf_id];
+        }
+        let leaf_id = match matching[..] {
+            [id] => id.to_string(),
+            [] => anyhow::bail!("No message {} in this session", leaf_id),
+            _ => anyhow::bail!("Message ID {} is ambiguous", leaf_id),
+        };
+
+        let path = path_to(&self.lookup(), &leaf_id);
+        let on_path: HashSet<&str> = path.iter().map(|sm| sm.id.as_str()).collect();
+        // A branch that forked before a compaction runs through messages it
+        // summarized; they'd be on the branch and in `compacted` at once
+        if self
+            .compacted
+            .iter()
+            .any(|c| on_path.contains(c.message.id.as_str()))
+        {
+            anyhow::bail!(
+                "Branch {} forked before a compaction: expand the session first",
+                leaf_id
+            );
+        }
+        let branches = self
+            .tree()
+            .filter(|sm| !on_path.contains(sm.id.as_str()))
+            .cloned()
+            .collect();
+
+        self.messages = path;
+        self.branches = branches;
+        self.recalculate_tokens();
+        Ok(())
+    }
+
+    /// Messages of every branch
+    fn tree(&self) -> impl Iterator<Item = &SessionMessage> {
+        self.branches.iter().chain(&self.messages)
+    }
+
+    /// Messages by ID, including ones compaction summarized (branches
+    /// may start from those)
+    fn lookup(&self) -> HashMap<&str, &SessionMessage> {
+        let mut lookup: HashMap<&str, &SessionMessage> =
+            self.tree().map(|sm| (sm.id.as_str(), sm)).collect();
+        for c in &self.compacted {
+            lookup.entry(c.message.id.as_str()).or_insert(&c.message);
+        }
+        lookup
+    }
+
+    /// Point each message of the active branch at the one before it
+    fn relink(&mut self) {
+        let mut parent = None;
+        for sm in &mut self.messages {
+            sm.parent_id = parent.replace(sm.id.clone());
+        }
+    }
+
     pub fn messages_for_llm(&self) -> Vec<Message> {
         let mut messages = Vec::new();
 
@@ -206,6 +436,7 @@ impl Session {
                 tool_calls: None,
                 tool_call_id: None,
                 images: Vec::new(),
+                thinking: Vec::new(),
             });
         }
 
@@ -230,48 +461,186 @@ impl Session {
             .collect()
     }
 
-    pub async fn compact(&mut self, provider: &dyn LLMProvider) -> Result<()> {
-        if self.messages.len() < 4 {
-            return Ok(());
-        }
+    /// Summarize old messages in windows of about `window_tokens`, oldest
+    /// first, until the session fits `target_tokens`. Pinned messages, tool
+    /// call/result pairs and the latest exchange (from the last user message)
+    /// are never split or summarized. Returns false if nothing could be.
+    pub async fn compact(
+        &mut self,
+        provider: &dyn LLMProvider,
+        target_tokens: usize,
+        window_tokens: usize,
+    ) -> Result<bool> {
+        let tail_start = self
+            .messages
+            .iter()
+            .rposition(|sm| sm.message.role == Role::User)
+            .unwrap_or(self.messages.len());
+
+        // A summary from an earlier compaction is folded into the new one
+        let has_earlier = self
+            .messages
+            .first()
+            .is_some_and(|sm| sm.summary_of.is_some());
+        let mut previous =
+            has_earlier.then(|| CompactionSummary::parse(&self.messages[0].message.content));
+        let mut tokens = self.token_count;
+        let mut summarized: Vec<usize> = Vec::new();
+        let mut summary = None;
+
+        let candidates: Vec<_> = compaction::units(&self.messages)
+            .into_iter()
+            .filter(|unit| unit.end <= tail_start && !(unit.start == 0 && has_earlier))
+            .filter(|unit| !self.messages[unit.clone()].iter().any(|sm| sm.pinned))
+            .collect();
+        let mut units = candidates.into_iter().peekable();
 
-        let keep_count = 4;
-        let to_summarize = &self.messages[..self.messages.len() - keep_count];
+        while tokens > target_tokens && units.peek().is_some() {
+            let mut window: Vec<usize> = Vec::new();
+            let mut window_size = 0;
+            while let Some(unit) =
+                units.next_if(|_| window.is_empty() || window_size < window_tokens)
+            {
+                for i in unit {
+                    window_size += self.tokenizer.count_message(&self.messages[i].message);
+                    window.push(i);
+                }
+            }
 
-        let text: String = to_summarize
-            .iter()
-            .map(|sm| format!("{:?}: {}", sm.message.role, sm.message.content))
-            .collect::<Vec<_>>()
-            .join("\n\n");
+            let window_messages: Vec<&SessionMessage> =
+                window.iter().map(|&i| &self.messages[i]).collect();
+            let previous_text = previous.as_ref().map(CompactionSummary::render);
+            let reply = provider
+                .summarize(&compaction::request(
+                    previous_text.as_deref(),
+                    &window_messages,
+                ))
+                .await?;
+
+            let mut next = CompactionSummary::parse(&reply);
+            if let Some(ref previous) = previous {
+                next.add_files(previous.files_touched.iter().cloned());
+            }
+            next.add_files(compaction::files_touched(&window_messages));
+
+            let replaced = previous_text.map_or(0, |text| self.tokenizer.count(&text));
+            tokens = (tokens + self.tokenizer.count(&next.render()))
+                .saturating_sub(window_size + replaced);
+            summarized.extend(window);
+            previous = Some(next.clone());
+            summary = Some(next);
+        }
+
+        let Some(summary) = summary else {
+            return Ok(false);
+        };
 
-        let summary = provider.summarize(&text).await?;
+        self.compaction_count += 1;
+        let id = self.compaction_count;
+        if has_earlier {
+            summarized.push(0);
+        }
+        summarized.sort_unstable();
 
-        let mut new_messages = vec![SessionMessage::new(Message {
+        let old = std::mem::take(&mut self.messages);
+        let mut summary_message = SessionMessage::new(Message {
             role: Role::System,
-            content: format!("Previous conversation summary:\n\n{}", summary),
+            content: summary.render(),
             tool_calls: None,
             tool_call_id: None,
             images: Vec::new(),
-        })];
+            thinking: Vec::new(),
+        });
+        summary_message.summary_of = Some(id);
+        self.messages.push(summary_message);
+
+        for (position, message) in old.into_iter().enumerate() {
+            if summarized.binary_search(&position).is_ok() {
+                self.compacted.push(CompactedMessage {
+                    compaction: id,
+                    position,
+                    message,
+                });
+            } else {
+                self.messages.push(message);
+            }
+        }
 
-        new_messages.extend(self.messages[self.messages.len() - keep_count..].to_vec());
+        self.relink();
+        self.recalculate_tokens();
+        Ok(true)
+    }
 
-        self.messages = new_messages;
-        self.compaction_count += 1;
+    /// Put back the messages the latest compaction summarized, in place of
+    /// its summary. Returns how many were restored (0: nothing to expand).
+    pub fn expand(&mut self) -> usize {
+        let Some(id) = self.messages.first().and_then(|sm| sm.summary_of) else {
+            return 0;
+        };
+        let (restored, kept): (Vec<_>, Vec<_>) = std::mem::take(&mut self.compacted)
+            .into_iter()
+            .partition(|c| c.compaction == id);
+        self.compacted = kept;
+        if restored.is_empty() {
+            return 0;
+        }
+
+        let mut restored: Vec<_> = restored
+            .into_iter()
+            .map(|c| (c.position, c.message))
+            .collect();
+        restored.sort_by_key(|(position, _)| *position);
+        let count = restored.len();
+
+        // The rest of the transcript fills the positions compaction kept
+        let mut rest = self.messages.split_off(1).into_iter();
+        let mut restored = restored.into_iter().peekable();
+        let mut messages = Vec::new();
+        for position in 0.. {
+            match restored.next_if(|(p, _)| *p == position) {
+                Some((_, message)) => messages.push(message),
+                None => match rest.next() {
+                    Some(message) => messages.push(message),
+                    None => break,
+                },
+            }
+        }
+        messages.extend(restored.map(|(_, message)| message));
+
+        self.messages = messages;
+        self.relink();
         self.recalculate_tokens();
+        count
+    }
 
+    /// Pin or unpin the message at `index` (0-based); compaction keeps
+    /// pinned messages verbatim
+    pub fn set_pinned(&mut self, index: usize, pinned: bool) -> Result<()> {
+        let len = self.messages.len();
+        let sm = self
+            .messages
+            .get_mut(index)
+            .ok_or_else(|| anyhow::anyhow!("No message {} (session has {})", index + 1, len))?;
+        sm.pinned = pinned;
         Ok(())
     }
 
+    /// Index of the latest user message
+    pub fn last_user_index(&self) -> Option<usize> {
+        self.messages
+            .iter()
+            .rposition(|sm| sm.message.role == Role::User)
+    }
+
     fn recalculate_tokens(&mut self) {
         self.token_count = 0;
 
         if let Some(ref context) = self.system_context {
-            self.token_count += estimate_tokens(context);
+            self.token_count += self.tokenizer.count(context);
         }
 
         for sm in &self.messages {
-            self.token_count += estimate_tokens(&sm.message.content);
+            self.token_count += self.tokenizer.count_message(&sm.message);
         }
     }
 
@@ -294,11 +663,20 @@ impl Session {
         Ok(path)
     }
 
+    /// Save into a given sessions directory (sub-agents save next to their parent)
+    pub fn save_to_dir(&self, dir: &Path) -> Result<PathBuf> {
+        fs::create_dir_all(dir)?;
+
+        let path = dir.join(format!("{}.jsonl", self.id));
+        self.save_to_path(&path)?;
+        Ok(path)
+    }
+
     fn save_to_path(&self, path: &PathBuf) -> Result<()> {
         let mut file = File::create(path)?;
 
         // Write Pi-compatible header
-        let header = json!({
+        let mut header = json!({
             "type": "session",
             "version": CURRENT_SESSION_VERSION,
             "id": self.id,
@@ -308,6 +686,9 @@ impl Session {
             "compactionCount": self.compaction_count,
             "memoryFlushCompactionCount": self.memory_flush_compaction_count
         });
+        if let Some(ref parent) = self.parent_id {
+            header["parentSession"] = json!(parent);
+        }
         writeln!(file, "{}", serde_json::to_string(&header)?)?;
 
         // Write system context as a system message
@@ -318,16 +699,27 @@ impl Session {
                 tool_calls: None,
                 tool_call_id: None,
                 images: Vec::new(),
+                thinking: Vec::new(),
             }));
             writeln!(file, "{}", serde_json::to_string(&system_msg)?)?;
         }
 
-        // Write messages in Pi format
-        for sm in &self.messages {
+        // Write messages in Pi format: other branches first, so the last
+        // message entry is the tip of the active branch
+        for sm in self.branches.iter().chain(&self.messages) {
             let entry = self.format_message_entry(sm);
             writeln!(file, "{}", serde_json::to_string(&entry)?)?;
         }
 
+        // Messages replaced by compaction summaries (LocalGPT extension)
+        for c in &self.compacted {
+            let mut entry = self.format_message_entry(&c.message);
+            entry["type"] = json!("compacted");
+            entry["compaction"] = json!(c.compaction);
+            entry["position"] = json!(c.position);
+            writeln!(file, "{}", serde_json::to_string(&entry)?)?;
+        }
+
         Ok(())
     }
 
@@ -343,6 +735,23 @@ impl Session {
         // Build content array (Pi format)
         let mut content = Vec::new();
 
+        // Add reasoning ahead of the answer, when enabled
+        if self.persist_thinking {
+            for block in &sm.message.thinking {
+                let mut entry = json!({
+                    "type": "thinking",
+                    "thinking": block.text
+                });
+                if let Some(ref signature) = block.signature {
+                    entry["thinkingSignature"] = json!(signature);
+                }
+                if let Some(ref data) = block.redacted {
+                    entry["redacted"] = json!(data);
+                }
+                content.push(entry);
+            }
+        }
+
         // Add text content
         if !sm.message.content.is_empty() {
             content.push(json!({
@@ -404,9 +813,17 @@ impl Session {
             message["stopReason"] = json!(reason);
         }
         message["timestamp"] = json!(sm.timestamp);
+        if sm.pinned {
+            message["pinned"] = json!(true);
+        }
+        if let Some(id) = sm.summary_of {
+            message["compactionSummary"] = json!(id);
+        }
 
         json!({
             "type": "message",
+            "id": sm.id,
+            "parentId": sm.parent_id,
             "message": message
         })
     }
@@ -432,11 +849,18 @@ impl Session {
             created_at: Utc::now(),
             cwd: ".".to_string(),
             messages: Vec::new(),
+            branches: Vec::new(),
             system_context: None,
             token_count: 0,
             compaction_count: 0,
             memory_flush_compaction_count: 0,
+            persist_thinking: false,
+            parent_id: None,
+            tokenizer: Arc::new(Tiktoken::cl100k()),
+            compacted: Vec::new(),
         };
+        // Messages of all branches, in file order
+        let mut tree: Vec<SessionMessage> = Vec::new();
 
         for line in reader.lines() {
             let line = line?;
@@ -466,28 +890,65 @@ impl Session {
                     if let Some(count) = entry["memoryFlushCompactionCount"].as_u64() {
                         session.memory_flush_compaction_count = count as u32;
                     }
+                    if let Some(parent) = entry["parentSession"].as_str() {
+                        session.parent_id = Some(parent.to_string());
+                    }
                 }
                 // Pi format message
                 Some("message") => {
-                    if let Some(msg_obj) = entry.get("message")
-                        && let Some(sm) = Self::parse_pi_message(msg_obj)
-                    {
+                    if let Some(mut sm) = Self::parse_entry(&entry) {
                         // System messages become system_context
-                        if sm.message.role == Role::System && session.system_context.is_none() {
+                        if sm.message.role == Role::System
+                            && sm.summary_of.is_none()
+                            && session.system_context.is_none()
+                        {
                             session.system_context = Some(sm.message.content);
                         } else {
-                            session.messages.push(sm);
+                            // Version 1 entries have no IDs: chain them in order
+                            if entry["id"].is_null() {
+                                sm.parent_id = tree.last().map(|last| last.id.clone());
+                            }
+                            tree.push(sm);
                         }
                     }
                 }
+                Some("compacted") => {
+                    if let (Some(compaction), Some(position), Some(sm)) = (
+                        entry["compaction"].as_u64(),
+                        entry["position"].as_u64(),
+                        Self::parse_entry(&entry),
+                    ) {
+                        session.compacted.push(CompactedMessage {
+                            compaction: compaction as u32,
+                            position: position as usize,
+                            message: sm,
+                        });
+                    }
+                }
                 _ => {}
             }
         }
 
+        // The active branch ends at the last message entry
+        session.branches = tree;
+        if let Some(leaf) = session.branches.last().map(|sm| sm.id.clone()) {
+            session.switch_branch(&leaf)?;
+        }
+
         session.recalculate_tokens();
         Ok(session)
     }
 
+    /// Parse a message or compacted entry, keeping its place in the tree
+    fn parse_entry(entry: &serde_json::Value) -> Option<SessionMessage> {
+        let mut sm = Self::parse_pi_message(entry.get("message")?)?;
+        if let Some(id) = entry["id"].as_str() {
+            sm.id = id.to_string();
+        }
+        sm.parent_id = entry["parentId"].as_str().map(|s| s.to_string());
+        Some(sm)
+    }
+
     /// Parse Pi format message
     fn parse_pi_message(msg: &serde_json::Value) -> Option<SessionMessage> {
         let role = match msg["role"].as_str()? {
@@ -531,16 +992,53 @@ impl Session {
 
         let tool_call_id = msg["toolCallId"].as_str().map(|s| s.to_string());
 
+        // Parse thinking blocks (only present when they were persisted)
+        let thinking = msg["content"]
+            .as_array()
+            .map(|arr| {
+                arr.iter()
+                    .filter(|item| item["type"].as_str() == Some("thinking"))
+                    .map(|item| ThinkingBlock {
+                        text: item["thinking"].as_str().unwrap_or("").to_string(),
+                        signature: item["thinkingSignature"].as_str().map(|s| s.to_string()),
+                        redacted: item["redacted"].as_str().map(|s| s.to_string()),
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        // Parse images saved as data URLs
+        let images = msg["content"]
+            .as_array()
+            .map(|arr| {
+                arr.iter()
+                    .filter(|item| item["type"].as_str() == Some("image_url"))
+                    .filter_map(|item| {
+                        let url = item["image_url"]["url"].as_str()?;
+                        let (media_type, data) =
+                            url.strip_prefix("data:")?.split_once(";base64,")?;
+                        Some(ImageAttachment {
+                            data: data.to_string(),
+                            media_type: media_type.to_string(),
+                        })
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+
         // Parse usage
         let usage = serde_json::from_value(msg["usage"].clone()).ok();
 
         Some(SessionMessage {
+            id: new_message_id(),
+            parent_id: None,
             message: Message {
                 role,
                 content,
                 tool_calls,
                 tool_call_id,
-                images: Vec::new(), // TODO: parse images from content array
+                images,
+                thinking,
             },
             provider: msg["provider"].as_str().map(|s| s.to_string()),
             model: msg["model"].as_str().map(|s| s.to_string()),
@@ -548,28 +1046,27 @@ impl Session {
             usage,
             stop_reason: msg["stopReason"].as_str().map(|s| s.to_string()),
             timestamp: msg["timestamp"].as_u64().unwrap_or(0),
+            pinned: msg["pinned"].as_bool().unwrap_or(false),
+            summary_of: msg["compactionSummary"].as_u64().map(|id| id as u32),
         })
     }
 
     pub fn status(&self) -> SessionStatus {
-        SessionStatus {
-            id: self.id.clone(),
-            message_count: self.messages.len(),
-            token_count: self.token_count,
-            compaction_count: self.compaction_count,
-            api_input_tokens: 0,
-            api_output_tokens: 0,
-        }
+        self.status_with_usage(&Usage::default())
     }
 
-    pub fn status_with_usage(&self, input_tokens: u64, output_tokens: u64) -> SessionStatus {
+    pub fn status_with_usage(&self, usage: &Usage) -> SessionStatus {
         SessionStatus {
             id: self.id.clone(),
             message_count: self.messages.len(),
             token_count: self.token_count,
+            tokenizer: self.tokenizer.name().to_string(),
             compaction_count: self.compaction_count,
-            api_input_tokens: input_tokens,
-            api_output_tokens: output_tokens,
+            api_input_tokens: usage.input_tokens,
+            api_output_tokens: usage.output_tokens,
+            api_cache_read_tokens: usage.cache_read_tokens,
+            api_cache_write_tokens: usage.cache_write_tokens,
+            api_cost_usd: 0.0,
         }
     }
 
@@ -600,15 +1097,31 @@ pub fn get_sessions_dir_for_agent(agent_id: &str) -> Result<PathBuf> {
     Ok(paths.sessions_dir(agent_id))
 }
 
+fn new_message_id() -> String {
+    Uuid::new_v4().simple().to_string()[..8].to_string()
+}
+
+/// Messages from the root to `leaf_id`, as far as parents can be found
+fn path_to(lookup: &HashMap<&str, &SessionMessage>, leaf_id: &str) -> Vec<SessionMessage> {
+    let mut path = Vec::new();
+    let mut next = Some(leaf_id);
+    while let Some(sm) = next.and_then(|id| lookup.get(id)) {
+        // A cycle would mean a corrupt file; stop rather than loop
+        if path.len() > lookup.len() {
+            break;
+        }
+        path.push((*sm).clone());
+        next = sm.parent_id.as_deref();
+    }
+    path.reverse();
+    path
+}
+
 pub fn get_state_dir() -> Result<PathBuf> {
     let paths = crate::paths::Paths::resolve()?;
     Ok(paths.state_dir)
 }
 
-fn estimate_tokens(text: &str) -> usize {
-    text.len() / 4
-}
-
 #[derive(Debug, Clone)]
 pub struct SessionInfo {
     pub id: String,
@@ -621,6 +1134,16 @@ pub fn list_sessions() -> Result<Vec<SessionInfo>> {
     list_sessions_for_agent(DEFAULT_AGENT_ID)
 }
 
+/// Message entries in a session file; those compaction replaced are
+/// "compacted" entries and don't count
+fn count_message_entries(content: &str) -> usize {
+    content
+        .lines()
+        .filter_map(|l| serde_json::from_str::<serde_json::Value>(l).ok())
+        .filter(|entry| entry["type"].as_str() == Some("message"))
+        .count()
+}
+
 pub fn list_sessions_for_agent(agent_id: &str) -> Result<Vec<SessionInfo>> {
     let sessions_dir = get_sessions_dir_for_agent(agent_id)?;
 
@@ -661,7 +1184,7 @@ pub fn list_sessions_for_agent(agent_id: &str) -> Result<Vec<SessionInfo>> {
                         .unwrap_or_else(Utc::now);
 
                     let message_count = fs::read_to_string(&path)
-                        .map(|s| s.lines().count().saturating_sub(1))
+                        .map(|s| count_message_entries(&s))
                         .unwrap_or(0);
 
                     sessions.push(SessionInfo {
@@ -784,10 +1307,311 @@ mod tests {
         let usage = Usage {
             input_tokens: 100,
             output_tokens: 50,
+            ..Default::default()
         };
         let msg_usage = MessageUsage::from(&usage);
         assert_eq!(msg_usage.input, 100);
         assert_eq!(msg_usage.output, 50);
         assert_eq!(msg_usage.total_tokens, 150);
+        assert!(msg_usage.cache_read.is_none());
+        assert!(msg_usage.cache_write.is_none());
+
+        let usage = Usage {
+            input_tokens: 10,
+            output_tokens: 50,
+            cache_read_tokens: 900,
+            cache_write_tokens: 0,
+        };
+        let msg_usage = MessageUsage::from(&usage);
+        assert_eq!(msg_usage.cache_read, Some(900));
+        assert!(msg_usage.cache_write.is_none());
+        assert_eq!(msg_usage.total_tokens, 960);
+    }
+
+    #[test]
+    fn test_thinking_persisted_only_when_enabled() {
+        let message = SessionMessage::new(Message {
+            role: Role::Assistant,
+            content: "Done.".to_string(),
+            tool_calls: None,
+            tool_call_id: None,
+            images: Vec::new(),
+            thinking: vec![ThinkingBlock {
+                text: "Check the file first.".to_string(),
+                signature: Some("sig".to_string()),
+                redacted: None,
+            }],
+        });
+
+        let entry = Session::new().format_message_entry(&message);
+        let parsed = Session::parse_pi_message(&entry["message"]).unwrap();
+        assert_eq!(parsed.message.content, "Done.");
+        assert!(parsed.message.thinking.is_empty());
+
+        let session = Session::new().with_persist_thinking(true);
+        let entry = session.format_message_entry(&message);
+        assert_eq!(entry["message"]["content"][0]["type"], "thinking");
+        let parsed = Session::parse_pi_message(&entry["message"]).unwrap();
+        assert_eq!(parsed.message.content, "Done.");
+        assert_eq!(parsed.message.thinking, message.message.thinking);
+    }
+
+    #[test]
+    fn test_parent_link_round_trip() {
+        let tmp = tempfile::tempdir().unwrap();
+        let mut child = Session::new();
+        child.set_parent("parent-session");
+        child.add_message(Message {
+            role: Role::User,
+            content: "Summarize the notes".to_string(),
+            tool_calls: None,
+            tool_call_id: None,
+            images: Vec::new(),
+            thinking: Vec::new(),
+        });
+
+        let path = child.save_to_dir(tmp.path()).unwrap();
+        assert_eq!(path, tmp.path().join(format!("{}.jsonl", child.id())));
+        let loaded = Session::load_from_path(&path, child.id()).unwrap();
+        assert_eq!(loaded.parent_id(), Some("parent-session"));
+        assert_eq!(loaded.messages.len(), 1);
+        assert!(Session::new().parent_id().is_none());
+    }
+
+    /// Answers every summary request in the structured format, recording it
+    #[derive(Default)]
+    struct Summarizer(std::sync::Mutex<Vec<String>>);
+
+    #[async_trait::async_trait]
+    impl LLMProvider for Summarizer {
+        async fn chat(
+            &self,
+            _: &[Message],
+            _: Option<&[super::super::providers::ToolSchema]>,
+        ) -> Result<super::super::providers::LLMResponse> {
+            anyhow::bail!("not used")
+        }
+
+        async fn summarize(&self, text: &str) -> Result<String> {
+            let mut requests = self.0.lock().unwrap();
+            requests.push(text.to_string());
+            Ok(format!(
+                "## Summary\nPart {}.\n\n## Decisions\n- none\n\n## Open tasks\n- Finish main\n",
+                requests.len()
+            ))
+        }
+    }
+
+    fn message(role: Role, content: &str) -> Message {
+        Message {
+            role,
+            content: content.to_string(),
+            tool_calls: None,
+            tool_call_id: None,
+            images: Vec::new(),
+            thinking: Vec::new(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_compaction_keeps_pairs_and_pins_and_expands() {
+        let tmp = tempfile::tempdir().unwrap();
+        let summarizer = Summarizer::default();
+        let mut session = Session::new();
+        session.add_message(message(Role::User, "My name is Ada"));
+        session.add_message(message(Role::Assistant, "Hi Ada"));
+        session.add_message(message(Role::User, "Read main"));
+        session.add_message(Message {
+            tool_calls: Some(vec![ToolCall {
+                id: "call_1".to_string(),
+                name: "read_file".to_string(),
+                arguments: r#"{"path":"src/main.rs"}"#.to_string(),
+            }]),
+            ..message(Role::Assistant, "")
+        });
+        session.add_message(Message {
+            tool_call_id: Some("call_1".to_string()),
+            ..message(Role::Tool, "fn main() {}")
+        });
+        session.add_message(message(Role::Assistant, "main is empty"));
+        session.add_message(message(Role::User, "What next?"));
+        session.add_message(message(Role::Assistant, "Fill it in"));
+        session.set_pinned(0, true).unwrap();
+        let original: Vec<String> = session
+            .messages()
+            .iter()
+            .map(|m| m.content.clone())
+            .collect();
+
+        // One unit per window: "Hi Ada", "Read main", the tool pair, the reply
+        assert!(session.compact(&summarizer, 0, 1).await.unwrap());
+        let requests = summarizer.0.lock().unwrap().clone();
+        assert_eq!(requests.len(), 4);
+        assert!(requests[1].contains("# Earlier summary"));
+        assert!(requests[2].contains("[tool call: read_file]"));
+        assert!(requests[2].contains("[tool result] fn main() {}"));
+
+        let contents: Vec<&str> = session
+            .messages()
+            .iter()
+            .map(|m| m.content.as_str())
+            .collect();
+        assert_eq!(
+            &contents[1..],
+            ["My name is Ada", "What next?", "Fill it in"]
+        );
+        let summary = CompactionSummary::parse(contents[0]);
+        assert_eq!(summary.summary, "Part 4.");
+        assert_eq!(summary.open_tasks, ["Finish main"]);
+        assert_eq!(summary.files_touched, ["src/main.rs"]);
+
+        // Summaries and the summarized messages survive a save and load
+        let path = session.save_to_dir(tmp.path()).unwrap();
+        let mut session = Session::load_from_path(&path, session.id()).unwrap();
+        assert_eq!(session.raw_messages()[0].summary_of, Some(1));
+        assert!(session.raw_messages()[1].pinned);
+        assert_eq!(session.compacted.len(), 5);
+
+        // Nothing left to summarize until the conversation moves on
+        assert!(!session.compact(&summarizer, 0, 1).await.unwrap());
+        session.add_message(message(Role::User, "Done"));
+        assert!(session.compact(&summarizer, 0, 1000).await.unwrap());
+        assert!(summarizer.0.lock().unwrap()[4].contains("Part 4."));
+        assert_eq!(session.messages().len(), 3);
+
+        assert_eq!(session.expand(), 3);
+        assert_eq!(session.expand(), 5);
+        assert_eq!(session.expand(), 0);
+        let mut expanded: Vec<String> = session
+            .messages()
+            .iter()
+            .map(|m| m.content.clone())
+            .collect();
+        assert_eq!(expanded.pop().as_deref(), Some("Done"));
+        assert_eq!(expanded, original);
+        assert!(session.compacted.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_switching_to_a_branch_from_before_a_compaction() {
+        let summarizer = Summarizer::default();
+        let mut session = Session::new();
+        session.add_message(message(Role::User, "Name a colour"));
+        session.add_message(message(Role::Assistant, "Red"));
+        session.add_message(message(Role::User, "Another"));
+        session.add_message(message(Role::Assistant, "Blue"));
+        let blue = session.leaf_id().unwrap().to_string();
+        session.rewind(3);
+        session.add_message(message(Role::Assistant, "Green"));
+        session.add_message(message(Role::User, "And one more"));
+        session.add_message(message(Role::Assistant, "Yellow"));
+        assert!(session.compact(&summarizer, 0, 1000).await.unwrap());
+
+        // The Blue branch starts with messages the summary replaced
+        let err = session.switch_branch(&blue).unwrap_err();
+        assert!(err.to_string().contains("expand"));
+
+        assert!(session.expand() > 0);
+        session.switch_branch(&blue).unwrap();
+        let contents: Vec<&str> = session
+            .messages()
+            .iter()
+            .map(|m| m.content.as_str())
+            .collect();
+        assert_eq!(contents, ["Name a colour", "Red", "Another", "Blue"]);
+        assert!(session.compacted.is_empty());
+        // Nothing to expand twice
+        assert_eq!(session.expand(), 0);
+        assert_eq!(session.tree().count(), 7);
+    }
+
+    #[test]
+    fn test_branches_switch_and_persist() {
+        let tmp = tempfile::tempdir().unwrap();
+        let contents = |session: &Session| -> Vec<String> {
+            session
+                .messages()
+                .iter()
+                .map(|m| m.content.clone())
+                .collect()
+        };
+        let mut session = Session::new();
+        session.add_message(message(Role::User, "Name a colour"));
+        session.add_message(message(Role::Assistant, "Red"));
+        session.add_message(message(Role::User, "Another"));
+        session.add_message(message(Role::Assistant, "Blue"));
+        let first_leaf = session.leaf_id().unwrap().to_string();
+
+        // Retry the last message: its reply moves to another branch
+        session.rewind(3);
+        session.add_message(message(Role::Assistant, "Green"));
+        // Edit the first message
+        session.rewind(0);
+        session.add_message(message(Role::User, "Name a fruit"));
+        session.add_message(message(Role::Assistant, "Pear"));
+        assert_eq!(session.raw_messages()[0].parent_id, None);
+        assert_eq!(
+            session.raw_messages()[1].parent_id.as_deref(),
+            Some(session.raw_messages()[0].id.as_str())
+        );
+
+        let branches = session.branches();
+        assert_eq!(branches.len(), 3);
+        assert_eq!(branches.iter().filter(|b| b.current).count(), 1);
+        let current = branches.iter().find(|b| b.current).unwrap();
+        assert_eq!(
+            (current.message_count, current.preview.as_str()),
+            (2, "Name a fruit")
+        );
+
+        session.switch_branch(&first_leaf[..6]).unwrap();
+        assert_eq!(
+            contents(&session),
+            ["Name a colour", "Red", "Another", "Blue"]
+        );
+        assert!(session.switch_branch("zzzz").is_err());
+
+        // The file's last message entry is the active leaf
+        let path = session.save_to_dir(tmp.path()).unwrap();
+        let mut loaded = Session::load_from_path(&path, session.id()).unwrap();
+        assert_eq!(contents(&loaded), contents(&session));
+        assert_eq!(loaded.branches().len(), 3);
+        let green = loaded
+            .branches()
+            .into_iter()
+            .find(|b| b.message_count == 4 && !b.current)
+            .unwrap();
+        loaded.switch_branch(&green.leaf_id).unwrap();
+        assert_eq!(loaded.messages()[3].content, "Green");
+
+        // Version 1 files have no IDs; their messages form one branch
+        let v1 = tmp.path().join("v1.jsonl");
+        fs::write(
+            &v1,
+            [
+                r#"{"type":"session","version":1,"id":"v1","timestamp":"2024-01-01T00:00:00Z","cwd":"."}"#,
+                r#"{"type":"message","message":{"role":"user","content":[{"type":"text","text":"Hi"}]}}"#,
+                r#"{"type":"message","message":{"role":"assistant","content":[{"type":"text","text":"Hello"}]}}"#,
+            ]
+            .join("\n"),
+        )
+        .unwrap();
+        let old = Session::load_from_path(&v1, "v1").unwrap();
+        assert_eq!(contents(&old), ["Hi", "Hello"]);
+        assert_eq!(old.branches().len(), 1);
+    }
+
+    #[test]
+    fn test_count_message_entries() {
+        let content = [
+            r#"{"type":"session","version":2,"id":"s","timestamp":"2024-01-01T00:00:00Z","cwd":"."}"#,
+            r#"{"type":"compacted","compaction":1,"position":0,"message":{"role":"user","content":[{"type":"text","text":"Old"}]}}"#,
+            // Text that happens to look like a compacted entry still counts
+            r#"{"type":"message","message":{"role":"user","content":[{"type":"text","text":"\"type\":\"compacted\""}]}}"#,
+            r#"{"type":"message","message":{"role":"assistant","content":[{"type":"text","text":"Hello"}]}}"#,
+            "not json",
+        ]
+        .join("\n");
+        assert_eq!(count_message_entries(&content), 2);
     }
 }
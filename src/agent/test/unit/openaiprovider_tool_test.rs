@@ -69,6 +69,7 @@ async fn test_default_chat_stream_forwards_tools() {
         tool_calls: None,
         tool_call_id: None,
         images: Vec::new(),
+        thinking: Vec::new(),
     }];
     let tools = vec![ToolSchema {
         name: "bash".to_string(),
@@ -96,6 +97,7 @@ async fn test_default_chat_stream_returns_text_as_stream_chunk() {
         tool_calls: None,
         tool_call_id: None,
         images: Vec::new(),
+        thinking: Vec::new(),
     }];
 
     let mut stream = provider
@@ -125,6 +127,7 @@ async fn test_default_chat_stream_returns_tool_calls_as_stream_chunk() {
         tool_calls: None,
         tool_call_id: None,
         images: Vec::new(),
+        thinking: Vec::new(),
     }];
 
     let mut stream = provider
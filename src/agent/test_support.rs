@@ -0,0 +1,103 @@
+//! Helpers for tests that drive a whole `Agent` offline
+
+use anyhow::Result;
+use async_trait::async_trait;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use super::providers::{LLMProvider, LLMResponse, Message, ToolCall, ToolSchema};
+use super::{Agent, AgentConfig};
+use crate::config::{Config, OllamaConfig};
+use crate::memory::MemoryManager;
+use crate::paths::Paths;
+
+/// Provider that plays back a fixed script of responses. `calls` logs
+/// the last message of each chat request, and "summarize" for summaries.
+pub(crate) struct ScriptedProvider {
+    responses: Mutex<Vec<LLMResponse>>,
+    pub(crate) calls: Arc<Mutex<Vec<String>>>,
+}
+
+impl ScriptedProvider {
+    pub(crate) fn new(mut responses: Vec<LLMResponse>) -> Self {
+        responses.reverse();
+        Self {
+            responses: Mutex::new(responses),
+            calls: Arc::default(),
+        }
+    }
+}
+
+#[async_trait]
+impl LLMProvider for ScriptedProvider {
+    async fn chat(
+        &self,
+        messages: &[Message],
+        _tools: Option<&[ToolSchema]>,
+    ) -> Result<LLMResponse> {
+        self.calls.lock().unwrap().push(
+            messages
+                .last()
+                .map(|m| m.content.clone())
+                .unwrap_or_default(),
+        );
+        self.responses
+            .lock()
+            .unwrap()
+            .pop()
+            .ok_or_else(|| anyhow::anyhow!("ScriptedProvider exhausted"))
+    }
+
+    async fn summarize(&self, text: &str) -> Result<String> {
+        self.calls.lock().unwrap().push("summarize".to_string());
+        Ok(format!("summary of {} chars", text.len()))
+    }
+}
+
+pub(crate) fn tool_call(name: &str, arguments: &str) -> ToolCall {
+    ToolCall {
+        id: "call_1".to_string(),
+        name: name.to_string(),
+        arguments: arguments.to_string(),
+    }
+}
+
+/// Config with every path under `root`, no embeddings and an unreachable
+/// Ollama endpoint
+pub(crate) fn test_config(root: &Path) -> Config {
+    let mut config = Config {
+        paths: Paths {
+            config_dir: root.join("config"),
+            data_dir: root.join("data"),
+            workspace: root.join("workspace"),
+            state_dir: root.join("state"),
+            cache_dir: root.join("cache"),
+            runtime_dir: None,
+        },
+        ..Default::default()
+    };
+    config.memory.embedding_provider = "none".to_string();
+    config.providers.ollama = Some(OllamaConfig {
+        endpoint: "http://127.0.0.1:9".to_string(),
+        model: "llama3".to_string(),
+        retry: Default::default(),
+    });
+    config
+}
+
+pub(crate) async fn new_agent(config: &Config, model: &str) -> Agent {
+    new_agent_for(config, model, "main").await
+}
+
+pub(crate) async fn new_agent_for(config: &Config, model: &str, agent_id: &str) -> Agent {
+    let memory =
+        MemoryManager::new_with_full_config(&config.memory, Some(config), agent_id).unwrap();
+    let agent_config = AgentConfig {
+        model: model.to_string(),
+        context_window: config.agent.context_window,
+        reserve_tokens: config.agent.reserve_tokens,
+    };
+    Agent::new(agent_config, config, agent_id, memory)
+        .await
+        .unwrap()
+}
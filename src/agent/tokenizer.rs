@@ -0,0 +1,223 @@
+//! Token counting per model
+//!
+//! Context usage, compaction thresholds and memory chunk sizes are measured
+//! with the tokenizer of the model in use: tiktoken's encodings for OpenAI
+//! models, cl100k scaled up as an approximation for Anthropic models (their
+//! tokenizer isn't public), and the model's own vocabulary for local GGUF
+//! models. Other providers are estimated with cl100k.
+
+use std::sync::Arc;
+
+use tiktoken_rs::CoreBPE;
+use tiktoken_rs::tokenizer::{Tokenizer as Encoding, get_tokenizer};
+
+use super::models::ModelInfo;
+use super::providers::{Message, ToolSchema};
+use crate::config::Config;
+
+/// Tokens of framing around each message (role, separators)
+const MESSAGE_OVERHEAD: usize = 4;
+
+/// Anthropic's tokenizer yields ~15% more tokens than cl100k on English and code
+const ANTHROPIC_SCALE_PERCENT: usize = 115;
+
+pub trait Tokenizer: Send + Sync {
+    /// Name for status output (e.g. "o200k_base")
+    fn name(&self) -> &str;
+
+    fn count(&self, text: &str) -> usize;
+
+    /// Tokens a message takes in a request: content, tool calls and framing
+    fn count_message(&self, message: &Message) -> usize {
+        let calls: usize = message
+            .tool_calls
+            .iter()
+            .flatten()
+            .map(|call| self.count(&call.name) + self.count(&call.arguments))
+            .sum();
+        MESSAGE_OVERHEAD + self.count(&message.content) + calls
+    }
+
+    /// Tokens the tool definitions take in a request
+    fn count_tools(&self, tools: &[ToolSchema]) -> usize {
+        tools
+            .iter()
+            .map(|tool| {
+                MESSAGE_OVERHEAD
+                    + self.count(&tool.name)
+                    + self.count(&tool.description)
+                    + self.count(&tool.parameters.to_string())
+            })
+            .sum()
+    }
+}
+
+impl std::fmt::Debug for dyn Tokenizer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Tokenizer({})", self.name())
+    }
+}
+
+/// A tiktoken encoding, optionally scaled to approximate another tokenizer
+pub struct Tiktoken {
+    name: &'static str,
+    bpe: &'static CoreBPE,
+    scale_percent: usize,
+}
+
+impl Tiktoken {
+    pub fn cl100k() -> Self {
+        Self::new(Encoding::Cl100kBase)
+    }
+
+    fn new(encoding: Encoding) -> Self {
+        let (name, bpe) = match encoding {
+            Encoding::O200kHarmony => ("o200k_harmony", tiktoken_rs::o200k_harmony_singleton()),
+            Encoding::O200kBase => ("o200k_base", tiktoken_rs::o200k_base_singleton()),
+            Encoding::Cl100kBase => ("cl100k_base", tiktoken_rs::cl100k_base_singleton()),
+            Encoding::P50kBase => ("p50k_base", tiktoken_rs::p50k_base_singleton()),
+            Encoding::P50kEdit => ("p50k_edit", tiktoken_rs::p50k_edit_singleton()),
+            Encoding::R50kBase | Encoding::Gpt2 => {
+                ("r50k_base", tiktoken_rs::r50k_base_singleton())
+            }
+        };
+        Self {
+            name,
+            bpe,
+            scale_percent: 100,
+        }
+    }
+
+    /// Approximation of Anthropic's tokenizer
+    fn anthropic() -> Self {
+        Self {
+            name: "anthropic (cl100k estimate)",
+            scale_percent: ANTHROPIC_SCALE_PERCENT,
+            ..Self::cl100k()
+        }
+    }
+}
+
+impl Tokenizer for Tiktoken {
+    fn name(&self) -> &str {
+        self.name
+    }
+
+    fn count(&self, text: &str) -> usize {
+        let tokens = self.bpe.encode_ordinary(text).len();
+        (tokens * self.scale_percent).div_ceil(100)
+    }
+}
+
+/// ~4 characters per token, for when no vocabulary is available
+pub struct Heuristic;
+
+impl Tokenizer for Heuristic {
+    fn name(&self) -> &str {
+        "heuristic (4 chars/token)"
+    }
+
+    fn count(&self, text: &str) -> usize {
+        text.len().div_ceil(4)
+    }
+}
+
+/// Tokenizer for a model
+#[cfg_attr(not(feature = "gguf"), allow(unused_variables))]
+pub fn for_model(info: &ModelInfo, config: &Config) -> Arc<dyn Tokenizer> {
+    match info.provider.as_str() {
+        "openai" => Arc::new(Tiktoken::new(
+            get_tokenizer(&info.api_id)
+                .or_else(|| get_tokenizer(&info.model))
+                .unwrap_or(Encoding::O200kBase),
+        )),
+        "anthropic" | "claude-cli" => Arc::new(Tiktoken::anthropic()),
+        #[cfg(feature = "gguf")]
+        "gguf" => {
+            let gguf_config = config.providers.gguf.clone().unwrap_or_default();
+            match super::gguf::resolve_model_path(&info.model, &gguf_config.models_dir) {
+                Ok(path) => Arc::new(super::gguf::GgufTokenizer::new(path)),
+                Err(_) => Arc::new(Tiktoken::cl100k()),
+            }
+        }
+        // Routers and local servers may serve Claude models too
+        _ if info.model.contains("claude") => Arc::new(Tiktoken::anthropic()),
+        _ => Arc::new(Tiktoken::cl100k()),
+    }
+}
+
+/// Tokenizer for the configured default model
+pub fn for_default_model(config: &Config) -> Arc<dyn Tokenizer> {
+    for_model(
+        &super::models::lookup_model(&config.agent.default_model, config),
+        config,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::agent::providers::{Role, ToolCall};
+
+    fn info(provider: &str, model: &str) -> ModelInfo {
+        let config = Config::default();
+        let mut info = super::super::models::lookup_model("gpt-4o", &config);
+        info.provider = provider.to_string();
+        info.model = model.to_string();
+        info.api_id = model.to_string();
+        info
+    }
+
+    #[test]
+    fn test_tokenizer_per_model() {
+        let config = Config::default();
+        let name = |provider, model| {
+            for_model(&info(provider, model), &config)
+                .name()
+                .to_string()
+        };
+
+        assert_eq!(name("openai", "gpt-4o"), "o200k_base");
+        assert_eq!(name("openai", "gpt-4-turbo"), "cl100k_base");
+        assert_eq!(name("openai", "some-new-model"), "o200k_base");
+        assert_eq!(
+            name("anthropic", "claude-sonnet-4-5"),
+            "anthropic (cl100k estimate)"
+        );
+        assert_eq!(
+            name("openrouter", "anthropic/claude-3.5"),
+            "anthropic (cl100k estimate)"
+        );
+        assert_eq!(name("ollama", "llama3"), "cl100k_base");
+    }
+
+    #[test]
+    fn test_counts_messages_and_tools() {
+        let tokenizer = Tiktoken::cl100k();
+        assert_eq!(tokenizer.count("hello world"), 2);
+        assert_eq!(Tiktoken::anthropic().count("hello world"), 3);
+
+        let mut message = Message {
+            role: Role::Assistant,
+            content: "hello world".to_string(),
+            tool_calls: None,
+            tool_call_id: None,
+            images: Vec::new(),
+            thinking: Vec::new(),
+        };
+        assert_eq!(tokenizer.count_message(&message), MESSAGE_OVERHEAD + 2);
+        message.tool_calls = Some(vec![ToolCall {
+            id: "call_1".to_string(),
+            name: "bash".to_string(),
+            arguments: r#"{"command":"ls"}"#.to_string(),
+        }]);
+        assert!(tokenizer.count_message(&message) > MESSAGE_OVERHEAD + 2 + 1);
+
+        let tools = [ToolSchema {
+            name: "bash".to_string(),
+            description: "Run a shell command".to_string(),
+            parameters: serde_json::json!({"type": "object"}),
+        }];
+        assert!(tokenizer.count_tools(&tools) > MESSAGE_OVERHEAD);
+    }
+}
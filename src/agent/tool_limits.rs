@@ -0,0 +1,152 @@
+//! Guard rails for the tool loop within a single turn
+//!
+//! A confused model can keep calling tools forever (often the same call
+//! with the same arguments). `ToolLoopGuard` counts rounds, calls, elapsed
+//! time and identical repeats; once a limit trips the agent stops running
+//! tools and asks the model for a final summary.
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use super::providers::ToolCall;
+use crate::config::ToolLimitsConfig;
+
+/// Instruction for the final turn after a limit was hit (not persisted)
+pub const TOOL_LIMIT_PROMPT: &str = "Tool use limit reached for this turn. Do not call any \
+     more tools. Summarize what you did and found so far, note anything left unfinished, \
+     and stop.";
+
+/// Per-turn counters checked before each round of tool calls
+pub struct ToolLoopGuard {
+    limits: ToolLimitsConfig,
+    started: Instant,
+    rounds: usize,
+    calls: usize,
+    /// (tool name, arguments) -> times called this turn
+    seen: HashMap<(String, String), usize>,
+}
+
+impl ToolLoopGuard {
+    pub fn new(limits: ToolLimitsConfig) -> Self {
+        Self {
+            limits,
+            started: Instant::now(),
+            rounds: 0,
+            calls: 0,
+            seen: HashMap::new(),
+        }
+    }
+
+    /// Count a round the model asked for. Returns why it must not run, if a
+    /// limit is hit; the round is still counted either way.
+    pub fn check_round(&mut self, calls: &[ToolCall]) -> Option<String> {
+        self.rounds += 1;
+        self.calls += calls.len();
+
+        let limits = &self.limits;
+        if limits.max_rounds > 0 && self.rounds > limits.max_rounds {
+            return Some(format!("more than {} tool rounds", limits.max_rounds));
+        }
+        if limits.max_calls > 0 && self.calls > limits.max_calls {
+            return Some(format!("more than {} tool calls", limits.max_calls));
+        }
+        if limits.max_turn_secs > 0
+            && self.started.elapsed() > Duration::from_secs(limits.max_turn_secs)
+        {
+            return Some(format!("turn ran longer than {}s", limits.max_turn_secs));
+        }
+
+        let mut repeated = None;
+        for call in calls {
+            let count = self
+                .seen
+                .entry((call.name.clone(), normalize_arguments(&call.arguments)))
+                .or_insert(0);
+            *count += 1;
+            if limits.max_repeated_calls > 0 && *count > limits.max_repeated_calls {
+                repeated.get_or_insert_with(|| {
+                    format!(
+                        "{} called with identical arguments more than {} times",
+                        call.name, limits.max_repeated_calls
+                    )
+                });
+            }
+        }
+        repeated
+    }
+}
+
+/// Compare arguments by JSON value so key order and whitespace don't matter
+fn normalize_arguments(arguments: &str) -> String {
+    serde_json::from_str::<serde_json::Value>(arguments)
+        .map(|v| v.to_string())
+        .unwrap_or_else(|_| arguments.trim().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn call(name: &str, arguments: &str) -> ToolCall {
+        ToolCall {
+            id: format!("call_{}", name),
+            name: name.to_string(),
+            arguments: arguments.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_round_and_call_limits() {
+        let limits = ToolLimitsConfig {
+            max_rounds: 2,
+            max_calls: 0,
+            max_turn_secs: 0,
+            max_repeated_calls: 0,
+        };
+        let mut guard = ToolLoopGuard::new(limits);
+        assert!(guard.check_round(&[call("bash", "{}")]).is_none());
+        assert!(guard.check_round(&[call("bash", "{}")]).is_none());
+        let hit = guard.check_round(&[call("bash", "{}")]).unwrap();
+        assert!(hit.contains("2 tool rounds"));
+
+        let limits = ToolLimitsConfig {
+            max_rounds: 0,
+            max_calls: 3,
+            max_turn_secs: 0,
+            max_repeated_calls: 0,
+        };
+        let mut guard = ToolLoopGuard::new(limits);
+        assert!(
+            guard
+                .check_round(&[call("a", "{}"), call("b", "{}")])
+                .is_none()
+        );
+        assert!(
+            guard
+                .check_round(&[call("c", "{}"), call("d", "{}")])
+                .is_some()
+        );
+    }
+
+    #[test]
+    fn test_identical_repeats_detected() {
+        let mut guard = ToolLoopGuard::new(ToolLimitsConfig::default());
+        let max = ToolLimitsConfig::default().max_repeated_calls;
+
+        // Same arguments with different key order and spacing count as one
+        let a = r#"{"path": "notes.md", "limit": 10}"#;
+        let b = r#"{"limit":10,"path":"notes.md"}"#;
+        for i in 0..max {
+            let args = if i % 2 == 0 { a } else { b };
+            assert!(guard.check_round(&[call("read_file", args)]).is_none());
+        }
+        // Different arguments are a different call
+        assert!(
+            guard
+                .check_round(&[call("read_file", r#"{"path": "other.md"}"#)])
+                .is_none()
+        );
+        let hit = guard.check_round(&[call("read_file", a)]).unwrap();
+        assert!(hit.contains("read_file"));
+    }
+}
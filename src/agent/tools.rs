@@ -4,9 +4,10 @@ use serde_json::{Value, json};
 use std::fs;
 use std::path::PathBuf;
 use std::sync::Arc;
+use tokio_util::sync::CancellationToken;
 use tracing::debug;
 
-use super::providers::ToolSchema;
+use super::providers::{ToolCall, ToolSchema};
 use crate::config::Config;
 use crate::memory::MemoryManager;
 use crate::sandbox::{self, SandboxPolicy};
@@ -22,6 +23,71 @@ pub trait Tool: Send + Sync {
     fn name(&self) -> &str;
     fn schema(&self) -> ToolSchema;
     async fn execute(&self, arguments: &str) -> Result<String>;
+
+    /// Side-effect-free tools may run concurrently with each other
+    fn is_read_only(&self) -> bool {
+        false
+    }
+
+    /// Execute, giving up when `cancel` fires. Tools that spawn processes
+    /// override this to kill them; others are simply dropped.
+    async fn execute_cancellable(
+        &self,
+        arguments: &str,
+        cancel: &CancellationToken,
+    ) -> Result<String> {
+        tokio::select! {
+            result = self.execute(arguments) => result,
+            _ = cancel.cancelled() => anyhow::bail!("Cancelled"),
+        }
+    }
+}
+
+/// Split a round of tool calls into batches that run one after another.
+/// Consecutive read-only calls share a batch (run concurrently); every other
+/// call is a batch of its own, so mutating tools stay serialized and in order.
+pub fn batch_tool_calls(
+    calls: &[ToolCall],
+    is_read_only: impl Fn(&str) -> bool,
+) -> Vec<&[ToolCall]> {
+    let mut batches = Vec::new();
+    let mut start = 0;
+    for (i, call) in calls.iter().enumerate() {
+        if !is_read_only(&call.name) {
+            if start < i {
+                batches.push(&calls[start..i]);
+            }
+            batches.push(&calls[i..=i]);
+            start = i + 1;
+        }
+    }
+    if start < calls.len() {
+        batches.push(&calls[start..]);
+    }
+    batches
+}
+
+/// Sandbox policy for shell commands, if the sandbox is enabled and supported
+pub(crate) fn sandbox_policy(config: &Config) -> Option<SandboxPolicy> {
+    if !config.sandbox.enabled {
+        return None;
+    }
+    let caps = sandbox::detect_capabilities();
+    let effective = caps.effective_level(&config.sandbox.level);
+    if effective > sandbox::SandboxLevel::None {
+        Some(sandbox::build_policy(
+            &config.sandbox,
+            &config.workspace_path(),
+            effective,
+        ))
+    } else {
+        tracing::warn!(
+            "Sandbox enabled but no kernel support detected (level: {:?}). \
+             Commands will run without sandbox enforcement.",
+            caps.level
+        );
+        None
+    }
 }
 
 pub fn create_default_tools(
@@ -30,28 +96,7 @@ pub fn create_default_tools(
 ) -> Result<Vec<Box<dyn Tool>>> {
     let workspace = config.workspace_path();
     let state_dir = config.paths.state_dir.clone();
-
-    // Build sandbox policy if enabled
-    let sandbox_policy = if config.sandbox.enabled {
-        let caps = sandbox::detect_capabilities();
-        let effective = caps.effective_level(&config.sandbox.level);
-        if effective > sandbox::SandboxLevel::None {
-            Some(sandbox::build_policy(
-                &config.sandbox,
-                &workspace,
-                effective,
-            ))
-        } else {
-            tracing::warn!(
-                "Sandbox enabled but no kernel support detected (level: {:?}). \
-                 Commands will run without sandbox enforcement.",
-                caps.level
-            );
-            None
-        }
-    } else {
-        None
-    };
+    let sandbox_policy = sandbox_policy(config);
 
     // Use indexed memory search if MemoryManager is provided, otherwise fallback to grep-based
     let memory_search_tool: Box<dyn Tool> = if let Some(ref mem) = memory {
@@ -127,6 +172,20 @@ impl Tool for BashTool {
     }
 
     async fn execute(&self, arguments: &str) -> Result<String> {
+        self.run(arguments, &CancellationToken::new()).await
+    }
+
+    async fn execute_cancellable(
+        &self,
+        arguments: &str,
+        cancel: &CancellationToken,
+    ) -> Result<String> {
+        self.run(arguments, cancel).await
+    }
+}
+
+impl BashTool {
+    async fn run(&self, arguments: &str, cancel: &CancellationToken) -> Result<String> {
         let args: Value = serde_json::from_str(arguments)?;
         let command = args["command"]
             .as_str()
@@ -161,7 +220,8 @@ impl Tool for BashTool {
 
         // Use sandbox if policy is configured
         if let Some(ref policy) = self.sandbox_policy {
-            let (output, exit_code) = sandbox::run_sandboxed(command, policy, timeout_ms).await?;
+            let (output, exit_code) =
+                sandbox::run_sandboxed(command, policy, timeout_ms, cancel).await?;
 
             if output.is_empty() {
                 return Ok(format!("Command completed with exit code: {}", exit_code));
@@ -172,15 +232,18 @@ impl Tool for BashTool {
 
         // Fallback: run command directly without sandbox
         let timeout_duration = std::time::Duration::from_millis(timeout_ms);
-        let output = tokio::time::timeout(
-            timeout_duration,
+        let child = sandbox::executor::new_process_group(
             tokio::process::Command::new("bash")
                 .arg("-c")
                 .arg(command)
-                .output(),
+                .stdin(std::process::Stdio::null())
+                .stdout(std::process::Stdio::piped())
+                .stderr(std::process::Stdio::piped())
+                .kill_on_drop(true),
         )
-        .await
-        .map_err(|_| anyhow::anyhow!("Command timed out after {}ms", timeout_ms))??;
+        .spawn()?;
+        let output =
+            sandbox::executor::wait_for_group(child, timeout_duration, cancel, "Command").await?;
 
         let stdout = String::from_utf8_lossy(&output.stdout);
         let stderr = String::from_utf8_lossy(&output.stderr);
@@ -226,6 +289,10 @@ impl Tool for ReadFileTool {
         "read_file"
     }
 
+    fn is_read_only(&self) -> bool {
+        true
+    }
+
     fn schema(&self) -> ToolSchema {
         ToolSchema {
             name: "read_file".to_string(),
@@ -529,6 +596,10 @@ impl Tool for MemorySearchTool {
         "memory_search"
     }
 
+    fn is_read_only(&self) -> bool {
+        true
+    }
+
     fn schema(&self) -> ToolSchema {
         ToolSchema {
             name: "memory_search".to_string(),
@@ -629,6 +700,10 @@ impl Tool for MemorySearchToolWithIndex {
         "memory_search"
     }
 
+    fn is_read_only(&self) -> bool {
+        true
+    }
+
     fn schema(&self) -> ToolSchema {
         let description = if self.memory.has_embeddings() {
             "Search the memory index using hybrid semantic + keyword search for relevant information"
@@ -729,6 +804,10 @@ impl Tool for MemoryGetTool {
         "memory_get"
     }
 
+    fn is_read_only(&self) -> bool {
+        true
+    }
+
     fn schema(&self) -> ToolSchema {
         ToolSchema {
             name: "memory_get".to_string(),
@@ -829,6 +908,10 @@ impl Tool for WebFetchTool {
         "web_fetch"
     }
 
+    fn is_read_only(&self) -> bool {
+        true
+    }
+
     fn schema(&self) -> ToolSchema {
         ToolSchema {
             name: "web_fetch".to_string(),
@@ -905,6 +988,54 @@ pub fn extract_tool_detail(tool_name: &str, arguments: &str) -> Option<String> {
             .get("url")
             .and_then(|v| v.as_str())
             .map(|s| s.to_string()),
+        "delegate" => args.get("task").and_then(|v| v.as_str()).map(|s| {
+            if s.chars().count() > 60 {
+                format!("{}...", s.chars().take(57).collect::<String>())
+            } else {
+                s.to_string()
+            }
+        }),
         _ => None,
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_batch_tool_calls_serializes_mutating_tools() {
+        let calls: Vec<ToolCall> = [
+            "memory_search",
+            "web_fetch",
+            "bash",
+            "read_file",
+            "edit_file",
+        ]
+        .iter()
+        .enumerate()
+        .map(|(i, name)| ToolCall {
+            id: format!("call_{}", i),
+            name: name.to_string(),
+            arguments: "{}".to_string(),
+        })
+        .collect();
+
+        let read_only = |name: &str| !matches!(name, "bash" | "write_file" | "edit_file");
+        let batches: Vec<Vec<&str>> = batch_tool_calls(&calls, read_only)
+            .iter()
+            .map(|b| b.iter().map(|c| c.name.as_str()).collect())
+            .collect();
+
+        assert_eq!(
+            batches,
+            vec![
+                vec!["memory_search", "web_fetch"],
+                vec!["bash"],
+                vec!["read_file"],
+                vec!["edit_file"],
+            ]
+        );
+        assert!(batch_tool_calls(&[], read_only).is_empty());
+    }
+}
@@ -21,7 +21,7 @@ pub struct AskArgs {
 }
 
 pub async fn run(args: AskArgs, agent_id: &str) -> Result<()> {
-    let config = Config::load()?;
+    let config = Config::load()?.for_agent(agent_id);
     let memory = MemoryManager::new_with_full_config(&config.memory, Some(&config), agent_id)?;
 
     let agent_config = AgentConfig {
@@ -30,12 +30,13 @@ pub async fn run(args: AskArgs, agent_id: &str) -> Result<()> {
         reserve_tokens: config.agent.reserve_tokens,
     };
 
-    let mut agent = Agent::new(agent_config, &config, memory).await?;
+    let mut agent = Agent::new(agent_config, &config, agent_id, memory).await?;
     agent.new_session().await?;
 
-    let workspace_lock = WorkspaceLock::new()?;
+    let workspace_lock = WorkspaceLock::for_config(&config)?;
     let _lock_guard = workspace_lock.acquire()?;
     let response = agent.chat(&args.question).await?;
+    agent.end_session("exit").await;
 
     match args.format.as_str() {
         "json" => {
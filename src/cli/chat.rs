@@ -3,11 +3,14 @@ use clap::Args;
 use futures::StreamExt;
 use rustyline::DefaultEditor;
 use rustyline::error::ReadlineError;
-use std::io::{self, Write};
+use std::io::{self, IsTerminal, Write};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
 
 use crate::agent::{
-    Agent, AgentConfig, ImageAttachment, Skill, extract_tool_detail, get_last_session_id_for_agent,
-    get_skills_summary, list_sessions_for_agent, load_skills, parse_skill_command,
+    Agent, AgentConfig, ApprovalBroker, ApprovalDecision, ApprovalRequest, CancelHandle,
+    ImageAttachment, Role, Skill, extract_tool_detail, get_last_session_id_for_agent,
+    get_skills_summary, is_cancelled, list_sessions_for_agent, load_skills, parse_skill_command,
     search_sessions_for_agent,
 };
 use crate::concurrency::WorkspaceLock;
@@ -112,10 +115,14 @@ pub struct ChatArgs {
     /// Resume the most recent session
     #[arg(long)]
     pub resume: bool,
+
+    /// Resume on the branch ending at this message ID (see /branches)
+    #[arg(long)]
+    pub leaf: Option<String>,
 }
 
 pub async fn run(args: ChatArgs, agent_id: &str) -> Result<()> {
-    let config = Config::load()?;
+    let config = Config::load()?.for_agent(agent_id);
     // Embedding provider is automatically created based on config.memory.embedding_provider
     let memory = MemoryManager::new_with_full_config(&config.memory, Some(&config), agent_id)?;
 
@@ -125,8 +132,9 @@ pub async fn run(args: ChatArgs, agent_id: &str) -> Result<()> {
         reserve_tokens: config.agent.reserve_tokens,
     };
 
-    let mut agent = Agent::new(agent_config, &config, memory).await?;
-    let workspace_lock = WorkspaceLock::new()?;
+    let mut agent = Agent::new(agent_config, &config, agent_id, memory).await?;
+    let workspace_lock = WorkspaceLock::for_config(&config)?;
+    spawn_approval_prompter(&mut agent);
 
     // Determine session to use
     let session_id = if let Some(id) = args.session {
@@ -139,7 +147,10 @@ pub async fn run(args: ChatArgs, agent_id: &str) -> Result<()> {
 
     // Resume or create session
     if let Some(session_id) = session_id {
-        match agent.resume_session(&session_id).await {
+        match agent
+            .resume_session(&session_id, args.leaf.as_deref())
+            .await
+        {
             Ok(()) => {
                 let status = agent.session_status();
                 println!(
@@ -188,6 +199,7 @@ pub async fn run(args: ChatArgs, agent_id: &str) -> Result<()> {
 
     let mut rl = DefaultEditor::new()?;
     let mut stdout = io::stdout();
+    let in_turn = spawn_interrupt_listener(agent.cancel_handle());
 
     // Track pending file attachments (text and images)
     enum Attachment {
@@ -340,13 +352,16 @@ pub async fn run(args: ChatArgs, agent_id: &str) -> Result<()> {
             match handle_command(input, &mut agent, &agent_id, &skills).await {
                 CommandResult::Continue => continue,
                 CommandResult::Quit => break,
-                CommandResult::SendMessage(msg) => {
-                    // Skill invocation - send message to agent
+                CommandResult::SendMessage(msg, images) => {
+                    // Skill invocation, retry or edit - send message to agent
                     print!("\nLocalGPT: ");
                     stdout.flush().ok();
                     let _lock_guard = workspace_lock.acquire()?;
-                    match agent.chat(&msg).await {
+                    in_turn.store(true, Ordering::SeqCst);
+                    match agent.chat_with_images(&msg, images).await {
                         Ok(response) => {
+                            print_recalled_memory(&agent);
+                            print_fallback_notices(&agent);
                             println!("{}\n", response);
                             if let Err(e) = agent.auto_save_session() {
                                 eprintln!("Warning: Failed to auto-save session: {}", e);
@@ -356,6 +371,7 @@ pub async fn run(args: ChatArgs, agent_id: &str) -> Result<()> {
                             eprintln!("Error: {}\n", e);
                         }
                     }
+                    in_turn.store(false, Ordering::SeqCst);
                     continue;
                 }
                 CommandResult::Error(e) => {
@@ -398,14 +414,35 @@ pub async fn run(args: ChatArgs, agent_id: &str) -> Result<()> {
         stdout.flush()?;
 
         let _lock_guard = workspace_lock.acquire()?;
+
+        // Ctrl-C cancels the turn instead of killing the process
+        in_turn.store(true, Ordering::SeqCst);
+
         match agent.chat_stream_with_images(&message, images).await {
             Ok(mut stream) => {
+                print_recalled_memory(&agent);
+                print_fallback_notices(&agent);
                 let mut full_response = String::new();
                 let mut pending_tool_calls = None;
-
-                while let Some(result) = stream.next().await {
+                let mut thinking = ThinkingPrinter::new();
+                let cancel_token = agent.cancel_handle().token();
+                let mut cancelled = false;
+
+                loop {
+                    let result = tokio::select! {
+                        result = stream.next() => result,
+                        _ = cancel_token.cancelled() => {
+                            cancelled = true;
+                            None
+                        }
+                    };
+                    let Some(result) = result else { break };
                     match result {
                         Ok(chunk) => {
+                            thinking.print(&chunk.thinking);
+                            if !chunk.delta.is_empty() {
+                                thinking.end();
+                            }
                             print!("{}", chunk.delta);
                             stdout.flush()?;
                             full_response.push_str(&chunk.delta);
@@ -416,72 +453,54 @@ pub async fn run(args: ChatArgs, agent_id: &str) -> Result<()> {
                             }
                         }
                         Err(e) => {
+                            thinking.end();
                             eprintln!("\nStream error: {}", e);
                             break;
                         }
                     }
                 }
+                thinking.end();
+                drop(stream);
 
                 // Handle tool calls if any
-                if let Some(tool_calls) = pending_tool_calls {
-                    // Check for tools requiring approval
-                    let mut approved_calls = Vec::new();
-                    let mut any_denied = false;
-
-                    for tc in tool_calls {
+                if cancelled {
+                    agent.cancel_chat_stream(&full_response);
+                    println!("\n[Cancelled]");
+                } else if let Some(tool_calls) = pending_tool_calls {
+                    for tc in &tool_calls {
                         let detail = extract_tool_detail(&tc.name, &tc.arguments);
                         if let Some(ref d) = detail {
                             println!("\n[{}: {}]", tc.name, d);
                         } else {
                             println!("\n[{}]", tc.name);
                         }
-
-                        if agent.requires_approval(&tc.name) {
-                            // Prompt for approval
-                            print!("Execute {}? [y/N]: ", tc.name);
-                            stdout.flush()?;
-
-                            let mut input = String::new();
-                            std::io::stdin().read_line(&mut input)?;
-                            let input = input.trim().to_lowercase();
-
-                            if input == "y" || input == "yes" {
-                                approved_calls.push(tc);
-                            } else {
-                                println!("Skipped: {}", tc.name);
-                                any_denied = true;
-                            }
-                        } else {
-                            approved_calls.push(tc);
-                        }
                     }
                     stdout.flush()?;
 
-                    if !approved_calls.is_empty() {
-                        match agent
-                            .execute_streaming_tool_calls(&full_response, approved_calls)
-                            .await
-                        {
-                            Ok((follow_up, warnings)) => {
-                                for (tool_name, tool_warnings) in &warnings {
-                                    for w in tool_warnings {
-                                        eprintln!(
-                                            "  \u{26a0} Suspicious content in {} output: {}",
-                                            tool_name, w
-                                        );
-                                    }
+                    // Tools needing approval are asked about by the prompter
+                    match agent
+                        .execute_streaming_tool_calls(&full_response, tool_calls)
+                        .await
+                    {
+                        Ok((follow_up, warnings)) => {
+                            print_fallback_notices(&agent);
+                            for (tool_name, tool_warnings) in &warnings {
+                                for w in tool_warnings {
+                                    eprintln!(
+                                        "  \u{26a0} Suspicious content in {} output: {}",
+                                        tool_name, w
+                                    );
                                 }
-                                print!("{}", follow_up);
-                                stdout.flush()?;
-                            }
-                            Err(e) => {
-                                eprintln!("Tool execution error: {}", e);
                             }
+                            print!("{}", follow_up);
+                            stdout.flush()?;
+                        }
+                        Err(e) if is_cancelled(&e) => {
+                            println!("[Cancelled]");
+                        }
+                        Err(e) => {
+                            eprintln!("Tool execution error: {}", e);
                         }
-                    } else if any_denied {
-                        // All tools were denied, just finish the stream
-                        agent.finish_chat_stream(&full_response);
-                        println!("\n(Tool execution skipped)");
                     }
                 } else {
                     // No tool calls - just finish the stream
@@ -497,16 +516,78 @@ pub async fn run(args: ChatArgs, agent_id: &str) -> Result<()> {
                 eprintln!("Error: {}\n", e);
             }
         }
+        in_turn.store(false, Ordering::SeqCst);
     }
 
+    agent.end_session("exit").await;
     println!("Goodbye!");
     Ok(())
 }
 
+/// Prints streamed reasoning dimmed, separated from the answer
+struct ThinkingPrinter {
+    active: bool,
+    styled: bool,
+}
+
+impl ThinkingPrinter {
+    fn new() -> Self {
+        Self {
+            active: false,
+            styled: io::stdout().is_terminal(),
+        }
+    }
+
+    fn print(&mut self, text: &str) {
+        if text.is_empty() {
+            return;
+        }
+        if !self.active {
+            self.active = true;
+            print!(
+                "{}",
+                if self.styled {
+                    "\x1b[2m"
+                } else {
+                    "[thinking] "
+                }
+            );
+        }
+        print!("{}", text);
+        let _ = io::stdout().flush();
+    }
+
+    /// Close the reasoning section before the answer starts
+    fn end(&mut self) {
+        if self.active {
+            self.active = false;
+            print!("{}\n\n", if self.styled { "\x1b[0m" } else { "" });
+        }
+    }
+}
+
+/// Cite the memory auto-recalled for the turn
+fn print_recalled_memory(agent: &Agent) {
+    let citations = agent.recalled_memory();
+    if !citations.is_empty() {
+        eprintln!("\n[Recalled from memory: {}]", citations.join(", "));
+    }
+}
+
+/// Print notices for models skipped by the fallback chain
+fn print_fallback_notices(agent: &Agent) {
+    for notice in agent.take_fallback_notices() {
+        eprintln!(
+            "\n[{} failed ({}), falling back to {}]",
+            notice.from, notice.reason, notice.to
+        );
+    }
+}
+
 enum CommandResult {
     Continue,
     Quit,
-    SendMessage(String),
+    SendMessage(String, Vec<ImageAttachment>),
     Error(String),
 }
 
@@ -615,9 +696,10 @@ async fn handle_command(
 
         "/resume" => {
             if parts.len() < 2 {
-                return CommandResult::Error("Usage: /resume <session-id>".into());
+                return CommandResult::Error("Usage: /resume <session-id> [leaf-id]".into());
             }
             let session_id = parts[1];
+            let leaf = parts.get(2).copied();
 
             // Find session by prefix match
             match list_sessions_for_agent(agent_id) {
@@ -634,7 +716,8 @@ async fn handle_command(
                         )),
                         1 => {
                             let full_id = matching[0].id.clone();
-                            match futures::executor::block_on(agent.resume_session(&full_id)) {
+                            match futures::executor::block_on(agent.resume_session(&full_id, leaf))
+                            {
                                 Ok(()) => {
                                     let status = agent.session_status();
                                     println!(
@@ -680,12 +763,169 @@ async fn handle_command(
             Err(e) => CommandResult::Error(format!("Failed to compact: {}", e)),
         },
 
+        "/expand" => match agent.expand_compaction() {
+            0 => {
+                println!("\nNothing to expand: the session has no compaction summary.\n");
+                CommandResult::Continue
+            }
+            restored => {
+                let _ = agent.auto_save_session();
+                println!(
+                    "\nRestored {} message(s). Context: {} tokens\n",
+                    restored,
+                    agent.context_tokens()
+                );
+                CommandResult::Continue
+            }
+        },
+
+        "/pin" | "/unpin" => {
+            let pinned = cmd == "/pin";
+            let index = match parts.get(1).map(|n| n.parse::<usize>()) {
+                Some(Ok(n)) if n > 0 => Some(n - 1),
+                Some(_) => return CommandResult::Error(format!("Usage: {} <n>", cmd)),
+                None if pinned => None,
+                None => return CommandResult::Error("Usage: /unpin <n>".to_string()),
+            };
+            match agent.pin_message(index, pinned) {
+                Ok(index) => {
+                    let _ = agent.auto_save_session();
+                    let preview: String = agent.raw_session_messages()[index]
+                        .message
+                        .content
+                        .chars()
+                        .take(60)
+                        .collect();
+                    let action = if pinned { "Pinned" } else { "Unpinned" };
+                    println!("\n{} message {}: {}\n", action, index + 1, preview.trim());
+                    CommandResult::Continue
+                }
+                Err(e) => CommandResult::Error(format!("Failed to {}: {}", &cmd[1..], e)),
+            }
+        }
+
+        "/history" => {
+            let messages = agent.raw_session_messages();
+            if messages.is_empty() {
+                println!("\nNo messages in this session.\n");
+                return CommandResult::Continue;
+            }
+            println!("\nMessages on this branch:");
+            for (i, sm) in messages.iter().enumerate() {
+                let role = match sm.message.role {
+                    Role::User => "you",
+                    Role::Assistant => "assistant",
+                    Role::System => "summary",
+                    Role::Tool => "tool",
+                };
+                let preview: String = sm
+                    .message
+                    .content
+                    .split_whitespace()
+                    .collect::<Vec<_>>()
+                    .join(" ")
+                    .chars()
+                    .take(60)
+                    .collect();
+                let pin = if sm.pinned { " [pinned]" } else { "" };
+                println!("  {:>3}. {:<9} {}{}", i + 1, role, preview, pin);
+            }
+            println!();
+            CommandResult::Continue
+        }
+
+        "/retry" => match agent.rewind_to_user_message(None) {
+            Ok(message) => CommandResult::SendMessage(message.content, message.images),
+            Err(e) => CommandResult::Error(format!("Failed to retry: {}", e)),
+        },
+
+        "/edit" => {
+            // Keep the new text's own spacing and line breaks
+            let args = input[cmd.len()..].trim_start();
+            let (n, text) = args
+                .split_once(char::is_whitespace)
+                .map(|(n, text)| (n, text.trim()))
+                .unwrap_or((args, ""));
+            let index = match n.parse::<usize>() {
+                Ok(n) if n > 0 && !text.is_empty() => n - 1,
+                _ => return CommandResult::Error("Usage: /edit <n> <new message>".into()),
+            };
+            match agent.rewind_to_user_message(Some(index)) {
+                // Images attached to the original message are kept
+                Ok(message) => CommandResult::SendMessage(text.to_string(), message.images),
+                Err(e) => CommandResult::Error(format!("Failed to edit: {}", e)),
+            }
+        }
+
+        "/branches" => {
+            let branches = agent.branches();
+            if branches.len() < 2 {
+                println!("\nThis session has one branch. /retry or /edit start another.\n");
+                return CommandResult::Continue;
+            }
+            println!("\nBranches:");
+            for (i, branch) in branches.iter().enumerate() {
+                let marker = if branch.current { "*" } else { " " };
+                println!(
+                    "  {} {}. [{}] {} messages: {}",
+                    marker,
+                    i + 1,
+                    branch.leaf_id,
+                    branch.message_count,
+                    branch.preview
+                );
+            }
+            println!("\nUse /branch <n> to switch.\n");
+            CommandResult::Continue
+        }
+
+        "/branch" => {
+            let Some(target) = parts.get(1) else {
+                return CommandResult::Error("Usage: /branch <n|leaf-id>".into());
+            };
+            let branches = agent.branches();
+            // A number from /branches, or a message ID
+            let leaf_id = match target.parse::<usize>() {
+                Ok(n) if n > 0 && n <= branches.len() => branches[n - 1].leaf_id.clone(),
+                _ => target.to_string(),
+            };
+            match agent.switch_branch(&leaf_id) {
+                Ok(()) => {
+                    let _ = agent.auto_save_session();
+                    let messages = agent.raw_session_messages();
+                    println!(
+                        "\nSwitched to branch {} ({} messages)\n",
+                        messages.last().map(|sm| sm.id.as_str()).unwrap_or(""),
+                        messages.len()
+                    );
+                    CommandResult::Continue
+                }
+                Err(e) => CommandResult::Error(format!("Failed to switch branch: {}", e)),
+            }
+        }
+
         "/clear" => {
             agent.clear_session();
             println!("\nSession cleared.\n");
             CommandResult::Continue
         }
 
+        "/undo" => match agent.undo_last_turn() {
+            Ok(Some(files)) => {
+                println!("\nRestored {} file(s):", files.len());
+                for file in files {
+                    println!("  {}", file.display());
+                }
+                println!();
+                CommandResult::Continue
+            }
+            Ok(None) => {
+                println!("\nNo file changes to undo in this session.\n");
+                CommandResult::Continue
+            }
+            Err(e) => CommandResult::Error(format!("Failed to undo: {}", e)),
+        },
+
         "/new" => {
             // Save current session to memory before starting new one
             match agent.save_session_to_memory().await {
@@ -771,8 +1011,19 @@ async fn handle_command(
             println!("  ID: {}", status.id);
             println!("  Model: {}", agent.model());
             println!("  Messages: {}", status.message_count);
-            println!("  Context tokens: ~{}", status.token_count);
+            println!(
+                "  Context tokens: {} ({})",
+                status.token_count, status.tokenizer
+            );
             println!("  Compactions: {}", status.compaction_count);
+            let pinned = agent
+                .raw_session_messages()
+                .iter()
+                .filter(|sm| sm.pinned)
+                .count();
+            if pinned > 0 {
+                println!("  Pinned messages: {}", pinned);
+            }
 
             println!("\nMemory:");
             println!("  Chunks: {}", agent.memory_chunk_count());
@@ -784,10 +1035,25 @@ async fn handle_command(
                 println!("\nAPI Usage:");
                 println!("  Input tokens: {}", status.api_input_tokens);
                 println!("  Output tokens: {}", status.api_output_tokens);
+                if status.api_cache_read_tokens > 0 || status.api_cache_write_tokens > 0 {
+                    println!("  Cache read tokens: {}", status.api_cache_read_tokens);
+                    println!("  Cache write tokens: {}", status.api_cache_write_tokens);
+                }
                 println!(
                     "  Total tokens: {}",
-                    status.api_input_tokens + status.api_output_tokens
+                    status.api_input_tokens
+                        + status.api_output_tokens
+                        + status.api_cache_read_tokens
+                        + status.api_cache_write_tokens
                 );
+                if let Some(rate) = status.cache_hit_rate()
+                    && status.api_cache_read_tokens + status.api_cache_write_tokens > 0
+                {
+                    println!("  Cache hit rate: {:.1}%", rate * 100.0);
+                }
+                if status.api_cost_usd > 0.0 {
+                    println!("  Session cost: ${:.4}", status.api_cost_usd);
+                }
             }
             println!();
             CommandResult::Continue
@@ -802,7 +1068,20 @@ async fn handle_command(
             println!("  claude-*        - Anthropic API (requires API key)");
             println!("  ollama/*        - Ollama local (e.g., ollama/llama3)");
             println!("  <other>         - Defaults to Ollama");
-            println!("\nCurrent model: {}", agent.model());
+            println!("  <alias>         - Entries from [models.<alias>] in config");
+            let info = agent.model_info();
+            println!(
+                "\nCurrent model: {} ({}/{})",
+                agent.model(),
+                info.provider,
+                info.api_id
+            );
+            println!(
+                "  Context: {} tokens, vision: {}, tools: {}",
+                agent.context_window(),
+                if info.vision { "yes" } else { "no" },
+                if info.tools { "yes" } else { "no" }
+            );
             println!("Use /model <name> to switch.\n");
             CommandResult::Continue
         }
@@ -815,6 +1094,7 @@ async fn handle_command(
             println!("  Usable: {} tokens", usable);
             println!("  Total: {} tokens", total);
             println!("  Reserve: {} tokens", total - usable);
+            println!("  Counted with: {}", agent.session_status().tokenizer);
 
             if pct > 80.0 {
                 println!("\n⚠ Context nearly full. Consider /compact or /new.");
@@ -864,7 +1144,7 @@ async fn handle_command(
                         skill.name,
                         skill.emoji.as_deref().unwrap_or("")
                     );
-                    return CommandResult::SendMessage(skill_prompt);
+                    return CommandResult::SendMessage(skill_prompt, Vec::new());
                 }
             }
 
@@ -875,3 +1155,108 @@ async fn handle_command(
         }
     }
 }
+
+/// Listen for Ctrl-C for the whole chat: it cancels the turn in flight, and
+/// outside a turn exits as it would with no handler (tokio's handler stays
+/// installed once a listener has started). Set the returned flag during turns.
+fn spawn_interrupt_listener(cancel: CancelHandle) -> Arc<AtomicBool> {
+    let in_turn = Arc::new(AtomicBool::new(false));
+    let turn_active = in_turn.clone();
+    tokio::spawn(async move {
+        while tokio::signal::ctrl_c().await.is_ok() {
+            if turn_active.load(Ordering::SeqCst) {
+                cancel.cancel();
+            } else {
+                println!();
+                std::process::exit(130);
+            }
+        }
+    });
+    in_turn
+}
+
+/// Ask at a y/N prompt about tool calls that need approval (see
+/// `tools.require_approval`), for every tool round of a turn
+fn spawn_approval_prompter(agent: &mut Agent) {
+    let mut requests = agent.subscribe_approvals();
+    let broker = agent.approval_broker();
+    tokio::spawn(async move {
+        while let Some(request) = requests.recv().await {
+            let id = request.id.clone();
+            let prompt_broker = broker.clone();
+            let decision =
+                tokio::task::spawn_blocking(move || confirm_tool_call(&request, &prompt_broker))
+                    .await
+                    .ok()
+                    .flatten();
+            // None: the request timed out or was cancelled before an answer,
+            // so there is nothing left to resolve
+            if let Some(decision) = decision {
+                broker.resolve(&id, decision);
+            }
+        }
+    });
+}
+
+fn confirm_tool_call(
+    request: &ApprovalRequest,
+    broker: &ApprovalBroker,
+) -> Option<ApprovalDecision> {
+    match &request.detail {
+        Some(detail) => print!("Execute {} ({})? ", request.name, detail),
+        None => print!("Execute {}? ", request.name),
+    }
+    let pattern = request
+        .pattern
+        .as_deref()
+        .unwrap_or("any call to this tool");
+    print!("[y/N, a = always allow `{}`]: ", pattern);
+    let _ = io::stdout().flush();
+
+    // Only read once there is input, so a request that times out or is
+    // cancelled doesn't leave a read behind to swallow the next chat line
+    if !wait_for_stdin(|| broker.pending().contains(&request.id)) {
+        println!("\nNo longer waiting for approval of {}", request.name);
+        return None;
+    }
+    let mut input = String::new();
+    if io::stdin().read_line(&mut input).is_err() {
+        return Some(ApprovalDecision::Deny);
+    }
+    Some(match input.trim().to_lowercase().as_str() {
+        "y" | "yes" => ApprovalDecision::Approve,
+        "a" | "always" => ApprovalDecision::AlwaysAllow,
+        _ => {
+            println!("Skipped: {}", request.name);
+            ApprovalDecision::Deny
+        }
+    })
+}
+
+/// Block until stdin is readable. Returns false as soon as `still_wanted`
+/// does instead.
+#[cfg(unix)]
+fn wait_for_stdin(still_wanted: impl Fn() -> bool) -> bool {
+    let mut fds = libc::pollfd {
+        fd: libc::STDIN_FILENO,
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    while still_wanted() {
+        // SAFETY: fds is a single valid pollfd for the duration of the call
+        let ready = unsafe { libc::poll(&mut fds, 1, 100) };
+        if ready > 0 {
+            return true;
+        }
+        if ready < 0 && io::Error::last_os_error().kind() != io::ErrorKind::Interrupted {
+            // Can't poll; fall back to a plain read
+            return true;
+        }
+    }
+    false
+}
+
+#[cfg(not(unix))]
+fn wait_for_stdin(still_wanted: impl Fn() -> bool) -> bool {
+    still_wanted()
+}
@@ -0,0 +1,96 @@
+use anyhow::Result;
+use clap::{Args, Subcommand};
+
+use crate::agent::CheckpointStore;
+use crate::concurrency::WorkspaceLock;
+use crate::config::Config;
+
+#[derive(Args)]
+pub struct CheckpointArgs {
+    #[command(subcommand)]
+    pub command: CheckpointCommands,
+}
+
+#[derive(Subcommand)]
+pub enum CheckpointCommands {
+    /// List checkpoints of the workspace, newest first
+    List {
+        /// Only checkpoints of this session
+        #[arg(short, long)]
+        session: Option<String>,
+
+        /// Maximum number of checkpoints to show
+        #[arg(short, long, default_value = "20")]
+        limit: usize,
+    },
+
+    /// Show what a checkpoint's turn changed
+    Diff {
+        /// Checkpoint ID (or unique prefix)
+        id: String,
+    },
+
+    /// Restore files to before a checkpoint (rolls back later ones too)
+    Restore {
+        /// Checkpoint ID (or unique prefix)
+        id: String,
+    },
+}
+
+pub async fn run(args: CheckpointArgs, agent_id: &str) -> Result<()> {
+    let config = Config::load()?.for_agent(agent_id);
+    let store = CheckpointStore::for_config(&config);
+
+    match args.command {
+        CheckpointCommands::List { session, limit } => {
+            list_checkpoints(&store, session.as_deref(), limit)
+        }
+        CheckpointCommands::Diff { id } => {
+            print!("{}", store.diff(&store.find(&id)?)?);
+            Ok(())
+        }
+        CheckpointCommands::Restore { id } => {
+            // Don't write files under a turn in progress
+            let lock = WorkspaceLock::for_config(&config)?;
+            let _guard = lock.acquire()?;
+            let (undone, files) = store.restore(&id)?;
+            println!("Rolled back {} checkpoint(s):", undone.len());
+            for id in &undone {
+                println!("  {}", id);
+            }
+            println!("Restored {} file(s):", files.len());
+            for file in &files {
+                println!("  {}", file.display());
+            }
+            Ok(())
+        }
+    }
+}
+
+fn list_checkpoints(store: &CheckpointStore, session: Option<&str>, limit: usize) -> Result<()> {
+    let checkpoints: Vec<_> = store
+        .list()?
+        .into_iter()
+        .rev()
+        .filter(|c| session.is_none_or(|s| c.session_id == s))
+        .take(limit)
+        .collect();
+
+    if checkpoints.is_empty() {
+        println!("No checkpoints.");
+        return Ok(());
+    }
+
+    for checkpoint in checkpoints {
+        println!(
+            "{}  {}  session {} (message {})",
+            checkpoint.id, checkpoint.agent_id, checkpoint.session_id, checkpoint.message_index
+        );
+        println!("   {}", checkpoint.prompt.replace('\n', " "));
+        for file in checkpoint.changed_files() {
+            println!("   ~ {}", file.display());
+        }
+        println!();
+    }
+    Ok(())
+}
@@ -6,10 +6,10 @@ use std::path::PathBuf;
 #[cfg(unix)]
 use daemonize::Daemonize;
 
-use crate::concurrency::TurnGate;
+use crate::concurrency::TurnGates;
 use crate::config::Config;
 use crate::heartbeat::HeartbeatRunner;
-use crate::memory::MemoryManager;
+use crate::memory::{MemoryManager, MemoryWatcher};
 use crate::server::Server;
 
 /// Synchronously stop the daemon (for use before Tokio runtime starts)
@@ -131,8 +131,7 @@ async fn run_daemon_server(config: Config, agent_id: &str) -> Result<()> {
         .with_ansi(false)
         .init();
 
-    let memory = MemoryManager::new_with_full_config(&config.memory, Some(&config), agent_id)?;
-    let _watcher = memory.start_watcher()?;
+    let _watchers = start_watchers(&config, agent_id)?;
 
     println!("Daemon started successfully");
 
@@ -145,47 +144,71 @@ async fn run_daemon_server(config: Config, agent_id: &str) -> Result<()> {
     Ok(())
 }
 
-/// Run daemon services (server and/or heartbeat)
+/// Watch the workspace of every hosted agent so their indexes stay fresh
+fn start_watchers(config: &Config, agent_id: &str) -> Result<Vec<MemoryWatcher>> {
+    config
+        .hosted_agents(agent_id)
+        .iter()
+        .map(|id| {
+            let agent_config = config.for_agent(id);
+            let memory =
+                MemoryManager::new_with_full_config(&agent_config.memory, Some(&agent_config), id)?;
+            memory.start_watcher()
+        })
+        .collect()
+}
+
+/// Run daemon services (server and/or heartbeat) for every hosted agent
 async fn run_daemon_services(config: &Config, agent_id: &str) -> Result<()> {
-    // Create shared turn gate for heartbeat + HTTP concurrency control
-    let turn_gate = TurnGate::new();
-
-    // Spawn heartbeat in background if enabled
-    let heartbeat_handle = if config.heartbeat.enabled {
-        let heartbeat_config = config.clone();
-        let heartbeat_agent_id = agent_id.to_string();
-        let heartbeat_gate = turn_gate.clone();
+    // Turn gates shared by heartbeat, HTTP and Telegram turns in a workspace
+    let turn_gates = TurnGates::new();
+
+    let hosted = config.hosted_agents(agent_id);
+    println!("  Agents: {}", hosted.join(", "));
+
+    // Spawn a heartbeat in background for each agent that has it enabled
+    let mut heartbeat_handles = Vec::new();
+    for id in &hosted {
+        let heartbeat_config = config.for_agent(id);
+        if !heartbeat_config.heartbeat.enabled {
+            continue;
+        }
         println!(
-            "  Heartbeat: enabled (interval: {})",
-            config.heartbeat.interval
+            "  Heartbeat ({}): enabled (interval: {})",
+            id, heartbeat_config.heartbeat.interval
         );
-        Some(tokio::spawn(async move {
+        let heartbeat_gate = turn_gates.for_workspace(&heartbeat_config.workspace_path());
+        let base_config = config.clone();
+        let heartbeat_agent_id = id.clone();
+        heartbeat_handles.push(tokio::spawn(async move {
             match HeartbeatRunner::new_with_gate(
-                &heartbeat_config,
+                &base_config,
                 &heartbeat_agent_id,
                 Some(heartbeat_gate),
             ) {
                 Ok(runner) => {
                     if let Err(e) = runner.run().await {
-                        tracing::error!("Heartbeat runner error: {}", e);
+                        tracing::error!("Heartbeat runner error ({}): {}", heartbeat_agent_id, e);
                     }
                 }
                 Err(e) => {
-                    tracing::error!("Failed to create heartbeat runner: {}", e);
+                    tracing::error!(
+                        "Failed to create heartbeat runner ({}): {}",
+                        heartbeat_agent_id,
+                        e
+                    );
                 }
             }
-        }))
-    } else {
-        None
-    };
+        }));
+    }
 
     // Spawn Telegram bot in background if configured
     let telegram_handle = if config.telegram.as_ref().is_some_and(|t| t.enabled) {
         let tg_config = config.clone();
-        let tg_gate = turn_gate.clone();
+        let tg_gates = turn_gates.clone();
         println!("  Telegram: enabled");
         Some(tokio::spawn(async move {
-            if let Err(e) = crate::server::telegram::run_telegram_bot(&tg_config, tg_gate).await {
+            if let Err(e) = crate::server::telegram::run_telegram_bot(&tg_config, tg_gates).await {
                 tracing::error!("Telegram bot error: {}", e);
             }
         }))
@@ -199,9 +222,9 @@ async fn run_daemon_services(config: &Config, agent_id: &str) -> Result<()> {
             "  Server: http://{}:{}",
             config.server.bind, config.server.port
         );
-        let server = Server::new_with_gate(config, turn_gate)?;
+        let server = Server::new_with_gates(config, agent_id, turn_gates)?;
         server.run().await?;
-    } else if heartbeat_handle.is_some() {
+    } else if !heartbeat_handles.is_empty() {
         // Server not enabled but heartbeat is - wait for Ctrl+C
         println!("  Server: disabled");
         tokio::signal::ctrl_c().await?;
@@ -211,7 +234,7 @@ async fn run_daemon_services(config: &Config, agent_id: &str) -> Result<()> {
     }
 
     // Abort background tasks on shutdown
-    if let Some(handle) = heartbeat_handle {
+    for handle in heartbeat_handles {
         handle.abort();
     }
     if let Some(handle) = telegram_handle {
@@ -300,8 +323,7 @@ async fn start_daemon(foreground: bool, agent_id: &str) -> Result<()> {
     fs::write(&pid_file, std::process::id().to_string())?;
 
     // Initialize components
-    let memory = MemoryManager::new_with_full_config(&config.memory, Some(&config), agent_id)?;
-    let _watcher = memory.start_watcher()?;
+    let _watchers = start_watchers(&config, agent_id)?;
 
     println!("Daemon started successfully");
 
@@ -0,0 +1,25 @@
+use anyhow::Result;
+use clap::{Args, Subcommand};
+
+use crate::config::Config;
+use crate::mcp::McpServer;
+
+#[derive(Args)]
+pub struct McpArgs {
+    #[command(subcommand)]
+    pub command: McpCommands,
+}
+
+#[derive(Subcommand)]
+pub enum McpCommands {
+    /// Serve memory and session search to MCP clients over stdio
+    Serve,
+}
+
+pub async fn run(args: McpArgs, agent_id: &str) -> Result<()> {
+    let config = Config::load()?.for_agent(agent_id);
+
+    match args.command {
+        McpCommands::Serve => McpServer::new(&config, agent_id)?.serve_stdio().await,
+    }
+}
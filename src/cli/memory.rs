@@ -41,7 +41,7 @@ pub enum MemoryCommands {
 }
 
 pub async fn run(args: MemoryArgs, agent_id: &str) -> Result<()> {
-    let config = Config::load()?;
+    let config = Config::load()?.for_agent(agent_id);
     let memory = MemoryManager::new_with_full_config(&config.memory, Some(&config), agent_id)?;
 
     match args.command {
@@ -1,11 +1,13 @@
 pub mod ask;
 pub mod chat;
+pub mod checkpoint;
 pub mod config;
 pub mod daemon;
 #[cfg(feature = "desktop")]
 pub mod desktop;
 #[cfg(feature = "gen")]
 pub mod gen3d;
+pub mod mcp;
 pub mod md;
 pub mod memory;
 pub mod paths;
@@ -62,6 +64,12 @@ pub enum Commands {
     /// Memory operations
     Memory(memory::MemoryArgs),
 
+    /// Workspace checkpoints of agent file changes
+    Checkpoint(checkpoint::CheckpointArgs),
+
+    /// Serve memory to MCP clients
+    Mcp(mcp::McpArgs),
+
     /// Configuration management
     Config(config::ConfigArgs),
 
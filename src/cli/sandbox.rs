@@ -1,5 +1,6 @@
 use anyhow::Result;
 use clap::{Args, Subcommand};
+use tokio_util::sync::CancellationToken;
 
 use crate::config::Config;
 use crate::sandbox::{SandboxLevel, build_policy, detect_capabilities, run_sandboxed};
@@ -76,6 +77,8 @@ async fn run_test() -> Result<()> {
 
     let workspace = config.workspace_path();
     let policy = build_policy(&config.sandbox, &workspace, effective);
+    // Smoke tests are never cancelled; timeouts still apply
+    let cancel = CancellationToken::new();
 
     println!("Running sandbox smoke tests...");
     println!("  Workspace: {}", workspace.display());
@@ -87,7 +90,7 @@ async fn run_test() -> Result<()> {
 
     // Test 1: Echo command succeeds
     print!("  [1/6] Echo command succeeds:        ");
-    match run_sandboxed("echo hello", &policy, 10_000).await {
+    match run_sandboxed("echo hello", &policy, 10_000, &cancel).await {
         Ok((output, code)) if code == 0 && output.contains("hello") => {
             println!("ok");
             passed += 1;
@@ -108,11 +111,25 @@ async fn run_test() -> Result<()> {
 
     // Test 2: Write outside workspace denied
     print!("  [2/6] Write outside workspace:      ");
-    match run_sandboxed("touch /tmp/localgpt-sandbox-test-ok 2>&1", &policy, 10_000).await {
+    match run_sandboxed(
+        "touch /tmp/localgpt-sandbox-test-ok 2>&1",
+        &policy,
+        10_000,
+        &cancel,
+    )
+    .await
+    {
         Ok((_, 0)) => {
             // /tmp is in extra_write_paths, so this should work.
             // Test a truly outside path instead.
-            match run_sandboxed("touch /localgpt-sandbox-test-deny 2>&1", &policy, 10_000).await {
+            match run_sandboxed(
+                "touch /localgpt-sandbox-test-deny 2>&1",
+                &policy,
+                10_000,
+                &cancel,
+            )
+            .await
+            {
                 Ok((_, code)) if code != 0 => {
                     println!("denied (ok)");
                     passed += 1;
@@ -127,7 +144,13 @@ async fn run_test() -> Result<()> {
                 }
             }
             // Clean up
-            let _ = run_sandboxed("rm -f /tmp/localgpt-sandbox-test-ok", &policy, 5_000).await;
+            let _ = run_sandboxed(
+                "rm -f /tmp/localgpt-sandbox-test-ok",
+                &policy,
+                5_000,
+                &cancel,
+            )
+            .await;
         }
         Ok((_, code)) => {
             println!("denied (ok, /tmp write also blocked: exit={})", code);
@@ -141,7 +164,7 @@ async fn run_test() -> Result<()> {
 
     // Test 3: Read ~/.ssh denied
     print!("  [3/6] Read ~/.ssh:                  ");
-    match run_sandboxed("ls ~/.ssh/ 2>&1", &policy, 10_000).await {
+    match run_sandboxed("ls ~/.ssh/ 2>&1", &policy, 10_000, &cancel).await {
         Ok((_, code)) if code != 0 => {
             println!("denied (ok)");
             passed += 1;
@@ -168,6 +191,7 @@ async fn run_test() -> Result<()> {
         "curl -s --connect-timeout 3 http://example.com 2>&1",
         &policy,
         15_000,
+        &cancel,
     )
     .await
     {
@@ -199,7 +223,7 @@ async fn run_test() -> Result<()> {
     // Test 5: Timeout enforcement
     print!("  [5/6] Timeout enforcement:          ");
     let start = std::time::Instant::now();
-    match run_sandboxed("sleep 30", &policy, 3_000).await {
+    match run_sandboxed("sleep 30", &policy, 3_000, &cancel).await {
         Err(e) if e.to_string().contains("timed out") => {
             let elapsed = start.elapsed();
             println!("killed after {:.1}s (ok)", elapsed.as_secs_f64());
@@ -223,7 +247,7 @@ async fn run_test() -> Result<()> {
 
     // Test 6: Sandbox disabled passthrough
     print!("  [6/6] Basic command works:          ");
-    match run_sandboxed("echo sandbox-ok && pwd", &policy, 10_000).await {
+    match run_sandboxed("echo sandbox-ok && pwd", &policy, 10_000, &cancel).await {
         Ok((output, 0)) if output.contains("sandbox-ok") => {
             println!("ok");
             passed += 1;
@@ -79,9 +79,9 @@ pub const COMMANDS: &[SlashCommand] = &[
     },
     SlashCommand {
         name: "resume",
-        description: "Resume a session",
+        description: "Resume a session (optionally on a branch)",
         aliases: &[],
-        usage: "<id>",
+        usage: "<id> [leaf]",
         interfaces: &[Interface::Cli],
     },
     SlashCommand {
@@ -133,6 +133,62 @@ pub const COMMANDS: &[SlashCommand] = &[
         usage: "",
         interfaces: &[Interface::Cli, Interface::Telegram],
     },
+    SlashCommand {
+        name: "expand",
+        description: "Restore messages the last compaction summarized",
+        aliases: &[],
+        usage: "",
+        interfaces: &[Interface::Cli],
+    },
+    SlashCommand {
+        name: "pin",
+        description: "Keep a message verbatim through compaction (default: your last)",
+        aliases: &[],
+        usage: "[n]",
+        interfaces: &[Interface::Cli],
+    },
+    SlashCommand {
+        name: "unpin",
+        description: "Let compaction summarize a pinned message",
+        aliases: &[],
+        usage: "<n>",
+        interfaces: &[Interface::Cli],
+    },
+    SlashCommand {
+        name: "history",
+        description: "List the messages of this branch, numbered",
+        aliases: &[],
+        usage: "",
+        interfaces: &[Interface::Cli],
+    },
+    SlashCommand {
+        name: "retry",
+        description: "Ask again for a reply to your last message",
+        aliases: &[],
+        usage: "",
+        interfaces: &[Interface::Cli],
+    },
+    SlashCommand {
+        name: "edit",
+        description: "Replace one of your messages and continue from it",
+        aliases: &[],
+        usage: "<n> <text>",
+        interfaces: &[Interface::Cli],
+    },
+    SlashCommand {
+        name: "branches",
+        description: "List branches made by retries and edits",
+        aliases: &[],
+        usage: "",
+        interfaces: &[Interface::Cli],
+    },
+    SlashCommand {
+        name: "branch",
+        description: "Switch to another branch",
+        aliases: &[],
+        usage: "<n|id>",
+        interfaces: &[Interface::Cli],
+    },
     SlashCommand {
         name: "clear",
         description: "Clear session history",
@@ -140,6 +196,13 @@ pub const COMMANDS: &[SlashCommand] = &[
         usage: "",
         interfaces: &[Interface::Cli, Interface::Telegram],
     },
+    SlashCommand {
+        name: "undo",
+        description: "Undo file changes of the last turn",
+        aliases: &[],
+        usage: "",
+        interfaces: &[Interface::Cli, Interface::Telegram],
+    },
     SlashCommand {
         name: "memory",
         description: "Search memory files",
@@ -161,6 +224,13 @@ pub const COMMANDS: &[SlashCommand] = &[
         usage: "",
         interfaces: &[Interface::Cli],
     },
+    SlashCommand {
+        name: "stop",
+        description: "Stop the response in progress",
+        aliases: &[],
+        usage: "",
+        interfaces: &[Interface::Telegram],
+    },
     SlashCommand {
         name: "status",
         description: "Show session info",
@@ -1,5 +1,5 @@
 mod turn_gate;
 mod workspace_lock;
 
-pub use turn_gate::TurnGate;
+pub use turn_gate::{TurnGate, TurnGates};
 pub use workspace_lock::{WorkspaceLock, WorkspaceLockGuard};
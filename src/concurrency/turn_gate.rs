@@ -3,6 +3,8 @@
 //! Prevents heartbeat and HTTP sessions from running agent turns
 //! simultaneously within the same daemon process.
 
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use tokio::sync::{OwnedSemaphorePermit, Semaphore};
 
@@ -48,6 +50,27 @@ impl Default for TurnGate {
     }
 }
 
+/// Turn gates by workspace, for a daemon hosting several agents. Heartbeat,
+/// HTTP and Telegram turns of agents sharing a workspace share one gate.
+#[derive(Clone, Default)]
+pub struct TurnGates {
+    gates: Arc<std::sync::Mutex<HashMap<PathBuf, TurnGate>>>,
+}
+
+impl TurnGates {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Gate for a workspace, created on first use
+    pub fn for_workspace(&self, workspace: &Path) -> TurnGate {
+        match self.gates.lock() {
+            Ok(mut gates) => gates.entry(workspace.to_path_buf()).or_default().clone(),
+            Err(_) => TurnGate::new(),
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -72,6 +95,15 @@ mod tests {
         assert!(gate.try_acquire().is_none());
     }
 
+    #[tokio::test]
+    async fn gates_are_per_workspace() {
+        let gates = TurnGates::new();
+        let work = Path::new("/data/workspace-work");
+        let _permit = gates.for_workspace(work).acquire().await;
+        assert!(gates.for_workspace(work).is_busy());
+        assert!(!gates.for_workspace(Path::new("/data/workspace")).is_busy());
+    }
+
     #[tokio::test]
     async fn try_acquire_succeeds_when_free() {
         let gate = TurnGate::new();
@@ -43,6 +43,16 @@ impl WorkspaceLock {
         Ok(Self { path })
     }
 
+    /// Lock for the workspace of a (per-agent) config; agents sharing a
+    /// workspace share the lock.
+    pub fn for_config(config: &crate::config::Config) -> Result<Self> {
+        let path = config.paths.workspace_lock_for(&config.workspace_path());
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        Ok(Self { path })
+    }
+
     /// Blocking acquire — waits until the lock is available.
     ///
     /// Returns an RAII guard that releases the lock on drop.
@@ -8,7 +8,7 @@ use std::fs;
 use std::path::PathBuf;
 use tracing::{debug, info, warn};
 
-use super::{AnthropicConfig, ClaudeCliConfig, Config, OpenAIConfig};
+use super::{AnthropicConfig, ClaudeCliConfig, Config, OpenAIConfig, RetryConfig};
 
 /// OpenClaw config structure (partial - only fields we can migrate)
 #[derive(Debug, Deserialize)]
@@ -137,6 +137,7 @@ fn convert_openclaw_config(oc: OpenClawConfig) -> Config {
             config.providers.openai = Some(OpenAIConfig {
                 api_key,
                 base_url: "https://api.openai.com/v1".to_string(),
+                retry: RetryConfig::default(),
             });
         }
 
@@ -146,6 +147,8 @@ fn convert_openclaw_config(oc: OpenClawConfig) -> Config {
             config.providers.anthropic = Some(AnthropicConfig {
                 api_key,
                 base_url: "https://api.anthropic.com".to_string(),
+                prompt_caching: true,
+                retry: RetryConfig::default(),
             });
         }
     }
@@ -6,6 +6,7 @@ pub use schema::*;
 
 use anyhow::Result;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::fs;
 use std::path::PathBuf;
 
@@ -23,9 +24,17 @@ pub struct Config {
     #[serde(default)]
     pub providers: ProvidersConfig,
 
+    /// Model registry: aliases with capability metadata, keyed by alias
+    #[serde(default)]
+    pub models: HashMap<String, ModelEntry>,
+
     #[serde(default)]
     pub heartbeat: HeartbeatConfig,
 
+    /// Agents hosted by the daemon, keyed by agent ID (none: just `--agent`)
+    #[serde(default)]
+    pub agents: HashMap<String, AgentProfile>,
+
     #[serde(default)]
     pub memory: MemoryConfig,
 
@@ -46,6 +55,19 @@ pub struct Config {
 
     #[serde(default)]
     pub telegram: Option<TelegramConfig>,
+
+    #[serde(default)]
+    pub cost: CostConfig,
+
+    #[serde(default)]
+    pub checkpoints: CheckpointConfig,
+
+    /// Commands run at lifecycle events (`[[hooks]]`)
+    #[serde(default)]
+    pub hooks: Vec<HookConfig>,
+
+    #[serde(default)]
+    pub mcp: McpConfig,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -62,6 +84,87 @@ pub struct AgentConfig {
     /// Maximum tokens for LLM response
     #[serde(default = "default_max_tokens")]
     pub max_tokens: usize,
+
+    /// Models to try, in order, when the active model fails with a
+    /// retryable error (5xx, overload, rate limit, network)
+    /// e.g., ["openai/gpt-4o", "ollama/llama3"]
+    #[serde(default)]
+    pub fallback_models: Vec<String>,
+
+    /// Save model reasoning (thinking blocks) in session transcripts
+    #[serde(default)]
+    pub persist_thinking: bool,
+
+    /// Limits on the tool loop within a single turn
+    #[serde(default)]
+    pub tool_limits: ToolLimitsConfig,
+
+    /// Sub-agents started by the `delegate` tool
+    #[serde(default)]
+    pub delegate: DelegateConfig,
+
+    /// How sessions are summarized when they outgrow the context window
+    #[serde(default)]
+    pub compaction: CompactionConfig,
+}
+
+/// Automatic compaction summarizes the oldest messages, window by window,
+/// until the session is back under the target
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CompactionConfig {
+    /// Share of the usable context window (percent) a compacted session aims for
+    #[serde(default = "default_compaction_target_percent")]
+    pub target_percent: usize,
+
+    /// Tokens of transcript summarized per summarizer call
+    #[serde(default = "default_compaction_window_tokens")]
+    pub window_tokens: usize,
+}
+
+/// Defaults and caps for the `delegate` tool. Budgets requested by the model
+/// are clamped to these; 0 disables a cap.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DelegateConfig {
+    /// Offer the `delegate` tool to the agent
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+
+    /// Model for sub-agents when the call doesn't name one (empty: the parent's)
+    #[serde(default)]
+    pub model: String,
+
+    /// Rounds of tool calls a sub-agent may make
+    #[serde(default = "default_delegate_max_turns")]
+    pub max_turns: usize,
+
+    /// Tokens (prompt + output) a sub-agent may spend
+    #[serde(default = "default_delegate_max_tokens")]
+    pub max_tokens: u64,
+
+    /// Characters of the sub-agent's answer returned to the parent
+    #[serde(default = "default_delegate_max_result_chars")]
+    pub max_result_chars: usize,
+}
+
+/// Guard rails for the tool loop. When one is hit the model gets a final
+/// turn to summarize and stop. 0 disables a limit.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ToolLimitsConfig {
+    /// Rounds of tool calls (model responses with tool use) per turn
+    #[serde(default = "default_max_tool_rounds")]
+    pub max_rounds: usize,
+
+    /// Individual tool calls per turn
+    #[serde(default = "default_max_tool_calls")]
+    pub max_calls: usize,
+
+    /// Wall-clock time per turn, checked before each round
+    #[serde(default = "default_max_turn_secs")]
+    pub max_turn_secs: u64,
+
+    /// Times the same tool may be called with identical arguments per turn
+    #[serde(default = "default_max_repeated_calls")]
+    pub max_repeated_calls: usize,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -79,6 +182,11 @@ pub struct ToolsConfig {
     #[serde(default)]
     pub require_approval: Vec<String>,
 
+    /// Allow/ask/deny rules matched on tool arguments; checked before
+    /// `require_approval`
+    #[serde(default)]
+    pub rules: Vec<ToolRule>,
+
     /// Maximum characters for tool output (0 = unlimited)
     #[serde(default = "default_tool_output_max_chars")]
     pub tool_output_max_chars: usize,
@@ -90,6 +198,62 @@ pub struct ToolsConfig {
     /// Wrap tool outputs and memory content with XML-style delimiters
     #[serde(default = "default_true")]
     pub use_content_delimiters: bool,
+
+    /// Read-only tool calls (read_file, memory_search, web_fetch, ...) from
+    /// one model response that may run at once (1 = one at a time)
+    #[serde(default = "default_max_parallel_tools")]
+    pub max_parallel: usize,
+
+    /// Seconds to wait for an approval decision before the call is denied
+    /// (0 = wait indefinitely)
+    #[serde(default = "default_approval_timeout_secs")]
+    pub approval_timeout_secs: u64,
+
+    /// What happens to calls needing approval when nobody can be asked
+    /// (heartbeat runs, plain HTTP requests): "deny", or "queue" to deny
+    /// and record them in approval_queue.jsonl for review
+    #[serde(default = "default_unattended_approval")]
+    pub unattended_approval: UnattendedApproval,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum UnattendedApproval {
+    Deny,
+    Queue,
+}
+
+/// Permission rule for tool calls (`[[tools.rules]]`)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ToolRule {
+    /// Tool name, or "*" for any tool
+    pub tool: String,
+
+    /// Glob on the call's main argument: the path for file tools, the
+    /// command for bash, the URL host for web_fetch. Prefix with "re:" for a
+    /// regex. Unset matches every call to the tool.
+    #[serde(default)]
+    pub pattern: Option<String>,
+
+    pub action: ToolAction,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ToolAction {
+    Allow,
+    Ask,
+    Deny,
+}
+
+impl ToolAction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Allow => "allow",
+            Self::Ask => "ask",
+            Self::Deny => "deny",
+        }
+    }
 }
 
 #[derive(Debug, Clone, Default, Serialize, Deserialize)]
@@ -109,6 +273,12 @@ pub struct SecurityConfig {
     /// Skip injecting the hardcoded security suffix (default: false)
     #[serde(default)]
     pub disable_suffix: bool,
+
+    /// After a tool's output matches a prompt injection pattern, ask before
+    /// running any tool but memory_search, memory_get and read_file for the
+    /// rest of the turn, even ones a rule allows (default: false)
+    #[serde(default)]
+    pub approve_after_injection: bool,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -203,6 +373,32 @@ pub struct ProvidersConfig {
 
     #[serde(default)]
     pub glm: Option<GlmConfig>,
+
+    /// In-process llama.cpp chat models (`gguf/<file>`, requires `gguf` feature)
+    #[serde(default)]
+    pub gguf: Option<GgufConfig>,
+
+    /// Record/replay provider for offline tests (`replay/<cassette>`)
+    #[serde(default)]
+    pub replay: Option<ReplayConfig>,
+
+    /// Named OpenAI-compatible endpoints, addressed as "<name>/<model>"
+    #[serde(default)]
+    pub custom: HashMap<String, CustomProviderConfig>,
+}
+
+impl ProvidersConfig {
+    /// OpenAI-compatible endpoint registered under `name`: a
+    /// `[providers.custom.<name>]` entry, or a preset such as `[providers.glm]`
+    pub fn custom_provider(&self, name: &str) -> Option<CustomProviderConfig> {
+        if let Some(custom) = self.custom.get(name) {
+            return Some(custom.clone());
+        }
+        match name {
+            "glm" => self.glm.as_ref().map(CustomProviderConfig::from),
+            _ => None,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -211,6 +407,9 @@ pub struct OpenAIConfig {
 
     #[serde(default = "default_openai_base_url")]
     pub base_url: String,
+
+    #[serde(default)]
+    pub retry: RetryConfig,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -219,6 +418,13 @@ pub struct AnthropicConfig {
 
     #[serde(default = "default_anthropic_base_url")]
     pub base_url: String,
+
+    /// Mark the system prompt and tool definitions as cacheable (prompt caching)
+    #[serde(default = "default_true")]
+    pub prompt_caching: bool,
+
+    #[serde(default)]
+    pub retry: RetryConfig,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -228,6 +434,29 @@ pub struct OllamaConfig {
 
     #[serde(default = "default_ollama_model")]
     pub model: String,
+
+    #[serde(default)]
+    pub retry: RetryConfig,
+}
+
+/// Retry policy for transient HTTP failures (429, 5xx, overloaded, connection errors)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RetryConfig {
+    /// Total attempts including the first request (1 = no retries)
+    #[serde(default = "default_retry_max_attempts")]
+    pub max_attempts: u32,
+
+    /// Delay before the first retry; doubles on each further attempt
+    #[serde(default = "default_retry_base_delay_ms")]
+    pub base_delay_ms: u64,
+
+    /// Upper bound for a single delay. Retry-After hints above this fail fast.
+    #[serde(default = "default_retry_max_delay_ms")]
+    pub max_delay_ms: u64,
+
+    /// Random spread applied to each delay (0.2 = ±20%)
+    #[serde(default = "default_retry_jitter")]
+    pub jitter: f64,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -245,6 +474,281 @@ pub struct GlmConfig {
 
     #[serde(default = "default_glm_base_url")]
     pub base_url: String,
+
+    #[serde(default)]
+    pub retry: RetryConfig,
+}
+
+/// Local GGUF chat models run in-process via llama.cpp
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GgufConfig {
+    /// Directory searched for `gguf/<file>` model files
+    #[serde(default = "default_gguf_models_dir")]
+    pub models_dir: String,
+
+    /// Context size in tokens (prompt + reply)
+    #[serde(default = "default_gguf_context_size")]
+    pub context_size: u32,
+
+    /// CPU threads for inference (default: llama.cpp's choice)
+    #[serde(default)]
+    pub threads: Option<i32>,
+
+    /// Sampling temperature (0 = greedy)
+    #[serde(default = "default_gguf_temperature")]
+    pub temperature: f32,
+}
+
+impl Default for GgufConfig {
+    fn default() -> Self {
+        Self {
+            models_dir: default_gguf_models_dir(),
+            context_size: default_gguf_context_size(),
+            threads: None,
+            temperature: default_gguf_temperature(),
+        }
+    }
+}
+
+/// Record/replay cassettes for `replay/<cassette>`
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ReplayConfig {
+    /// Directory for relative cassette names (default: <data_dir>/cassettes)
+    #[serde(default)]
+    pub cassette_dir: Option<String>,
+
+    /// Model to record from. When set, `replay/<cassette>` wraps this model
+    /// and writes a new cassette instead of replaying one.
+    #[serde(default)]
+    pub record: Option<String>,
+}
+
+/// A named OpenAI-compatible endpoint (`[providers.custom.<name>]`),
+/// e.g. LM Studio, vLLM or llamafile
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CustomProviderConfig {
+    /// Base URL including the API version, e.g. "http://127.0.0.1:8000/v1"
+    pub base_url: String,
+
+    /// Sent as a bearer token; leave empty for servers without auth
+    #[serde(default)]
+    pub api_key: String,
+
+    /// Extra HTTP headers sent with every request
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+
+    /// Model used when addressed without one (e.g. "vllm" or "vllm/")
+    #[serde(default)]
+    pub default_model: Option<String>,
+
+    #[serde(default)]
+    pub retry: RetryConfig,
+}
+
+impl From<&GlmConfig> for CustomProviderConfig {
+    fn from(glm: &GlmConfig) -> Self {
+        Self {
+            base_url: glm.base_url.clone(),
+            api_key: glm.api_key.clone(),
+            headers: HashMap::new(),
+            default_model: Some("glm-4.7".to_string()),
+            retry: glm.retry.clone(),
+        }
+    }
+}
+
+/// A `[models.<alias>]` registry entry. Unset capabilities fall back to
+/// built-in metadata for the same provider/id, if any.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ModelEntry {
+    /// Provider name: "anthropic", "openai", "ollama", "glm", "claude-cli",
+    /// "gguf", or a `[providers.custom.<name>]` endpoint
+    pub provider: String,
+
+    /// Model ID sent to the provider API
+    pub id: String,
+
+    /// Context window in tokens (overrides agent.context_window)
+    #[serde(default)]
+    pub context_window: Option<usize>,
+
+    /// Maximum output tokens per response (caps agent.max_tokens)
+    #[serde(default)]
+    pub max_output_tokens: Option<usize>,
+
+    /// Accepts image attachments
+    #[serde(default)]
+    pub vision: Option<bool>,
+
+    /// Supports tool calling (tool schemas are only sent when true)
+    #[serde(default)]
+    pub tools: Option<bool>,
+
+    /// Price in USD per million tokens
+    #[serde(default)]
+    pub price: Option<ModelPricing>,
+
+    /// Extended thinking budget in tokens (Anthropic); any value enables
+    /// thinking on Ollama models that support it
+    #[serde(default)]
+    pub thinking_budget: Option<u32>,
+
+    /// Reasoning effort for OpenAI-compatible reasoning models
+    /// ("low", "medium", "high")
+    #[serde(default)]
+    pub reasoning_effort: Option<String>,
+}
+
+/// Spend tracking: price overrides and budgets
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CostConfig {
+    /// Price overrides keyed by "provider/model" (or "provider/*"),
+    /// merged over the built-in pricing table
+    #[serde(default)]
+    pub pricing: HashMap<String, ModelPricing>,
+
+    /// Daily spend limit in USD across all agents and sources (local time)
+    #[serde(default)]
+    pub daily_budget_usd: Option<f64>,
+
+    /// Monthly spend limit in USD across all agents and sources
+    #[serde(default)]
+    pub monthly_budget_usd: Option<f64>,
+
+    /// Heartbeat behavior once a budget is exceeded: "block" | "downgrade"
+    #[serde(default = "default_budget_action")]
+    pub budget_action: BudgetAction,
+
+    /// Model heartbeat switches to when budget_action = "downgrade"
+    /// (e.g., "ollama/llama3"). Without it, heartbeat is blocked.
+    #[serde(default)]
+    pub downgrade_model: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BudgetAction {
+    Block,
+    Downgrade,
+}
+
+/// Snapshots of workspace files taken before the agent changes them
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CheckpointConfig {
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+
+    /// Checkpoints kept per workspace; older ones are pruned (0 = keep all)
+    #[serde(default = "default_checkpoint_keep")]
+    pub keep: usize,
+
+    /// Files larger than this are left out of snapshots
+    #[serde(default = "default_checkpoint_max_file_kb")]
+    pub max_file_kb: u64,
+}
+
+/// Events a hook can run at
+pub const HOOK_EVENTS: &[&str] = &[
+    "pre_tool_use",
+    "post_tool_use",
+    "user_prompt_submit",
+    "session_start",
+    "session_end",
+    "post_compact",
+];
+
+/// Shell command run at a lifecycle event (`[[hooks]]`), with the event as
+/// JSON on stdin
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HookConfig {
+    /// One of `HOOK_EVENTS`
+    pub event: String,
+
+    /// Glob on the tool name for tool events (unset: every tool)
+    #[serde(default)]
+    pub tool: Option<String>,
+
+    pub command: String,
+
+    #[serde(default = "default_hook_timeout_ms")]
+    pub timeout_ms: u64,
+
+    /// What a hook that fails, times out or can't run means for its tool
+    /// call: "block" (default for pre_tool_use) or "allow" (default for
+    /// post_tool_use). Only tool events use it.
+    #[serde(default)]
+    pub on_error: Option<HookOnError>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HookOnError {
+    /// Don't run the call (pre_tool_use) or withhold its output (post_tool_use)
+    Block,
+    /// Carry on as if the hook weren't there
+    Allow,
+}
+
+impl HookConfig {
+    pub fn blocks_on_error(&self) -> bool {
+        let default = if self.event == "pre_tool_use" {
+            HookOnError::Block
+        } else {
+            HookOnError::Allow
+        };
+        self.on_error.unwrap_or(default) == HookOnError::Block
+    }
+}
+
+/// Model Context Protocol servers whose tools the agent can use
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct McpConfig {
+    /// Servers by name; tools are exposed as `<name>__<tool>`
+    #[serde(default)]
+    pub servers: HashMap<String, McpServerConfig>,
+}
+
+/// An MCP server: launched over stdio (`command`) or reached over
+/// streamable HTTP (`url`)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct McpServerConfig {
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+
+    #[serde(default)]
+    pub command: Option<String>,
+
+    #[serde(default)]
+    pub args: Vec<String>,
+
+    /// Extra environment for the server process (values support ${VAR})
+    #[serde(default)]
+    pub env: HashMap<String, String>,
+
+    #[serde(default)]
+    pub url: Option<String>,
+
+    /// Extra HTTP headers, e.g. Authorization (values support ${VAR})
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+
+    /// Timeout for each request to the server
+    #[serde(default = "default_mcp_timeout_secs")]
+    pub timeout_secs: u64,
+}
+
+/// Model price in USD per million tokens
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct ModelPricing {
+    pub input: f64,
+    pub output: f64,
+    /// Cache read price (defaults to the input price)
+    #[serde(default)]
+    pub cache_read: Option<f64>,
+    /// Cache write price (defaults to the input price)
+    #[serde(default)]
+    pub cache_write: Option<f64>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -262,6 +766,23 @@ pub struct HeartbeatConfig {
     pub timezone: Option<String>,
 }
 
+/// Per-agent overrides of the top-level config (`[agents.<id>]`)
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct AgentProfile {
+    /// Workspace directory (default: data_dir/workspace-{id}; "main" keeps
+    /// the regular workspace)
+    #[serde(default)]
+    pub workspace: Option<String>,
+
+    /// Default model for this agent
+    #[serde(default)]
+    pub model: Option<String>,
+
+    /// Heartbeat schedule for this agent (default: `[heartbeat]`)
+    #[serde(default)]
+    pub heartbeat: Option<HeartbeatConfig>,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ActiveHours {
     pub start: String,
@@ -306,6 +827,30 @@ pub struct MemoryConfig {
     /// Set to 0 to preserve full message content like OpenClaw
     #[serde(default)]
     pub session_max_chars: usize,
+
+    /// Search memory on each user message and add the best matches to the turn
+    #[serde(default)]
+    pub auto_recall: AutoRecallConfig,
+}
+
+/// Automatic memory recall (`[memory.auto_recall]`)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AutoRecallConfig {
+    #[serde(default)]
+    pub enabled: bool,
+
+    /// Chunks added per turn at most
+    #[serde(default = "default_recall_max_results")]
+    pub max_results: usize,
+
+    /// Chunks scoring lower are left out. Keyword-only search scores are
+    /// BM25 (unbounded); hybrid search scores are between 0 and 1.
+    #[serde(default)]
+    pub min_score: f64,
+
+    /// Token budget for recalled chunks per turn
+    #[serde(default = "default_recall_max_tokens")]
+    pub max_tokens: usize,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -346,9 +891,41 @@ pub struct TelegramConfig {
     pub enabled: bool,
 
     pub api_token: String,
+
+    /// Agent for chats not listed in `chat_agents`
+    #[serde(default = "default_telegram_agent")]
+    pub default_agent: String,
+
+    /// Agent ID by Telegram chat ID, e.g. { "123456789" = "work" }
+    #[serde(default)]
+    pub chat_agents: HashMap<String, String>,
+}
+
+impl TelegramConfig {
+    /// Agent that handles a chat
+    pub fn agent_for_chat(&self, chat_id: i64) -> &str {
+        self.chat_agents
+            .get(&chat_id.to_string())
+            .unwrap_or(&self.default_agent)
+    }
 }
 
 // Default value functions
+fn default_checkpoint_keep() -> usize {
+    50
+}
+fn default_checkpoint_max_file_kb() -> u64 {
+    1024
+}
+fn default_mcp_timeout_secs() -> u64 {
+    60
+}
+fn default_hook_timeout_ms() -> u64 {
+    10_000
+}
+fn default_telegram_agent() -> String {
+    "telegram".to_string()
+}
 fn default_model() -> String {
     // Default to Claude CLI (uses existing Claude Code auth, no API key needed)
     "claude-cli/opus".to_string()
@@ -371,6 +948,15 @@ fn default_web_fetch_max_bytes() -> usize {
 fn default_tool_output_max_chars() -> usize {
     50000 // 50k characters max for tool output by default
 }
+fn default_max_parallel_tools() -> usize {
+    4
+}
+fn default_approval_timeout_secs() -> u64 {
+    300
+}
+fn default_unattended_approval() -> UnattendedApproval {
+    UnattendedApproval::Deny
+}
 fn default_openai_base_url() -> String {
     "https://api.openai.com/v1".to_string()
 }
@@ -392,6 +978,57 @@ fn default_claude_cli_model() -> String {
 fn default_glm_base_url() -> String {
     "https://api.z.ai/api/coding/paas/v4".to_string()
 }
+fn default_gguf_models_dir() -> String {
+    "~/.cache/localgpt/models".to_string()
+}
+fn default_gguf_context_size() -> u32 {
+    8192
+}
+fn default_gguf_temperature() -> f32 {
+    0.7
+}
+fn default_retry_max_attempts() -> u32 {
+    3
+}
+fn default_retry_base_delay_ms() -> u64 {
+    500
+}
+fn default_retry_max_delay_ms() -> u64 {
+    30000 // 30 seconds
+}
+fn default_retry_jitter() -> f64 {
+    0.2
+}
+fn default_max_tool_rounds() -> usize {
+    25
+}
+fn default_max_tool_calls() -> usize {
+    100
+}
+fn default_max_turn_secs() -> u64 {
+    900 // 15 minutes
+}
+fn default_max_repeated_calls() -> usize {
+    3
+}
+fn default_delegate_max_turns() -> usize {
+    10
+}
+fn default_delegate_max_tokens() -> u64 {
+    200_000
+}
+fn default_delegate_max_result_chars() -> usize {
+    4000
+}
+fn default_compaction_target_percent() -> usize {
+    50
+}
+fn default_compaction_window_tokens() -> usize {
+    16_000
+}
+fn default_budget_action() -> BudgetAction {
+    BudgetAction::Block
+}
 fn default_true() -> bool {
     true
 }
@@ -428,6 +1065,12 @@ fn default_pattern() -> String {
 fn default_session_max_messages() -> usize {
     15 // Match OpenClaw's default
 }
+fn default_recall_max_results() -> usize {
+    3
+}
+fn default_recall_max_tokens() -> usize {
+    1000
+}
 fn default_port() -> u16 {
     31327
 }
@@ -466,6 +1109,43 @@ impl Default for AgentConfig {
             context_window: default_context_window(),
             reserve_tokens: default_reserve_tokens(),
             max_tokens: default_max_tokens(),
+            fallback_models: Vec::new(),
+            persist_thinking: false,
+            tool_limits: ToolLimitsConfig::default(),
+            delegate: DelegateConfig::default(),
+            compaction: CompactionConfig::default(),
+        }
+    }
+}
+
+impl Default for CompactionConfig {
+    fn default() -> Self {
+        Self {
+            target_percent: default_compaction_target_percent(),
+            window_tokens: default_compaction_window_tokens(),
+        }
+    }
+}
+
+impl Default for DelegateConfig {
+    fn default() -> Self {
+        Self {
+            enabled: default_true(),
+            model: String::new(),
+            max_turns: default_delegate_max_turns(),
+            max_tokens: default_delegate_max_tokens(),
+            max_result_chars: default_delegate_max_result_chars(),
+        }
+    }
+}
+
+impl Default for ToolLimitsConfig {
+    fn default() -> Self {
+        Self {
+            max_rounds: default_max_tool_rounds(),
+            max_calls: default_max_tool_calls(),
+            max_turn_secs: default_max_turn_secs(),
+            max_repeated_calls: default_max_repeated_calls(),
         }
     }
 }
@@ -476,9 +1156,46 @@ impl Default for ToolsConfig {
             bash_timeout_ms: default_bash_timeout(),
             web_fetch_max_bytes: default_web_fetch_max_bytes(),
             require_approval: Vec::new(),
+            rules: Vec::new(),
             tool_output_max_chars: default_tool_output_max_chars(),
             log_injection_warnings: default_true(),
             use_content_delimiters: default_true(),
+            max_parallel: default_max_parallel_tools(),
+            approval_timeout_secs: default_approval_timeout_secs(),
+            unattended_approval: default_unattended_approval(),
+        }
+    }
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        Self {
+            max_attempts: default_retry_max_attempts(),
+            base_delay_ms: default_retry_base_delay_ms(),
+            max_delay_ms: default_retry_max_delay_ms(),
+            jitter: default_retry_jitter(),
+        }
+    }
+}
+
+impl Default for CheckpointConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            keep: default_checkpoint_keep(),
+            max_file_kb: default_checkpoint_max_file_kb(),
+        }
+    }
+}
+
+impl Default for CostConfig {
+    fn default() -> Self {
+        Self {
+            pricing: HashMap::new(),
+            daily_budget_usd: None,
+            monthly_budget_usd: None,
+            budget_action: default_budget_action(),
+            downgrade_model: None,
         }
     }
 }
@@ -506,6 +1223,18 @@ impl Default for MemoryConfig {
             paths: default_index_paths(),
             session_max_messages: default_session_max_messages(),
             session_max_chars: 0, // 0 = unlimited (preserve full content like OpenClaw)
+            auto_recall: AutoRecallConfig::default(),
+        }
+    }
+}
+
+impl Default for AutoRecallConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            max_results: default_recall_max_results(),
+            min_score: 0.0,
+            max_tokens: default_recall_max_tokens(),
         }
     }
 }
@@ -571,9 +1300,121 @@ impl Config {
             }
         }
 
+        for id in config.agents.keys() {
+            if !is_valid_agent_id(id) {
+                anyhow::bail!(
+                    "Invalid agent ID [agents.{}]: use letters, digits, '-' and '_'",
+                    id
+                );
+            }
+        }
+        if let Some(ref telegram) = config.telegram {
+            for id in std::iter::once(&telegram.default_agent).chain(telegram.chat_agents.values())
+            {
+                if !is_valid_agent_id(id) {
+                    anyhow::bail!(
+                        "Invalid Telegram agent ID {:?}: use letters, digits, '-' and '_'",
+                        id
+                    );
+                }
+            }
+        }
+        for hook in &config.hooks {
+            if !HOOK_EVENTS.contains(&hook.event.as_str()) {
+                anyhow::bail!(
+                    "Unknown hook event {:?}: expected one of {}",
+                    hook.event,
+                    HOOK_EVENTS.join(", ")
+                );
+            }
+            if hook.on_error.is_some() && !hook.event.ends_with("_tool_use") {
+                anyhow::bail!(
+                    "Hook on_error only applies to pre_tool_use and post_tool_use, not {:?}",
+                    hook.event
+                );
+            }
+            if let Some(ref tool) = hook.tool {
+                glob::Pattern::new(tool)
+                    .map_err(|e| anyhow::anyhow!("Invalid hook tool pattern {:?}: {}", tool, e))?;
+            }
+        }
+        config.check_context_windows()?;
+        for (name, server) in &config.mcp.servers {
+            if !is_valid_agent_id(name) || name.contains("__") {
+                anyhow::bail!(
+                    "Invalid MCP server name [mcp.servers.{}]: use letters, digits, '-' and single '_'",
+                    name
+                );
+            }
+            if server.command.is_some() == server.url.is_some() {
+                anyhow::bail!(
+                    "MCP server [mcp.servers.{}] needs either command or url",
+                    name
+                );
+            }
+        }
+
         Ok(config)
     }
 
+    /// Every context window must leave room for the transcript after
+    /// `agent.reserve_tokens` and the security block
+    fn check_context_windows(&self) -> Result<()> {
+        let reserved = self.agent.reserve_tokens + crate::agent::SECURITY_BLOCK_RESERVE;
+        let windows = std::iter::once(("agent".to_string(), self.agent.context_window)).chain(
+            self.models
+                .iter()
+                .filter_map(|(name, m)| Some((format!("models.{}", name), m.context_window?))),
+        );
+        for (table, window) in windows {
+            if window <= reserved {
+                anyhow::bail!(
+                    "[{}] context_window = {} leaves no room for the conversation: \
+                     agent.reserve_tokens ({}) and the security block ({}) take {} tokens. \
+                     Lower agent.reserve_tokens.",
+                    table,
+                    window,
+                    self.agent.reserve_tokens,
+                    crate::agent::SECURITY_BLOCK_RESERVE,
+                    reserved
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Agent IDs the daemon hosts: the `[agents.*]` tables, or just `fallback`
+    /// (the `--agent` flag) when there are none
+    pub fn hosted_agents(&self, fallback: &str) -> Vec<String> {
+        if self.agents.is_empty() {
+            return vec![fallback.to_string()];
+        }
+        let mut ids: Vec<String> = self.agents.keys().cloned().collect();
+        ids.sort();
+        ids
+    }
+
+    /// This config with an agent's `[agents.<id>]` overrides applied. Agents
+    /// without a profile use the config as is.
+    pub fn for_agent(&self, agent_id: &str) -> Config {
+        let mut config = self.clone();
+        let Some(profile) = self.agents.get(agent_id) else {
+            return config;
+        };
+        if let Some(ref workspace) = profile.workspace {
+            config.paths.workspace = PathBuf::from(shellexpand::tilde(workspace).to_string());
+        } else if agent_id != "main" {
+            config.paths.workspace = self.paths.data_dir.join(format!("workspace-{}", agent_id));
+        }
+        if let Some(ref model) = profile.model {
+            config.agent.default_model = model.clone();
+        }
+        if let Some(ref heartbeat) = profile.heartbeat {
+            config.heartbeat = heartbeat.clone();
+        }
+        config
+    }
+
     pub fn save(&self) -> Result<()> {
         let path = self.paths.config_file();
 
@@ -615,9 +1456,23 @@ impl Config {
         if let Some(ref mut anthropic) = self.providers.anthropic {
             anthropic.api_key = expand_env(&anthropic.api_key);
         }
+        if let Some(ref mut glm) = self.providers.glm {
+            glm.api_key = expand_env(&glm.api_key);
+        }
+        for custom in self.providers.custom.values_mut() {
+            custom.api_key = expand_env(&custom.api_key);
+            for value in custom.headers.values_mut() {
+                *value = expand_env(value);
+            }
+        }
         if let Some(ref mut telegram) = self.telegram {
             telegram.api_token = expand_env(&telegram.api_token);
         }
+        for server in self.mcp.servers.values_mut() {
+            for value in server.env.values_mut().chain(server.headers.values_mut()) {
+                *value = expand_env(value);
+            }
+        }
     }
 
     pub fn get_value(&self, key: &str) -> Result<String> {
@@ -670,6 +1525,14 @@ impl Config {
     }
 }
 
+/// Agent IDs name directories and index files, so keep them to simple names
+pub fn is_valid_agent_id(id: &str) -> bool {
+    !id.is_empty()
+        && id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
 fn expand_env(s: &str) -> String {
     if let Some(var_name) = s.strip_prefix("${").and_then(|s| s.strip_suffix('}')) {
         std::env::var(var_name).unwrap_or_else(|_| s.to_string())
@@ -689,15 +1552,48 @@ const DEFAULT_CONFIG_TEMPLATE: &str = r#"# LocalGPT Configuration
 default_model = "claude-cli/opus"
 context_window = 128000
 reserve_tokens = 8000
+# Models to try in order when the default model is down (5xx, overload, rate limit)
+# fallback_models = ["openai/gpt-4o", "ollama/llama3"]
+# Save model reasoning (thinking blocks) in session transcripts
+# persist_thinking = false
+#
+# Tool loop limits per turn (0 = unlimited); on a hit the model summarizes and stops
+# [agent.tool_limits]
+# max_rounds = 25
+# max_calls = 100
+# max_turn_secs = 900
+# max_repeated_calls = 3
+#
+# Sub-agents started by the delegate tool (budgets requested by the model are
+# capped by these; 0 = no cap)
+# [agent.delegate]
+# enabled = true
+# model = "anthropic/claude-haiku-4-5"   # default: the parent's model
+# max_turns = 10
+# max_tokens = 200000
+# max_result_chars = 4000
 
 # Anthropic API (for anthropic/* models)
 # [providers.anthropic]
 # api_key = "${ANTHROPIC_API_KEY}"
+# prompt_caching = true  # cache system prompt + tools between turns
+#
+# Retry transient failures (429, 5xx, overloaded) with exponential backoff
+# [providers.anthropic.retry]
+# max_attempts = 3
+# base_delay_ms = 500
+# max_delay_ms = 30000
+# jitter = 0.2
 
 # OpenAI API (for openai/* models)
 # [providers.openai]
 # api_key = "${OPENAI_API_KEY}"
 
+# Named OpenAI-compatible endpoints (for <name>/* models, e.g. vllm/<model>)
+# [providers.custom.vllm]
+# base_url = "http://127.0.0.1:8000/v1"
+# default_model = "Qwen/Qwen2.5-7B-Instruct"
+
 # Claude CLI (for claude-cli/* models, requires claude CLI installed)
 [providers.claude_cli]
 command = "claude"
@@ -749,4 +1645,181 @@ level = "info"
 # [telegram]
 # enabled = true
 # api_token = "${TELEGRAM_BOT_TOKEN}"
+
+# Model registry: aliases with capability metadata (usable as default_model or /model)
+# [models.fast]
+# provider = "anthropic"
+# id = "claude-haiku-4-5-20251001"
+# context_window = 200000
+# max_output_tokens = 8192
+# vision = true
+# tools = true
+# price = { input = 1.0, output = 5.0 }
+# thinking_budget = 4000                # extended thinking (Anthropic, Ollama)
+# reasoning_effort = "medium"           # OpenAI-compatible reasoning models
+
+# Spend budgets (USD). When exceeded, heartbeat is blocked or downgraded.
+# [cost]
+# daily_budget_usd = 5.0
+# monthly_budget_usd = 100.0
+# budget_action = "block"               # block | downgrade
+# downgrade_model = "ollama/llama3"     # used when budget_action = "downgrade"
+#
+# Override built-in prices (USD per million tokens)
+# [cost.pricing."openai/gpt-4o"]
+# input = 2.5
+# output = 10.0
+# cache_read = 1.25
 "#;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hooks_config() {
+        let config: Config = toml::from_str(
+            r#"
+            [[hooks]]
+            event = "pre_tool_use"
+            tool = "*_file"
+            command = "./check.sh"
+
+            [[hooks]]
+            event = "session_end"
+            command = "./archive.sh"
+            timeout_ms = 500
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.hooks.len(), 2);
+        assert_eq!(config.hooks[0].tool.as_deref(), Some("*_file"));
+        assert_eq!(config.hooks[0].timeout_ms, 10_000);
+        assert_eq!(config.hooks[1].tool, None);
+        // Failing pre_tool_use hooks block unless told otherwise
+        assert!(config.hooks[0].blocks_on_error());
+        assert!(!config.hooks[1].blocks_on_error());
+        assert!(
+            config
+                .hooks
+                .iter()
+                .all(|h| HOOK_EVENTS.contains(&h.event.as_str()))
+        );
+    }
+
+    #[test]
+    fn test_context_window_must_exceed_reserves() {
+        let mut config: Config = toml::from_str(
+            r#"
+            [models.local]
+            provider = "ollama"
+            id = "llama3"
+            context_window = 8192
+            "#,
+        )
+        .unwrap();
+        let err = config.check_context_windows().unwrap_err().to_string();
+        assert!(err.contains("[models.local]"), "{}", err);
+
+        config.agent.reserve_tokens = 2000;
+        assert!(config.check_context_windows().is_ok());
+    }
+
+    #[test]
+    fn test_tool_rule_actions() {
+        let config: Config = toml::from_str(
+            r#"
+            [[tools.rules]]
+            tool = "bash"
+            pattern = "sudo *"
+            action = "deny"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.tools.rules[0].action, ToolAction::Deny);
+
+        // A misspelled action fails to load instead of meaning "ask"
+        for action in ["Deny", "block"] {
+            let toml = format!("[[tools.rules]]\ntool = \"bash\"\naction = \"{}\"", action);
+            assert!(toml::from_str::<Config>(&toml).is_err());
+        }
+    }
+
+    #[test]
+    fn test_budget_action() {
+        let config: Config = toml::from_str("").unwrap();
+        assert_eq!(config.cost.budget_action, BudgetAction::Block);
+
+        let config: Config = toml::from_str("[cost]\nbudget_action = \"downgrade\"").unwrap();
+        assert_eq!(config.cost.budget_action, BudgetAction::Downgrade);
+        assert!(toml::from_str::<Config>("[cost]\nbudget_action = \"downgrad\"").is_err());
+    }
+
+    #[test]
+    fn test_unattended_approval() {
+        let config: Config = toml::from_str("").unwrap();
+        assert_eq!(config.tools.unattended_approval, UnattendedApproval::Deny);
+
+        let config: Config = toml::from_str("[tools]\nunattended_approval = \"queue\"").unwrap();
+        assert_eq!(config.tools.unattended_approval, UnattendedApproval::Queue);
+
+        // A typo fails to load instead of silently denying
+        assert!(toml::from_str::<Config>("[tools]\nunattended_approval = \"queu\"").is_err());
+    }
+
+    #[test]
+    fn test_agent_profiles() {
+        let mut config: Config = toml::from_str(
+            r#"
+            [agents.main]
+
+            [agents.work]
+            model = "sonnet"
+            heartbeat = { enabled = false, interval = "1h" }
+
+            [agents.notes]
+            workspace = "/srv/notes"
+            "#,
+        )
+        .unwrap();
+        config.paths.data_dir = PathBuf::from("/data");
+        config.paths.workspace = PathBuf::from("/data/workspace");
+
+        assert_eq!(config.hosted_agents("main"), vec!["main", "notes", "work"]);
+        assert_eq!(Config::default().hosted_agents("cli"), vec!["cli"]);
+
+        let main = config.for_agent("main");
+        assert_eq!(main.workspace_path(), PathBuf::from("/data/workspace"));
+
+        let work = config.for_agent("work");
+        assert_eq!(work.workspace_path(), PathBuf::from("/data/workspace-work"));
+        assert_eq!(work.agent.default_model, "sonnet");
+        assert!(!work.heartbeat.enabled);
+        assert_eq!(work.heartbeat.interval, "1h");
+
+        let notes = config.for_agent("notes");
+        assert_eq!(notes.workspace_path(), PathBuf::from("/srv/notes"));
+        assert_eq!(notes.agent.default_model, config.agent.default_model);
+
+        // No profile: the config as is
+        let other = config.for_agent("telegram");
+        assert_eq!(other.workspace_path(), config.workspace_path());
+    }
+
+    #[test]
+    fn test_telegram_chat_agents() {
+        let telegram: TelegramConfig = toml::from_str(
+            r#"
+            enabled = true
+            api_token = "token"
+            chat_agents = { "42" = "work" }
+            "#,
+        )
+        .unwrap();
+        assert_eq!(telegram.agent_for_chat(42), "work");
+        assert_eq!(telegram.agent_for_chat(7), "telegram");
+
+        assert!(is_valid_agent_id("work_2"));
+        assert!(!is_valid_agent_id("../main"));
+    }
+}
@@ -44,6 +44,8 @@ pub enum WorkerMessage {
     },
     /// Streaming content chunk
     ContentChunk(String),
+    /// Streaming reasoning chunk
+    ThinkingChunk(String),
     /// Tool call started
     ToolCallStart {
         name: String,
@@ -79,6 +81,8 @@ pub struct ChatMessage {
     pub role: MessageRole,
     pub content: String,
     pub tool_info: Option<ToolInfo>,
+    /// Model reasoning shown collapsed above the answer
+    pub thinking: Option<String>,
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -115,6 +119,8 @@ pub struct UiState {
     pub is_loading: bool,
     /// Current streaming response (being built)
     pub streaming_content: String,
+    /// Reasoning streamed for the current response
+    pub streaming_thinking: String,
     /// Active tool calls
     pub active_tools: Vec<ToolInfo>,
     /// Tool calls pending approval
@@ -169,6 +175,10 @@ impl UiState {
                 self.streaming_content.push_str(&content);
                 self.scroll_to_bottom = true;
             }
+            WorkerMessage::ThinkingChunk(thinking) => {
+                self.streaming_thinking.push_str(&thinking);
+                self.scroll_to_bottom = true;
+            }
             WorkerMessage::ToolCallStart {
                 name,
                 id: _,
@@ -202,11 +212,13 @@ impl UiState {
             }
             WorkerMessage::Done => {
                 // Finalize streaming content as assistant message
+                let thinking = std::mem::take(&mut self.streaming_thinking);
                 if !self.streaming_content.is_empty() {
                     self.messages.push(ChatMessage {
                         role: MessageRole::Assistant,
                         content: std::mem::take(&mut self.streaming_content),
                         tool_info: None,
+                        thinking: (!thinking.is_empty()).then_some(thinking),
                     });
                 }
                 self.active_tools.clear();
@@ -217,6 +229,7 @@ impl UiState {
                 self.error = Some(err);
                 self.is_loading = false;
                 self.streaming_content.clear();
+                self.streaming_thinking.clear();
             }
             WorkerMessage::Status(status) => {
                 self.status = Some(status);
@@ -234,12 +247,14 @@ impl UiState {
                 // Clear chat on session change
                 self.messages.clear();
                 self.streaming_content.clear();
+                self.streaming_thinking.clear();
             }
             WorkerMessage::SystemMessage(text) => {
                 self.messages.push(ChatMessage {
                     role: MessageRole::System,
                     content: text,
                     tool_info: None,
+                    thinking: None,
                 });
                 self.scroll_to_bottom = true;
             }
@@ -252,6 +267,7 @@ impl UiState {
             role: MessageRole::User,
             content,
             tool_info: None,
+            thinking: None,
         });
         self.scroll_to_bottom = true;
     }
@@ -23,13 +23,13 @@ impl ChatView {
                 ui.set_min_width(ui.available_width());
 
                 // Show messages
-                for msg in &state.messages {
-                    Self::render_message(ui, msg);
+                for (index, msg) in state.messages.iter().enumerate() {
+                    Self::render_message(ui, index, msg);
                     ui.add_space(8.0);
                 }
 
                 // Show streaming content if any
-                if !state.streaming_content.is_empty() {
+                if !state.streaming_content.is_empty() || !state.streaming_thinking.is_empty() {
                     ui.horizontal(|ui| {
                         ui.label(
                             RichText::new("Assistant")
@@ -37,6 +37,13 @@ impl ChatView {
                                 .color(Color32::from_rgb(100, 149, 237)),
                         );
                     });
+                    if !state.streaming_thinking.is_empty() {
+                        ui.label(
+                            RichText::new(&state.streaming_thinking)
+                                .italics()
+                                .color(Color32::GRAY),
+                        );
+                    }
                     ui.label(&state.streaming_content);
                     ui.add_space(8.0);
                 }
@@ -76,10 +83,12 @@ impl ChatView {
                             if ui.button("Approve").clicked() {
                                 message_to_send = Some(UiMessage::ApproveTools(tools.clone()));
                                 state.pending_approval = None;
+                                state.is_loading = true;
                             }
                             if ui.button("Deny").clicked() {
                                 message_to_send = Some(UiMessage::DenyTools);
                                 state.pending_approval = None;
+                                state.is_loading = true;
                             }
                         });
                     });
@@ -170,6 +179,7 @@ impl ChatView {
                         role: MessageRole::System,
                         content: format!("Current model: {}", state.model),
                         tool_info: None,
+                        thinking: None,
                     });
                     state.scroll_to_bottom = true;
                     None // No message to send to worker
@@ -184,6 +194,7 @@ impl ChatView {
                         role: MessageRole::System,
                         content: "Usage: /memory <query>".to_string(),
                         tool_info: None,
+                        thinking: None,
                     });
                     state.scroll_to_bottom = true;
                     None
@@ -200,6 +211,7 @@ impl ChatView {
                         role: MessageRole::System,
                         content: "Usage: /resume <session-id>".to_string(),
                         tool_info: None,
+                        thinking: None,
                     });
                     state.scroll_to_bottom = true;
                     None
@@ -219,6 +231,7 @@ impl ChatView {
                         cmd
                     ),
                     tool_info: None,
+                    thinking: None,
                 });
                 state.scroll_to_bottom = true;
                 None
@@ -226,7 +239,7 @@ impl ChatView {
         }
     }
 
-    fn render_message(ui: &mut Ui, msg: &ChatMessage) {
+    fn render_message(ui: &mut Ui, index: usize, msg: &ChatMessage) {
         let (label, color) = match msg.role {
             MessageRole::User => ("You", Color32::from_rgb(52, 152, 219)),
             MessageRole::Assistant => ("Assistant", Color32::from_rgb(100, 149, 237)),
@@ -237,6 +250,15 @@ impl ChatView {
             ui.label(RichText::new(label).strong().color(color));
         });
 
+        // Reasoning stays collapsed so the answer reads first
+        if let Some(ref thinking) = msg.thinking {
+            egui::CollapsingHeader::new(RichText::new("Thinking").small().color(Color32::GRAY))
+                .id_salt(("thinking", index))
+                .show(ui, |ui| {
+                    ui.label(RichText::new(thinking).italics().color(Color32::GRAY));
+                });
+        }
+
         // Render content with basic markdown-like formatting
         ui.label(&msg.content);
 
@@ -78,9 +78,21 @@ impl StatusView {
                     ui.label(RichText::new("API Usage (Session)").strong());
                     ui.label(format!("Input: {} tokens", status.api_input_tokens));
                     ui.label(format!("Output: {} tokens", status.api_output_tokens));
+                    if status.api_cache_read_tokens > 0 || status.api_cache_write_tokens > 0 {
+                        ui.label(format!(
+                            "Cache: {} read / {} written",
+                            status.api_cache_read_tokens, status.api_cache_write_tokens
+                        ));
+                        if let Some(rate) = status.cache_hit_rate() {
+                            ui.label(format!("Cache hit rate: {:.1}%", rate * 100.0));
+                        }
+                    }
                     ui.label(format!(
                         "Total: {} tokens",
-                        status.api_input_tokens + status.api_output_tokens
+                        status.api_input_tokens
+                            + status.api_output_tokens
+                            + status.api_cache_read_tokens
+                            + status.api_cache_write_tokens
                     ));
                 });
             }
@@ -11,8 +11,8 @@ use anyhow::Result;
 use futures::StreamExt;
 
 use crate::agent::{
-    Agent, AgentConfig, DEFAULT_AGENT_ID, StreamEvent, ToolCall, extract_tool_detail,
-    list_sessions_for_agent,
+    Agent, AgentConfig, ApprovalDecision, DEFAULT_AGENT_ID, StreamEvent, ToolCall,
+    extract_tool_detail, list_sessions_for_agent,
 };
 use crate::config::Config;
 use crate::memory::MemoryManager;
@@ -76,7 +76,7 @@ async fn worker_loop(
     tx: Sender<WorkerMessage>,
 ) -> Result<()> {
     // Initialize agent
-    let config = Config::load()?;
+    let config = Config::load()?.for_agent(&agent_id);
     let memory = MemoryManager::new_with_full_config(&config.memory, Some(&config), &agent_id)?;
 
     let agent_config = AgentConfig {
@@ -85,7 +85,7 @@ async fn worker_loop(
         reserve_tokens: config.agent.reserve_tokens,
     };
 
-    let mut agent = Agent::new(agent_config, &config, memory).await?;
+    let mut agent = Agent::new(agent_config, &config, &agent_id, memory).await?;
     agent.new_session().await?;
 
     // Send ready message
@@ -103,8 +103,8 @@ async fn worker_loop(
     // Send initial status
     let _ = tx.send(WorkerMessage::Status(agent.session_status()));
 
-    // Track tools requiring approval
-    let approval_tools: Vec<String> = agent.approval_required_tools().to_vec();
+    // Answers tool approvals while a turn is streaming
+    let approvals = agent.approval_broker();
 
     // Main loop
     while let Ok(msg) = rx.recv() {
@@ -116,7 +116,6 @@ async fn worker_loop(
                 match agent.chat_stream_with_tools(&message).await {
                     Ok(stream) => {
                         let mut stream = pin!(stream);
-                        let mut pending_tools: Vec<ToolCall> = Vec::new();
 
                         while let Some(result) = stream.next().await {
                             match result {
@@ -124,27 +123,42 @@ async fn worker_loop(
                                     StreamEvent::Content(text) => {
                                         let _ = tx.send(WorkerMessage::ContentChunk(text));
                                     }
-                                    StreamEvent::ToolCallStart {
+                                    StreamEvent::Thinking(text) => {
+                                        let _ = tx.send(WorkerMessage::ThinkingChunk(text));
+                                    }
+                                    StreamEvent::ToolApprovalRequired {
+                                        request_id,
                                         name,
                                         id,
                                         arguments,
+                                        ..
                                     } => {
-                                        // Check if this tool requires approval
-                                        if approval_tools.contains(&name) {
-                                            // Collect for approval
-                                            pending_tools.push(ToolCall {
+                                        let _ = tx.send(WorkerMessage::ToolsPendingApproval(vec![
+                                            ToolCall {
                                                 id,
                                                 name,
-                                                arguments: String::new(),
-                                            });
-                                        } else {
-                                            let detail = extract_tool_detail(&name, &arguments);
-                                            let _ = tx.send(WorkerMessage::ToolCallStart {
-                                                name,
-                                                id,
-                                                detail,
-                                            });
-                                        }
+                                                arguments,
+                                            },
+                                        ]));
+                                        // The turn waits on the broker; block
+                                        // until the dialog answers
+                                        let approved = wait_for_approval(&rx);
+                                        approvals.resolve(
+                                            &request_id,
+                                            ApprovalDecision::from_flags(approved, false),
+                                        );
+                                    }
+                                    StreamEvent::ToolCallStart {
+                                        name,
+                                        id,
+                                        arguments,
+                                    } => {
+                                        let detail = extract_tool_detail(&name, &arguments);
+                                        let _ = tx.send(WorkerMessage::ToolCallStart {
+                                            name,
+                                            id,
+                                            detail,
+                                        });
                                     }
                                     StreamEvent::ToolCallEnd {
                                         name,
@@ -159,15 +173,20 @@ async fn worker_loop(
                                             warnings,
                                         });
                                     }
+                                    StreamEvent::MemoryRecalled { citations } => {
+                                        let _ = tx.send(WorkerMessage::SystemMessage(format!(
+                                            "Recalled from memory: {}",
+                                            citations.join(", ")
+                                        )));
+                                    }
+                                    StreamEvent::Fallback { from, to, reason } => {
+                                        let _ = tx.send(WorkerMessage::SystemMessage(format!(
+                                            "{} failed ({}), falling back to {}",
+                                            from, reason, to
+                                        )));
+                                    }
                                     StreamEvent::Done => {
-                                        if !pending_tools.is_empty() {
-                                            let _ = tx.send(WorkerMessage::ToolsPendingApproval(
-                                                pending_tools.clone(),
-                                            ));
-                                            pending_tools.clear();
-                                        } else {
-                                            let _ = tx.send(WorkerMessage::Done);
-                                        }
+                                        let _ = tx.send(WorkerMessage::Done);
                                         should_auto_save = true;
                                     }
                                 },
@@ -196,27 +215,23 @@ async fn worker_loop(
                     let _ = tx.send(WorkerMessage::Error(e.to_string()));
                 }
             },
-            UiMessage::ResumeSession(session_id) => match agent.resume_session(&session_id).await {
-                Ok(()) => {
-                    let status = agent.session_status();
-                    let _ = tx.send(WorkerMessage::SessionChanged {
-                        id: status.id.clone(),
-                        message_count: status.message_count,
-                    });
-                    let _ = tx.send(WorkerMessage::Status(status));
-                }
-                Err(e) => {
-                    let _ = tx.send(WorkerMessage::Error(e.to_string()));
+            UiMessage::ResumeSession(session_id) => {
+                match agent.resume_session(&session_id, None).await {
+                    Ok(()) => {
+                        let status = agent.session_status();
+                        let _ = tx.send(WorkerMessage::SessionChanged {
+                            id: status.id.clone(),
+                            message_count: status.message_count,
+                        });
+                        let _ = tx.send(WorkerMessage::Status(status));
+                    }
+                    Err(e) => {
+                        let _ = tx.send(WorkerMessage::Error(e.to_string()));
+                    }
                 }
-            },
-            UiMessage::ApproveTools(_tools) => {
-                // Tool approval is handled in chat loop
-                // For now, just send done
-                let _ = tx.send(WorkerMessage::Done);
-            }
-            UiMessage::DenyTools => {
-                let _ = tx.send(WorkerMessage::Done);
             }
+            // Answered inside the chat turn; nothing is waiting otherwise
+            UiMessage::ApproveTools(_) | UiMessage::DenyTools => {}
             UiMessage::RefreshSessions => {
                 if let Ok(sessions) = list_sessions_for_agent(&agent_id) {
                     let _ = tx.send(WorkerMessage::Sessions(sessions));
@@ -341,3 +356,15 @@ Available commands:
 
     Ok(())
 }
+
+/// Wait for the approval dialog; other UI messages are dropped meanwhile
+fn wait_for_approval(rx: &Receiver<UiMessage>) -> bool {
+    while let Ok(msg) = rx.recv() {
+        match msg {
+            UiMessage::ApproveTools(_) => return true,
+            UiMessage::DenyTools => return false,
+            _ => {}
+        }
+    }
+    false
+}
@@ -1,6 +1,7 @@
 //! Heartbeat event tracking for UI status display
 
 use serde::Serialize;
+use std::collections::HashMap;
 use std::sync::RwLock;
 
 /// Heartbeat event status
@@ -32,21 +33,29 @@ pub struct HeartbeatEvent {
     /// Reason for skip/failure
     #[serde(skip_serializing_if = "Option::is_none")]
     pub reason: Option<String>,
+    /// Tool loop limit that stopped the run early
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tool_limit: Option<String>,
 }
 
-/// Global state for last heartbeat event
-static LAST_HEARTBEAT: RwLock<Option<HeartbeatEvent>> = RwLock::new(None);
+/// Global state for the last heartbeat event of each agent
+static LAST_HEARTBEAT: RwLock<Option<HashMap<String, HeartbeatEvent>>> = RwLock::new(None);
 
-/// Emit a heartbeat event (stores it for later retrieval)
-pub fn emit_heartbeat_event(event: HeartbeatEvent) {
+/// Emit a heartbeat event for an agent (stores it for later retrieval)
+pub fn emit_heartbeat_event(agent_id: &str, event: HeartbeatEvent) {
     if let Ok(mut guard) = LAST_HEARTBEAT.write() {
-        *guard = Some(event);
+        guard
+            .get_or_insert_with(HashMap::new)
+            .insert(agent_id.to_string(), event);
     }
 }
 
-/// Get the last heartbeat event
-pub fn get_last_heartbeat_event() -> Option<HeartbeatEvent> {
-    LAST_HEARTBEAT.read().ok().and_then(|guard| guard.clone())
+/// Get the last heartbeat event of an agent
+pub fn get_last_heartbeat_event(agent_id: &str) -> Option<HeartbeatEvent> {
+    LAST_HEARTBEAT
+        .read()
+        .ok()
+        .and_then(|guard| guard.as_ref()?.get(agent_id).cloned())
 }
 
 /// Helper to get current timestamp in milliseconds
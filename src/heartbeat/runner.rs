@@ -10,12 +10,21 @@ use tracing::{debug, info, warn};
 
 use super::events::{HeartbeatEvent, HeartbeatStatus, emit_heartbeat_event, now_ms};
 use crate::agent::{
-    Agent, AgentConfig, HEARTBEAT_OK_TOKEN, SessionStore, build_heartbeat_prompt, is_heartbeat_ok,
+    Agent, AgentConfig, CostLedger, HEARTBEAT_OK_TOKEN, SessionStore, UsageSource,
+    build_heartbeat_prompt, is_heartbeat_ok,
 };
 use crate::concurrency::{TurnGate, WorkspaceLock};
-use crate::config::{Config, parse_duration, parse_time};
+use crate::config::{BudgetAction, Config, parse_duration, parse_time};
 use crate::memory::MemoryManager;
 
+/// Outcome of the spend budget check before a heartbeat run
+enum BudgetDecision {
+    /// Run with this model (the default, or the downgrade model)
+    Run(String),
+    /// Budget exceeded and no downgrade available
+    Blocked(String),
+}
+
 pub struct HeartbeatRunner {
     config: Config,
     interval: Duration,
@@ -28,6 +37,8 @@ pub struct HeartbeatRunner {
     turn_gate: Option<TurnGate>,
     /// Cross-process workspace lock
     workspace_lock: WorkspaceLock,
+    /// Tool loop limit hit by the last run, reported with its event
+    tool_limit: std::sync::Mutex<Option<String>>,
 }
 
 impl HeartbeatRunner {
@@ -45,11 +56,13 @@ impl HeartbeatRunner {
     ///
     /// When running inside the daemon alongside the HTTP server, pass a
     /// shared `TurnGate` so heartbeat skips when an HTTP agent turn is active.
+    /// The agent's `[agents.<id>]` overrides (workspace, model, schedule) apply.
     pub fn new_with_gate(
         config: &Config,
         agent_id: &str,
         turn_gate: Option<TurnGate>,
     ) -> Result<Self> {
+        let config = &config.for_agent(agent_id);
         let interval = parse_duration(&config.heartbeat.interval)
             .map_err(|e| anyhow::anyhow!("Invalid heartbeat interval: {}", e))?;
 
@@ -71,7 +84,7 @@ impl HeartbeatRunner {
 
         // Create MemoryManager once and reuse it to avoid reinitializing embedding provider
         let memory = MemoryManager::new_with_full_config(&config.memory, Some(config), agent_id)?;
-        let workspace_lock = WorkspaceLock::new()?;
+        let workspace_lock = WorkspaceLock::for_config(config)?;
 
         Ok(Self {
             config: config.clone(),
@@ -82,6 +95,7 @@ impl HeartbeatRunner {
             memory,
             turn_gate,
             workspace_lock,
+            tool_limit: std::sync::Mutex::new(None),
         })
     }
 
@@ -99,13 +113,17 @@ impl HeartbeatRunner {
             // Check active hours
             if !self.in_active_hours() {
                 debug!("Outside active hours, skipping heartbeat");
-                emit_heartbeat_event(HeartbeatEvent {
-                    ts: now_ms(),
-                    status: HeartbeatStatus::Skipped,
-                    duration_ms: 0,
-                    preview: None,
-                    reason: Some("outside active hours".to_string()),
-                });
+                emit_heartbeat_event(
+                    &self.agent_id,
+                    HeartbeatEvent {
+                        ts: now_ms(),
+                        status: HeartbeatStatus::Skipped,
+                        duration_ms: 0,
+                        preview: None,
+                        reason: Some("outside active hours".to_string()),
+                        tool_limit: None,
+                    },
+                );
                 continue;
             }
 
@@ -120,13 +138,17 @@ impl HeartbeatRunner {
                         Some(response.clone())
                     };
 
-                    emit_heartbeat_event(HeartbeatEvent {
-                        ts: now_ms(),
-                        status,
-                        duration_ms,
-                        preview,
-                        reason: None,
-                    });
+                    emit_heartbeat_event(
+                        &self.agent_id,
+                        HeartbeatEvent {
+                            ts: now_ms(),
+                            status,
+                            duration_ms,
+                            preview,
+                            reason: None,
+                            tool_limit: self.take_tool_limit(),
+                        },
+                    );
 
                     if is_heartbeat_ok(&response) {
                         debug!("Heartbeat: OK");
@@ -136,13 +158,17 @@ impl HeartbeatRunner {
                 }
                 Err(e) => {
                     let duration_ms = start.elapsed().as_millis() as u64;
-                    emit_heartbeat_event(HeartbeatEvent {
-                        ts: now_ms(),
-                        status: HeartbeatStatus::Failed,
-                        duration_ms,
-                        preview: None,
-                        reason: Some(e.to_string()),
-                    });
+                    emit_heartbeat_event(
+                        &self.agent_id,
+                        HeartbeatEvent {
+                            ts: now_ms(),
+                            status: HeartbeatStatus::Failed,
+                            duration_ms,
+                            preview: None,
+                            reason: Some(e.to_string()),
+                            tool_limit: self.take_tool_limit(),
+                        },
+                    );
                     warn!("Heartbeat error: {}", e);
                 }
             }
@@ -162,25 +188,33 @@ impl HeartbeatRunner {
                     Some(response.clone())
                 };
 
-                emit_heartbeat_event(HeartbeatEvent {
-                    ts: now_ms(),
-                    status,
-                    duration_ms,
-                    preview,
-                    reason: None,
-                });
+                emit_heartbeat_event(
+                    &self.agent_id,
+                    HeartbeatEvent {
+                        ts: now_ms(),
+                        status,
+                        duration_ms,
+                        preview,
+                        reason: None,
+                        tool_limit: self.take_tool_limit(),
+                    },
+                );
 
                 Ok(response)
             }
             Err(e) => {
                 let duration_ms = start.elapsed().as_millis() as u64;
-                emit_heartbeat_event(HeartbeatEvent {
-                    ts: now_ms(),
-                    status: HeartbeatStatus::Failed,
-                    duration_ms,
-                    preview: None,
-                    reason: Some(e.to_string()),
-                });
+                emit_heartbeat_event(
+                    &self.agent_id,
+                    HeartbeatEvent {
+                        ts: now_ms(),
+                        status: HeartbeatStatus::Failed,
+                        duration_ms,
+                        preview: None,
+                        reason: Some(e.to_string()),
+                        tool_limit: self.take_tool_limit(),
+                    },
+                );
                 Err(e)
             }
         }
@@ -233,14 +267,32 @@ impl HeartbeatRunner {
             return Ok((HEARTBEAT_OK_TOKEN.to_string(), HeartbeatStatus::Skipped));
         }
 
+        // Enforce spend budgets: block, or downgrade to a cheaper model
+        let model = match self.budget_model() {
+            BudgetDecision::Run(model) => model,
+            BudgetDecision::Blocked(reason) => {
+                return Ok((
+                    format!("Heartbeat blocked: {}", reason),
+                    HeartbeatStatus::Skipped,
+                ));
+            }
+        };
+
         // Create agent for heartbeat (clone the cached MemoryManager to share the embedding provider)
         let agent_config = AgentConfig {
-            model: self.config.agent.default_model.clone(),
+            model,
             context_window: self.config.agent.context_window,
             reserve_tokens: self.config.agent.reserve_tokens,
         };
 
-        let mut agent = Agent::new(agent_config, &self.config, self.memory.clone()).await?;
+        let mut agent = Agent::new(
+            agent_config,
+            &self.config,
+            &self.agent_id,
+            self.memory.clone(),
+        )
+        .await?;
+        agent.set_usage_attribution(&self.agent_id, UsageSource::Heartbeat);
         agent.new_session().await?;
 
         // Check if workspace is a git repo
@@ -248,7 +300,11 @@ impl HeartbeatRunner {
 
         // Send heartbeat prompt
         let heartbeat_prompt = build_heartbeat_prompt(workspace_is_git);
-        let response = agent.chat(&heartbeat_prompt).await?;
+        let response = agent.chat(&heartbeat_prompt).await;
+        if let Ok(mut slot) = self.tool_limit.lock() {
+            *slot = agent.take_tool_limit();
+        }
+        let response = response?;
 
         // Determine status based on response
         if is_heartbeat_ok(&response) {
@@ -282,6 +338,39 @@ impl HeartbeatRunner {
         Ok((response, HeartbeatStatus::Sent))
     }
 
+    fn take_tool_limit(&self) -> Option<String> {
+        self.tool_limit.lock().ok().and_then(|mut slot| slot.take())
+    }
+
+    /// Decide which model heartbeat runs with under the configured budgets
+    fn budget_model(&self) -> BudgetDecision {
+        let cost = &self.config.cost;
+        let exceeded = match CostLedger::for_config(&self.config)
+            .exceeded_budget(cost, Local::now().date_naive())
+        {
+            Ok(exceeded) => exceeded,
+            Err(e) => {
+                warn!("Failed to read cost ledger, ignoring budgets: {}", e);
+                None
+            }
+        };
+
+        let Some(reason) = exceeded else {
+            return BudgetDecision::Run(self.config.agent.default_model.clone());
+        };
+
+        match (cost.budget_action, &cost.downgrade_model) {
+            (BudgetAction::Downgrade, Some(model)) => {
+                info!("Heartbeat: {}, downgrading to {}", reason, model);
+                BudgetDecision::Run(model.clone())
+            }
+            (BudgetAction::Block, _) | (BudgetAction::Downgrade, None) => {
+                warn!("Heartbeat blocked: {}", reason);
+                BudgetDecision::Blocked(reason)
+            }
+        }
+    }
+
     fn in_active_hours(&self) -> bool {
         let Some((start, end)) = self.active_hours else {
             return true; // No active hours configured, always active
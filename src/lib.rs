@@ -17,6 +17,7 @@ pub mod desktop;
 #[cfg(feature = "gen")]
 pub mod gen3d;
 pub mod heartbeat;
+pub mod mcp;
 pub mod memory;
 pub mod paths;
 pub mod sandbox;
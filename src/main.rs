@@ -56,12 +56,16 @@ fn main() -> Result<()> {
 async fn async_main(cli: Cli) -> Result<()> {
     // Initialize logging
     let log_level = if cli.verbose { "debug" } else { "info" };
-    tracing_subscriber::fmt()
-        .with_env_filter(
-            tracing_subscriber::EnvFilter::try_from_default_env()
-                .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new(log_level)),
-        )
-        .init();
+    let subscriber = tracing_subscriber::fmt().with_env_filter(
+        tracing_subscriber::EnvFilter::try_from_default_env()
+            .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new(log_level)),
+    );
+    // `mcp serve` speaks the protocol on stdout, so it logs to stderr
+    if matches!(cli.command, Commands::Mcp(_)) {
+        subscriber.with_writer(std::io::stderr).init();
+    } else {
+        subscriber.init();
+    }
 
     match cli.command {
         Commands::Chat(args) => localgpt::cli::chat::run(args, &cli.agent).await,
@@ -72,6 +76,8 @@ async fn async_main(cli: Cli) -> Result<()> {
         Commands::Gen(_) => unreachable!("Gen is handled before tokio runtime starts"),
         Commands::Daemon(args) => localgpt::cli::daemon::run(args, &cli.agent).await,
         Commands::Memory(args) => localgpt::cli::memory::run(args, &cli.agent).await,
+        Commands::Checkpoint(args) => localgpt::cli::checkpoint::run(args, &cli.agent).await,
+        Commands::Mcp(args) => localgpt::cli::mcp::run(args, &cli.agent).await,
         Commands::Config(args) => localgpt::cli::config::run(args).await,
         Commands::Paths => localgpt::cli::paths::run(),
         Commands::Md(args) => localgpt::cli::md::run(args).await,
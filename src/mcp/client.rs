@@ -0,0 +1,796 @@
+//! MCP client: tools from configured servers
+//!
+//! Each `[mcp.servers.<name>]` is launched over stdio (newline-delimited
+//! JSON-RPC on the process's stdin/stdout) or reached over streamable HTTP (a
+//! POST per message, answered with JSON or an SSE stream). After the
+//! initialize handshake its tools are listed and mounted as `name__tool`.
+//! Connections are shared by every agent in the process; a server that exits
+//! or loses its session is reconnected on the next call. Tool output goes
+//! through the same sanitizing as the built-in tools.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex as StdMutex, OnceLock};
+use std::time::Duration;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use serde_json::{Value, json};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::process::{Child, ChildStdin};
+use tokio::sync::oneshot;
+use tracing::{debug, info, warn};
+
+use super::{METHOD_NOT_FOUND, PROTOCOL_VERSION, TOOL_NAME_SEPARATOR};
+use crate::agent::ToolSchema;
+use crate::agent::tools::Tool;
+use crate::config::{Config, McpServerConfig};
+
+/// Longest tool name providers accept
+const MAX_TOOL_NAME_LEN: usize = 64;
+
+/// Requests re-sent on a new connection when the old one broke
+const IDEMPOTENT_METHODS: &[&str] = &["initialize", "tools/list"];
+
+/// Clients by server name, shared by every agent in the process
+static CLIENTS: OnceLock<StdMutex<HashMap<String, Arc<McpClient>>>> = OnceLock::new();
+
+/// The connection broke (as opposed to an error the server answered with);
+/// a new connection may succeed
+#[derive(Debug)]
+struct TransportError(String);
+
+impl std::fmt::Display for TransportError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for TransportError {}
+
+fn transport_error(message: impl Into<String>) -> anyhow::Error {
+    TransportError(message.into()).into()
+}
+
+/// A tool as the server lists it
+#[derive(Debug, Clone)]
+pub struct RemoteTool {
+    pub name: String,
+    pub description: String,
+    pub input_schema: Value,
+    /// The server says the tool doesn't change anything (`readOnlyHint`)
+    pub read_only: bool,
+}
+
+pub struct McpClient {
+    name: String,
+    config: McpServerConfig,
+    connection: tokio::sync::Mutex<Option<Arc<Connection>>>,
+    tools: tokio::sync::Mutex<Option<Vec<RemoteTool>>>,
+}
+
+impl McpClient {
+    pub fn new(name: &str, config: McpServerConfig) -> Self {
+        Self {
+            name: name.to_string(),
+            config,
+            connection: tokio::sync::Mutex::new(None),
+            tools: tokio::sync::Mutex::new(None),
+        }
+    }
+
+    /// The process's client for a server (a new one if its config changed)
+    pub fn shared(name: &str, config: &McpServerConfig) -> Arc<Self> {
+        let mut clients = CLIENTS
+            .get_or_init(Default::default)
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        match clients.get(name) {
+            Some(client) if client.config == *config => Arc::clone(client),
+            _ => {
+                let client = Arc::new(Self::new(name, config.clone()));
+                clients.insert(name.to_string(), Arc::clone(&client));
+                client
+            }
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Tools the server offers (listed once per client)
+    pub async fn list_tools(&self) -> Result<Vec<RemoteTool>> {
+        let mut cached = self.tools.lock().await;
+        if let Some(tools) = cached.as_ref() {
+            return Ok(tools.clone());
+        }
+
+        let mut tools = Vec::new();
+        let mut cursor: Option<String> = None;
+        loop {
+            let params = match &cursor {
+                Some(cursor) => json!({ "cursor": cursor }),
+                None => json!({}),
+            };
+            let result = self.request("tools/list", params).await?;
+            for tool in result["tools"].as_array().into_iter().flatten() {
+                let Some(name) = tool["name"].as_str() else {
+                    continue;
+                };
+                tools.push(RemoteTool {
+                    name: name.to_string(),
+                    description: tool["description"].as_str().unwrap_or_default().to_string(),
+                    input_schema: tool
+                        .get("inputSchema")
+                        .filter(|s| s.is_object())
+                        .cloned()
+                        .unwrap_or_else(|| json!({ "type": "object", "properties": {} })),
+                    read_only: tool["annotations"]["readOnlyHint"] == true,
+                });
+            }
+            cursor = result["nextCursor"].as_str().map(|c| c.to_string());
+            if cursor.is_none() {
+                break;
+            }
+        }
+
+        info!("MCP server {}: {} tools", self.name, tools.len());
+        *cached = Some(tools.clone());
+        Ok(tools)
+    }
+
+    /// Call a tool, returning its content as text
+    pub async fn call_tool(&self, name: &str, arguments: Value) -> Result<String> {
+        let result = self
+            .request(
+                "tools/call",
+                json!({ "name": name, "arguments": arguments }),
+            )
+            .await?;
+        format_tool_result(&result)
+    }
+
+    /// Send a request, reconnecting if the connection broke. Only requests
+    /// that are safe to repeat are re-sent: the server may have run a
+    /// `tools/call` before the connection failed.
+    async fn request(&self, method: &str, params: Value) -> Result<Value> {
+        let connection = self.connection().await?;
+        match connection.request(method, params.clone()).await {
+            Err(e) if e.is::<TransportError>() => {
+                self.drop_connection(&connection).await;
+                if !IDEMPOTENT_METHODS.contains(&method) {
+                    anyhow::bail!(
+                        "MCP server {}: {} during {}; the call may have run, so it wasn't retried",
+                        self.name,
+                        e,
+                        method
+                    );
+                }
+                warn!("MCP server {}: {}; reconnecting", self.name, e);
+                self.connection().await?.request(method, params).await
+            }
+            result => result,
+        }
+    }
+
+    /// The live connection, opening a new one if there's none or it died
+    async fn connection(&self) -> Result<Arc<Connection>> {
+        let mut current = self.connection.lock().await;
+        if let Some(connection) = current.as_ref()
+            && connection.is_alive()
+        {
+            return Ok(Arc::clone(connection));
+        }
+        let connection = Arc::new(Connection::open(&self.name, &self.config).await?);
+        *current = Some(Arc::clone(&connection));
+        Ok(connection)
+    }
+
+    async fn drop_connection(&self, broken: &Arc<Connection>) {
+        let mut current = self.connection.lock().await;
+        if current.as_ref().is_some_and(|c| Arc::ptr_eq(c, broken)) {
+            *current = None;
+        }
+    }
+}
+
+/// An MCP server's tool, as an agent tool
+pub struct McpTool {
+    client: Arc<McpClient>,
+    name: String,
+    remote: RemoteTool,
+}
+
+impl McpTool {
+    pub fn new(client: Arc<McpClient>, remote: RemoteTool) -> Self {
+        Self {
+            name: tool_name(client.name(), &remote.name),
+            client,
+            remote,
+        }
+    }
+}
+
+#[async_trait]
+impl Tool for McpTool {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn schema(&self) -> ToolSchema {
+        ToolSchema {
+            name: self.name.clone(),
+            description: self.remote.description.clone(),
+            parameters: self.remote.input_schema.clone(),
+        }
+    }
+
+    fn is_read_only(&self) -> bool {
+        self.remote.read_only
+    }
+
+    async fn execute(&self, arguments: &str) -> Result<String> {
+        let arguments: Value = if arguments.trim().is_empty() {
+            json!({})
+        } else {
+            serde_json::from_str(arguments)?
+        };
+        self.client.call_tool(&self.remote.name, arguments).await
+    }
+}
+
+/// Tools of every enabled `[mcp.servers.*]`. Servers that can't be reached
+/// are logged and left out.
+pub async fn create_mcp_tools(config: &Config) -> Vec<Box<dyn Tool>> {
+    let mut servers: Vec<_> = config
+        .mcp
+        .servers
+        .iter()
+        .filter(|(_, server)| server.enabled)
+        .collect();
+    if servers.is_empty() {
+        return Vec::new();
+    }
+    servers.sort_by(|a, b| a.0.cmp(b.0));
+
+    let clients: Vec<Arc<McpClient>> = servers
+        .into_iter()
+        .map(|(name, server)| McpClient::shared(name, server))
+        .collect();
+    let listed = futures::future::join_all(clients.iter().map(|c| c.list_tools())).await;
+
+    let mut tools: Vec<Box<dyn Tool>> = Vec::new();
+    for (client, listed) in clients.iter().zip(listed) {
+        match listed {
+            Ok(remote_tools) => {
+                tools.extend(remote_tools.into_iter().map(|remote| {
+                    Box::new(McpTool::new(Arc::clone(client), remote)) as Box<dyn Tool>
+                }))
+            }
+            Err(e) => warn!("MCP server {} unavailable: {}", client.name(), e),
+        }
+    }
+    tools
+}
+
+/// Agent tool name for a server's tool, limited to what providers accept
+fn tool_name(server: &str, tool: &str) -> String {
+    format!("{}{}{}", server, TOOL_NAME_SEPARATOR, tool)
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '_' || c == '-' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .take(MAX_TOOL_NAME_LEN)
+        .collect()
+}
+
+/// Text of a `tools/call` result; results flagged `isError` are errors
+fn format_tool_result(result: &Value) -> Result<String> {
+    let mut parts = Vec::new();
+    for item in result["content"].as_array().into_iter().flatten() {
+        let part = match item["type"].as_str() {
+            Some("text") => item["text"].as_str().unwrap_or_default().to_string(),
+            Some("image") | Some("audio") => format!(
+                "[{} content: {}]",
+                item["type"].as_str().unwrap_or_default(),
+                item["mimeType"].as_str().unwrap_or("unknown type")
+            ),
+            Some("resource") => match item["resource"]["text"].as_str() {
+                Some(text) => text.to_string(),
+                None => format!(
+                    "[resource: {}]",
+                    item["resource"]["uri"].as_str().unwrap_or_default()
+                ),
+            },
+            Some("resource_link") => format!(
+                "[resource link: {}]",
+                item["uri"].as_str().unwrap_or_default()
+            ),
+            _ => item.to_string(),
+        };
+        parts.push(part);
+    }
+    if parts.is_empty()
+        && let Some(structured) = result.get("structuredContent")
+    {
+        parts.push(serde_json::to_string_pretty(structured)?);
+    }
+
+    let text = parts.join("\n");
+    if result["isError"] == true {
+        anyhow::bail!("{}", text);
+    }
+    Ok(text)
+}
+
+/// A JSON-RPC response's result, or its error
+fn into_result(response: Value) -> Result<Value> {
+    if let Some(error) = response.get("error") {
+        anyhow::bail!(
+            "{} (code {})",
+            error["message"].as_str().unwrap_or("unknown error"),
+            error["code"]
+        );
+    }
+    Ok(response.get("result").cloned().unwrap_or(Value::Null))
+}
+
+/// JSON-RPC messages in an SSE stream (`data:` lines of each event)
+fn sse_messages(body: &str) -> Vec<Value> {
+    body.replace("\r\n", "\n")
+        .split("\n\n")
+        .filter_map(|event| {
+            let data: Vec<&str> = event
+                .lines()
+                .filter_map(|line| line.strip_prefix("data:"))
+                .map(|data| data.strip_prefix(' ').unwrap_or(data))
+                .collect();
+            serde_json::from_str(&data.join("\n")).ok()
+        })
+        .collect()
+}
+
+enum Connection {
+    Stdio(StdioConnection),
+    Http(HttpConnection),
+}
+
+impl Connection {
+    /// Connect and run the initialize handshake
+    async fn open(name: &str, config: &McpServerConfig) -> Result<Self> {
+        let timeout = Duration::from_secs(config.timeout_secs.max(1));
+        let connection = match (&config.command, &config.url) {
+            (Some(command), _) => {
+                Self::Stdio(StdioConnection::spawn(name, command, config, timeout)?)
+            }
+            (None, Some(url)) => Self::Http(HttpConnection::new(url, config, timeout)?),
+            (None, None) => anyhow::bail!("MCP server {} has neither command nor url", name),
+        };
+
+        let init = connection
+            .request(
+                "initialize",
+                json!({
+                    "protocolVersion": PROTOCOL_VERSION,
+                    "capabilities": {},
+                    "clientInfo": { "name": "localgpt", "version": env!("CARGO_PKG_VERSION") },
+                }),
+            )
+            .await?;
+        if let Self::Http(http) = &connection
+            && let Some(version) = init["protocolVersion"].as_str()
+        {
+            http.set_protocol_version(version);
+        }
+        connection
+            .notify("notifications/initialized", json!({}))
+            .await?;
+
+        info!(
+            "Connected to MCP server {} ({} {})",
+            name,
+            init["serverInfo"]["name"].as_str().unwrap_or("unknown"),
+            init["serverInfo"]["version"].as_str().unwrap_or("")
+        );
+        Ok(connection)
+    }
+
+    fn is_alive(&self) -> bool {
+        match self {
+            Self::Stdio(stdio) => stdio.alive.load(Ordering::SeqCst),
+            Self::Http(http) => http.alive.load(Ordering::SeqCst),
+        }
+    }
+
+    async fn request(&self, method: &str, params: Value) -> Result<Value> {
+        let response = match self {
+            Self::Stdio(stdio) => stdio.request(method, params).await?,
+            Self::Http(http) => http.request(method, params).await?,
+        };
+        into_result(response)
+    }
+
+    async fn notify(&self, method: &str, params: Value) -> Result<()> {
+        let message = super::notification(method, params);
+        match self {
+            Self::Stdio(stdio) => stdio.send(&message).await,
+            Self::Http(http) => http.post(&message, None).await.map(|_| ()),
+        }
+    }
+}
+
+/// Server process speaking newline-delimited JSON-RPC on stdin/stdout
+struct StdioConnection {
+    _child: Child,
+    stdin: Arc<tokio::sync::Mutex<ChildStdin>>,
+    pending: Arc<StdMutex<HashMap<u64, oneshot::Sender<Value>>>>,
+    next_id: AtomicU64,
+    alive: Arc<AtomicBool>,
+    timeout: Duration,
+}
+
+impl StdioConnection {
+    fn spawn(
+        name: &str,
+        command: &str,
+        config: &McpServerConfig,
+        timeout: Duration,
+    ) -> Result<Self> {
+        let mut child = tokio::process::Command::new(shellexpand::tilde(command).as_ref())
+            .args(&config.args)
+            .envs(&config.env)
+            .stdin(std::process::Stdio::piped())
+            .stdout(std::process::Stdio::piped())
+            .stderr(std::process::Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(|e| anyhow::anyhow!("Failed to start MCP server {}: {}", name, e))?;
+
+        let stdin = Arc::new(tokio::sync::Mutex::new(
+            child.stdin.take().expect("stdin is piped"),
+        ));
+        let stdout = child.stdout.take().expect("stdout is piped");
+        let stderr = child.stderr.take().expect("stderr is piped");
+        let pending: Arc<StdMutex<HashMap<u64, oneshot::Sender<Value>>>> = Arc::default();
+        let alive = Arc::new(AtomicBool::new(true));
+
+        let server = name.to_string();
+        tokio::spawn(async move {
+            let mut lines = BufReader::new(stderr).lines();
+            while let Ok(Some(line)) = lines.next_line().await {
+                debug!("MCP server {}: {}", server, line);
+            }
+        });
+
+        let server = name.to_string();
+        let (reader_stdin, reader_pending, reader_alive) =
+            (Arc::clone(&stdin), Arc::clone(&pending), Arc::clone(&alive));
+        tokio::spawn(async move {
+            let mut lines = BufReader::new(stdout).lines();
+            while let Ok(Some(line)) = lines.next_line().await {
+                let Ok(message) = serde_json::from_str::<Value>(&line) else {
+                    debug!("MCP server {} sent non-JSON: {}", server, line);
+                    continue;
+                };
+                match (message.get("method"), message.get("id")) {
+                    // Requests from the server: we only answer pings
+                    (Some(method), Some(id)) => {
+                        let reply = if method == "ping" {
+                            super::response(id, json!({}))
+                        } else {
+                            super::error_response(id, METHOD_NOT_FOUND, "Method not supported")
+                        };
+                        let mut stdin = reader_stdin.lock().await;
+                        let _ = write_message(&mut stdin, &reply).await;
+                    }
+                    (Some(method), None) => debug!("MCP server {}: {}", server, method),
+                    (None, Some(id)) => {
+                        let sender = id
+                            .as_u64()
+                            .and_then(|id| reader_pending.lock().ok()?.remove(&id));
+                        if let Some(sender) = sender {
+                            let _ = sender.send(message);
+                        }
+                    }
+                    (None, None) => {}
+                }
+            }
+            warn!("MCP server {} exited", server);
+            reader_alive.store(false, Ordering::SeqCst);
+            // Fail the requests still waiting
+            if let Ok(mut pending) = reader_pending.lock() {
+                pending.clear();
+            }
+        });
+
+        Ok(Self {
+            _child: child,
+            stdin,
+            pending,
+            next_id: AtomicU64::new(1),
+            alive,
+            timeout,
+        })
+    }
+
+    async fn request(&self, method: &str, params: Value) -> Result<Value> {
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+        let (tx, rx) = oneshot::channel();
+        if let Ok(mut pending) = self.pending.lock() {
+            pending.insert(id, tx);
+        }
+        self.send(&super::request(id, method, params)).await?;
+
+        match tokio::time::timeout(self.timeout, rx).await {
+            Ok(Ok(response)) => Ok(response),
+            Ok(Err(_)) => Err(transport_error("MCP server exited")),
+            Err(_) => {
+                if let Ok(mut pending) = self.pending.lock() {
+                    pending.remove(&id);
+                }
+                anyhow::bail!(
+                    "MCP request {} timed out after {}s",
+                    method,
+                    self.timeout.as_secs()
+                )
+            }
+        }
+    }
+
+    async fn send(&self, message: &Value) -> Result<()> {
+        if !self.alive.load(Ordering::SeqCst) {
+            return Err(transport_error("MCP server exited"));
+        }
+        let mut stdin = self.stdin.lock().await;
+        write_message(&mut stdin, message)
+            .await
+            .map_err(|e| transport_error(format!("Failed to write to MCP server: {}", e)))
+    }
+}
+
+async fn write_message(stdin: &mut ChildStdin, message: &Value) -> std::io::Result<()> {
+    let mut line = message.to_string();
+    line.push('\n');
+    stdin.write_all(line.as_bytes()).await?;
+    stdin.flush().await
+}
+
+/// Server reached over streamable HTTP
+struct HttpConnection {
+    client: reqwest::Client,
+    url: String,
+    headers: HashMap<String, String>,
+    session_id: StdMutex<Option<String>>,
+    protocol_version: StdMutex<Option<String>>,
+    next_id: AtomicU64,
+    alive: AtomicBool,
+}
+
+impl HttpConnection {
+    fn new(url: &str, config: &McpServerConfig, timeout: Duration) -> Result<Self> {
+        Ok(Self {
+            client: reqwest::Client::builder().timeout(timeout).build()?,
+            url: url.to_string(),
+            headers: config.headers.clone(),
+            session_id: StdMutex::new(None),
+            protocol_version: StdMutex::new(None),
+            next_id: AtomicU64::new(1),
+            alive: AtomicBool::new(true),
+        })
+    }
+
+    fn set_protocol_version(&self, version: &str) {
+        if let Ok(mut current) = self.protocol_version.lock() {
+            *current = Some(version.to_string());
+        }
+    }
+
+    async fn request(&self, method: &str, params: Value) -> Result<Value> {
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+        self.post(&super::request(id, method, params), Some(id))
+            .await?
+            .ok_or_else(|| anyhow::anyhow!("MCP server sent no response to {}", method))
+    }
+
+    /// POST a message; for requests, the response with `id`
+    async fn post(&self, message: &Value, id: Option<u64>) -> Result<Option<Value>> {
+        let mut request = self
+            .client
+            .post(&self.url)
+            .header("Accept", "application/json, text/event-stream")
+            .json(message);
+        for (name, value) in &self.headers {
+            request = request.header(name, value);
+        }
+        let session_id = self.session_id.lock().ok().and_then(|s| s.clone());
+        if let Some(ref session_id) = session_id {
+            request = request.header("Mcp-Session-Id", session_id);
+        }
+        if let Some(version) = self.protocol_version.lock().ok().and_then(|v| v.clone()) {
+            request = request.header("MCP-Protocol-Version", version);
+        }
+
+        let response = request
+            .send()
+            .await
+            .map_err(|e| transport_error(format!("MCP request failed: {}", e)))?;
+        let status = response.status();
+        if status == reqwest::StatusCode::NOT_FOUND && session_id.is_some() {
+            // The server forgot our session; a new connection starts another
+            self.alive.store(false, Ordering::SeqCst);
+            return Err(transport_error("MCP session expired"));
+        }
+        if !status.is_success() {
+            let body = response.text().await.unwrap_or_default();
+            anyhow::bail!("MCP server returned {}: {}", status, body);
+        }
+        if let Some(new_id) = response
+            .headers()
+            .get("Mcp-Session-Id")
+            .and_then(|v| v.to_str().ok())
+            && let Ok(mut current) = self.session_id.lock()
+        {
+            *current = Some(new_id.to_string());
+        }
+
+        let Some(id) = id else {
+            return Ok(None);
+        };
+        let is_sse = response
+            .headers()
+            .get("Content-Type")
+            .and_then(|v| v.to_str().ok())
+            .is_some_and(|t| t.starts_with("text/event-stream"));
+        let body = response
+            .text()
+            .await
+            .map_err(|e| transport_error(format!("MCP response failed: {}", e)))?;
+        if is_sse {
+            return Ok(sse_messages(&body)
+                .into_iter()
+                .find(|m| m["id"] == id && m.get("method").is_none()));
+        }
+        Ok(Some(serde_json::from_str(&body)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Answers initialize, tools/list and tools/call by the request's ID;
+    /// exits after answering a call to "crash", and without answering a call
+    /// to "die" (logging it to $DIE_LOG)
+    const FAKE_SERVER: &str = r#"
+while IFS= read -r line; do
+  id=$(printf '%s' "$line" | sed -n 's/.*"id":\([0-9]*\).*/\1/p')
+  case "$line" in
+    *'"method":"initialize"'*)
+      echo "{\"jsonrpc\":\"2.0\",\"id\":$id,\"result\":{\"protocolVersion\":\"2025-06-18\",\"capabilities\":{\"tools\":{}},\"serverInfo\":{\"name\":\"fake\",\"version\":\"1\"}}}" ;;
+    *'"method":"tools/list"'*)
+      echo "{\"jsonrpc\":\"2.0\",\"id\":$id,\"result\":{\"tools\":[{\"name\":\"echo\",\"description\":\"Echo\",\"inputSchema\":{\"type\":\"object\"},\"annotations\":{\"readOnlyHint\":true}},{\"name\":\"crash\",\"description\":\"Exit\"}]}}" ;;
+    *'"name":"die"'*)
+      echo died >> "$DIE_LOG"
+      exit 0 ;;
+    *'"name":"crash"'*)
+      echo "{\"jsonrpc\":\"2.0\",\"id\":$id,\"result\":{\"content\":[{\"type\":\"text\",\"text\":\"bye\"}]}}"
+      exit 0 ;;
+    *'"method":"tools/call"'*)
+      echo "{\"jsonrpc\":\"2.0\",\"id\":$id,\"result\":{\"content\":[{\"type\":\"text\",\"text\":\"pong $$\"}]}}" ;;
+  esac
+done
+"#;
+
+    fn fake_server() -> McpServerConfig {
+        McpServerConfig {
+            enabled: true,
+            command: Some("bash".to_string()),
+            args: vec!["-c".to_string(), FAKE_SERVER.to_string()],
+            env: HashMap::new(),
+            url: None,
+            headers: HashMap::new(),
+            timeout_secs: 10,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_stdio_tools_and_restart() {
+        let client = Arc::new(McpClient::new("fake", fake_server()));
+        let tools = client.list_tools().await.unwrap();
+        assert_eq!(tools.len(), 2);
+        assert!(tools[0].read_only);
+        assert_eq!(tools[1].input_schema["type"], "object");
+
+        let echo = McpTool::new(Arc::clone(&client), tools[0].clone());
+        assert_eq!(echo.name(), "fake__echo");
+        assert!(echo.is_read_only());
+        let first = echo.execute("{}").await.unwrap();
+        assert!(first.starts_with("pong "));
+
+        let crash = McpTool::new(Arc::clone(&client), tools[1].clone());
+        assert_eq!(crash.execute("").await.unwrap(), "bye");
+
+        // The server exited; the next call starts a new one
+        let mut second = String::new();
+        for _ in 0..50 {
+            if !client.connection.lock().await.as_ref().unwrap().is_alive() {
+                second = echo.execute("{}").await.unwrap();
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        assert!(second.starts_with("pong "));
+        assert_ne!(first, second);
+    }
+
+    #[tokio::test]
+    async fn test_tool_call_is_not_resent_after_connection_loss() {
+        let tmp = tempfile::tempdir().unwrap();
+        let log = tmp.path().join("die.log");
+        let mut config = fake_server();
+        config
+            .env
+            .insert("DIE_LOG".to_string(), log.display().to_string());
+        let client = McpClient::new("fake", config);
+
+        let err = client.call_tool("die", json!({})).await.unwrap_err();
+        assert!(err.to_string().contains("may have run"), "{}", err);
+        assert_eq!(std::fs::read_to_string(&log).unwrap(), "died\n");
+
+        // Listing tools is safe to repeat, and reconnects
+        assert_eq!(client.list_tools().await.unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_tool_names_and_results() {
+        assert_eq!(
+            tool_name("github", "search.issues"),
+            "github__search_issues"
+        );
+        assert_eq!(tool_name("s", &"x".repeat(100)).len(), MAX_TOOL_NAME_LEN);
+
+        let result = json!({
+            "content": [
+                { "type": "text", "text": "hello" },
+                { "type": "image", "data": "...", "mimeType": "image/png" },
+                { "type": "resource", "resource": { "uri": "file:///a", "text": "inline" } },
+            ]
+        });
+        assert_eq!(
+            format_tool_result(&result).unwrap(),
+            "hello\n[image content: image/png]\ninline"
+        );
+        let structured = json!({ "content": [], "structuredContent": { "n": 1 } });
+        assert!(
+            format_tool_result(&structured)
+                .unwrap()
+                .contains("\"n\": 1")
+        );
+        let error =
+            json!({ "content": [{ "type": "text", "text": "no such repo" }], "isError": true });
+        assert_eq!(
+            format_tool_result(&error).unwrap_err().to_string(),
+            "no such repo"
+        );
+    }
+
+    #[test]
+    fn test_sse_messages() {
+        let body = "event: message\r\ndata: {\"jsonrpc\":\"2.0\",\"method\":\"notifications/progress\"}\r\n\r\nid: 1\ndata: {\"jsonrpc\":\"2.0\",\ndata: \"id\":3,\"result\":{}}\n\n";
+        let messages = sse_messages(body);
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[1]["id"], 3);
+        assert!(into_result(messages[1].clone()).is_ok());
+        let error =
+            json!({ "jsonrpc": "2.0", "id": 4, "error": { "code": -32602, "message": "bad" } });
+        assert_eq!(
+            into_result(error).unwrap_err().to_string(),
+            "bad (code -32602)"
+        );
+    }
+}
@@ -0,0 +1,41 @@
+//! Model Context Protocol (MCP) support
+//!
+//! MCP is JSON-RPC 2.0 between a client and a server that offers tools and
+//! resources. `client` mounts the tools of configured servers as agent tools;
+//! `server` offers the agent's memory to other clients (`localgpt mcp serve`).
+
+pub mod client;
+pub mod server;
+
+pub use client::{McpClient, McpTool, create_mcp_tools};
+pub use server::McpServer;
+
+use serde_json::{Value, json};
+
+/// Protocol revision we speak
+pub const PROTOCOL_VERSION: &str = "2025-06-18";
+
+/// Joins server and tool names in the agent's tool names (`server__tool`)
+pub const TOOL_NAME_SEPARATOR: &str = "__";
+
+/// JSON-RPC error codes
+pub const PARSE_ERROR: i64 = -32700;
+pub const METHOD_NOT_FOUND: i64 = -32601;
+pub const INVALID_PARAMS: i64 = -32602;
+pub const INTERNAL_ERROR: i64 = -32603;
+
+pub(crate) fn request(id: u64, method: &str, params: Value) -> Value {
+    json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params })
+}
+
+pub(crate) fn notification(method: &str, params: Value) -> Value {
+    json!({ "jsonrpc": "2.0", "method": method, "params": params })
+}
+
+pub(crate) fn response(id: &Value, result: Value) -> Value {
+    json!({ "jsonrpc": "2.0", "id": id, "result": result })
+}
+
+pub(crate) fn error_response(id: &Value, code: i64, message: &str) -> Value {
+    json!({ "jsonrpc": "2.0", "id": id, "error": { "code": code, "message": message } })
+}
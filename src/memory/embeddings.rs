@@ -299,6 +299,24 @@ impl EmbeddingProvider for FastEmbedProvider {
 // GGUF Embedding Provider (llama.cpp) - Optional, requires `gguf` feature
 // ============================================================================
 
+/// Process-wide llama.cpp backend. It can only be initialized once, so GGUF
+/// embeddings and GGUF chat models share it.
+#[cfg(feature = "gguf")]
+pub(crate) fn shared_llama_backend() -> Result<Arc<llama_cpp_2::llama_backend::LlamaBackend>> {
+    static BACKEND: StdMutex<Option<Arc<llama_cpp_2::llama_backend::LlamaBackend>>> =
+        StdMutex::new(None);
+
+    let mut backend = BACKEND
+        .lock()
+        .map_err(|e| anyhow::anyhow!("Lock poisoned: {}", e))?;
+    if let Some(backend) = backend.as_ref() {
+        return Ok(Arc::clone(backend));
+    }
+    let initialized = Arc::new(llama_cpp_2::llama_backend::LlamaBackend::init()?);
+    *backend = Some(Arc::clone(&initialized));
+    Ok(initialized)
+}
+
 #[cfg(feature = "gguf")]
 pub struct LlamaCppProvider {
     model: Arc<StdMutex<llama_cpp_2::model::LlamaModel>>,
@@ -322,12 +340,11 @@ impl LlamaCppProvider {
     /// - nomic-embed-text-v1.5.Q8_0.gguf (~270MB, 768 dims)
     /// - mxbai-embed-large-v1-q8_0.gguf (~670MB, 1024 dims)
     pub fn new(model_path: &str, cache_dir: Option<&str>) -> Result<Self> {
-        use llama_cpp_2::llama_backend::LlamaBackend;
         use llama_cpp_2::model::LlamaModel;
         use llama_cpp_2::model::params::LlamaModelParams;
 
-        // Initialize backend
-        let backend = LlamaBackend::init()?;
+        // Initialize backend (shared with GGUF chat models)
+        let backend = shared_llama_backend()?;
 
         // Resolve model path - check if it's a file or needs downloading
         let resolved_path = Self::resolve_model_path(model_path, cache_dir)?;
@@ -350,7 +367,7 @@ impl LlamaCppProvider {
 
         Ok(Self {
             model: Arc::new(StdMutex::new(model)),
-            backend: Arc::new(backend),
+            backend,
             model_name,
             dimensions,
             cache_dir: cache_dir.map(|s| s.to_string()),
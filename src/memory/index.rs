@@ -10,6 +10,7 @@ use uuid::Uuid;
 
 use super::embeddings::{cosine_similarity, deserialize_embedding, serialize_embedding};
 use super::search::MemoryChunk;
+use crate::agent::tokenizer::{Tiktoken, Tokenizer};
 
 #[derive(Clone)]
 pub struct MemoryIndex {
@@ -22,6 +23,8 @@ pub struct MemoryIndex {
     chunk_size: usize,
     /// Token overlap between chunks (default: 80)
     chunk_overlap: usize,
+    /// Measures chunk sizes (default: cl100k)
+    tokenizer: Arc<dyn Tokenizer>,
 }
 
 #[derive(Debug)]
@@ -123,6 +126,7 @@ impl MemoryIndex {
             has_vec_extension,
             chunk_size: 400,
             chunk_overlap: 80,
+            tokenizer: Arc::new(Tiktoken::cl100k()),
         })
     }
 
@@ -133,6 +137,12 @@ impl MemoryIndex {
         self
     }
 
+    /// Measure chunks with a model's tokenizer (builder pattern)
+    pub fn with_tokenizer(mut self, tokenizer: Arc<dyn Tokenizer>) -> Self {
+        self.tokenizer = tokenizer;
+        self
+    }
+
     /// Try to load sqlite-vec extension
     #[allow(unsafe_code)]
     fn try_load_sqlite_vec(conn: &Connection) -> bool {
@@ -239,7 +249,12 @@ impl MemoryIndex {
         Self::delete_chunks_for_path(&conn, &relative_path)?;
 
         // Create new chunks (OpenClaw-compatible)
-        let chunks = chunk_text(&content, self.chunk_size, self.chunk_overlap);
+        let chunks = chunk_text(
+            &content,
+            self.chunk_size,
+            self.chunk_overlap,
+            &*self.tokenizer,
+        );
 
         for chunk in chunks.iter() {
             let chunk_id = Uuid::new_v4().to_string();
@@ -972,7 +987,12 @@ struct ChunkInfo {
     content: String,
 }
 
-fn chunk_text(text: &str, target_tokens: usize, overlap_tokens: usize) -> Vec<ChunkInfo> {
+fn chunk_text(
+    text: &str,
+    target_tokens: usize,
+    overlap_tokens: usize,
+    tokenizer: &dyn Tokenizer,
+) -> Vec<ChunkInfo> {
     let lines: Vec<&str> = text.lines().collect();
     let mut chunks = Vec::new();
 
@@ -980,19 +1000,18 @@ fn chunk_text(text: &str, target_tokens: usize, overlap_tokens: usize) -> Vec<Ch
         return chunks;
     }
 
-    // Rough estimate: 4 chars per token
-    let target_chars = target_tokens * 4;
-    let overlap_chars = overlap_tokens * 4;
+    // Tokens per line, +1 for the newline
+    let line_tokens: Vec<usize> = lines.iter().map(|l| tokenizer.count(l) + 1).collect();
 
     let mut start_line = 0;
-    let mut current_chars = 0;
+    let mut current_tokens = 0;
     let mut chunk_lines = Vec::new();
 
     for (i, line) in lines.iter().enumerate() {
         chunk_lines.push(*line);
-        current_chars += line.len() + 1; // +1 for newline
+        current_tokens += line_tokens[i];
 
-        if current_chars >= target_chars || i == lines.len() - 1 {
+        if current_tokens >= target_tokens || i == lines.len() - 1 {
             // Create chunk
             chunks.push(ChunkInfo {
                 line_start: (start_line + 1) as i32,
@@ -1004,9 +1023,9 @@ fn chunk_text(text: &str, target_tokens: usize, overlap_tokens: usize) -> Vec<Ch
             let mut overlap_len = 0;
             let mut overlap_start = chunk_lines.len();
 
-            for (j, line) in chunk_lines.iter().enumerate().rev() {
-                overlap_len += line.len() + 1;
-                if overlap_len >= overlap_chars {
+            for j in (0..chunk_lines.len()).rev() {
+                overlap_len += line_tokens[start_line + j];
+                if overlap_len >= overlap_tokens {
                     overlap_start = j;
                     break;
                 }
@@ -1016,11 +1035,11 @@ fn chunk_text(text: &str, target_tokens: usize, overlap_tokens: usize) -> Vec<Ch
             if overlap_start < chunk_lines.len() {
                 start_line += overlap_start;
                 chunk_lines = chunk_lines[overlap_start..].to_vec();
-                current_chars = chunk_lines.iter().map(|l| l.len() + 1).sum();
+                current_tokens = line_tokens[start_line..=i].iter().sum();
             } else {
                 start_line = i + 1;
                 chunk_lines.clear();
-                current_chars = 0;
+                current_tokens = 0;
             }
         }
     }
@@ -1031,17 +1050,32 @@ fn chunk_text(text: &str, target_tokens: usize, overlap_tokens: usize) -> Vec<Ch
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::agent::tokenizer::Heuristic;
     use tempfile::TempDir;
 
     #[test]
     fn test_chunk_text() {
         let text = "Line 1\nLine 2\nLine 3\nLine 4\nLine 5";
-        let chunks = chunk_text(text, 10, 2); // Small chunks for testing
+        let chunks = chunk_text(text, 10, 2, &Heuristic); // Small chunks for testing
 
         assert!(!chunks.is_empty());
         assert_eq!(chunks[0].line_start, 1);
     }
 
+    #[test]
+    fn test_chunk_text_measures_with_tokenizer() {
+        // 12 characters: 3 tokens plus the newline with the heuristic
+        let text = "aaaaaaaaaaaa\n".repeat(6);
+        let chunks = chunk_text(&text, 8, 4, &Heuristic);
+        let spans: Vec<_> = chunks.iter().map(|c| (c.line_start, c.line_end)).collect();
+        assert_eq!(spans, [(1, 2), (2, 3), (3, 4), (4, 5), (5, 6)]);
+
+        // 47 characters but 8 cl100k tokens per line
+        let text = "hello hello hello hello hello hello hello hello\n".repeat(4);
+        assert_eq!(chunk_text(&text, 10, 0, &Heuristic).len(), 4);
+        assert_eq!(chunk_text(&text, 10, 0, &Tiktoken::cl100k()).len(), 3);
+    }
+
     #[test]
     fn test_memory_index() -> Result<()> {
         let temp_dir = TempDir::new()?;
@@ -6,6 +6,8 @@ mod workspace;
 
 #[cfg(feature = "gguf")]
 pub use embeddings::LlamaCppProvider;
+#[cfg(feature = "gguf")]
+pub(crate) use embeddings::shared_llama_backend;
 pub use embeddings::{EmbeddingProvider, FastEmbedProvider, OpenAIEmbeddingProvider, hash_text};
 pub use index::{MemoryIndex, ReindexStats};
 pub use search::MemoryChunk;
@@ -93,8 +95,12 @@ impl MemoryManager {
             std::fs::create_dir_all(parent)?;
         }
 
-        let index = MemoryIndex::new_with_db_path(&workspace, &db_path)?
+        let mut index = MemoryIndex::new_with_db_path(&workspace, &db_path)?
             .with_chunk_config(memory_config.chunk_size, memory_config.chunk_overlap);
+        // Chunk sizes are measured with the default model's tokenizer
+        if let Some(config) = app_config {
+            index = index.with_tokenizer(crate::agent::tokenizer::for_default_model(config));
+        }
 
         // Create embedding provider based on config
         let embedding_provider: Option<Arc<dyn EmbeddingProvider>> = match memory_config
@@ -122,6 +122,16 @@ impl Paths {
             .join("sessions")
     }
 
+    /// Cost ledger directory: state_dir/costs (one JSON file per month)
+    pub fn cost_ledger_dir(&self) -> PathBuf {
+        self.state_dir.join("costs")
+    }
+
+    /// Record/replay cassettes: data_dir/cassettes
+    pub fn cassettes_dir(&self) -> PathBuf {
+        self.data_dir.join("cassettes")
+    }
+
     /// Logs directory
     pub fn logs_dir(&self) -> PathBuf {
         self.state_dir.join("logs")
@@ -143,6 +153,23 @@ impl Paths {
             .join("workspace.lock")
     }
 
+    /// Lock file for another workspace (agents with their own workspace)
+    pub fn workspace_lock_for(&self, workspace: &Path) -> PathBuf {
+        if workspace == self.workspace {
+            return self.workspace_lock();
+        }
+        let digest = crate::security::content_sha256(&workspace.to_string_lossy());
+        self.runtime_dir
+            .as_ref()
+            .unwrap_or(&self.state_dir)
+            .join(format!("workspace-{}.lock", &digest[..12]))
+    }
+
+    /// Workspace checkpoints (snapshots of files the agent changed)
+    pub fn checkpoints_dir(&self) -> PathBuf {
+        self.state_dir.join("checkpoints")
+    }
+
     /// Telegram pairing file
     pub fn pairing_file(&self) -> PathBuf {
         self.state_dir.join("telegram_paired_user.json")
@@ -398,6 +425,22 @@ mod tests {
         assert!(paths.managed_skills_dir().ends_with("skills"));
         assert!(paths.embedding_cache_dir().ends_with("embeddings"));
         assert!(paths.pairing_file().ends_with("telegram_paired_user.json"));
+        assert!(paths.checkpoints_dir().ends_with("checkpoints"));
+    }
+
+    #[test]
+    fn workspace_locks_per_workspace() {
+        let env: HashMap<&str, &str> = HashMap::new();
+        let paths = Paths::resolve_with_env(make_env(env)).unwrap();
+
+        assert_eq!(
+            paths.workspace_lock_for(&paths.workspace),
+            paths.workspace_lock()
+        );
+        let work = paths.workspace_lock_for(Path::new("/srv/work"));
+        assert_ne!(work, paths.workspace_lock());
+        assert_eq!(work, paths.workspace_lock_for(Path::new("/srv/work")));
+        assert_ne!(work, paths.workspace_lock_for(Path::new("/srv/notes")));
     }
 
     #[test]
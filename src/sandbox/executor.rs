@@ -1,5 +1,6 @@
 use anyhow::Result;
 use std::time::Duration;
+use tokio_util::sync::CancellationToken;
 
 use super::policy::SandboxPolicy;
 
@@ -10,35 +11,16 @@ use super::policy::SandboxPolicy;
 /// 2. Re-execs the current binary with argv[0]="localgpt-sandbox"
 /// 3. Passes policy + command as arguments
 /// 4. Collects output and enforces timeout
+///
+/// The child and everything it started are killed on timeout or when
+/// `cancel` fires.
 pub async fn run_sandboxed(
     command: &str,
     policy: &SandboxPolicy,
     timeout_ms: u64,
+    cancel: &CancellationToken,
 ) -> Result<(String, i32)> {
-    let policy_json = serde_json::to_string(policy)?;
-
-    // Get path to current executable for re-exec
-    let exe_path = std::env::current_exe()?;
-
-    // Build the child command:
-    // argv[0] = "localgpt-sandbox" (sentinel for dispatch)
-    // argv[1] = policy JSON
-    // argv[2] = shell command to execute
-    let timeout_duration = Duration::from_millis(timeout_ms);
-
-    let output = tokio::time::timeout(
-        timeout_duration,
-        tokio::process::Command::new(&exe_path)
-            .arg0("localgpt-sandbox")
-            .arg(&policy_json)
-            .arg(command)
-            .current_dir(&policy.workspace_path)
-            .stdout(std::process::Stdio::piped())
-            .stderr(std::process::Stdio::piped())
-            .output(),
-    )
-    .await
-    .map_err(|_| anyhow::anyhow!("Sandboxed command timed out after {}ms", timeout_ms))??;
+    let output = run_sandboxed_with_input(command, None, policy, timeout_ms, cancel).await?;
 
     let stdout = String::from_utf8_lossy(&output.stdout);
     let stderr = String::from_utf8_lossy(&output.stderr);
@@ -77,6 +59,105 @@ pub async fn run_sandboxed(
     Ok((result, exit_code))
 }
 
+/// Run a shell command inside the sandbox with `input` on its stdin,
+/// returning its raw output (stdout and stderr kept apart, untruncated).
+pub async fn run_sandboxed_with_input(
+    command: &str,
+    input: Option<&str>,
+    policy: &SandboxPolicy,
+    timeout_ms: u64,
+    cancel: &CancellationToken,
+) -> Result<std::process::Output> {
+    let policy_json = serde_json::to_string(policy)?;
+
+    // Get path to current executable for re-exec
+    let exe_path = std::env::current_exe()?;
+
+    // Build the child command:
+    // argv[0] = "localgpt-sandbox" (sentinel for dispatch)
+    // argv[1] = policy JSON
+    // argv[2] = shell command to execute
+    let timeout_duration = Duration::from_millis(timeout_ms);
+
+    let stdin = match input {
+        Some(_) => std::process::Stdio::piped(),
+        None => std::process::Stdio::null(),
+    };
+    let mut child = new_process_group(
+        tokio::process::Command::new(&exe_path)
+            .arg0("localgpt-sandbox")
+            .arg(&policy_json)
+            .arg(command)
+            .current_dir(&policy.workspace_path)
+            .stdin(stdin)
+            .stdout(std::process::Stdio::piped())
+            .stderr(std::process::Stdio::piped())
+            .kill_on_drop(true),
+    )
+    .spawn()?;
+    write_stdin(&mut child, input);
+
+    wait_for_group(child, timeout_duration, cancel, "Sandboxed command").await
+}
+
+/// Start the command in a process group of its own, so everything it
+/// spawns can be killed along with it
+pub(crate) fn new_process_group(
+    command: &mut tokio::process::Command,
+) -> &mut tokio::process::Command {
+    #[cfg(unix)]
+    command.process_group(0);
+    command
+}
+
+/// Collect the output of a child started with [`new_process_group`]. On
+/// timeout or cancel the whole group is killed: dropping the child only
+/// kills `bash` itself, leaving anything it started running.
+pub(crate) async fn wait_for_group(
+    child: tokio::process::Child,
+    timeout: Duration,
+    cancel: &CancellationToken,
+    what: &str,
+) -> Result<std::process::Output> {
+    let pid = child.id();
+    let result = tokio::select! {
+        output = tokio::time::timeout(timeout, child.wait_with_output()) => output
+            .map_err(|_| anyhow::anyhow!("{} timed out after {}ms", what, timeout.as_millis())),
+        _ = cancel.cancelled() => Err(anyhow::anyhow!("{} cancelled", what)),
+    };
+    match result {
+        Ok(output) => Ok(output?),
+        Err(e) => {
+            kill_process_group(pid);
+            Err(e)
+        }
+    }
+}
+
+fn kill_process_group(pid: Option<u32>) {
+    #[cfg(unix)]
+    if let Some(pid) = pid {
+        use nix::sys::signal::{Signal, killpg};
+        use nix::unistd::Pid;
+        let _ = killpg(Pid::from_raw(pid as i32), Signal::SIGKILL);
+    }
+    #[cfg(not(unix))]
+    let _ = pid;
+}
+
+/// Feed `input` to the child's stdin in the background (a child that
+/// doesn't read it must not block collecting its output)
+pub(crate) fn write_stdin(child: &mut tokio::process::Child, input: Option<&str>) {
+    use tokio::io::AsyncWriteExt;
+
+    if let (Some(mut stdin), Some(input)) = (child.stdin.take(), input) {
+        let input = input.to_string();
+        tokio::spawn(async move {
+            let _ = stdin.write_all(input.as_bytes()).await;
+        });
+    }
+}
+
 /// Trait extension for Command to set argv[0].
 #[allow(dead_code)]
 trait CommandExt {
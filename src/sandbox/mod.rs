@@ -21,5 +21,5 @@ pub mod policy;
 #[cfg(unix)]
 pub use child::sandbox_child_main;
 pub use detect::{SandboxCapabilities, detect_capabilities};
-pub use executor::run_sandboxed;
+pub use executor::{run_sandboxed, run_sandboxed_with_input};
 pub use policy::{NetworkPolicy, SandboxLevel, SandboxMode, SandboxPolicy, build_policy};
@@ -78,6 +78,12 @@ pub enum AuditAction {
     WriteBlocked,
     /// Previous audit entry corrupted, new chain segment started.
     ChainRecovery,
+    /// Agent tool loop stopped by a per-turn limit (rounds, calls, time, repeats).
+    ToolLimitReached,
+    /// Tool call allowed by a permission rule or the user.
+    ToolAllowed,
+    /// Tool call denied by a permission rule, the user, or no one to ask.
+    ToolDenied,
 }
 
 /// Append a new entry to the audit log.
@@ -73,7 +73,7 @@
 //! };
 //!
 //! // At context assembly — always append the security block last:
-//! let block = build_ending_security_block(user_policy.as_deref());
+//! let block = build_ending_security_block(user_policy.as_deref(), true, false);
 //! ```
 //!
 //! ## File Hierarchy
@@ -129,7 +129,9 @@ pub use super::protected_files::{
 
 // ── Context Window Suffix ───────────────────────────────────────────
 
-pub use super::suffix::{HARDCODED_SECURITY_SUFFIX, build_ending_security_block};
+pub use super::suffix::{
+    ELEVATED_SECURITY_SUFFIX, HARDCODED_SECURITY_SUFFIX, build_ending_security_block,
+};
 
 // ── Constants ───────────────────────────────────────────────────────
 
@@ -2,15 +2,19 @@
 //!
 //! Supports multiple sessions with session ID-based routing.
 //! Sessions are created on demand and cached for reuse.
+//!
+//! A daemon can host several agents, each with its own workspace, index and
+//! sessions. Agent routes are served under `/api` for the default agent and
+//! under `/api/agents/{agent_id}` for every hosted one.
 
 use anyhow::Result;
 use axum::{
     Router,
     extract::{
-        Path, Query, State,
+        FromRequestParts, Path, Query, State,
         ws::{Message as WsMessage, WebSocket, WebSocketUpgrade},
     },
-    http::{StatusCode, header},
+    http::{StatusCode, header, request::Parts},
     response::{
         IntoResponse, Json, Response,
         sse::{Event, Sse},
@@ -30,8 +34,11 @@ use tokio::sync::Mutex;
 use tower_http::cors::{Any, CorsLayer};
 use tracing::{debug, info};
 
-use crate::agent::{Agent, AgentConfig, StreamEvent, extract_tool_detail};
-use crate::concurrency::{TurnGate, WorkspaceLock};
+use crate::agent::{
+    Agent, AgentConfig, ApprovalBroker, ApprovalDecision, CancelHandle, CheckpointStore,
+    CostLedger, LedgerEntry, StreamEvent, Usage, extract_tool_detail, is_cancelled,
+};
+use crate::concurrency::{TurnGate, TurnGates, WorkspaceLock};
 use crate::config::Config;
 use crate::heartbeat::{HeartbeatStatus, get_last_heartbeat_event};
 use crate::memory::MemoryManager;
@@ -47,12 +54,14 @@ const SESSION_TIMEOUT: Duration = Duration::from_secs(30 * 60);
 /// Maximum number of concurrent sessions
 const MAX_SESSIONS: usize = 100;
 
-/// Agent ID for HTTP sessions
+/// Agent ID for HTTP sessions of the default agent (`http-<id>` for others)
 const HTTP_AGENT_ID: &str = "http";
 
 pub struct Server {
     config: Config,
-    turn_gate: TurnGate,
+    /// Agent served under `/api`
+    default_agent: String,
+    turn_gates: TurnGates,
 }
 
 struct SessionEntry {
@@ -62,9 +71,16 @@ struct SessionEntry {
     dirty: bool,
 }
 
-struct AppState {
+/// One hosted agent: its config (with `[agents.<id>]` applied), memory and
+/// sessions
+struct AgentHost {
+    id: String,
     config: Config,
+    /// Agent ID its HTTP sessions are saved and billed under
+    sessions_id: String,
     sessions: Mutex<HashMap<String, SessionEntry>>,
+    /// Cancel handles by session ID, reachable while a turn holds `sessions`
+    cancel_handles: std::sync::Mutex<HashMap<String, CancelHandle>>,
     /// Shared MemoryManager to avoid reinitializing embedding provider
     memory: MemoryManager,
     /// In-process turn gate shared with heartbeat runner
@@ -73,41 +89,117 @@ struct AppState {
     workspace_lock: WorkspaceLock,
 }
 
-impl Server {
-    pub fn new(config: &Config) -> Result<Self> {
+impl AgentHost {
+    fn new(config: &Config, agent_id: &str, is_default: bool, gates: &TurnGates) -> Result<Self> {
+        let config = config.for_agent(agent_id);
+        let memory = MemoryManager::new_with_full_config(&config.memory, Some(&config), agent_id)?;
+        let sessions_id = if is_default {
+            HTTP_AGENT_ID.to_string()
+        } else {
+            format!("{}-{}", HTTP_AGENT_ID, agent_id)
+        };
+
         Ok(Self {
-            config: config.clone(),
-            turn_gate: TurnGate::new(),
+            id: agent_id.to_string(),
+            sessions_id,
+            sessions: Mutex::new(HashMap::new()),
+            cancel_handles: std::sync::Mutex::new(HashMap::new()),
+            memory,
+            turn_gate: gates.for_workspace(&config.workspace_path()),
+            workspace_lock: WorkspaceLock::for_config(&config)?,
+            config,
         })
     }
+}
+
+struct AppState {
+    config: Config,
+    agents: HashMap<String, Arc<AgentHost>>,
+    default_agent: String,
+    /// Tool approvals of all sessions, answered by request ID
+    approvals: ApprovalBroker,
+}
+
+/// The agent a request is for: `{agent_id}` under `/api/agents`, else the
+/// default agent
+struct AgentScope(Arc<AgentHost>);
+
+impl FromRequestParts<Arc<AppState>> for AgentScope {
+    type Rejection = AppError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &Arc<AppState>,
+    ) -> Result<Self, Self::Rejection> {
+        let params = Path::<HashMap<String, String>>::from_request_parts(parts, state)
+            .await
+            .map(|Path(params)| params)
+            .unwrap_or_default();
+        let agent_id = params.get("agent_id").unwrap_or(&state.default_agent);
+
+        match state.agents.get(agent_id) {
+            Some(agent) => Ok(Self(agent.clone())),
+            None => Err(AppError(
+                StatusCode::NOT_FOUND,
+                format!("Unknown agent: {}", agent_id),
+            )),
+        }
+    }
+}
+
+/// Path of the session routes (the agent ID, if any, is read by `AgentScope`)
+#[derive(Deserialize)]
+struct SessionPath {
+    session_id: String,
+}
+
+impl Server {
+    pub fn new(config: &Config) -> Result<Self> {
+        Self::new_with_gates(config, "main", TurnGates::new())
+    }
+
+    /// Create a server for the agents in `[agents]` (or just `default_agent`
+    /// when there are none), sharing turn gates with the daemon's heartbeat
+    /// runners and Telegram bot.
+    pub fn new_with_gates(
+        config: &Config,
+        default_agent: &str,
+        turn_gates: TurnGates,
+    ) -> Result<Self> {
+        let hosted = config.hosted_agents(default_agent);
+        let default_agent = if hosted.iter().any(|id| id == default_agent) {
+            default_agent.to_string()
+        } else {
+            hosted[0].clone()
+        };
 
-    /// Create a server with a shared TurnGate (for daemon mode where
-    /// heartbeat and HTTP share concurrency control).
-    pub fn new_with_gate(config: &Config, turn_gate: TurnGate) -> Result<Self> {
         Ok(Self {
             config: config.clone(),
-            turn_gate,
+            default_agent,
+            turn_gates,
         })
     }
 
     pub async fn run(&self) -> Result<()> {
-        // Create shared MemoryManager once to avoid reinitializing embedding provider
-        let memory =
-            MemoryManager::new_with_full_config(&self.config.memory, Some(&self.config), "main")?;
-
-        let workspace_lock = WorkspaceLock::new()?;
+        let mut agents = HashMap::new();
+        for agent_id in self.config.hosted_agents(&self.default_agent) {
+            let is_default = agent_id == self.default_agent;
+            let host = AgentHost::new(&self.config, &agent_id, is_default, &self.turn_gates)?;
+            agents.insert(agent_id, Arc::new(host));
+        }
 
         let state = Arc::new(AppState {
             config: self.config.clone(),
-            sessions: Mutex::new(HashMap::new()),
-            memory,
-            turn_gate: self.turn_gate.clone(),
-            workspace_lock,
+            agents,
+            default_agent: self.default_agent.clone(),
+            approvals: ApprovalBroker::new(),
         });
 
         // Load persisted sessions on startup
-        if let Err(e) = load_persisted_sessions(&state).await {
-            info!("Could not load persisted sessions: {}", e);
+        for agent in state.agents.values() {
+            if let Err(e) = load_persisted_sessions(&state, agent).await {
+                info!("Could not load persisted sessions of {}: {}", agent.id, e);
+            }
         }
 
         // Spawn session cleanup task
@@ -141,29 +233,13 @@ impl Server {
             .route("/ui/{*path}", get(serve_ui_file))
             // API routes
             .route("/health", get(health_check))
-            .route("/api/sessions", post(create_session))
-            .route("/api/sessions", get(list_sessions))
-            .route("/api/sessions/{session_id}", delete(delete_session))
-            .route("/api/sessions/{session_id}", get(get_session_status))
-            .route(
-                "/api/sessions/{session_id}/messages",
-                get(get_session_messages),
-            )
-            .route("/api/sessions/{session_id}/compact", post(compact_session))
-            .route("/api/sessions/{session_id}/clear", post(clear_session))
-            .route("/api/sessions/{session_id}/model", post(set_session_model))
-            .route("/api/chat", post(chat))
-            .route("/api/chat/stream", post(chat_stream))
-            .route("/api/ws", get(websocket_handler))
-            .route("/api/memory/search", get(memory_search))
-            .route("/api/memory/stats", get(memory_stats))
-            .route("/api/memory/reindex", post(memory_reindex))
-            .route("/api/status", get(status))
+            .route("/api/agents", get(list_agents))
+            .route("/api/approvals/{request_id}", post(answer_approval))
             .route("/api/config", get(get_config))
-            .route("/api/heartbeat/status", get(heartbeat_status))
-            .route("/api/saved-sessions", get(list_saved_sessions))
-            .route("/api/saved-sessions/{session_id}", get(get_saved_session))
+            .route("/api/costs", get(get_costs))
             .route("/api/logs/daemon", get(get_daemon_logs))
+            .nest("/api", agent_routes())
+            .nest("/api/agents/{agent_id}", agent_routes())
             .layer(cors)
             .with_state(state);
 
@@ -179,6 +255,51 @@ impl Server {
     }
 }
 
+/// Routes served for each hosted agent
+fn agent_routes() -> Router<Arc<AppState>> {
+    Router::new()
+        .route("/sessions", post(create_session))
+        .route("/sessions", get(list_sessions))
+        .route("/sessions/{session_id}", delete(delete_session))
+        .route("/sessions/{session_id}", get(get_session_status))
+        .route("/sessions/{session_id}/messages", get(get_session_messages))
+        .route("/sessions/{session_id}/compact", post(compact_session))
+        .route("/sessions/{session_id}/expand", post(expand_session))
+        .route(
+            "/sessions/{session_id}/messages/{index}/pin",
+            post(pin_session_message),
+        )
+        .route("/sessions/{session_id}/retry", post(retry_session_turn))
+        .route("/sessions/{session_id}/edit", post(edit_session_message))
+        .route(
+            "/sessions/{session_id}/branches",
+            get(list_session_branches),
+        )
+        .route("/sessions/{session_id}/branch", post(switch_session_branch))
+        .route("/sessions/{session_id}/clear", post(clear_session))
+        .route("/sessions/{session_id}/model", post(set_session_model))
+        .route("/sessions/{session_id}/cancel", post(cancel_session_turn))
+        .route("/chat", post(chat))
+        .route("/chat/stream", post(chat_stream))
+        .route("/ws", get(websocket_handler))
+        .route("/memory/search", get(memory_search))
+        .route("/memory/stats", get(memory_stats))
+        .route("/memory/reindex", post(memory_reindex))
+        .route("/status", get(status))
+        .route("/heartbeat/status", get(heartbeat_status))
+        .route("/saved-sessions", get(list_saved_sessions))
+        .route("/saved-sessions/{session_id}", get(get_saved_session))
+        .route("/checkpoints", get(list_checkpoints))
+        .route(
+            "/checkpoints/{checkpoint_id}/diff",
+            get(get_checkpoint_diff),
+        )
+        .route(
+            "/checkpoints/{checkpoint_id}/restore",
+            post(restore_checkpoint),
+        )
+}
+
 // Error response type
 struct AppError(StatusCode, String);
 
@@ -190,42 +311,66 @@ impl IntoResponse for AppError {
 
 // Session cleanup task
 async fn cleanup_expired_sessions(state: &Arc<AppState>) {
-    let mut sessions = state.sessions.lock().await;
-    let before_count = sessions.len();
+    for host in state.agents.values() {
+        let mut sessions = host.sessions.lock().await;
+        let before_count = sessions.len();
+
+        sessions.retain(|id, entry| {
+            let expired = entry.last_accessed.elapsed() > SESSION_TIMEOUT;
+            if expired {
+                debug!("Expiring session: {}", id);
+            }
+            !expired
+        });
 
-    sessions.retain(|id, entry| {
-        let expired = entry.last_accessed.elapsed() > SESSION_TIMEOUT;
-        if expired {
-            debug!("Expiring session: {}", id);
+        let removed = before_count - sessions.len();
+        if removed > 0 {
+            info!("Cleaned up {} expired sessions of {}", removed, host.id);
         }
-        !expired
-    });
 
-    let removed = before_count - sessions.len();
-    if removed > 0 {
-        info!("Cleaned up {} expired sessions", removed);
+        // Also drops handles of sessions evicted to stay under MAX_SESSIONS
+        if let Ok(mut handles) = host.cancel_handles.lock() {
+            handles.retain(|id, _| sessions.contains_key(id));
+        }
+    }
+}
+
+fn register_cancel_handle(host: &AgentHost, session_id: &str, agent: &Agent) {
+    if let Ok(mut handles) = host.cancel_handles.lock() {
+        handles.insert(session_id.to_string(), agent.cancel_handle());
     }
 }
 
 // Load persisted sessions from disk
-async fn load_persisted_sessions(state: &Arc<AppState>) -> Result<(), anyhow::Error> {
+async fn load_persisted_sessions(
+    state: &Arc<AppState>,
+    host: &AgentHost,
+) -> Result<(), anyhow::Error> {
     use crate::agent::list_sessions_for_agent;
 
-    let sessions_list = list_sessions_for_agent(HTTP_AGENT_ID)?;
+    let sessions_list = list_sessions_for_agent(&host.sessions_id)?;
     let mut loaded = 0;
 
     for session_info in sessions_list.into_iter().take(MAX_SESSIONS) {
         let agent_config = AgentConfig {
-            model: state.config.agent.default_model.clone(),
-            context_window: state.config.agent.context_window,
-            reserve_tokens: state.config.agent.reserve_tokens,
+            model: host.config.agent.default_model.clone(),
+            context_window: host.config.agent.context_window,
+            reserve_tokens: host.config.agent.reserve_tokens,
         };
 
-        let mut agent = Agent::new(agent_config, &state.config, state.memory.clone()).await?;
+        let mut agent = Agent::new(
+            agent_config,
+            &host.config,
+            &host.sessions_id,
+            host.memory.clone(),
+        )
+        .await?;
+        agent.set_approval_broker(state.approvals.clone());
 
         // Try to resume the session
-        if agent.resume_session(&session_info.id).await.is_ok() {
-            let mut sessions = state.sessions.lock().await;
+        if agent.resume_session(&session_info.id, None).await.is_ok() {
+            register_cancel_handle(host, &session_info.id, &agent);
+            let mut sessions = host.sessions.lock().await;
             sessions.insert(
                 session_info.id.clone(),
                 SessionEntry {
@@ -239,7 +384,7 @@ async fn load_persisted_sessions(state: &Arc<AppState>) -> Result<(), anyhow::Er
     }
 
     if loaded > 0 {
-        info!("Loaded {} persisted HTTP sessions", loaded);
+        info!("Loaded {} persisted HTTP sessions of {}", loaded, host.id);
     }
 
     Ok(())
@@ -247,16 +392,18 @@ async fn load_persisted_sessions(state: &Arc<AppState>) -> Result<(), anyhow::Er
 
 // Save dirty sessions to disk
 async fn save_dirty_sessions(state: &Arc<AppState>) {
-    let mut sessions = state.sessions.lock().await;
     let mut saved = 0;
 
-    for (id, entry) in sessions.iter_mut() {
-        if entry.dirty {
-            if let Err(e) = entry.agent.save_session_for_agent(HTTP_AGENT_ID).await {
-                debug!("Failed to save session {}: {}", id, e);
-            } else {
-                entry.dirty = false;
-                saved += 1;
+    for host in state.agents.values() {
+        let mut sessions = host.sessions.lock().await;
+        for (id, entry) in sessions.iter_mut() {
+            if entry.dirty {
+                if let Err(e) = entry.agent.save_session_for_agent(&host.sessions_id).await {
+                    debug!("Failed to save session {}: {}", id, e);
+                } else {
+                    entry.dirty = false;
+                    saved += 1;
+                }
             }
         }
     }
@@ -269,9 +416,10 @@ async fn save_dirty_sessions(state: &Arc<AppState>) {
 // Get or create a session
 async fn get_or_create_session(
     state: &Arc<AppState>,
+    host: &AgentHost,
     session_id: Option<String>,
 ) -> Result<String, AppError> {
-    let mut sessions = state.sessions.lock().await;
+    let mut sessions = host.sessions.lock().await;
 
     // If session_id provided, try to use existing session
     if let Some(ref id) = session_id
@@ -301,20 +449,27 @@ async fn get_or_create_session(
     let new_id = session_id.unwrap_or_else(|| uuid::Uuid::new_v4().to_string());
 
     let agent_config = AgentConfig {
-        model: state.config.agent.default_model.clone(),
-        context_window: state.config.agent.context_window,
-        reserve_tokens: state.config.agent.reserve_tokens,
+        model: host.config.agent.default_model.clone(),
+        context_window: host.config.agent.context_window,
+        reserve_tokens: host.config.agent.reserve_tokens,
     };
 
-    let mut agent = Agent::new(agent_config, &state.config, state.memory.clone())
-        .await
-        .map_err(|e| AppError(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    let mut agent = Agent::new(
+        agent_config,
+        &host.config,
+        &host.sessions_id,
+        host.memory.clone(),
+    )
+    .await
+    .map_err(|e| AppError(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    agent.set_approval_broker(state.approvals.clone());
 
     agent
         .new_session()
         .await
         .map_err(|e| AppError(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
 
+    register_cancel_handle(host, &new_id, &agent);
     sessions.insert(
         new_id.clone(),
         SessionEntry {
@@ -324,7 +479,7 @@ async fn get_or_create_session(
         },
     );
 
-    info!("Created new session: {}", new_id);
+    info!("Created new session of {}: {}", host.id, new_id);
     Ok(new_id)
 }
 
@@ -362,19 +517,77 @@ fn serve_ui_asset(path: &str) -> Response {
 #[derive(Serialize)]
 struct StatusResponse {
     version: String,
+    agent_id: String,
     model: String,
     memory_chunks: usize,
     active_sessions: usize,
+    /// API usage summed over active sessions
+    api_usage: Usage,
+    cache_hit_rate: Option<f64>,
+    /// Spend from the cost ledger (all agents and sources)
+    spend_today_usd: f64,
+    spend_month_usd: f64,
 }
 
-async fn status(State(state): State<Arc<AppState>>) -> Json<StatusResponse> {
-    let sessions = state.sessions.lock().await;
+async fn status(
+    State(state): State<Arc<AppState>>,
+    AgentScope(host): AgentScope,
+) -> Json<StatusResponse> {
+    let sessions = host.sessions.lock().await;
+
+    let mut api_usage = Usage::default();
+    for entry in sessions.values() {
+        api_usage.add(entry.agent.usage());
+    }
+
+    let ledger = CostLedger::for_config(&state.config);
+    let today = chrono::Local::now().date_naive();
 
     Json(StatusResponse {
         version: env!("CARGO_PKG_VERSION").to_string(),
-        model: state.config.agent.default_model.clone(),
-        memory_chunks: state.memory.chunk_count().unwrap_or(0),
+        agent_id: host.id.clone(),
+        model: host.config.agent.default_model.clone(),
+        memory_chunks: host.memory.chunk_count().unwrap_or(0),
         active_sessions: sessions.len(),
+        cache_hit_rate: api_usage.cache_hit_rate(),
+        api_usage,
+        spend_today_usd: ledger.spend_on(today).unwrap_or(0.0),
+        spend_month_usd: ledger.spend_in_month(today).unwrap_or(0.0),
+    })
+}
+
+// Hosted agents
+#[derive(Serialize)]
+struct AgentInfo {
+    agent_id: String,
+    model: String,
+    workspace: String,
+    heartbeat_enabled: bool,
+    active_sessions: usize,
+}
+
+#[derive(Serialize)]
+struct ListAgentsResponse {
+    default_agent: String,
+    agents: Vec<AgentInfo>,
+}
+
+async fn list_agents(State(state): State<Arc<AppState>>) -> Json<ListAgentsResponse> {
+    let mut agents = Vec::new();
+    for host in state.agents.values() {
+        agents.push(AgentInfo {
+            agent_id: host.id.clone(),
+            model: host.config.agent.default_model.clone(),
+            workspace: host.config.workspace_path().display().to_string(),
+            heartbeat_enabled: host.config.heartbeat.enabled,
+            active_sessions: host.sessions.lock().await.len(),
+        });
+    }
+    agents.sort_by(|a, b| a.agent_id.cmp(&b.agent_id));
+
+    Json(ListAgentsResponse {
+        default_agent: state.default_agent.clone(),
+        agents,
     })
 }
 
@@ -392,12 +605,13 @@ struct SessionResponse {
 
 async fn create_session(
     State(state): State<Arc<AppState>>,
+    AgentScope(host): AgentScope,
     Json(request): Json<CreateSessionRequest>,
 ) -> Response {
-    match get_or_create_session(&state, request.session_id).await {
+    match get_or_create_session(&state, &host, request.session_id).await {
         Ok(session_id) => Json(SessionResponse {
             session_id,
-            model: state.config.agent.default_model.clone(),
+            model: host.config.agent.default_model.clone(),
         })
         .into_response(),
         Err(e) => e.into_response(),
@@ -415,8 +629,8 @@ struct ListSessionsResponse {
     sessions: Vec<SessionInfo>,
 }
 
-async fn list_sessions(State(state): State<Arc<AppState>>) -> Json<ListSessionsResponse> {
-    let sessions = state.sessions.lock().await;
+async fn list_sessions(AgentScope(host): AgentScope) -> Json<ListSessionsResponse> {
+    let sessions = host.sessions.lock().await;
 
     let session_list: Vec<SessionInfo> = sessions
         .iter()
@@ -433,12 +647,17 @@ async fn list_sessions(State(state): State<Arc<AppState>>) -> Json<ListSessionsR
 
 // Delete a session
 async fn delete_session(
-    State(state): State<Arc<AppState>>,
-    Path(session_id): Path<String>,
+    AgentScope(host): AgentScope,
+    Path(SessionPath { session_id }): Path<SessionPath>,
 ) -> Response {
-    let mut sessions = state.sessions.lock().await;
+    let mut sessions = host.sessions.lock().await;
 
-    if sessions.remove(&session_id).is_some() {
+    if let Some(entry) = sessions.remove(&session_id) {
+        if let Ok(mut handles) = host.cancel_handles.lock() {
+            handles.remove(&session_id);
+        }
+        drop(sessions);
+        entry.agent.end_session("deleted").await;
         info!("Deleted session: {}", session_id);
         Json(json!({"deleted": true, "session_id": session_id})).into_response()
     } else {
@@ -453,16 +672,20 @@ struct SessionStatusResponse {
     model: String,
     message_count: usize,
     token_count: usize,
+    tokenizer: String,
     idle_seconds: u64,
     api_input_tokens: u64,
     api_output_tokens: u64,
+    api_cache_read_tokens: u64,
+    api_cache_write_tokens: u64,
+    cache_hit_rate: Option<f64>,
 }
 
 async fn get_session_status(
-    State(state): State<Arc<AppState>>,
-    Path(session_id): Path<String>,
+    AgentScope(host): AgentScope,
+    Path(SessionPath { session_id }): Path<SessionPath>,
 ) -> Response {
-    let sessions = state.sessions.lock().await;
+    let sessions = host.sessions.lock().await;
 
     match sessions.get(&session_id) {
         Some(entry) => {
@@ -472,9 +695,13 @@ async fn get_session_status(
                 model: entry.agent.model().to_string(),
                 message_count: status.message_count,
                 token_count: status.token_count,
+                tokenizer: status.tokenizer.clone(),
                 idle_seconds: entry.last_accessed.elapsed().as_secs(),
                 api_input_tokens: status.api_input_tokens,
                 api_output_tokens: status.api_output_tokens,
+                api_cache_read_tokens: status.api_cache_read_tokens,
+                api_cache_write_tokens: status.api_cache_write_tokens,
+                cache_hit_rate: status.cache_hit_rate(),
             })
             .into_response()
         }
@@ -485,11 +712,15 @@ async fn get_session_status(
 // Get session messages - returns message history for an active session
 #[derive(Serialize)]
 struct ActiveSessionMessage {
+    id: String,
+    /// Message this one follows (None: the first)
+    parent_id: Option<String>,
     role: String,
     content: Option<String>,
     tool_calls: Option<Vec<serde_json::Value>>,
     tool_call_id: Option<String>,
     timestamp: u64,
+    pinned: bool,
 }
 
 #[derive(Serialize)]
@@ -499,10 +730,10 @@ struct SessionMessagesResponse {
 }
 
 async fn get_session_messages(
-    State(state): State<Arc<AppState>>,
-    Path(session_id): Path<String>,
+    AgentScope(host): AgentScope,
+    Path(SessionPath { session_id }): Path<SessionPath>,
 ) -> Response {
-    let mut sessions = state.sessions.lock().await;
+    let mut sessions = host.sessions.lock().await;
 
     match sessions.get_mut(&session_id) {
         Some(entry) => {
@@ -534,6 +765,8 @@ async fn get_session_messages(
                     });
 
                     ActiveSessionMessage {
+                        id: sm.id.clone(),
+                        parent_id: sm.parent_id.clone(),
                         role: role.to_string(),
                         content: if sm.message.content.is_empty() {
                             None
@@ -543,6 +776,7 @@ async fn get_session_messages(
                         tool_calls,
                         tool_call_id: sm.message.tool_call_id.clone(),
                         timestamp: sm.timestamp,
+                        pinned: sm.pinned,
                     }
                 })
                 .collect();
@@ -559,10 +793,10 @@ async fn get_session_messages(
 
 // Compact session history
 async fn compact_session(
-    State(state): State<Arc<AppState>>,
-    Path(session_id): Path<String>,
+    AgentScope(host): AgentScope,
+    Path(SessionPath { session_id }): Path<SessionPath>,
 ) -> Response {
-    let mut sessions = state.sessions.lock().await;
+    let mut sessions = host.sessions.lock().await;
 
     match sessions.get_mut(&session_id) {
         Some(entry) => {
@@ -584,12 +818,148 @@ async fn compact_session(
     }
 }
 
+// Restore the messages the latest compaction summarized
+async fn expand_session(
+    AgentScope(host): AgentScope,
+    Path(SessionPath { session_id }): Path<SessionPath>,
+) -> Response {
+    let mut sessions = host.sessions.lock().await;
+
+    match sessions.get_mut(&session_id) {
+        Some(entry) => {
+            entry.last_accessed = Instant::now();
+            let restored = entry.agent.expand_compaction();
+            entry.dirty |= restored > 0;
+            Json(json!({
+                "session_id": session_id,
+                "restored": restored,
+                "token_count": entry.agent.context_tokens(),
+            }))
+            .into_response()
+        }
+        None => AppError(StatusCode::NOT_FOUND, "Session not found".to_string()).into_response(),
+    }
+}
+
+// Pin or unpin a message (index into the session's messages) so compaction
+// keeps it verbatim
+#[derive(Deserialize)]
+struct MessagePath {
+    session_id: String,
+    index: usize,
+}
+
+#[derive(Deserialize)]
+struct PinRequest {
+    pinned: bool,
+}
+
+async fn pin_session_message(
+    AgentScope(host): AgentScope,
+    Path(MessagePath { session_id, index }): Path<MessagePath>,
+    Json(request): Json<PinRequest>,
+) -> Response {
+    let mut sessions = host.sessions.lock().await;
+
+    match sessions.get_mut(&session_id) {
+        Some(entry) => {
+            entry.last_accessed = Instant::now();
+            match entry.agent.pin_message(Some(index), request.pinned) {
+                Ok(index) => {
+                    entry.dirty = true;
+                    Json(json!({
+                        "session_id": session_id,
+                        "index": index,
+                        "pinned": request.pinned,
+                    }))
+                    .into_response()
+                }
+                Err(e) => AppError(StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+            }
+        }
+        None => AppError(StatusCode::NOT_FOUND, "Session not found".to_string()).into_response(),
+    }
+}
+
+// Tips of the session's branches
+#[derive(Serialize)]
+struct BranchInfo {
+    leaf_id: String,
+    message_count: usize,
+    preview: String,
+    current: bool,
+}
+
+async fn list_session_branches(
+    AgentScope(host): AgentScope,
+    Path(SessionPath { session_id }): Path<SessionPath>,
+) -> Response {
+    let mut sessions = host.sessions.lock().await;
+
+    match sessions.get_mut(&session_id) {
+        Some(entry) => {
+            entry.last_accessed = Instant::now();
+            let branches: Vec<BranchInfo> = entry
+                .agent
+                .branches()
+                .into_iter()
+                .map(|b| BranchInfo {
+                    leaf_id: b.leaf_id,
+                    message_count: b.message_count,
+                    preview: b.preview,
+                    current: b.current,
+                })
+                .collect();
+            Json(json!({
+                "session_id": session_id,
+                "branches": branches,
+            }))
+            .into_response()
+        }
+        None => AppError(StatusCode::NOT_FOUND, "Session not found".to_string()).into_response(),
+    }
+}
+
+// Continue the session on another branch
+#[derive(Deserialize)]
+struct SwitchBranchRequest {
+    leaf_id: String,
+}
+
+async fn switch_session_branch(
+    AgentScope(host): AgentScope,
+    Path(SessionPath { session_id }): Path<SessionPath>,
+    Json(request): Json<SwitchBranchRequest>,
+) -> Response {
+    let mut sessions = host.sessions.lock().await;
+
+    match sessions.get_mut(&session_id) {
+        Some(entry) => {
+            entry.last_accessed = Instant::now();
+            match entry.agent.switch_branch(&request.leaf_id) {
+                Ok(()) => {
+                    entry.dirty = true;
+                    let messages = entry.agent.raw_session_messages();
+                    Json(json!({
+                        "session_id": session_id,
+                        "leaf_id": messages.last().map(|sm| sm.id.clone()),
+                        "message_count": messages.len(),
+                    }))
+                    .into_response()
+                }
+                Err(e) => AppError(StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+            }
+        }
+        None => AppError(StatusCode::NOT_FOUND, "Session not found".to_string()).into_response(),
+    }
+}
+
 // Clear session history
 async fn clear_session(
-    State(state): State<Arc<AppState>>,
-    Path(session_id): Path<String>,
+    AgentScope(host): AgentScope,
+    Path(SessionPath { session_id }): Path<SessionPath>,
 ) -> Response {
-    let mut sessions = state.sessions.lock().await;
+    let mut sessions = host.sessions.lock().await;
 
     match sessions.get_mut(&session_id) {
         Some(entry) => {
@@ -601,6 +971,52 @@ async fn clear_session(
     }
 }
 
+// Cancel the turn in flight (doesn't wait for the session lock the turn holds)
+async fn cancel_session_turn(
+    AgentScope(host): AgentScope,
+    Path(SessionPath { session_id }): Path<SessionPath>,
+) -> Response {
+    let handle = host
+        .cancel_handles
+        .lock()
+        .ok()
+        .and_then(|handles| handles.get(&session_id).cloned());
+
+    match handle {
+        Some(handle) => {
+            handle.cancel();
+            Json(json!({"session_id": session_id, "cancelled": true})).into_response()
+        }
+        None => AppError(StatusCode::NOT_FOUND, "Session not found".to_string()).into_response(),
+    }
+}
+
+// Approve or deny a tool call waiting in a turn
+#[derive(Deserialize)]
+struct ApprovalAnswer {
+    approved: bool,
+    /// Also allow the request's pattern from now on
+    #[serde(default)]
+    always: bool,
+}
+
+async fn answer_approval(
+    State(state): State<Arc<AppState>>,
+    Path(request_id): Path<String>,
+    Json(answer): Json<ApprovalAnswer>,
+) -> Response {
+    let decision = ApprovalDecision::from_flags(answer.approved, answer.always);
+    if state.approvals.resolve(&request_id, decision) {
+        Json(json!({"request_id": request_id, "approved": answer.approved})).into_response()
+    } else {
+        AppError(
+            StatusCode::NOT_FOUND,
+            "No pending approval with that ID".to_string(),
+        )
+        .into_response()
+    }
+}
+
 // Set session model
 #[derive(Deserialize)]
 struct SetModelRequest {
@@ -608,11 +1024,11 @@ struct SetModelRequest {
 }
 
 async fn set_session_model(
-    State(state): State<Arc<AppState>>,
-    Path(session_id): Path<String>,
+    AgentScope(host): AgentScope,
+    Path(SessionPath { session_id }): Path<SessionPath>,
     Json(request): Json<SetModelRequest>,
 ) -> Response {
-    let mut sessions = state.sessions.lock().await;
+    let mut sessions = host.sessions.lock().await;
 
     match sessions.get_mut(&session_id) {
         Some(entry) => {
@@ -645,20 +1061,82 @@ struct ChatResponse {
     response: String,
     session_id: String,
     model: String,
+    /// Memory auto-recalled for the turn (`file:start-end`)
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    recalled: Vec<String>,
 }
 
-async fn chat(State(state): State<Arc<AppState>>, Json(request): Json<ChatRequest>) -> Response {
+async fn chat(
+    State(state): State<Arc<AppState>>,
+    AgentScope(host): AgentScope,
+    Json(request): Json<ChatRequest>,
+) -> Response {
     // Get or create session
-    let session_id = match get_or_create_session(&state, request.session_id).await {
+    let session_id = match get_or_create_session(&state, &host, request.session_id).await {
         Ok(id) => id,
         Err(e) => return e.into_response(),
     };
 
+    run_turn(
+        &host,
+        session_id,
+        request.model,
+        TurnInput::Message(request.message),
+    )
+    .await
+}
+
+// Ask again for a reply to the last user message, on a new branch
+async fn retry_session_turn(
+    AgentScope(host): AgentScope,
+    Path(SessionPath { session_id }): Path<SessionPath>,
+) -> Response {
+    run_turn(&host, session_id, None, TurnInput::Retry).await
+}
+
+// Replace a user message (index into the session's messages) and continue
+// from it on a new branch
+#[derive(Deserialize)]
+struct EditRequest {
+    index: usize,
+    message: String,
+}
+
+async fn edit_session_message(
+    AgentScope(host): AgentScope,
+    Path(SessionPath { session_id }): Path<SessionPath>,
+    Json(request): Json<EditRequest>,
+) -> Response {
+    let input = TurnInput::Edit {
+        index: request.index,
+        message: request.message,
+    };
+    run_turn(&host, session_id, None, input).await
+}
+
+/// What starts a non-streaming turn
+enum TurnInput {
+    Message(String),
+    /// Resend the last user message
+    Retry,
+    /// Send `message` in place of user message `index`
+    Edit {
+        index: usize,
+        message: String,
+    },
+}
+
+async fn run_turn(
+    host: &AgentHost,
+    session_id: String,
+    model: Option<String>,
+    input: TurnInput,
+) -> Response {
     // Acquire in-process turn gate (waits for other turns to finish)
-    let _gate_permit = state.turn_gate.acquire().await;
+    let _gate_permit = host.turn_gate.acquire().await;
 
     // Acquire cross-process workspace lock (blocking, so use spawn_blocking)
-    let ws_lock_path = state.workspace_lock.clone();
+    let ws_lock_path = host.workspace_lock.clone();
     let ws_guard = match tokio::task::spawn_blocking(move || ws_lock_path.acquire()).await {
         Ok(Ok(guard)) => guard,
         Ok(Err(e)) => {
@@ -678,7 +1156,7 @@ async fn chat(State(state): State<Arc<AppState>>, Json(request): Json<ChatReques
     };
 
     // Get agent from session
-    let mut sessions = state.sessions.lock().await;
+    let mut sessions = host.sessions.lock().await;
     let entry = match sessions.get_mut(&session_id) {
         Some(e) => e,
         None => {
@@ -690,13 +1168,38 @@ async fn chat(State(state): State<Arc<AppState>>, Json(request): Json<ChatReques
     entry.last_accessed = Instant::now();
 
     // Switch model if requested
-    if let Some(ref model) = request.model
+    if let Some(ref model) = model
         && let Err(e) = entry.agent.set_model(model)
     {
         return AppError(StatusCode::BAD_REQUEST, format!("Invalid model: {}", e)).into_response();
     }
 
-    let result = entry.agent.chat(&request.message).await;
+    let result = match input {
+        TurnInput::Message(message) => entry.agent.chat(&message).await,
+        TurnInput::Retry => match entry.agent.rewind_to_user_message(None) {
+            Ok(message) => {
+                entry
+                    .agent
+                    .chat_with_images(&message.content, message.images)
+                    .await
+            }
+            Err(e) => return AppError(StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+        },
+        // Images attached to the original message are kept
+        TurnInput::Edit { index, message } => {
+            match entry.agent.rewind_to_user_message(Some(index)) {
+                Ok(original) => {
+                    entry
+                        .agent
+                        .chat_with_images(&message, original.images)
+                        .await
+                }
+                Err(e) => {
+                    return AppError(StatusCode::BAD_REQUEST, e.to_string()).into_response();
+                }
+            }
+        }
+    };
 
     // Release workspace lock explicitly before returning
     drop(ws_guard);
@@ -708,6 +1211,7 @@ async fn chat(State(state): State<Arc<AppState>>, Json(request): Json<ChatReques
                 response,
                 session_id,
                 model: entry.agent.model().to_string(),
+                recalled: entry.agent.recalled_memory().to_vec(),
             })
             .into_response()
         }
@@ -718,15 +1222,15 @@ async fn chat(State(state): State<Arc<AppState>>, Json(request): Json<ChatReques
 // Streaming chat endpoint (SSE) with tool support
 async fn chat_stream(
     State(state): State<Arc<AppState>>,
+    AgentScope(host): AgentScope,
     Json(request): Json<ChatRequest>,
 ) -> Response {
     // Get or create session first (outside the stream)
-    let session_id = match get_or_create_session(&state, request.session_id).await {
+    let session_id = match get_or_create_session(&state, &host, request.session_id).await {
         Ok(id) => id,
         Err(e) => return e.into_response(),
     };
 
-    let state_clone = state.clone();
     let message = request.message.clone();
 
     let stream = async_stream::stream! {
@@ -734,10 +1238,10 @@ async fn chat_stream(
         yield Ok::<Event, Infallible>(Event::default().data(json!({"type": "session", "session_id": session_id}).to_string()));
 
         // Acquire in-process turn gate
-        let _gate_permit = state_clone.turn_gate.acquire().await;
+        let _gate_permit = host.turn_gate.acquire().await;
 
         // Acquire cross-process workspace lock
-        let ws_lock = state_clone.workspace_lock.clone();
+        let ws_lock = host.workspace_lock.clone();
         let _ws_guard = match tokio::task::spawn_blocking(move || ws_lock.acquire()).await {
             Ok(Ok(guard)) => Some(guard),
             Ok(Err(e)) => {
@@ -750,7 +1254,7 @@ async fn chat_stream(
             }
         };
 
-        let mut sessions = state_clone.sessions.lock().await;
+        let mut sessions = host.sessions.lock().await;
         let entry = match sessions.get_mut(&session_id) {
             Some(e) => e,
             None => {
@@ -776,11 +1280,28 @@ async fn chat_stream(
                             let data = json!({"type": "content", "delta": content});
                             yield Ok(Event::default().data(data.to_string()));
                         }
+                        Ok(StreamEvent::Thinking(thinking)) => {
+                            let data = json!({"type": "thinking", "delta": thinking});
+                            yield Ok(Event::default().data(data.to_string()));
+                        }
                         Ok(StreamEvent::ToolCallStart { name, id, arguments }) => {
                             let detail = extract_tool_detail(&name, &arguments);
                             let data = json!({"type": "tool_start", "name": name, "id": id, "detail": detail});
                             yield Ok(Event::default().data(data.to_string()));
                         }
+                        Ok(StreamEvent::ToolApprovalRequired { request_id, name, id, arguments, pattern }) => {
+                            let detail = extract_tool_detail(&name, &arguments);
+                            let data = json!({
+                                "type": "tool_approval_required",
+                                "request_id": request_id,
+                                "name": name,
+                                "id": id,
+                                "arguments": arguments,
+                                "detail": detail,
+                                "pattern": pattern
+                            });
+                            yield Ok(Event::default().data(data.to_string()));
+                        }
                         Ok(StreamEvent::ToolCallEnd { name, id, output, warnings }) => {
                             let data = json!({
                                 "type": "tool_end",
@@ -791,10 +1312,22 @@ async fn chat_stream(
                             });
                             yield Ok(Event::default().data(data.to_string()));
                         }
+                        Ok(StreamEvent::MemoryRecalled { citations }) => {
+                            let data = json!({"type": "memory_recalled", "citations": citations});
+                            yield Ok(Event::default().data(data.to_string()));
+                        }
+                        Ok(StreamEvent::Fallback { from, to, reason }) => {
+                            let data = json!({"type": "fallback", "from": from, "to": to, "reason": reason});
+                            yield Ok(Event::default().data(data.to_string()));
+                        }
                         Ok(StreamEvent::Done) => {
                             let data = json!({"type": "done"});
                             yield Ok(Event::default().data(data.to_string()));
                         }
+                        Err(e) if is_cancelled(&e) => {
+                            yield Ok(Event::default().data(json!({"type": "cancelled"}).to_string()));
+                            break;
+                        }
                         Err(e) => {
                             yield Ok(Event::default().data(json!({"error": e.to_string()}).to_string()));
                             break;
@@ -835,11 +1368,8 @@ struct SearchResponse {
     query: String,
 }
 
-async fn memory_search(
-    State(state): State<Arc<AppState>>,
-    Query(query): Query<SearchQuery>,
-) -> Response {
-    match memory_search_inner(&state.memory, &query.q, query.limit) {
+async fn memory_search(AgentScope(host): AgentScope, Query(query): Query<SearchQuery>) -> Response {
+    match memory_search_inner(&host.memory, &query.q, query.limit) {
         Ok(response) => Json(response).into_response(),
         Err(e) => AppError(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
     }
@@ -879,8 +1409,8 @@ struct StatsResponse {
     index_size_kb: u64,
 }
 
-async fn memory_stats(State(state): State<Arc<AppState>>) -> Response {
-    match memory_stats_inner(&state.memory) {
+async fn memory_stats(AgentScope(host): AgentScope) -> Response {
+    match memory_stats_inner(&host.memory) {
         Ok(response) => Json(response).into_response(),
         Err(e) => AppError(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
     }
@@ -913,11 +1443,11 @@ struct ReindexResponse {
 }
 
 async fn memory_reindex(
-    State(state): State<Arc<AppState>>,
+    AgentScope(host): AgentScope,
     Json(request): Json<ReindexRequest>,
 ) -> Response {
     // Run reindex in blocking task since it uses sqlite
-    let memory = state.memory.clone();
+    let memory = host.memory.clone();
     let force = request.force;
 
     match tokio::task::spawn_blocking(move || memory_reindex_inner(&memory, force)).await {
@@ -1020,11 +1550,12 @@ struct HeartbeatEventInfo {
     duration_ms: u64,
     preview: Option<String>,
     reason: Option<String>,
+    tool_limit: Option<String>,
     age_seconds: u64,
 }
 
-async fn heartbeat_status(State(state): State<Arc<AppState>>) -> Json<HeartbeatStatusResponse> {
-    let last_event = get_last_heartbeat_event().map(|event| {
+async fn heartbeat_status(AgentScope(host): AgentScope) -> Json<HeartbeatStatusResponse> {
+    let last_event = get_last_heartbeat_event(&host.id).map(|event| {
         let now_ms = std::time::SystemTime::now()
             .duration_since(std::time::UNIX_EPOCH)
             .map(|d| d.as_millis() as u64)
@@ -1044,13 +1575,14 @@ async fn heartbeat_status(State(state): State<Arc<AppState>>) -> Json<HeartbeatS
             duration_ms: event.duration_ms,
             preview: event.preview,
             reason: event.reason,
+            tool_limit: event.tool_limit,
             age_seconds,
         }
     });
 
     Json(HeartbeatStatusResponse {
-        enabled: state.config.heartbeat.enabled,
-        interval: state.config.heartbeat.interval.clone(),
+        enabled: host.config.heartbeat.enabled,
+        interval: host.config.heartbeat.interval.clone(),
         last_event,
     })
 }
@@ -1068,10 +1600,10 @@ struct SavedSessionsResponse {
     sessions: Vec<SavedSessionInfo>,
 }
 
-async fn list_saved_sessions(State(_state): State<Arc<AppState>>) -> Response {
+async fn list_saved_sessions(AgentScope(host): AgentScope) -> Response {
     use crate::agent::list_sessions_for_agent;
 
-    match list_sessions_for_agent(HTTP_AGENT_ID) {
+    match list_sessions_for_agent(&host.sessions_id) {
         Ok(sessions) => {
             let session_list: Vec<SavedSessionInfo> = sessions
                 .into_iter()
@@ -1094,6 +1626,9 @@ async fn list_saved_sessions(State(_state): State<Arc<AppState>>) -> Response {
 // Get saved session detail - read and parse JSONL session file
 #[derive(Serialize)]
 struct SavedSessionMessage {
+    /// Place in the session tree (absent in version 1 files)
+    id: Option<String>,
+    parent_id: Option<String>,
     role: String,
     content: Option<String>,
     tool_calls: Option<Vec<serde_json::Value>>,
@@ -1108,12 +1643,15 @@ struct SavedSessionDetail {
     messages: Vec<SavedSessionMessage>,
 }
 
-async fn get_saved_session(Path(session_id): Path<String>) -> Response {
+async fn get_saved_session(
+    AgentScope(host): AgentScope,
+    Path(SessionPath { session_id }): Path<SessionPath>,
+) -> Response {
     use crate::agent::get_sessions_dir_for_agent;
     use std::fs::File;
     use std::io::{BufRead, BufReader};
 
-    let sessions_dir = match get_sessions_dir_for_agent(HTTP_AGENT_ID) {
+    let sessions_dir = match get_sessions_dir_for_agent(&host.sessions_id) {
         Ok(dir) => dir,
         Err(e) => {
             return AppError(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response();
@@ -1188,6 +1726,8 @@ async fn get_saved_session(Path(session_id): Path<String>) -> Response {
             let timestamp = msg["timestamp"].as_u64();
 
             messages.push(SavedSessionMessage {
+                id: parsed["id"].as_str().map(String::from),
+                parent_id: parsed["parentId"].as_str().map(String::from),
                 role,
                 content: if content.is_empty() {
                     None
@@ -1209,6 +1749,135 @@ async fn get_saved_session(Path(session_id): Path<String>) -> Response {
     .into_response()
 }
 
+// Workspace checkpoints (file changes of agent turns)
+#[derive(Deserialize)]
+struct CheckpointsQuery {
+    session_id: Option<String>,
+    limit: Option<usize>,
+}
+
+#[derive(Deserialize)]
+struct CheckpointPath {
+    checkpoint_id: String,
+}
+
+async fn list_checkpoints(
+    AgentScope(host): AgentScope,
+    Query(query): Query<CheckpointsQuery>,
+) -> Response {
+    let store = CheckpointStore::for_config(&host.config);
+    match store.list() {
+        Ok(checkpoints) => {
+            let checkpoints: Vec<_> = checkpoints
+                .into_iter()
+                .rev()
+                .filter(|c| query.session_id.as_ref().is_none_or(|s| &c.session_id == s))
+                .take(query.limit.unwrap_or(50))
+                .collect();
+            Json(json!({ "checkpoints": checkpoints })).into_response()
+        }
+        Err(e) => AppError(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+// Unified diff of what a checkpoint's turn changed
+async fn get_checkpoint_diff(
+    AgentScope(host): AgentScope,
+    Path(CheckpointPath { checkpoint_id }): Path<CheckpointPath>,
+) -> Response {
+    let store = CheckpointStore::for_config(&host.config);
+    let checkpoint = match store.find(&checkpoint_id) {
+        Ok(c) => c,
+        Err(e) => return AppError(StatusCode::NOT_FOUND, e.to_string()).into_response(),
+    };
+    match store.diff(&checkpoint) {
+        Ok(diff) => Json(json!({
+            "checkpoint_id": checkpoint.id,
+            "session_id": checkpoint.session_id,
+            "message_index": checkpoint.message_index,
+            "files": checkpoint.changed_files(),
+            "diff": diff,
+        }))
+        .into_response(),
+        Err(e) => AppError(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+// Roll the workspace back to before a checkpoint (and every later one)
+async fn restore_checkpoint(
+    AgentScope(host): AgentScope,
+    Path(CheckpointPath { checkpoint_id }): Path<CheckpointPath>,
+) -> Response {
+    let store = CheckpointStore::for_config(&host.config);
+    let checkpoint = match store.find(&checkpoint_id) {
+        Ok(c) => c,
+        Err(e) => return AppError(StatusCode::NOT_FOUND, e.to_string()).into_response(),
+    };
+
+    let _gate_permit = host.turn_gate.acquire().await;
+    let ws_lock = host.workspace_lock.clone();
+    let restored = tokio::task::spawn_blocking(move || {
+        let _guard = ws_lock.acquire()?;
+        store.restore(&checkpoint.id)
+    })
+    .await;
+
+    match restored {
+        Ok(Ok((checkpoints, files))) => Json(json!({
+            "restored_checkpoints": checkpoints,
+            "files": files,
+        }))
+        .into_response(),
+        Ok(Err(e)) => AppError(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+        Err(e) => AppError(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+// Cost ledger endpoint - spend per day/agent/session/source for a month
+#[derive(Deserialize)]
+struct CostsQuery {
+    /// Month as YYYY-MM (default: current month)
+    month: Option<String>,
+}
+
+#[derive(Serialize)]
+struct CostsResponse {
+    month: String,
+    total_usd: f64,
+    daily_budget_usd: Option<f64>,
+    monthly_budget_usd: Option<f64>,
+    entries: Vec<LedgerEntry>,
+}
+
+async fn get_costs(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<CostsQuery>,
+) -> Response {
+    let month = query
+        .month
+        .unwrap_or_else(|| chrono::Local::now().format("%Y-%m").to_string());
+
+    if chrono::NaiveDate::parse_from_str(&format!("{}-01", month), "%Y-%m-%d").is_err() {
+        return AppError(
+            StatusCode::BAD_REQUEST,
+            "month must be formatted as YYYY-MM".to_string(),
+        )
+        .into_response();
+    }
+
+    match CostLedger::for_config(&state.config).month_entries(&month) {
+        Ok(entries) => Json(CostsResponse {
+            total_usd: entries.iter().map(|e| e.totals.cost_usd).sum(),
+            month,
+            daily_budget_usd: state.config.cost.daily_budget_usd,
+            monthly_budget_usd: state.config.cost.monthly_budget_usd,
+            entries,
+        })
+        .into_response(),
+        Err(e) => AppError(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
 // Daemon logs endpoint - read log file
 #[derive(Deserialize)]
 struct LogsQuery {
@@ -1288,8 +1957,9 @@ async fn get_daemon_logs(Query(query): Query<LogsQuery>) -> Response {
 async fn websocket_handler(
     ws: WebSocketUpgrade,
     State(state): State<Arc<AppState>>,
+    AgentScope(host): AgentScope,
 ) -> impl IntoResponse {
-    ws.on_upgrade(|socket| handle_websocket(socket, state))
+    ws.on_upgrade(|socket| handle_websocket(socket, state, host))
 }
 
 /// WebSocket message types
@@ -1303,6 +1973,17 @@ enum WsIncoming {
     /// For streaming, use the SSE endpoint at /api/chat/stream
     #[serde(rename = "chat")]
     Chat { message: String },
+    /// Cancel the chat turn in progress
+    #[serde(rename = "cancel")]
+    Cancel,
+    /// Answer a tool_approval_required message
+    #[serde(rename = "approval")]
+    Approval {
+        request_id: String,
+        approved: bool,
+        #[serde(default)]
+        always: bool,
+    },
     /// Ping for keepalive
     #[serde(rename = "ping")]
     Ping,
@@ -1328,9 +2009,33 @@ enum WsOutgoing {
         id: String,
         output: String,
     },
+    /// Memory auto-recalled for the turn
+    #[serde(rename = "memory_recalled")]
+    MemoryRecalled { citations: Vec<String> },
+    /// Model failed and the fallback chain moved to the next one
+    #[serde(rename = "fallback")]
+    Fallback {
+        from: String,
+        to: String,
+        reason: String,
+    },
+    /// Tool call waiting for an approval message
+    #[serde(rename = "tool_approval_required")]
+    ToolApprovalRequired {
+        request_id: String,
+        name: String,
+        id: String,
+        arguments: String,
+        detail: Option<String>,
+        /// Offered for "always allow"
+        pattern: Option<String>,
+    },
     /// Message complete
     #[serde(rename = "done")]
     Done,
+    /// Turn stopped by a cancel message
+    #[serde(rename = "cancelled")]
+    Cancelled,
     /// Pong response
     #[serde(rename = "pong")]
     Pong,
@@ -1339,7 +2044,18 @@ enum WsOutgoing {
     Error { message: String },
 }
 
-async fn handle_websocket(socket: WebSocket, state: Arc<AppState>) {
+/// Answer an approval from a WebSocket message; an error reply if unknown
+fn answer_ws_approval(
+    state: &AppState,
+    request_id: &str,
+    decision: ApprovalDecision,
+) -> Option<WsOutgoing> {
+    (!state.approvals.resolve(request_id, decision)).then(|| WsOutgoing::Error {
+        message: format!("No pending approval with ID {}", request_id),
+    })
+}
+
+async fn handle_websocket(socket: WebSocket, state: Arc<AppState>, host: Arc<AgentHost>) {
     let (mut sender, mut receiver) = socket.split();
 
     debug!("WebSocket client connected");
@@ -1355,7 +2071,7 @@ async fn handle_websocket(socket: WebSocket, state: Arc<AppState>) {
                 match serde_json::from_str::<WsIncoming>(&text) {
                     Ok(WsIncoming::Session { session_id }) => {
                         // Create or resume session
-                        match get_or_create_session(&state, session_id).await {
+                        match get_or_create_session(&state, &host, session_id).await {
                             Ok(id) => {
                                 current_session_id = Some(id.clone());
                                 let connected = WsOutgoing::Connected { session_id: id };
@@ -1379,7 +2095,7 @@ async fn handle_websocket(socket: WebSocket, state: Arc<AppState>) {
                             Some(id) => id.clone(),
                             None => {
                                 // Auto-create session if none exists
-                                match get_or_create_session(&state, None).await {
+                                match get_or_create_session(&state, &host, None).await {
                                     Ok(id) => {
                                         current_session_id = Some(id.clone());
                                         // Notify client of new session
@@ -1407,10 +2123,10 @@ async fn handle_websocket(socket: WebSocket, state: Arc<AppState>) {
                         debug!("WebSocket chat [{}]: {}", session_id, message);
 
                         // Acquire in-process turn gate
-                        let _gate_permit = state.turn_gate.acquire().await;
+                        let _gate_permit = host.turn_gate.acquire().await;
 
                         // Acquire cross-process workspace lock
-                        let ws_lock = state.workspace_lock.clone();
+                        let ws_lock = host.workspace_lock.clone();
                         let _ws_guard =
                             match tokio::task::spawn_blocking(move || ws_lock.acquire()).await {
                                 Ok(Ok(guard)) => guard,
@@ -1435,7 +2151,7 @@ async fn handle_websocket(socket: WebSocket, state: Arc<AppState>) {
                             };
 
                         // Process chat
-                        let mut sessions = state.sessions.lock().await;
+                        let mut sessions = host.sessions.lock().await;
                         let entry = match sessions.get_mut(&session_id) {
                             Some(e) => e,
                             None => {
@@ -1452,7 +2168,103 @@ async fn handle_websocket(socket: WebSocket, state: Arc<AppState>) {
 
                         entry.last_accessed = Instant::now();
 
-                        match entry.agent.chat(&message).await {
+                        // Keep reading the socket during the turn so a cancel
+                        // or approval can reach it; a disconnect cancels too
+                        let cancel = entry.agent.cancel_handle();
+                        let mut approval_requests = entry.agent.subscribe_approvals();
+                        let result = {
+                            let chat = entry.agent.chat(&message);
+                            tokio::pin!(chat);
+                            let mut connected = true;
+                            loop {
+                                tokio::select! {
+                                    result = &mut chat => break result,
+                                    Some(request) = approval_requests.recv() => {
+                                        let outgoing = WsOutgoing::ToolApprovalRequired {
+                                            request_id: request.id,
+                                            name: request.name,
+                                            id: request.call_id,
+                                            arguments: request.arguments,
+                                            detail: request.detail,
+                                            pattern: request.pattern,
+                                        };
+                                        if let Ok(json) = serde_json::to_string(&outgoing) {
+                                            let _ = sender.send(WsMessage::Text(json.into())).await;
+                                        }
+                                    }
+                                    incoming = receiver.next(), if connected => {
+                                        let reply = match incoming {
+                                            Some(Ok(WsMessage::Text(text))) => {
+                                                match serde_json::from_str::<WsIncoming>(&text) {
+                                                    Ok(WsIncoming::Cancel) => {
+                                                        cancel.cancel();
+                                                        None
+                                                    }
+                                                    Ok(WsIncoming::Approval {
+                                                        request_id,
+                                                        approved,
+                                                        always,
+                                                    }) => answer_ws_approval(
+                                                        &state,
+                                                        &request_id,
+                                                        ApprovalDecision::from_flags(
+                                                            approved, always,
+                                                        ),
+                                                    ),
+                                                    Ok(WsIncoming::Ping) => Some(WsOutgoing::Pong),
+                                                    Ok(_) => Some(WsOutgoing::Error {
+                                                        message: "A response is still in progress"
+                                                            .to_string(),
+                                                    }),
+                                                    Err(e) => Some(WsOutgoing::Error {
+                                                        message: format!(
+                                                            "Invalid message format: {}",
+                                                            e
+                                                        ),
+                                                    }),
+                                                }
+                                            }
+                                            Some(Ok(WsMessage::Ping(data))) => {
+                                                let _ = sender.send(WsMessage::Pong(data)).await;
+                                                None
+                                            }
+                                            Some(Ok(_)) => None,
+                                            Some(Err(_)) | None => {
+                                                connected = false;
+                                                cancel.cancel();
+                                                None
+                                            }
+                                        };
+                                        if let Some(reply) = reply
+                                            && let Ok(json) = serde_json::to_string(&reply)
+                                        {
+                                            let _ = sender.send(WsMessage::Text(json.into())).await;
+                                        }
+                                    }
+                                }
+                            }
+                        };
+
+                        let citations = entry.agent.recalled_memory().to_vec();
+                        if !citations.is_empty()
+                            && let Ok(json) =
+                                serde_json::to_string(&WsOutgoing::MemoryRecalled { citations })
+                        {
+                            let _ = sender.send(WsMessage::Text(json.into())).await;
+                        }
+
+                        for notice in entry.agent.take_fallback_notices() {
+                            let fallback = WsOutgoing::Fallback {
+                                from: notice.from,
+                                to: notice.to,
+                                reason: notice.reason,
+                            };
+                            if let Ok(json) = serde_json::to_string(&fallback) {
+                                let _ = sender.send(WsMessage::Text(json.into())).await;
+                            }
+                        }
+
+                        match result {
                             Ok(response) => {
                                 // Send response as content
                                 let content = WsOutgoing::Content { delta: response };
@@ -1466,6 +2278,11 @@ async fn handle_websocket(socket: WebSocket, state: Arc<AppState>) {
                                     let _ = sender.send(WsMessage::Text(json.into())).await;
                                 }
                             }
+                            Err(e) if is_cancelled(&e) => {
+                                if let Ok(json) = serde_json::to_string(&WsOutgoing::Cancelled) {
+                                    let _ = sender.send(WsMessage::Text(json.into())).await;
+                                }
+                            }
                             Err(e) => {
                                 let error = WsOutgoing::Error {
                                     message: e.to_string(),
@@ -1476,6 +2293,21 @@ async fn handle_websocket(socket: WebSocket, state: Arc<AppState>) {
                             }
                         }
                     }
+                    // Nothing in flight to cancel
+                    Ok(WsIncoming::Cancel) => {}
+                    // May answer a request shown elsewhere (e.g. an SSE turn)
+                    Ok(WsIncoming::Approval {
+                        request_id,
+                        approved,
+                        always,
+                    }) => {
+                        let decision = ApprovalDecision::from_flags(approved, always);
+                        if let Some(error) = answer_ws_approval(&state, &request_id, decision)
+                            && let Ok(json) = serde_json::to_string(&error)
+                        {
+                            let _ = sender.send(WsMessage::Text(json.into())).await;
+                        }
+                    }
                     Ok(WsIncoming::Ping) => {
                         let pong = WsOutgoing::Pong;
                         if let Ok(json) = serde_json::to_string(&pong) {
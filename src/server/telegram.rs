@@ -2,24 +2,32 @@
 //!
 //! Provides a Telegram bot that allows interacting with LocalGPT remotely.
 //! Uses a one-time pairing code mechanism to restrict access to the owner.
+//! Each chat is served by the agent `telegram.chat_agents` maps it to
+//! (`telegram.default_agent` otherwise), with that agent's workspace and index.
 
 use anyhow::Result;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap};
 use std::path::PathBuf;
 use std::sync::Arc;
 use std::time::Instant;
 use teloxide::prelude::*;
-use teloxide::types::{MessageId, ParseMode};
+use teloxide::types::{
+    InlineKeyboardButton, InlineKeyboardMarkup, MessageId, ParseMode, UpdateKind,
+};
 use tokio::sync::Mutex;
 use tracing::{debug, error, info, warn};
 
-use crate::agent::{Agent, AgentConfig, StreamEvent, extract_tool_detail};
-use crate::concurrency::TurnGate;
-use crate::config::Config;
+use crate::agent::{
+    Agent, AgentConfig, ApprovalBroker, ApprovalDecision, CancelHandle, StreamEvent, UsageSource,
+    extract_tool_detail, is_cancelled,
+};
+use crate::concurrency::{TurnGate, TurnGates};
+use crate::config::{Config, TelegramConfig};
 use crate::memory::MemoryManager;
 
-/// Agent ID for Telegram sessions
+/// Agent ID for Telegram sessions of the "telegram" agent (`telegram-<id>`
+/// for others)
 const TELEGRAM_AGENT_ID: &str = "telegram";
 
 /// Maximum Telegram message length
@@ -28,6 +36,9 @@ const MAX_MESSAGE_LENGTH: usize = 4096;
 /// Debounce interval for message edits (seconds)
 const EDIT_DEBOUNCE_SECS: u64 = 2;
 
+/// Tail of the model's reasoning shown until the answer starts
+const THINKING_PREVIEW_CHARS: usize = 300;
+
 #[derive(Debug, Serialize, Deserialize)]
 struct PairedUser {
     user_id: u64,
@@ -40,15 +51,60 @@ struct SessionEntry {
     last_accessed: Instant,
 }
 
-struct BotState {
+/// An agent serving Telegram chats
+struct ChatAgent {
+    /// Config with the agent's `[agents.<id>]` overrides applied
     config: Config,
-    sessions: Mutex<HashMap<i64, SessionEntry>>,
+    /// Agent ID its sessions are saved and billed under
+    sessions_id: String,
     memory: MemoryManager,
+    /// Gate shared with turns of other agents in the same workspace
     turn_gate: TurnGate,
+}
+
+impl ChatAgent {
+    fn new(config: &Config, agent_id: &str, gates: &TurnGates) -> Result<Self> {
+        let config = config.for_agent(agent_id);
+        let memory = MemoryManager::new_with_full_config(&config.memory, Some(&config), agent_id)?;
+        let sessions_id = if agent_id == TELEGRAM_AGENT_ID {
+            agent_id.to_string()
+        } else {
+            format!("{}-{}", TELEGRAM_AGENT_ID, agent_id)
+        };
+
+        Ok(Self {
+            sessions_id,
+            memory,
+            turn_gate: gates.for_workspace(&config.workspace_path()),
+            config,
+        })
+    }
+}
+
+struct BotState {
+    telegram: TelegramConfig,
+    /// Agents by ID: the default agent and every one in `chat_agents`
+    agents: HashMap<String, ChatAgent>,
+    sessions: Mutex<HashMap<i64, SessionEntry>>,
+    /// Cancel handles by chat ID, reachable while a turn holds `sessions`
+    cancel_handles: std::sync::Mutex<HashMap<i64, CancelHandle>>,
+    /// Tool approvals, answered from inline keyboard buttons
+    approvals: ApprovalBroker,
     paired_user: Mutex<Option<PairedUser>>,
     pending_pairing_code: Mutex<Option<String>>,
 }
 
+impl BotState {
+    /// Agent that serves a chat
+    fn agent_for(&self, chat_id: ChatId) -> &ChatAgent {
+        let agent_id = self.telegram.agent_for_chat(chat_id.0);
+        self.agents
+            .get(agent_id)
+            .or_else(|| self.agents.get(&self.telegram.default_agent))
+            .expect("default Telegram agent is always loaded")
+    }
+}
+
 fn pairing_file_path() -> Result<PathBuf> {
     let paths = crate::paths::Paths::resolve()?;
     Ok(paths.pairing_file())
@@ -73,7 +129,7 @@ fn generate_pairing_code() -> String {
     format!("{:06}", rng.random_range(100000..999999u32))
 }
 
-pub async fn run_telegram_bot(config: &Config, turn_gate: TurnGate) -> Result<()> {
+pub async fn run_telegram_bot(config: &Config, turn_gates: TurnGates) -> Result<()> {
     let telegram_config = config
         .telegram
         .as_ref()
@@ -90,8 +146,14 @@ pub async fn run_telegram_bot(config: &Config, turn_gate: TurnGate) -> Result<()
 
     let bot = Bot::new(token);
 
-    let memory =
-        MemoryManager::new_with_full_config(&config.memory, Some(config), TELEGRAM_AGENT_ID)?;
+    let agent_ids: BTreeSet<&String> = std::iter::once(&telegram_config.default_agent)
+        .chain(telegram_config.chat_agents.values())
+        .collect();
+    let mut agents = HashMap::new();
+    for agent_id in agent_ids {
+        let agent = ChatAgent::new(config, agent_id, &turn_gates)?;
+        agents.insert(agent_id.clone(), agent);
+    }
 
     let paired_user = load_paired_user();
     if let Some(ref user) = paired_user {
@@ -105,10 +167,11 @@ pub async fn run_telegram_bot(config: &Config, turn_gate: TurnGate) -> Result<()
     }
 
     let state = Arc::new(BotState {
-        config: config.clone(),
+        telegram: telegram_config.clone(),
+        agents,
         sessions: Mutex::new(HashMap::new()),
-        memory,
-        turn_gate,
+        cancel_handles: std::sync::Mutex::new(HashMap::new()),
+        approvals: ApprovalBroker::new(),
         paired_user: Mutex::new(paired_user),
         pending_pairing_code: Mutex::new(None),
     });
@@ -121,9 +184,12 @@ pub async fn run_telegram_bot(config: &Config, turn_gate: TurnGate) -> Result<()
 
     info!("Starting Telegram bot...");
 
-    let handler = Update::filter_message().endpoint(handle_message);
+    let handler = dptree::entry()
+        .branch(Update::filter_message().endpoint(handle_message))
+        .branch(Update::filter_callback_query().endpoint(handle_callback_query));
 
     Dispatcher::builder(bot, handler)
+        .distribution_function(distribute_update)
         .default_handler(|_upd| async {})
         .dependencies(dptree::deps![state])
         .enable_ctrlc_handler()
@@ -134,6 +200,71 @@ pub async fn run_telegram_bot(config: &Config, turn_gate: TurnGate) -> Result<()
     Ok(())
 }
 
+/// Updates from one chat are handled in order, except /stop and approval
+/// buttons, which have to run while that chat's turn is still in progress
+fn distribute_update(update: &Update) -> Option<ChatId> {
+    match &update.kind {
+        UpdateKind::Message(msg) if msg.text().is_some_and(|t| t.trim() == "/stop") => None,
+        UpdateKind::CallbackQuery(_) => None,
+        _ => update.chat().map(|c| c.id),
+    }
+}
+
+/// Approve/Always/Deny buttons under a tool approval prompt
+async fn handle_callback_query(
+    bot: Bot,
+    query: CallbackQuery,
+    state: Arc<BotState>,
+) -> ResponseResult<()> {
+    let authorized = state
+        .paired_user
+        .lock()
+        .await
+        .as_ref()
+        .is_some_and(|pu| pu.user_id == query.from.id.0);
+
+    let answer = match query.data.as_deref().and_then(parse_approval_callback) {
+        _ if !authorized => "Not authorized.",
+        Some((request_id, decision)) if state.approvals.resolve(request_id, decision) => {
+            match decision {
+                ApprovalDecision::Approve => "Approved",
+                ApprovalDecision::AlwaysAllow => "Approved, and allowed from now on",
+                ApprovalDecision::Deny => "Denied",
+            }
+        }
+        Some(_) => "This request is no longer pending.",
+        None => "Unknown action.",
+    };
+    bot.answer_callback_query(query.id.clone())
+        .text(answer)
+        .await?;
+
+    // Replace the buttons with the outcome
+    if authorized && let Some(message) = query.regular_message() {
+        let prompt = message.text().unwrap_or_default();
+        let _ = bot
+            .edit_message_text(
+                message.chat.id,
+                message.id,
+                format!("{}\n{}", prompt, answer),
+            )
+            .await;
+    }
+    Ok(())
+}
+
+/// "approve:<id>" / "always:<id>" / "deny:<id>" callback data
+fn parse_approval_callback(data: &str) -> Option<(&str, ApprovalDecision)> {
+    let (action, id) = data.split_once(':')?;
+    let decision = match action {
+        "approve" => ApprovalDecision::Approve,
+        "always" => ApprovalDecision::AlwaysAllow,
+        "deny" => ApprovalDecision::Deny,
+        _ => return None,
+    };
+    Some((id, decision))
+}
+
 async fn handle_message(bot: Bot, msg: Message, state: Arc<BotState>) -> ResponseResult<()> {
     let text = match msg.text() {
         Some(t) => t.to_string(),
@@ -274,12 +405,27 @@ async fn handle_command(
             )
             .await?;
         }
+        "/stop" => {
+            let handle = state
+                .cancel_handles
+                .lock()
+                .ok()
+                .and_then(|handles| handles.get(&chat_id.0).cloned());
+            let reply = match handle {
+                Some(handle) => {
+                    handle.cancel();
+                    "Stopping."
+                }
+                None => "No active session.",
+            };
+            bot.send_message(chat_id, reply).await?;
+        }
         "/status" => {
             let sessions = state.sessions.lock().await;
             let status_text = if let Some(entry) = sessions.get(&chat_id.0) {
                 let status = entry.agent.session_status();
                 let (used, usable, total) = entry.agent.context_usage();
-                format!(
+                let mut text = format!(
                     "Session active\n\
                      Model: {}\n\
                      Messages: {}\n\
@@ -293,7 +439,16 @@ async fn handle_command(
                     total,
                     status.compaction_count,
                     entry.last_accessed.elapsed().as_secs()
-                )
+                );
+                if status.api_cache_read_tokens + status.api_cache_write_tokens > 0
+                    && let Some(rate) = status.cache_hit_rate()
+                {
+                    text.push_str(&format!("\nCache hit rate: {:.1}%", rate * 100.0));
+                }
+                if status.api_cost_usd > 0.0 {
+                    text.push_str(&format!("\nSession cost: ${:.4}", status.api_cost_usd));
+                }
+                text
             } else {
                 "No active session. Send a message to start one.".to_string()
             };
@@ -334,12 +489,31 @@ async fn handle_command(
                 bot.send_message(chat_id, "No active session.").await?;
             }
         }
+        "/undo" => {
+            let mut sessions = state.sessions.lock().await;
+            let reply = match sessions.get_mut(&chat_id.0) {
+                Some(entry) => {
+                    entry.last_accessed = Instant::now();
+                    match entry.agent.undo_last_turn() {
+                        Ok(Some(files)) => {
+                            let names: Vec<String> =
+                                files.iter().map(|f| f.display().to_string()).collect();
+                            format!("Restored {} file(s):\n{}", files.len(), names.join("\n"))
+                        }
+                        Ok(None) => "No file changes to undo.".to_string(),
+                        Err(e) => format!("Undo failed: {}", e),
+                    }
+                }
+                None => "No active session.".to_string(),
+            };
+            bot.send_message(chat_id, reply).await?;
+        }
         "/memory" => {
             if args.is_empty() {
                 bot.send_message(chat_id, "Usage: /memory <search query>")
                     .await?;
             } else {
-                match state.memory.search(args, 5) {
+                match state.agent_for(chat_id).memory.search(args, 5) {
                     Ok(results) => {
                         if results.is_empty() {
                             bot.send_message(chat_id, "No results found.").await?;
@@ -371,7 +545,7 @@ async fn handle_command(
                 let current = sessions
                     .get(&chat_id.0)
                     .map(|e| e.agent.model().to_string())
-                    .unwrap_or_else(|| state.config.agent.default_model.clone());
+                    .unwrap_or_else(|| state.agent_for(chat_id).config.agent.default_model.clone());
                 bot.send_message(
                     chat_id,
                     format!("Current model: {}\n\nUsage: /model <name>", current),
@@ -400,7 +574,7 @@ async fn handle_command(
             }
         }
         "/skills" => {
-            let workspace_path = state.config.workspace_path();
+            let workspace_path = state.agent_for(chat_id).config.workspace_path();
             match crate::agent::load_skills(&workspace_path) {
                 Ok(skills) => {
                     if skills.is_empty() {
@@ -629,21 +803,32 @@ async fn handle_chat(
     let thinking_msg = bot.send_message(chat_id, "Thinking...").await?;
     let msg_id = thinking_msg.id;
 
+    let chat_agent = state.agent_for(chat_id);
+
     // Acquire turn gate
-    let _gate_permit = state.turn_gate.acquire().await;
+    let _gate_permit = chat_agent.turn_gate.acquire().await;
 
     // Get or create agent session, then stream response
     let mut sessions = state.sessions.lock().await;
 
     if let std::collections::hash_map::Entry::Vacant(e) = sessions.entry(chat_id.0) {
         let agent_config = AgentConfig {
-            model: state.config.agent.default_model.clone(),
-            context_window: state.config.agent.context_window,
-            reserve_tokens: state.config.agent.reserve_tokens,
+            model: chat_agent.config.agent.default_model.clone(),
+            context_window: chat_agent.config.agent.context_window,
+            reserve_tokens: chat_agent.config.agent.reserve_tokens,
         };
 
-        match Agent::new(agent_config, &state.config, state.memory.clone()).await {
+        match Agent::new(
+            agent_config,
+            &chat_agent.config,
+            &chat_agent.sessions_id,
+            chat_agent.memory.clone(),
+        )
+        .await
+        {
             Ok(mut agent) => {
+                agent.set_usage_attribution(&chat_agent.sessions_id, UsageSource::Telegram);
+                agent.set_approval_broker(state.approvals.clone());
                 if let Err(err) = agent.new_session().await {
                     error!("Failed to create session: {}", err);
                     let _ = bot
@@ -651,6 +836,9 @@ async fn handle_chat(
                         .await;
                     return Ok(());
                 }
+                if let Ok(mut handles) = state.cancel_handles.lock() {
+                    handles.insert(chat_id.0, agent.cancel_handle());
+                }
                 e.insert(SessionEntry {
                     agent,
                     last_accessed: Instant::now(),
@@ -678,9 +866,22 @@ async fn handle_chat(
             let mut last_edit = Instant::now();
             let mut pinned_stream = std::pin::pin!(event_stream);
             let mut tool_info = String::new();
+            let mut thinking = String::new();
 
             while let Some(event) = pinned_stream.next().await {
                 match event {
+                    Ok(StreamEvent::Thinking(delta)) => {
+                        thinking.push_str(&delta);
+
+                        // Preview reasoning until the answer starts
+                        if full_response.is_empty()
+                            && last_edit.elapsed().as_secs() >= EDIT_DEBOUNCE_SECS
+                        {
+                            let display = format_display(&thinking_preview(&thinking), &tool_info);
+                            let _ = bot.edit_message_text(chat_id, msg_id, &display).await;
+                            last_edit = Instant::now();
+                        }
+                    }
                     Ok(StreamEvent::Content(delta)) => {
                         full_response.push_str(&delta);
 
@@ -706,6 +907,40 @@ async fn handle_chat(
                         let _ = bot.edit_message_text(chat_id, msg_id, &display).await;
                         last_edit = Instant::now();
                     }
+                    Ok(StreamEvent::ToolApprovalRequired {
+                        request_id,
+                        name,
+                        arguments,
+                        pattern,
+                        ..
+                    }) => {
+                        let mut prompt = match extract_tool_detail(&name, &arguments) {
+                            Some(d) => format!("🔐 Run {}({})?", name, d),
+                            None => format!("🔐 Run {}?", name),
+                        };
+                        prompt.push_str(&format!(
+                            "\nAlways allow: {}",
+                            pattern.as_deref().unwrap_or("any call to this tool")
+                        ));
+                        let keyboard = InlineKeyboardMarkup::new([[
+                            InlineKeyboardButton::callback(
+                                "✅ Approve",
+                                format!("approve:{}", request_id),
+                            ),
+                            InlineKeyboardButton::callback(
+                                "♾ Always",
+                                format!("always:{}", request_id),
+                            ),
+                            InlineKeyboardButton::callback(
+                                "❌ Deny",
+                                format!("deny:{}", request_id),
+                            ),
+                        ]]);
+                        let _ = bot
+                            .send_message(chat_id, prompt)
+                            .reply_markup(keyboard)
+                            .await;
+                    }
                     Ok(StreamEvent::ToolCallEnd { name, warnings, .. }) => {
                         if !warnings.is_empty() {
                             for w in &warnings {
@@ -719,7 +954,25 @@ async fn handle_chat(
                             last_edit = Instant::now();
                         }
                     }
+                    Ok(StreamEvent::MemoryRecalled { citations }) => {
+                        tool_info
+                            .push_str(&format!("\u{1f9e0} Recalled: {}\n", citations.join(", ")));
+                        let display = format_display(&full_response, &tool_info);
+                        let _ = bot.edit_message_text(chat_id, msg_id, &display).await;
+                        last_edit = Instant::now();
+                    }
+                    Ok(StreamEvent::Fallback { from, to, .. }) => {
+                        tool_info
+                            .push_str(&format!("\u{21aa} {} unavailable, using {}\n", from, to));
+                        let display = format_display(&full_response, &tool_info);
+                        let _ = bot.edit_message_text(chat_id, msg_id, &display).await;
+                        last_edit = Instant::now();
+                    }
                     Ok(StreamEvent::Done) => break,
+                    Err(e) if is_cancelled(&e) => {
+                        full_response.push_str("\n\n⏹ Stopped.");
+                        break;
+                    }
                     Err(e) => {
                         error!("Stream error: {}", e);
                         full_response.push_str(&format!("\n\nError: {}", e));
@@ -738,7 +991,11 @@ async fn handle_chat(
     };
 
     // Save session before releasing lock
-    if let Err(e) = entry.agent.save_session_for_agent(TELEGRAM_AGENT_ID).await {
+    if let Err(e) = entry
+        .agent
+        .save_session_for_agent(&chat_agent.sessions_id)
+        .await
+    {
         debug!("Failed to save telegram session: {}", e);
     }
 
@@ -750,6 +1007,18 @@ async fn handle_chat(
     Ok(())
 }
 
+/// "💭 ..." line with the tail of the reasoning so far
+fn thinking_preview(thinking: &str) -> String {
+    let chars: Vec<char> = thinking.trim().chars().collect();
+    let start = chars.len().saturating_sub(THINKING_PREVIEW_CHARS);
+    let tail: String = chars[start..].iter().collect();
+    if start > 0 {
+        format!("💭 ...{}", tail)
+    } else {
+        format!("💭 {}", tail)
+    }
+}
+
 fn format_display(response: &str, tool_info: &str) -> String {
     let mut display = String::new();
     if !tool_info.is_empty() {
@@ -14,7 +14,13 @@ document.addEventListener('DOMContentLoaded', () => {
 });
 
 function setupEventListeners() {
-    document.getElementById('send').onclick = sendMessage;
+    document.getElementById('send').onclick = () => {
+        if (isStreaming) {
+            cancelResponse();
+        } else {
+            sendMessage();
+        }
+    };
     document.getElementById('new-session').onclick = newSession;
 
     const input = document.getElementById('input');
@@ -197,8 +203,9 @@ async function sendMessage() {
     const assistantDiv = appendMessage('assistant', '');
     assistantDiv.classList.add('loading');
 
+    // The send button doubles as a stop button while streaming
     const sendBtn = document.getElementById('send');
-    sendBtn.disabled = true;
+    sendBtn.textContent = 'Stop';
     isStreaming = true;
 
     try {
@@ -243,12 +250,33 @@ async function sendMessage() {
         assistantDiv.textContent = `Error: ${err.message}`;
     } finally {
         assistantDiv.classList.remove('loading');
-        sendBtn.disabled = false;
+        sendBtn.textContent = 'Send';
         isStreaming = false;
         scrollToBottom();
     }
 }
 
+function cancelResponse() {
+    if (!sessionId) return;
+    fetch(`${API}/sessions/${sessionId}/cancel`, { method: 'POST' })
+        .catch(err => appendSystemMessage(`Cancel failed: ${err.message}`));
+}
+
+function answerApproval(requestId, approved, always, approvalDiv) {
+    approvalDiv.querySelectorAll('button').forEach(b => b.disabled = true);
+    fetch(`${API}/approvals/${requestId}`, {
+        method: 'POST',
+        headers: { 'Content-Type': 'application/json' },
+        body: JSON.stringify({ approved, always })
+    })
+        .then(res => {
+            if (!res.ok) throw new Error('request is no longer pending');
+            const label = approvalDiv.querySelector('.tool-name').outerHTML;
+            approvalDiv.innerHTML = `${label} ${approved ? 'Approved' : 'Denied'}`;
+        })
+        .catch(err => appendSystemMessage(`Approval failed: ${err.message}`));
+}
+
 function handleEvent(event, assistantDiv) {
     switch (event.type) {
         case 'session':
@@ -261,6 +289,19 @@ function handleEvent(event, assistantDiv) {
             scrollToBottom();
             break;
 
+        case 'thinking': {
+            // Reasoning goes in its own block just above the answer
+            let thinkingDiv = assistantDiv.previousElementSibling;
+            if (!thinkingDiv || !thinkingDiv.classList.contains('thinking')) {
+                thinkingDiv = document.createElement('div');
+                thinkingDiv.className = 'message thinking';
+                assistantDiv.before(thinkingDiv);
+            }
+            thinkingDiv.textContent += event.delta;
+            scrollToBottom();
+            break;
+        }
+
         case 'tool_start':
             const toolStartDiv = document.createElement('div');
             toolStartDiv.className = 'message tool';
@@ -273,6 +314,25 @@ function handleEvent(event, assistantDiv) {
             scrollToBottom();
             break;
 
+        case 'tool_approval_required': {
+            const approvalDiv = document.createElement('div');
+            approvalDiv.className = 'message tool approval';
+            const label = event.detail
+                ? `[${event.name}: ${escapeHtml(event.detail)}]`
+                : `[${event.name}]`;
+            const pattern = event.pattern ? escapeHtml(event.pattern) : 'any call to this tool';
+            approvalDiv.innerHTML = `<span class="tool-name">${label}</span> Needs approval
+                <button class="approve">Approve</button>
+                <button class="always" title="Always allow: ${pattern}">Always allow</button>
+                <button class="deny">Deny</button>`;
+            approvalDiv.querySelector('.approve').onclick = () => answerApproval(event.request_id, true, false, approvalDiv);
+            approvalDiv.querySelector('.always').onclick = () => answerApproval(event.request_id, true, true, approvalDiv);
+            approvalDiv.querySelector('.deny').onclick = () => answerApproval(event.request_id, false, false, approvalDiv);
+            assistantDiv.after(approvalDiv);
+            scrollToBottom();
+            break;
+        }
+
         case 'tool_end':
             const toolEl = document.getElementById(`tool-${event.id}`);
             if (toolEl) {
@@ -287,6 +347,10 @@ function handleEvent(event, assistantDiv) {
             assistantDiv.textContent = `Error: ${event.message}`;
             break;
 
+        case 'cancelled':
+            appendSystemMessage('Response cancelled.');
+            break;
+
         case 'done':
             break;
     }
@@ -491,9 +555,11 @@ function updateStatusPanel(status, heartbeat) {
     }
 
     // Show detail if available
-    if (event.reason || event.preview) {
+    if (event.reason || event.tool_limit || event.preview) {
         heartbeatDetailRow.style.display = 'flex';
-        const detail = event.reason || (event.preview ? event.preview.slice(0, 100) + '...' : '-');
+        const detail = event.reason
+            || (event.tool_limit ? `Tool loop stopped: ${event.tool_limit}` : null)
+            || (event.preview ? event.preview.slice(0, 100) + '...' : '-');
         heartbeatDetailEl.textContent = detail;
     } else {
         heartbeatDetailRow.style.display = 'none';